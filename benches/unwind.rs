@@ -0,0 +1,287 @@
+//! Benchmarks of the unwinding hot path: walking a 30 frame stack and
+//! resolving its frames, against a `FakeTarget` laid out like the V8 11.3 of
+//! Node 20, so that they run without a live process. The stack is a native
+//! leaf frame, 28 interpreted frames of 14 functions and an entry frame.
+//!
+//!     cargo bench --bench unwind
+//!
+//! `sample` times a whole sample, and `resolve` reports frames resolved per
+//! second, each with and without the frame cache.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use v8spy::{FakeTarget, Registers, V8Spy};
+
+const DEPTH: usize = 30;
+const FUNCTIONS: usize = 14;
+
+const HEAP_BASE: usize = 0x1000_0000;
+const STACK_BASE: usize = 0x7ff0_0000;
+const FRAME_SIZE: usize = 0x80;
+
+// Instance types, as in Node 20
+const ONE_BYTE_STRING_TYPE: u16 = 8;
+const CONTEXT_TYPE: u16 = 205;
+const JS_FUNCTION_TYPE: u16 = 2066;
+const SHARED_FUNCTION_INFO_TYPE: u16 = 262;
+const SCRIPT_TYPE: u16 = 167;
+const FIXED_ARRAY_TYPE: u16 = 175;
+const BYTE_ARRAY_TYPE: u16 = 190;
+const BYTECODE_ARRAY_TYPE: u16 = 191;
+const CODE_TYPE: u16 = 245;
+const ENTRY_FRAME: i32 = 1;
+
+/// Offset of the bytecode from a tagged BytecodeArray pointer
+const BYTECODE_HEADER_SIZE: i64 = 54 - 1;
+
+/// The `v8dbg_` constants the benchmarks need, as read from Node 20
+const SYMBOLS: &[(&str, i32)] = &[
+    ("v8dbg_HeapObjectTagMask", 3),
+    ("v8dbg_SmiTagMask", 1),
+    ("v8dbg_HeapObjectTag", 1),
+    ("v8dbg_SmiTag", 0),
+    ("v8dbg_SmiShiftSize", 31),
+    ("v8dbg_FirstNonstringType", 128),
+    ("v8dbg_StringEncodingMask", 8),
+    ("v8dbg_StringRepresentationMask", 7),
+    ("v8dbg_SeqStringTag", 0),
+    ("v8dbg_OneByteStringTag", 8),
+    ("v8dbg_FirstJSFunctionType", 2066),
+    ("v8dbg_LastJSFunctionType", 2081),
+    ("v8dbg_FirstContextType", 205),
+    ("v8dbg_LastContextType", 214),
+    ("v8dbg_off_fp_function", -16),
+    ("v8dbg_off_fp_context", -8),
+    ("v8dbg_off_fp_bytecode_array", -32),
+    ("v8dbg_off_fp_bytecode_offset", -40),
+    ("v8dbg_frametype_EntryFrame", ENTRY_FRAME),
+    ("v8dbg_CodeKindFieldMask", 15),
+    ("v8dbg_CodeKindFieldShift", 0),
+    ("v8dbg_CodeKindBaseline", 11),
+    ("v8dbg_class_HeapObject__map__Map", 0),
+    ("v8dbg_class_Map__instance_type__uint16_t", 12),
+    ("v8dbg_class_String__length__int32_t", 12),
+    ("v8dbg_class_SeqOneByteString__chars__char", 16),
+    ("v8dbg_class_FixedArrayBase__length__SMI", 8),
+    ("v8dbg_class_FixedArray__data__uintptr_t", 16),
+    ("v8dbg_class_JSFunction__shared__SharedFunctionInfo", 24),
+    ("v8dbg_class_JSFunction__code__Code", 48),
+    ("v8dbg_class_SharedFunctionInfo__function_data__Object", 8),
+    ("v8dbg_class_SharedFunctionInfo__name_or_scope_info__Object", 16),
+    ("v8dbg_class_SharedFunctionInfo__script_or_debug_info__HeapObject", 32),
+    ("v8dbg_class_Script__source__Object", 8),
+    ("v8dbg_class_Script__name__Object", 16),
+    ("v8dbg_class_Script__line_ends__Object", 56),
+    ("v8dbg_class_BytecodeArray__source_position_table__Object", 32),
+    ("v8dbg_class_BytecodeArray__data__uintptr_t", 54),
+    ("v8dbg_class_Code__flags__uint32_t", 48),
+    ("v8dbg_type_JSFunction__JS_FUNCTION_TYPE", JS_FUNCTION_TYPE as i32),
+    ("v8dbg_type_SharedFunctionInfo__SHARED_FUNCTION_INFO_TYPE", SHARED_FUNCTION_INFO_TYPE as i32),
+    ("v8dbg_type_Script__SCRIPT_TYPE", SCRIPT_TYPE as i32),
+    ("v8dbg_type_FixedArray__FIXED_ARRAY_TYPE", FIXED_ARRAY_TYPE as i32),
+    ("v8dbg_type_ByteArray__BYTE_ARRAY_TYPE", BYTE_ARRAY_TYPE as i32),
+    ("v8dbg_type_BytecodeArray__BYTECODE_ARRAY_TYPE", BYTECODE_ARRAY_TYPE as i32),
+    ("v8dbg_type_Code__CODE_TYPE", CODE_TYPE as i32),
+];
+
+fn smi(value: i64) -> u64 {
+    (value as u64) << 32
+}
+
+/// Lays out heap objects one after the other, each returned as a tagged
+/// pointer
+struct Heap {
+    bytes: Vec<u8>,
+}
+
+impl Heap {
+    fn alloc(&mut self, size: usize) -> usize {
+        let addr = HEAP_BASE + self.bytes.len();
+        self.bytes.resize(self.bytes.len() + size.next_multiple_of(8), 0);
+        addr + 1
+    }
+
+    fn write(&mut self, object: usize, offset: usize, bytes: &[u8]) {
+        let start = object - 1 - HEAP_BASE + offset;
+        self.bytes[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn object(&mut self, map: usize, size: usize) -> usize {
+        let object = self.alloc(size);
+        self.write(object, 0, &(map as u64).to_ne_bytes());
+        object
+    }
+
+    fn map(&mut self, instance_type: u16) -> usize {
+        let map = self.alloc(16);
+        self.write(map, 12, &instance_type.to_ne_bytes());
+        map
+    }
+
+    fn string(&mut self, map: usize, s: &str) -> usize {
+        let string = self.object(map, 16 + s.len());
+        self.write(string, 12, &(s.len() as i32).to_ne_bytes());
+        self.write(string, 16, s.as_bytes());
+        string
+    }
+
+    fn fixed_array(&mut self, map: usize, elements: &[u64]) -> usize {
+        let array = self.object(map, 16 + 8 * elements.len());
+        self.write(array, 8, &smi(elements.len() as i64).to_ne_bytes());
+        for (i, element) in elements.iter().enumerate() {
+            self.write(array, 16 + 8 * i, &element.to_ne_bytes());
+        }
+        array
+    }
+
+    fn byte_array(&mut self, map: usize, bytes: &[u8]) -> usize {
+        let array = self.object(map, 16 + bytes.len());
+        self.write(array, 8, &smi(bytes.len() as i64).to_ne_bytes());
+        self.write(array, 16, bytes);
+        array
+    }
+}
+
+/// Appends a zigzag VLQ, as V8 encodes source position tables
+fn push_vlq(table: &mut Vec<u8>, value: i64) {
+    let mut bits = ((value << 1) ^ (value >> 63)) as u64;
+    loop {
+        let byte = (bits & 0x7f) as u8;
+        bits >>= 7;
+        if bits == 0 {
+            table.push(byte);
+            break;
+        }
+        table.push(byte | 0x80);
+    }
+}
+
+/// Builds the target and the registers of its one thread
+fn fake_process() -> (FakeTarget, Registers) {
+    let mut heap = Heap { bytes: Vec::new() };
+    let string_map = heap.map(ONE_BYTE_STRING_TYPE);
+    let context_map = heap.map(CONTEXT_TYPE);
+    let function_map = heap.map(JS_FUNCTION_TYPE);
+    let sfi_map = heap.map(SHARED_FUNCTION_INFO_TYPE);
+    let script_map = heap.map(SCRIPT_TYPE);
+    let fixed_array_map = heap.map(FIXED_ARRAY_TYPE);
+    let byte_array_map = heap.map(BYTE_ARRAY_TYPE);
+    let bytecode_array_map = heap.map(BYTECODE_ARRAY_TYPE);
+    let code_map = heap.map(CODE_TYPE);
+
+    // A script of 400 lines of 40 characters
+    let script = heap.object(script_map, 64);
+    let script_name = heap.string(string_map, "/srv/app/lib/server.js");
+    let line_ends: Vec<u64> = (1..=400).map(|line| smi(line * 40 - 1)).collect();
+    let line_ends = heap.fixed_array(fixed_array_map, &line_ends);
+    heap.write(script, 8, &smi(0).to_ne_bytes());
+    heap.write(script, 16, &(script_name as u64).to_ne_bytes());
+    heap.write(script, 56, &(line_ends as u64).to_ne_bytes());
+
+    let context = heap.object(context_map, 16);
+    let functions: Vec<(usize, usize)> = (0..FUNCTIONS)
+        .map(|i| {
+            // One statement every 8 bytes of bytecode, each on a line of its own
+            let mut table = Vec::new();
+            for statement in 0..8 {
+                push_vlq(&mut table, if statement == 0 { 0 } else { 8 });
+                let position = if statement == 0 { (i as i64 * 20 + 2) * 40 } else { 40 };
+                push_vlq(&mut table, position << 1);
+            }
+            let table = heap.byte_array(byte_array_map, &table);
+            let bytecode_array = heap.object(bytecode_array_map, 54 + 64);
+            heap.write(bytecode_array, 8, &smi(64).to_ne_bytes());
+            heap.write(bytecode_array, 32, &(table as u64).to_ne_bytes());
+
+            let sfi = heap.object(sfi_map, 64);
+            let name = heap.string(string_map, &format!("handleRequest{}", i));
+            heap.write(sfi, 8, &(bytecode_array as u64).to_ne_bytes());
+            heap.write(sfi, 16, &(name as u64).to_ne_bytes());
+            heap.write(sfi, 32, &(script as u64).to_ne_bytes());
+
+            // Interpreted functions run the InterpreterEntryTrampoline builtin
+            let code = heap.object(code_map, 64);
+            heap.write(code, 48, &4u32.to_ne_bytes());
+            let function = heap.object(function_map, 64);
+            heap.write(function, 24, &(sfi as u64).to_ne_bytes());
+            heap.write(function, 48, &(code as u64).to_ne_bytes());
+            (function, bytecode_array)
+        })
+        .collect();
+
+    // Frames from the innermost up, each with its caller's fp and return
+    // address at fp, above the slots V8 keeps below it
+    let mut stack = vec![0u8; (DEPTH + 1) * FRAME_SIZE];
+    let fp_of = |depth: usize| STACK_BASE + (depth + 1) * FRAME_SIZE;
+    let mut slot = |fp: usize, offset: isize, value: u64| {
+        let start = (fp as isize + offset) as usize - STACK_BASE;
+        stack[start..start + 8].copy_from_slice(&value.to_ne_bytes());
+    };
+    for depth in 0..DEPTH {
+        let fp = fp_of(depth);
+        let caller_fp = if depth + 1 < DEPTH { fp_of(depth + 1) } else { 0 };
+        slot(fp, 0, caller_fp as u64);
+        slot(fp, 8, 0x5555_0000 + depth as u64);
+        match depth {
+            // Native code keeps no context or function where V8 frames do
+            0 => {
+                slot(fp, -8, 0x7777_0001);
+                slot(fp, -16, 0x7777_0000);
+            }
+            _ if depth == DEPTH - 1 => slot(fp, -8, (ENTRY_FRAME as u64) << 1),
+            _ => {
+                let (function, bytecode_array) = functions[depth % FUNCTIONS];
+                slot(fp, -8, context as u64);
+                slot(fp, -16, function as u64);
+                slot(fp, -32, bytecode_array as u64);
+                slot(fp, -40, smi(BYTECODE_HEADER_SIZE + 8 * (depth as i64 % 8) + 1));
+            }
+        }
+    }
+
+    let mut builder = FakeTarget::builder()
+        .symbol_value("_ZN2v88internal7Version6major_E", 0x1000, 11u32)
+        .symbol_value("_ZN2v88internal7Version6minor_E", 0x1004, 3u32)
+        .symbol_value("_ZN2v88internal7Version6build_E", 0x1008, 244u32)
+        .symbol_value("_ZN2v88internal7Version6patch_E", 0x100c, 8u32);
+    for (i, &(name, value)) in SYMBOLS.iter().enumerate() {
+        builder = builder.symbol_value(name, 0x2000 + 4 * i, value);
+    }
+    let target = builder.bytes(HEAP_BASE, &heap.bytes).bytes(STACK_BASE, &stack).build();
+    let regs = Registers { pc: 0x5555_0000, sp: fp_of(0) - FRAME_SIZE / 2, fp: fp_of(0) };
+    (target, regs)
+}
+
+fn spy(frame_cache: bool) -> (V8Spy<FakeTarget>, Registers) {
+    let (target, regs) = fake_process();
+    let mut spy = V8Spy::from_memory(1, target).expect("the fake target has a supported V8 version");
+    if frame_cache {
+        spy.enable_frame_cache();
+    }
+    let frames = spy.walk_stack(regs);
+    assert_eq!(frames.len(), DEPTH, "the fake stack should walk to its entry frame");
+    assert!(spy.resolve_stack(&frames).iter().all(|frame| frame.name != "<unknown>"));
+    (spy, regs)
+}
+
+fn bench_sample(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sample");
+    for (name, frame_cache) in [("uncached", false), ("cached", true)] {
+        let (spy, regs) = spy(frame_cache);
+        group.bench_function(name, |b| b.iter(|| spy.resolve_stack(&spy.walk_stack(regs))));
+    }
+    group.finish();
+}
+
+fn bench_resolve(c: &mut Criterion) {
+    let mut group = c.benchmark_group("resolve");
+    group.throughput(Throughput::Elements(DEPTH as u64));
+    for (name, frame_cache) in [("uncached", false), ("cached", true)] {
+        let (spy, regs) = spy(frame_cache);
+        let frames = spy.walk_stack(regs);
+        group.bench_function(name, |b| b.iter(|| spy.resolve_stack(&frames)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_sample, bench_resolve);
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+//! Attaches to a Node process, samples it for a few seconds and prints the
+//! profile in folded format.
+//!
+//!     cargo run --example sample -- <pid>
+
+use std::time::Duration;
+
+use anyhow::Context;
+use v8spy::output::{self, Format};
+use v8spy::{FrameFormat, Profile, V8Spy};
+
+fn main() -> anyhow::Result<()> {
+    let pid = std::env::args()
+        .nth(1)
+        .context("usage: sample <pid>")?
+        .parse()
+        .context("pid must be a number")?;
+
+    let spy = V8Spy::new(pid)?;
+    let mut profile = Profile::new();
+    for _ in 0..300 {
+        spy.sample(&mut profile)?;
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    output::write_profile(&profile, Format::Folded, &FrameFormat::default(), &mut std::io::stdout().lock())
+}
@@ -0,0 +1,66 @@
+//! Names of V8's builtins by id. V8 numbers its builtins in the order of
+//! `builtins-definitions.h`, which changes from one version to the next, so
+//! there's a table for each version it's known for. The tables are the
+//! `builtin_metadata` names of a build of that version, one per line in id
+//! order, and are left out without the `builtin-names` feature.
+
+use crate::v8_spy::Version;
+
+#[cfg(feature = "builtin-names")]
+mod tables {
+    use std::sync::OnceLock;
+
+    /// V8 11.3, as in Node 20
+    static V8_11_3: &str = include_str!("builtins/v8_11_3.txt");
+
+    /// The table for a major and minor version, split into lines on first use
+    pub(super) fn table(major: u32, minor: u32) -> Option<&'static [&'static str]> {
+        static TABLE_11_3: OnceLock<Vec<&'static str>> = OnceLock::new();
+        match (major, minor) {
+            (11, 3) => Some(TABLE_11_3.get_or_init(|| V8_11_3.lines().collect())),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(feature = "builtin-names"))]
+mod tables {
+    pub(super) fn table(_major: u32, _minor: u32) -> Option<&'static [&'static str]> {
+        None
+    }
+}
+
+/// Returns the name of builtin `id` in the given V8 version, or `None` if
+/// there's no table for the version or the id is out of its range. The
+/// table is picked by major and minor version only, as builds of the same
+/// minor version rarely add builtins.
+pub fn builtin_name(version: &Version, id: u32) -> Option<&'static str> {
+    tables::table(version.major, version.minor)?.get(id as usize).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u32, minor: u32, build: u32) -> Version {
+        Version { major, minor, build, patch: 0, label: None }
+    }
+
+    #[cfg(feature = "builtin-names")]
+    #[test]
+    fn names_builtins_by_id() {
+        let node_20 = version(11, 3, 244);
+        assert_eq!(builtin_name(&node_20, 0), Some("DeoptimizationEntry_Eager"));
+        assert_eq!(builtin_name(&node_20, 70), Some("InterpreterEntryTrampoline"));
+        assert_eq!(builtin_name(&node_20, 165), Some("Abort"));
+        // Other builds of the minor version share its table
+        assert_eq!(builtin_name(&version(11, 3, 0), 165), Some("Abort"));
+        assert_eq!(builtin_name(&node_20, 2182), None);
+    }
+
+    #[test]
+    fn names_nothing_for_unknown_versions() {
+        assert_eq!(builtin_name(&version(10, 2, 154), 0), None);
+        assert_eq!(builtin_name(&version(11, 4, 183), 0), None);
+    }
+}
@@ -0,0 +1,366 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::Parser;
+use regex::Regex;
+use remoteprocess::Pid;
+
+use crate::term::ColorChoice;
+use v8spy::output::Format;
+use v8spy::{FilterOptions, FrameFormat, GroupBy, NameFilter, V8SpyOptions, DEFAULT_FRAME_FORMAT};
+
+/// Command line options
+#[derive(Debug, Clone, Parser)]
+#[command(name = "v8spy", version, about = "Sampling profiler for Node.js and other V8 processes")]
+pub struct Config {
+    /// PID of the process to profile. Give it more than once to profile
+    /// several processes at the same time, all in one profile under a root
+    /// frame for each, or each in a profile of its own with --separate
+    #[arg(long, conflicts_with_all = ["name", "pid_file"], required_unless_present_any = ["name", "pid_file", "replay"])]
+    pub pid: Vec<Pid>,
+
+    /// With more than one --pid, write the profile of each process on its
+    /// own, to OUTPUT.PID or after a "# pid" line on stdout
+    #[arg(long, requires = "pid")]
+    pub separate: bool,
+
+    /// Profile the Node process whose executable or arguments contain NAME
+    #[arg(long, conflicts_with = "pid_file")]
+    pub name: Option<String>,
+
+    /// Wait for this file to hold the PID of the process to profile, and for
+    /// that process to start V8, then attach to it
+    #[arg(long)]
+    pub pid_file: Option<PathBuf>,
+
+    /// Seconds to wait for --pid-file, or for the process to come back with
+    /// --follow-restarts, before giving up. Waits forever if not given
+    #[arg(long, value_parser = parse_duration)]
+    pub wait_timeout: Option<Duration>,
+
+    /// When the process exits, write out its profile and wait for it to be
+    /// restarted, found again by --name or --pid-file, then keep sampling.
+    /// Each run is written to its own file, named OUTPUT.1, OUTPUT.2 and so
+    /// on after the first, or after a "# segment" line on stdout
+    #[arg(long, conflicts_with_all = ["pid", "top", "incremental"])]
+    pub follow_restarts: bool,
+
+    /// Attach even if the build or patch number of the V8 version can't be
+    /// read, taking it to be 0. Pass false to refuse instead
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub allow_partial_version: bool,
+
+    /// Seconds a single read of the process's memory may take before the
+    /// sample it's for is dropped, for bounding the time a sample takes on a
+    /// loaded machine. A read in progress can't be cut short, so one slow
+    /// read still takes as long as it takes
+    #[arg(long, value_parser = parse_duration)]
+    pub read_timeout: Option<Duration>,
+
+    /// Samples per second
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..=10000))]
+    pub rate: u32,
+
+    /// Seconds to sample for. Samples until the process exits if not given
+    #[arg(long, value_parser = parse_duration, allow_negative_numbers = true)]
+    pub duration: Option<Duration>,
+
+    /// File to write the profile to, instead of stdout. Either way, nothing
+    /// but the profile is written there: warnings and errors go to stderr
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Periodically rewrite the output file with the samples so far, so a
+    /// crash doesn't lose the whole run. With --format folded, the counts
+    /// since the last flush are appended instead, to --output or stdout
+    #[arg(long)]
+    pub incremental: bool,
+
+    /// Seconds between flushes of the output with --incremental
+    #[arg(long, default_value = "10", value_parser = parse_duration)]
+    pub flush_interval: Duration,
+
+    /// Profile output format: folded, pprof, otlp, svg for a flame graph, or
+    /// jsonl to stream each sample as a line of JSON
+    #[arg(long, default_value = "folded")]
+    pub format: Format,
+
+    /// How frames are written in the folded, svg and jsonl formats, with
+    /// {name}, {file}, {line}, {kind}, {receiver}, {source_url} and
+    /// {source_map} for the frame's. A part in brackets is left out if a
+    /// placeholder in it has nothing to show.
+    #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_FRAME_FORMAT)]
+    pub frame_format: FrameFormat,
+
+    /// Send the profile to this OTLP/HTTP receiver, e.g.
+    /// http://localhost:4318, instead of writing it out. Needs --format otlp
+    #[arg(long, conflicts_with = "output")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Show a live view of the hottest functions instead of writing a profile
+    #[arg(long)]
+    pub top: bool,
+
+    /// Print the scripts of the functions on the process's stacks instead of
+    /// writing a profile. With --duration, keeps looking at --rate until then
+    #[arg(long, conflicts_with_all = ["top", "follow_restarts"])]
+    pub list_scripts: bool,
+
+    /// Print the stack of every JavaScript thread once, instead of writing
+    /// a profile, and save everything read of the process to do so to FILE,
+    /// for reproducing unwinding bugs with --replay
+    #[arg(long, value_name = "FILE", requires = "pid", conflicts_with_all = ["top", "list_scripts"])]
+    pub record: Option<PathBuf>,
+
+    /// Print the stacks of a recording made with --record, read from the
+    /// recording alone, without the process
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["pid", "name", "pid_file", "top", "list_scripts", "record"])]
+    pub replay: Option<PathBuf>,
+
+    /// Check that the process can be profiled, by walking and resolving a
+    /// stack of it once, and print PASS with the number of frames or FAIL
+    /// with the step that failed. Exits with 1 on failure, for scripts
+    #[arg(long, requires = "pid", conflicts_with_all = ["top", "list_scripts", "record", "replay"])]
+    pub selftest: bool,
+
+    /// Print the stack slots around frame pointer FP, given in hex as 0x...
+    /// or in decimal, each with a guess at what it holds, for working out
+    /// the frame layout of a new V8 version
+    #[arg(long, value_name = "FP", value_parser = parse_address, conflicts_with_all = ["top", "list_scripts", "record", "replay", "selftest"])]
+    pub dump_frame: Option<usize>,
+
+    /// Address of the V8 isolate to walk stacks on from, given in hex as
+    /// 0x... or in decimal, as found with a debugger. Recovers the stacks of
+    /// threads stopped in native code built without frame pointers, from the
+    /// isolate's last call out of JavaScript, and warns if the debugger has
+    /// the isolate paused
+    #[arg(long, value_name = "ADDR", value_parser = parse_address)]
+    pub isolate_addr: Option<usize>,
+
+    /// Leave native frames out of stacks, showing only JavaScript and V8 frames
+    #[arg(long, conflicts_with = "native")]
+    pub exclude_native: bool,
+
+    /// Name native frames after their C++ function. Slows sampling down
+    #[arg(long)]
+    pub native: bool,
+
+    /// Show runs of builtin frames as a single frame
+    #[arg(long)]
+    pub collapse_builtins: bool,
+
+    /// Name unnamed builtins after their id, for the V8 versions v8spy has a
+    /// table of builtins for, instead of showing them as builtin#<id>
+    #[arg(long)]
+    pub demangle_builtins: bool,
+
+    /// Name JavaScript frames after the class of their `this` as well, as
+    /// Class.method
+    #[arg(long)]
+    pub receiver_classes: bool,
+
+    /// Reuse the names and lines of frames that recur from one sample to the
+    /// next, which speeds up sampling of steady workloads
+    #[arg(long)]
+    pub cache_frames: bool,
+
+    /// Keep only the frames whose function name or file matches REGEX. The
+    /// samples of a dropped frame go to the innermost frame kept below it,
+    /// and samples with no frames kept are dropped
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new, conflicts_with = "list_scripts")]
+    pub include: Option<Regex>,
+
+    /// Drop the frames whose function name or file matches REGEX, as for
+    /// --include. Applies after --include
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new, conflicts_with = "list_scripts")]
+    pub exclude: Option<Regex>,
+
+    /// Include samples of JavaScript threads that are idle, as an <idle> stack
+    #[arg(long)]
+    pub include_idle: bool,
+
+    /// Only sample the threads with this name, as in /proc/<pid>/task/*/comm
+    #[arg(long, value_name = "NAME")]
+    pub thread: Option<String>,
+
+    /// Only sample the worker with this threadId, as in worker_threads, or
+    /// the main thread for 0
+    #[arg(long, value_name = "ID", conflicts_with = "thread")]
+    pub worker: Option<u64>,
+
+    /// Root each stack at the phase of the event loop it was sampled in, as
+    /// <loop:timers>, <loop:poll>, <loop:check> or the like
+    #[arg(long)]
+    pub event_loop_phase: bool,
+
+    /// What each frame of a stack stands for: a function, or the file it's
+    /// in, with consecutive frames of the same file merged into one, for
+    /// seeing which modules the time goes to
+    #[arg(long, value_name = "UNIT", default_value = "function")]
+    pub group_by: GroupBy,
+
+    /// When to color --top and error messages: auto, always or never. Auto
+    /// colors terminals, unless NO_COLOR is set
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    pub color: ColorChoice,
+
+    /// Never color, the same as --color never
+    #[arg(long, conflicts_with = "color")]
+    pub no_color: bool,
+
+    /// Hold at most N samples of each run of the process, then do as
+    /// --on-full says. Samples are kept until the process exits if not given
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["top", "list_scripts"])]
+    pub max_samples: Option<u64>,
+
+    /// What to do once --max-samples are held: stop sampling, or ring to keep
+    /// sampling and drop the oldest samples to make room
+    #[arg(long, value_name = "ACTION", default_value = "stop", requires = "max_samples")]
+    pub on_full: OnFull,
+
+    /// Print counts of samples taken and failed to stderr when done
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Print a summary of the profile as JSON to stderr when done, with the
+    /// top N functions by own samples and the counts of --stats, whatever
+    /// the --format
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "10", conflicts_with_all = ["top", "list_scripts"])]
+    pub summary: Option<usize>,
+
+    /// Describe every frame that can't be resolved, with what was read of it
+    /// and the step that failed, on stderr or appended to FILE
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    pub debug_frames: Option<Option<PathBuf>>,
+}
+
+/// What to do once --max-samples are held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnFull {
+    Stop,
+    Ring,
+}
+
+impl FromStr for OnFull {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "stop" => Ok(OnFull::Stop),
+            "ring" => Ok(OnFull::Ring),
+            _ => anyhow::bail!("Unknown action '{}': expected stop or ring", s),
+        }
+    }
+}
+
+impl Config {
+    pub fn color_choice(&self) -> ColorChoice {
+        if self.no_color {
+            ColorChoice::Never
+        } else {
+            self.color
+        }
+    }
+
+    pub fn filter_options(&self) -> FilterOptions {
+        FilterOptions {
+            exclude_native: self.exclude_native,
+            collapse_builtins: self.collapse_builtins,
+            include_idle: self.include_idle,
+            event_loop_phase: self.event_loop_phase,
+            group_by: self.group_by,
+        }
+    }
+
+    pub fn name_filter(&self) -> NameFilter {
+        NameFilter { include: self.include.clone(), exclude: self.exclude.clone() }
+    }
+
+    pub fn spy_options(&self) -> V8SpyOptions {
+        V8SpyOptions {
+            allow_partial_version: self.allow_partial_version,
+            read_timeout: self.read_timeout,
+            ..V8SpyOptions::default()
+        }
+    }
+}
+
+fn parse_address(value: &str) -> Result<usize, String> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' is not an address", value))
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value.parse().map_err(|_| format!("'{}' is not a number of seconds", value))?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(format!("duration must be a non-negative number of seconds, got {}", value));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, clap::Error> {
+        Config::try_parse_from(std::iter::once("v8spy").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn parses_pid_rate_duration_and_output() {
+        let config = parse(&["--pid", "42", "--rate", "250", "--duration", "1.5", "--output", "out.folded"]).unwrap();
+        assert_eq!(config.pid, vec![42]);
+        assert_eq!(config.rate, 250);
+        assert_eq!(config.duration, Some(Duration::from_millis(1500)));
+        assert_eq!(config.output, Some(PathBuf::from("out.folded")));
+
+        let config = parse(&["--pid", "42"]).unwrap();
+        assert_eq!(config.rate, 100);
+        assert_eq!(config.duration, None);
+        assert_eq!(config.output, None);
+    }
+
+    #[test]
+    fn rate_out_of_range() {
+        for rate in ["0", "10001"] {
+            let error = parse(&["--pid", "42", "--rate", rate]).unwrap_err();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation, "{}", rate);
+        }
+        assert!(parse(&["--pid", "42", "--rate", "-1"]).is_err());
+        assert_eq!(parse(&["--pid", "42", "--rate", "10000"]).unwrap().rate, 10000);
+        assert_eq!(parse(&["--pid", "42", "--rate", "1"]).unwrap().rate, 1);
+    }
+
+    #[test]
+    fn negative_duration() {
+        let error = parse(&["--pid", "42", "--duration", "-1"]).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+        assert!(error.to_string().contains("duration must be a non-negative number of seconds, got -1"), "{}", error);
+        for duration in ["soon", "NaN", "inf"] {
+            assert!(parse(&["--pid", "42", "--duration", duration]).is_err(), "{}", duration);
+        }
+        assert_eq!(parse(&["--pid", "42", "--duration", "0"]).unwrap().duration, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn isolate_address() {
+        assert_eq!(parse(&["--pid", "42", "--isolate-addr", "0x3f00a0"]).unwrap().isolate_addr, Some(0x3f00a0));
+        assert_eq!(parse(&["--pid", "42", "--isolate-addr", "4128928"]).unwrap().isolate_addr, Some(0x3f00a0));
+        assert_eq!(parse(&["--pid", "42"]).unwrap().isolate_addr, None);
+        assert!(parse(&["--pid", "42", "--isolate-addr", "0xnope"]).is_err());
+    }
+
+    #[test]
+    fn missing_pid() {
+        let error = parse(&["--rate", "100"]).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+        assert!(parse(&["--pid", "not-a-pid"]).is_err());
+        // The other ways of naming the process stand in for --pid
+        assert!(parse(&["--name", "server.js"]).is_ok());
+        assert!(parse(&["--replay", "stacks.rec"]).is_ok());
+    }
+}
@@ -0,0 +1,37 @@
+//! Sampling profiler for Node.js and other processes embedding V8. Stacks
+//! are read from the outside, using ptrace and V8's postmortem debugging
+//! symbols, without any cooperation from the target.
+//!
+//! ```no_run
+//! use v8spy::{FrameFormat, Profile, V8Spy};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let spy = V8Spy::new(1234)?;
+//! let mut profile = Profile::new();
+//! spy.sample(&mut profile)?;
+//! v8spy::output::write_profile(&profile, v8spy::output::Format::Folded, &FrameFormat::default(), &mut std::io::stdout())?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod builtins;
+pub mod memory;
+pub mod native;
+pub mod output;
+pub mod profile;
+pub mod sampler;
+pub mod stack_trace;
+pub mod v8_spy;
+pub mod versions;
+
+pub use crate::memory::{FakeTarget, LiveTarget, MemoryMap, MemorySource, RecordedThread, Recorder, Recording};
+pub use crate::native::NativeSymbols;
+pub use crate::profile::{FunctionStat, Profile, SampleRing};
+pub use crate::sampler::{sample_all, SamplerHandle};
+pub use crate::stack_trace::{
+    filter_frames, group_by_file, FilterOptions, Frame, FrameAddress, FrameDebugRecord, FrameFormat, FrameKind, GroupBy, NameFilter, Registers, ResolvedFrame, Tier, DEFAULT_FRAME_FORMAT,
+};
+pub use crate::v8_spy::{
+    CodeFlags, Deadlines, Error, EventLoopPhase, FunctionDataKind, IsolateRef, OnMissingSymbol, SampleStats, ScriptInfo, SelfTest, SelfTestStep, Snapshot, ThreadInfo, V8Spy, V8SpyOptions, Version, MAX_TESTED_VERSION, MIN_SUPPORTED_VERSION,
+};
+
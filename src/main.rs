@@ -1,13 +1,690 @@
 extern crate anyhow;
 extern crate log;
 
-mod v8_spy;
-use crate::v8_spy::V8Spy;
+mod config;
+mod term;
+mod ui;
+use crate::config::{Config, OnFull};
+use anyhow::Context;
+use clap::Parser;
 use remoteprocess::Pid;
-use std::env;
+use v8spy::{output, sample_all, Deadlines, FrameKind, NameFilter, Profile, Recording, ResolvedFrame, SampleRing, SampleStats, SamplerHandle, V8Spy};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+const TOP_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const ATTACH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Stack slots shown on either side of the frame pointer by --dump-frame
+const DUMP_FRAME_WORDS: usize = 8;
+
+/// Writes the warnings the library logs to stderr, as they are
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let pid = Pid::from(args[1].parse::<i32>().unwrap());
-    let _spy = V8Spy::new(pid).unwrap();
+    let config = Config::parse();
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+
+    if let Err(e) = run(&config) {
+        let style = term::Style::stderr(config.color_choice());
+        eprintln!("{} {:?}", style.red(&style.bold("Error:")), e);
+        std::process::exit(1);
+    }
+}
+
+fn run(config: &Config) -> anyhow::Result<()> {
+    if config.otlp_endpoint.is_some() && config.format != output::Format::Otlp {
+        anyhow::bail!("--otlp-endpoint needs --format otlp");
+    }
+    if config.incremental && config.output.is_none() && config.format != output::Format::Folded {
+        anyhow::bail!("--incremental needs --output, except with --format folded");
+    }
+    // Formats that can't be written one after the other on stdout
+    let whole_file_format = matches!(config.format, output::Format::Pprof | output::Format::Otlp | output::Format::Svg);
+    if config.follow_restarts && whole_file_format && config.output.is_none() && config.otlp_endpoint.is_none() {
+        anyhow::bail!("--follow-restarts with --format pprof, otlp or svg needs --output or --otlp-endpoint");
+    }
+    // Samples already written out can't be dropped again
+    let streamed = config.format == output::Format::Jsonl || (config.incremental && config.format == output::Format::Folded);
+    if config.max_samples.is_some() && config.on_full == OnFull::Ring && streamed {
+        anyhow::bail!("--on-full ring can't be used with --format jsonl or --incremental --format folded");
+    }
+
+    if let Some(path) = &config.replay {
+        let recording = Recording::load(path)?;
+        let spy = V8Spy::from_memory_with_options(recording.pid, recording, config.spy_options())?;
+        print!("{}", spy.replay());
+        return Ok(());
+    }
+    if config.pid.len() > 1 {
+        return sample_pids(config);
+    }
+    if let (true, Some(&pid)) = (config.selftest, config.pid.first()) {
+        let result = V8Spy::selftest(pid, config.spy_options());
+        println!("{}", result);
+        if !result.passed() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let (Some(path), Some(&pid)) = (&config.record, config.pid.first()) {
+        let (snapshot, recording) = V8Spy::record(pid, config.spy_options())?;
+        recording.save(path)?;
+        print!("{}", snapshot);
+        eprintln!("Saved the recording to {}", path.display());
+        return Ok(());
+    }
+
+    // Stop sampling on Ctrl-C, but still write out what was collected
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst))?;
+
+    let mut spy = match config.pid.first() {
+        Some(&pid) => V8Spy::with_options(pid, config.spy_options())?,
+        None => match wait_to_attach(config, &running, None)? {
+            Some(spy) => spy,
+            None => return Ok(()),
+        },
+    };
+    configure(&mut spy, config)?;
+
+    if let Some(fp) = config.dump_frame {
+        return dump_frame(&spy, fp);
+    }
+    if config.list_scripts {
+        return list_scripts(&spy, config, &running);
+    }
+    if config.top {
+        run_top(&spy, config.rate, &config.name_filter(), term::Style::stdout(config.color_choice()), &running)?;
+        if config.stats {
+            eprintln!("{}", spy.stats());
+        }
+        return Ok(());
+    }
+
+    // Samples are timed with Instants, which can only be turned into wall
+    // clock time relative to a known point
+    let (start, start_time) = (Instant::now(), SystemTime::now());
+    let mut jsonl_out: Option<Box<dyn Write>> = match (config.format, &config.output) {
+        (output::Format::Jsonl, Some(path)) => {
+            Some(Box::new(File::create(path).with_context(|| format!("Failed to create {}", path.display()))?))
+        }
+        (output::Format::Jsonl, None) => Some(Box::new(std::io::stdout().lock())),
+        _ => None,
+    };
+    // Whatever the format, the summary needs the whole run as a profile,
+    // and the stats of every spy sampling it
+    let mut summary = config.summary.map(|_| Profile::new());
+    let mut run_stats = SampleStats::default();
+    let filter = config.name_filter();
+    for segment in 0.. {
+        let duration = config.duration.map(|duration| duration.saturating_sub(start.elapsed()));
+        let stats = match &mut jsonl_out {
+            Some(out) => {
+                if segment > 0 {
+                    output::jsonl::write_segment_marker(out, SystemTime::now(), segment, spy.pid)?;
+                }
+                let mut taken = 0;
+                spy.sample_stream(config.rate, duration, &running, |taken_at, tid, stack| {
+                    let Some(stack) = filter_stack(&filter, stack) else {
+                        return Ok(());
+                    };
+                    if !has_room(config, taken, &running) {
+                        return Ok(());
+                    }
+                    taken += 1;
+                    if let Some(summary) = &mut summary {
+                        summary.add_sample(stack.to_vec());
+                    }
+                    output::jsonl::write_sample(out, start_time + taken_at.duration_since(start), tid, &stack, &config.frame_format)
+                })?
+            }
+            None if config.incremental && config.format == output::Format::Folded => {
+                stream_folded(&spy, config, duration, &running, segment, &mut summary)?
+            }
+            None => sample_segment(&spy, config, duration, &running, segment, &mut summary)?,
+        };
+        if config.stats {
+            eprintln!("{}", stats);
+        }
+        run_stats.merge(&stats);
+
+        let time_left = config.duration.is_none_or(|duration| start.elapsed() < duration);
+        if !config.follow_restarts || !running.load(Ordering::SeqCst) || !time_left || !spy.has_exited() {
+            break;
+        }
+        eprintln!("Process {} exited, waiting for it to restart", spy.pid);
+        let end = config.duration.map(|duration| start + duration);
+        spy = match wait_to_attach(config, &running, end)? {
+            Some(spy) => spy,
+            None => break,
+        };
+        configure(&mut spy, config)?;
+    }
+    write_summary(config, &summary, &run_stats, &mut std::io::stderr())?;
+    Ok(())
+}
+
+fn configure(spy: &mut V8Spy, config: &Config) -> anyhow::Result<()> {
+    spy.filter = config.filter_options();
+    spy.demangle_builtins = config.demangle_builtins;
+    spy.receiver_classes = config.receiver_classes;
+    if let Some(name) = &config.thread {
+        spy.set_thread_filter(name)?;
+    }
+    if let Some(thread_id) = config.worker {
+        spy.set_worker_filter(thread_id)?;
+    }
+    if let Some(addr) = config.isolate_addr {
+        spy.set_isolate(addr);
+        match spy.is_paused_in_debugger() {
+            Ok(true) => log::warn!("Process {} is paused in the debugger, so its samples will only show where it stopped", spy.pid),
+            Ok(false) => {}
+            Err(e) => log::debug!("Can't tell whether process {} is paused in the debugger: {:#}", spy.pid, e),
+        }
+    }
+    if config.native {
+        spy.enable_native_symbols()?;
+    }
+    if config.cache_frames {
+        spy.enable_frame_cache();
+    }
+    if let Some(path) = &config.debug_frames {
+        let mut out: Box<dyn Write> = match path {
+            Some(path) => Box::new(
+                File::options()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open {}", path.display()))?,
+            ),
+            None => Box::new(std::io::stderr()),
+        };
+        spy.on_unresolved_frame(move |record| {
+            let _ = writeln!(out, "{}", record);
+        });
+    }
+    Ok(())
+}
+
+/// Samples into a profile until the process exits or sampling is stopped,
+/// then writes it out. With --follow-restarts, the profile of each run of the
+/// process is a segment of the output: segments after the first are written
+/// to their own numbered file, or follow a marker line on stdout.
+fn sample_segment(
+    spy: &V8Spy,
+    config: &Config,
+    duration: Option<Duration>,
+    running: &AtomicBool,
+    segment: u32,
+    summary: &mut Option<Profile>,
+) -> anyhow::Result<SampleStats> {
+    let path = segment_path(config, segment);
+    let filter = config.name_filter();
+    let mut profile = Profile::new();
+    let mut last_flush = Instant::now();
+    let mut flush = |profile: &Profile| -> anyhow::Result<()> {
+        if let Some(path) = &path {
+            if config.incremental && last_flush.elapsed() >= config.flush_interval {
+                output::write_profile_atomic(profile, config.format, &config.frame_format, path)?;
+                last_flush = Instant::now();
+            }
+        }
+        Ok(())
+    };
+    let stats = match config.max_samples {
+        None => {
+            // Filtered as a whole, as the profile is sampled into directly
+            let stats = spy.sample_loop(config.rate, duration, running, &mut profile, &mut |profile: &Profile| {
+                if filter.is_empty() {
+                    flush(profile)
+                } else {
+                    flush(&profile.filtered(&filter))
+                }
+            })?;
+            if !filter.is_empty() {
+                profile = profile.filtered(&filter);
+            }
+            stats
+        }
+        Some(max_samples) => {
+            // Stacks are added one at a time, so that no more than the
+            // maximum are ever held
+            let mut ring = SampleRing::new(usize::try_from(max_samples).unwrap_or(usize::MAX));
+            let stats = spy.sample_stream(config.rate, duration, running, |_, _, stack| {
+                let Some(stack) = filter_stack(&filter, stack) else {
+                    return Ok(());
+                };
+                if !has_room(config, ring.len() as u64, running) {
+                    return Ok(());
+                }
+                ring.add_sample(stack.into_owned());
+                flush(ring.profile())
+            })?;
+            if ring.evicted() > 0 {
+                eprintln!("Dropped the oldest {} samples to hold --max-samples {}", ring.evicted(), max_samples);
+            }
+            profile = ring.into_profile();
+            stats
+        }
+    };
+    let marker = (segment > 0).then(|| format!("# segment {}: pid {}", segment, spy.pid));
+    write_out(config, &profile, path.as_deref(), marker, &mut std::io::stdout().lock())?;
+    if let Some(summary) = summary {
+        summary.merge(profile);
+    }
+    Ok(stats)
+}
+
+/// Writes a whole profile out: to `path`, to --otlp-endpoint, or else to
+/// `stdout`, after `marker` if given. Nothing but the profile goes to
+/// `stdout`, so that it can be piped elsewhere: diagnostics go to stderr.
+fn write_out(config: &Config, profile: &Profile, path: Option<&Path>, marker: Option<String>, stdout: &mut impl Write) -> anyhow::Result<()> {
+    match (path, &config.otlp_endpoint) {
+        (Some(path), _) => output::write_profile_atomic(profile, config.format, &config.frame_format, path)?,
+        (None, Some(endpoint)) => output::otlp::write_otlp(profile, Some(endpoint), &mut std::io::sink())?,
+        (None, None) => {
+            if let Some(marker) = marker {
+                writeln!(stdout, "{}", marker)?;
+            }
+            output::write_profile(profile, config.format, &config.frame_format, stdout)?
+        }
+    }
+    Ok(())
+}
+
+/// Writes the --summary of the run, if asked for, to `stderr`
+fn write_summary(config: &Config, summary: &Option<Profile>, stats: &SampleStats, stderr: &mut impl Write) -> std::io::Result<()> {
+    match (summary, config.summary) {
+        (Some(summary), Some(top_n)) => writeln!(stderr, "{}", summary.summary_json(top_n, stats)),
+        _ => Ok(()),
+    }
+}
+
+/// Samples every process given with --pid at once, each on a thread of its
+/// own, until --duration or Ctrl-C, or until they've all exited. A process
+/// that exits or fails to be sampled stops contributing, and the rest carry
+/// on. The profiles are written out as --separate says.
+fn sample_pids(config: &Config) -> anyhow::Result<()> {
+    let unsupported = [
+        (config.top, "--top"),
+        (config.list_scripts, "--list-scripts"),
+        (config.record.is_some(), "--record"),
+        (config.selftest, "--selftest"),
+        (config.dump_frame.is_some(), "--dump-frame"),
+        (config.incremental, "--incremental"),
+        (config.max_samples.is_some(), "--max-samples"),
+        (config.format == output::Format::Jsonl, "--format jsonl"),
+    ];
+    if let Some((_, option)) = unsupported.iter().find(|(given, _)| *given) {
+        anyhow::bail!("{} can't be used with more than one --pid", option);
+    }
+    let whole_file_format = matches!(config.format, output::Format::Pprof | output::Format::Otlp | output::Format::Svg);
+    if config.separate && whole_file_format && config.output.is_none() && config.otlp_endpoint.is_none() {
+        anyhow::bail!("--separate with --format pprof, otlp or svg needs --output or --otlp-endpoint");
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst))?;
+
+    let mut samplers = Vec::new();
+    for &pid in &config.pid {
+        let setup_config = config.clone();
+        let sampler = SamplerHandle::spawn_with_options(pid, config.spy_options(), config.rate, move |spy| configure(spy, &setup_config))
+            .with_context(|| format!("Failed to attach to process {}", pid))?;
+        samplers.push((pid, sampler));
+    }
+    let mut run_stats = SampleStats::default();
+    let profiles = sample_all(samplers, &running, config.duration, |pid, exited, result| {
+        let stats = match result {
+            Ok(stats) => stats,
+            Err(e) => return eprintln!("Stopped sampling process {}: {:#}", pid, e),
+        };
+        match (exited, config.stats) {
+            (true, true) => eprintln!("Process {} exited: {}", pid, stats),
+            (true, false) => eprintln!("Process {} exited", pid),
+            (false, true) => eprintln!("Process {}: {}", pid, stats),
+            (false, false) => {}
+        }
+        run_stats.merge(&stats);
+    });
+
+    let filter = config.name_filter();
+    let mut merged = Profile::new();
+    let mut summary = config.summary.map(|_| Profile::new());
+    for (pid, profile) in profiles {
+        let mut profile = if filter.is_empty() { profile } else { profile.filtered(&filter) };
+        if let Some(summary) = &mut summary {
+            summary.merge(Profile { stacks: profile.stacks.clone() });
+        }
+        if config.separate {
+            let path = config.output.as_ref().map(|path| PathBuf::from(format!("{}.{}", path.display(), pid)));
+            write_out(config, &profile, path.as_deref(), Some(format!("# pid {}", pid)), &mut std::io::stdout().lock())?;
+            continue;
+        }
+        let root = ResolvedFrame::synthetic(&format!("<pid {}>", pid), FrameKind::Native);
+        for (mut stack, count) in std::mem::take(&mut profile.stacks) {
+            stack.push(root.clone());
+            *merged.stacks.entry(stack).or_insert(0) += count;
+        }
+    }
+    if !config.separate {
+        write_out(config, &merged, config.output.as_deref(), None, &mut std::io::stdout().lock())?;
+    }
+    write_summary(config, &summary, &run_stats, &mut std::io::stderr())?;
+    Ok(())
+}
+
+/// Samples until the process exits or sampling is stopped, appending the
+/// folded counts sampled since the last flush to the output every
+/// --flush-interval. Segments are written as by `sample_segment`.
+fn stream_folded(
+    spy: &V8Spy,
+    config: &Config,
+    duration: Option<Duration>,
+    running: &AtomicBool,
+    segment: u32,
+    summary: &mut Option<Profile>,
+) -> anyhow::Result<SampleStats> {
+    let mut out: Box<dyn Write> = match segment_path(config, segment) {
+        Some(path) => Box::new(File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    if segment > 0 && config.output.is_none() {
+        writeln!(out, "# segment {}: pid {}", segment, spy.pid)?;
+    }
+    let mut writer = output::folded::IncrementalFoldedWriter::new(config.frame_format.clone());
+    let filter = config.name_filter();
+    let mut last_flush = Instant::now();
+    let mut taken = 0;
+    let stats = spy.sample_stream(config.rate, duration, running, |_, _, stack| {
+        let Some(stack) = filter_stack(&filter, stack) else {
+            return Ok(());
+        };
+        if !has_room(config, taken, running) {
+            return Ok(());
+        }
+        taken += 1;
+        writer.add_sample(&stack);
+        if let Some(summary) = summary.as_mut() {
+            summary.add_sample(stack.to_vec());
+        }
+        if last_flush.elapsed() >= config.flush_interval {
+            writer.flush(&mut out)?;
+            last_flush = Instant::now();
+        }
+        Ok(())
+    })?;
+    writer.flush(&mut out)?;
+    Ok(stats)
+}
+
+/// Applies --include and --exclude to a stack, or returns `None` if they
+/// leave no frames of it
+fn filter_stack<'a>(filter: &NameFilter, stack: &'a [ResolvedFrame]) -> Option<Cow<'a, [ResolvedFrame]>> {
+    let stack = if filter.is_empty() { Cow::Borrowed(stack) } else { Cow::Owned(filter.apply(stack)) };
+    (!stack.is_empty()).then_some(stack)
+}
+
+/// Whether another sample can be held after `held` of them. With
+/// --on-full stop, sampling is stopped once --max-samples are held.
+fn has_room(config: &Config, held: u64, running: &AtomicBool) -> bool {
+    match config.max_samples {
+        Some(max_samples) if config.on_full == OnFull::Stop && held >= max_samples => {
+            if running.swap(false, Ordering::SeqCst) {
+                eprintln!("Stopping at --max-samples {}", max_samples);
+            }
+            false
+        }
+        _ => true,
+    }
+}
+
+/// The file a segment's profile goes to: --output for the first, and
+/// --output with the segment number appended for the rest
+fn segment_path(config: &Config, segment: u32) -> Option<PathBuf> {
+    config.output.as_ref().map(|path| match segment {
+        0 => path.clone(),
+        _ => PathBuf::from(format!("{}.{}", path.display(), segment)),
+    })
+}
+
+/// Waits for the process named by --pid-file or --name to be running V8,
+/// then attaches to it. Until then, a missing or partially written pid file,
+/// no matching process, and a process that can't be attached to yet are
+/// retried, and the last error is returned once --wait-timeout has passed.
+/// Returns `None` if interrupted with Ctrl-C, or once it's past `end`.
+fn wait_to_attach(config: &Config, running: &AtomicBool, end: Option<Instant>) -> anyhow::Result<Option<V8Spy>> {
+    let retry = config.pid_file.is_some() || config.follow_restarts;
+    poll_to_attach(retry, config.wait_timeout, ATTACH_POLL_INTERVAL, running, end, || match (&config.pid_file, &config.name) {
+        (Some(path), _) => read_pid_file(path, |path| std::fs::read_to_string(path)).and_then(|pid| V8Spy::with_options(pid, config.spy_options())),
+        (None, Some(name)) => attach_by_name(name, config),
+        (None, None) => unreachable!("clap requires --pid, --name or --pid-file"),
+    })
+}
+
+/// The polling of `wait_to_attach`, calling `attach` every `interval` until
+/// it succeeds, fails with `retry` off, or `timeout` has passed
+fn poll_to_attach<T>(
+    retry: bool,
+    timeout: Option<Duration>,
+    interval: Duration,
+    running: &AtomicBool,
+    end: Option<Instant>,
+    mut attach: impl FnMut() -> anyhow::Result<T>,
+) -> anyhow::Result<Option<T>> {
+    let start = Instant::now();
+    while running.load(Ordering::SeqCst) && end.is_none_or(|end| Instant::now() < end) {
+        match attach() {
+            Ok(attached) => return Ok(Some(attached)),
+            Err(e) if !retry => return Err(e),
+            Err(e) if timeout.is_some_and(|timeout| start.elapsed() >= timeout) => {
+                return Err(e.context("Gave up waiting for a V8 process to attach to"));
+            }
+            Err(_) => std::thread::sleep(interval),
+        }
+    }
+    Ok(None)
+}
+
+fn attach_by_name(name: &str, config: &Config) -> anyhow::Result<V8Spy> {
+    let mut spies = V8Spy::from_process_name_with_options(name, config.spy_options())?;
+    if spies.len() > 1 {
+        let pids: Vec<String> = spies.iter().map(|spy| spy.pid.to_string()).collect();
+        anyhow::bail!("Multiple Node processes match '{}': {}. Pass one of them with --pid", name, pids.join(", "));
+    }
+    Ok(spies.remove(0))
+}
+
+/// Reads the PID in the file at `path` with `read_to_string`, which is
+/// `std::fs::read_to_string` outside of tests
+fn read_pid_file(path: &Path, read_to_string: impl Fn(&Path) -> std::io::Result<String>) -> anyhow::Result<Pid> {
+    let contents = read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    contents
+        .trim()
+        .parse()
+        .with_context(|| format!("{} doesn't hold a PID: '{}'", path.display(), contents.trim()))
+}
+
+/// Samples continuously, redrawing a table of the hottest functions until
+/// interrupted with Ctrl-C
+fn run_top(spy: &V8Spy, rate: u32, filter: &NameFilter, style: term::Style, running: &AtomicBool) -> anyhow::Result<()> {
+    let _terminal = ui::TerminalGuard::new()?;
+    let mut deadlines = Deadlines::new(rate);
+    let mut profile = Profile::new();
+    let mut errors = 0;
+    let mut last_refresh = Instant::now() - TOP_REFRESH_INTERVAL;
+    while running.load(Ordering::SeqCst) {
+        if spy.sample(&mut profile).is_err() {
+            if spy.has_exited() {
+                // The process exited underneath us
+                break;
+            }
+            // The next sample may well succeed, so the view keeps going
+            errors += 1;
+        }
+        if last_refresh.elapsed() >= TOP_REFRESH_INTERVAL {
+            let (width, height) = ui::terminal_size();
+            let filtered;
+            let shown = if filter.is_empty() {
+                &profile
+            } else {
+                filtered = profile.filtered(filter);
+                &filtered
+            };
+            ui::render(shown, spy.pid, errors, width, height, style, &mut std::io::stdout().lock())?;
+            last_refresh = Instant::now();
+        }
+        deadlines.wait();
+    }
+    Ok(())
+}
+
+/// Prints the scripts found on the stacks, looking again at --rate until
+/// --duration has passed if one is given. Fails only if none were found.
+fn list_scripts(spy: &V8Spy, config: &Config, running: &AtomicBool) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let interval = Duration::from_secs(1) / config.rate;
+    let mut scripts = BTreeMap::new();
+    let mut last_error = None;
+    loop {
+        match spy.list_scripts() {
+            Ok(found) => scripts.extend(found.into_iter().map(|script| (script.address, script))),
+            Err(e) => last_error = Some(e),
+        }
+        if !running.load(Ordering::SeqCst) || config.duration.is_none_or(|duration| start.elapsed() >= duration) {
+            break;
+        }
+        std::thread::sleep(interval);
+    }
+    if let Some(e) = last_error.filter(|_| scripts.is_empty()) {
+        return Err(e);
+    }
+    let mut scripts: Vec<_> = scripts.into_values().collect();
+    scripts.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut stdout = std::io::stdout().lock();
+    for script in scripts {
+        // Evaluated code has no name, but may have named itself
+        match script.name.as_ref().or(script.source_url.as_ref()) {
+            Some(name) => write!(stdout, "{}", name)?,
+            None => write!(stdout, "<unnamed script at 0x{:x}>", script.address)?,
+        }
+        match &script.source_mapping_url {
+            Some(url) => writeln!(stdout, " (source map {})", url)?,
+            None => writeln!(stdout)?,
+        }
+    }
+    Ok(())
+}
+
+/// Prints the slots around a frame pointer with --dump-frame, one per line,
+/// with each slot's offset from the frame pointer
+fn dump_frame(spy: &V8Spy, fp: usize) -> anyhow::Result<()> {
+    let slots = spy.with_process_paused(|| spy.dump_frame_bytes(fp, DUMP_FRAME_WORDS))??;
+    let mut stdout = std::io::stdout().lock();
+    for (address, value, guess) in slots {
+        let offset = address.wrapping_sub(fp) as isize;
+        writeln!(stdout, "0x{:x} {:+4} {:#018x} {}", address, offset, value, guess)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn segment_paths() {
+        let config = Config::try_parse_from(["v8spy", "--pid-file", "app.pid", "--follow-restarts", "--output", "out/profile.folded"]).unwrap();
+        assert_eq!(segment_path(&config, 0), Some(PathBuf::from("out/profile.folded")));
+        assert_eq!(segment_path(&config, 1), Some(PathBuf::from("out/profile.folded.1")));
+        assert_eq!(segment_path(&config, 12), Some(PathBuf::from("out/profile.folded.12")));
+        let config = Config::try_parse_from(["v8spy", "--pid-file", "app.pid", "--follow-restarts"]).unwrap();
+        assert_eq!(segment_path(&config, 1), None);
+    }
+
+    #[test]
+    fn keeps_stdout_to_the_profile() {
+        let config = Config::try_parse_from(["v8spy", "--pid", "1", "--summary", "1"]).unwrap();
+        let mut profile = Profile::new();
+        let frame = |name: &str| ResolvedFrame { file: Some("/srv/app.js".to_owned()), line: Some(3), ..ResolvedFrame::synthetic(name, FrameKind::Interpreted) };
+        profile.add_sample(vec![frame("parse"), frame("main")]);
+        profile.add_sample(vec![frame("main")]);
+        let summary = Some(Profile { stacks: profile.stacks.clone() });
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        write_out(&config, &profile, None, None, &mut stdout).unwrap();
+        write_summary(&config, &summary, &SampleStats::default(), &mut stderr).unwrap();
+
+        // Every line of stdout is a stack and its count
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert_eq!(stdout.lines().count(), 2);
+        for line in stdout.lines() {
+            let (stack, count) = line.rsplit_once(' ').unwrap();
+            assert!(stack.split(';').all(|frame| frame.ends_with("(/srv/app.js:3)")), "{}", line);
+            count.parse::<u64>().unwrap();
+        }
+        let stderr = String::from_utf8(stderr).unwrap();
+        assert!(stderr.starts_with(r#"{"samples":2,"#) && stderr.ends_with("}\n"), "{}", stderr);
+    }
+
+    #[test]
+    fn polls_pid_file() {
+        let running = AtomicBool::new(true);
+        let interval = Duration::from_millis(1);
+
+        // The file doesn't exist yet, then is created empty, and then the
+        // PID is written to it
+        let path = Path::new("/run/app.pid");
+        let files = std::cell::RefCell::new(vec![Ok("4321\n"), Ok(""), Err(std::io::ErrorKind::NotFound)]);
+        let mut reads = 0;
+        let attached = poll_to_attach(true, None, interval, &running, None, || {
+            reads += 1;
+            read_pid_file(path, |read| {
+                assert_eq!(read, path);
+                files.borrow_mut().pop().unwrap().map(str::to_owned).map_err(std::io::Error::from)
+            })
+        });
+        assert_eq!(attached.unwrap(), Some(4321));
+        assert_eq!(reads, 3);
+
+        // Without a PID in the file by the timeout, the last error is kept
+        let missing = |_: &Path| Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+        let timeout = Some(Duration::from_millis(20));
+        let error = poll_to_attach(true, timeout, interval, &running, None, || read_pid_file(path, missing)).unwrap_err();
+        assert_eq!(format!("{:#}", error), "Gave up waiting for a V8 process to attach to: Failed to read /run/app.pid: entity not found");
+        // and without retrying, the first one
+        let error = poll_to_attach(false, None, interval, &running, None, || read_pid_file(path, |_| Ok("node\n".to_owned()))).unwrap_err();
+        assert_eq!(error.to_string(), "/run/app.pid doesn't hold a PID: 'node'");
+
+        // Nor is it polled once interrupted
+        running.store(false, Ordering::SeqCst);
+        assert!(poll_to_attach(true, None, interval, &running, None, || read_pid_file(path, missing)).unwrap().is_none());
+    }
 }
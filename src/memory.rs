@@ -0,0 +1,523 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use remoteprocess::{Pid, Process, ProcessMemory, Tid};
+use spytools::binary_parser::{parse_binary, BinaryInfo};
+use spytools::process::process_info::is_lib;
+use spytools::process::NodeProcessType;
+use spytools::ProcessInfo;
+
+use crate::stack_trace::Registers;
+
+/// The memory and symbols of a V8 process, as needed to read its layout
+pub trait MemorySource: ProcessMemory {
+    /// Returns the address of a symbol in the target's binaries
+    fn symbol(&self, name: &str) -> Option<usize>;
+}
+
+/// A live process, along with the symbols of its binaries. Symbols are
+/// looked up in the executable first, then in the shared libraries, where
+/// Node builds linking V8 in as libnode or libv8 keep them.
+pub struct LiveTarget<'a> {
+    pub process_info: &'a ProcessInfo,
+    /// The libnode and libv8 shared objects other than
+    /// `process_info.library`, from `load_shared_libraries`
+    pub libraries: &'a [BinaryInfo],
+    pub process: &'a Process,
+}
+
+impl LiveTarget<'_> {
+    /// The binaries searched for symbols, in the order they're searched
+    pub fn modules(&self) -> impl Iterator<Item = &BinaryInfo> {
+        self.process_info.binary.iter().chain(self.process_info.library.iter()).chain(self.libraries.iter())
+    }
+
+    /// The path, as the process sees it, of the binary a symbol is found in
+    pub fn symbol_module(&self, name: &str) -> Option<PathBuf> {
+        let module = self.modules().find(|module| module.symbols.contains_key(name))?;
+        // The binaries are read through the process's view of the filesystem
+        let exe = PathBuf::from(format!("/proc/{}/exe", self.process.pid));
+        let root = PathBuf::from(format!("/proc/{}/root", self.process.pid));
+        if module.filename == exe {
+            return Some(self.process_info.path.clone());
+        }
+        Some(match module.filename.strip_prefix(&root) {
+            Ok(path) => Path::new("/").join(path),
+            Err(_) => module.filename.clone(),
+        })
+    }
+}
+
+impl ProcessMemory for LiveTarget<'_> {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+        self.process.read(addr, buf)
+    }
+}
+
+impl MemorySource for LiveTarget<'_> {
+    fn symbol(&self, name: &str) -> Option<usize> {
+        self.modules().find_map(|module| module.symbols.get(name)).map(|&addr| addr as usize)
+    }
+}
+
+/// Parses the libnode and libv8 shared objects mapped into a process that
+/// `ProcessInfo` leaves out, as it only parses the first one it finds. A
+/// Node linked against both keeps V8's symbols in libv8, which needn't be
+/// the first. Libraries that can't be parsed are skipped.
+pub fn load_shared_libraries(pid: Pid, process_info: &ProcessInfo) -> Vec<BinaryInfo> {
+    let parsed = process_info.library.as_ref().map(|library| library.filename.clone());
+    let mut libraries: Vec<BinaryInfo> = Vec::new();
+    for map in process_info.maps.iter().filter(|map| map.is_exec()) {
+        let filename = match map.filename() {
+            Some(filename) if is_lib::<NodeProcessType>(filename) => filename,
+            _ => continue,
+        };
+        let root_filename = PathBuf::from(format!("/proc/{}/root{}", pid, filename.display()));
+        let seen = |path: &Path| path == filename || path == root_filename;
+        if parsed.as_deref().is_some_and(seen) || libraries.iter().any(|library| seen(&library.filename)) {
+            continue;
+        }
+        match parse_binary(pid, filename, map.start() as u64, map.size() as u64, false) {
+            Ok(library) => libraries.push(library),
+            Err(e) => log::warn!("Failed to parse {}: {}", filename.display(), e),
+        }
+    }
+    libraries
+}
+
+/// The address ranges mapped into a process, for telling a read that runs
+/// into unmapped memory, as reads through stale pointers do, from one that
+/// fails for other reasons
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// `[start, end)` ranges, sorted, with touching ranges merged
+    ranges: Vec<(usize, usize)>,
+}
+
+impl MemoryMap {
+    /// Reads the mappings of a live process from `/proc/<pid>/maps`
+    #[cfg(target_os = "linux")]
+    pub fn load(pid: Pid) -> Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(format!("/proc/{}/maps", pid))?))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn load(_pid: Pid) -> Result<Self> {
+        anyhow::bail!("Reading memory maps is not supported on this platform")
+    }
+
+    /// Parses mappings in the format of `/proc/<pid>/maps`, which starts each
+    /// line with the range as `start-end` in hex. Lines that don't are
+    /// skipped.
+    pub fn parse(maps: &str) -> Self {
+        Self::from_ranges(maps.lines().filter_map(|line| {
+            let (start, end) = line.split_whitespace().next()?.split_once('-')?;
+            Some((usize::from_str_radix(start, 16).ok()?, usize::from_str_radix(end, 16).ok()?))
+        }))
+    }
+
+    /// Builds a map from `[start, end)` ranges, in any order
+    pub fn from_ranges(ranges: impl IntoIterator<Item = (usize, usize)>) -> Self {
+        let mut sorted: Vec<(usize, usize)> = ranges.into_iter().filter(|&(start, end)| start < end).collect();
+        sorted.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(sorted.len());
+        for (start, end) in sorted {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        MemoryMap { ranges: merged }
+    }
+
+    /// Whether all of `[addr, addr + size)` is mapped
+    pub fn contains(&self, addr: usize, size: usize) -> bool {
+        let end = match addr.checked_add(size) {
+            Some(end) => end,
+            None => return false,
+        };
+        if size == 0 {
+            return true;
+        }
+        let index = self.ranges.partition_point(|&(start, _)| start <= addr);
+        index > 0 && end <= self.ranges[index - 1].1
+    }
+}
+
+/// An in-memory stand-in for a process: a symbol table and a set of mapped
+/// regions, for exercising the heap readers and unwinder without a live
+/// target. Reads must fall entirely within one region.
+#[derive(Debug, Clone, Default)]
+pub struct FakeTarget {
+    symbols: HashMap<String, usize>,
+    regions: BTreeMap<usize, Vec<u8>>,
+}
+
+impl FakeTarget {
+    pub fn builder() -> FakeTargetBuilder {
+        FakeTargetBuilder::default()
+    }
+}
+
+impl ProcessMemory for FakeTarget {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+        if let Some((&start, bytes)) = self.regions.range(..=addr).next_back() {
+            let offset = addr - start;
+            if let Some(src) = bytes.get(offset..offset + buf.len()) {
+                buf.copy_from_slice(src);
+                return Ok(());
+            }
+        }
+        Err(remoteprocess::Error::Other(format!("No memory mapped at 0x{:x} for {} bytes", addr, buf.len())))
+    }
+}
+
+impl MemorySource for FakeTarget {
+    fn symbol(&self, name: &str) -> Option<usize> {
+        self.symbols.get(name).copied()
+    }
+}
+
+/// Builds a `FakeTarget` region by region
+#[derive(Debug, Default)]
+pub struct FakeTargetBuilder {
+    target: FakeTarget,
+}
+
+impl FakeTargetBuilder {
+    /// Maps `bytes` at `addr`, replacing any region starting at the same address
+    pub fn bytes(mut self, addr: usize, bytes: &[u8]) -> Self {
+        self.target.regions.insert(addr, bytes.to_vec());
+        self
+    }
+
+    /// Maps `value` at `addr`, in the host's byte order
+    pub fn value<T: Copy>(self, addr: usize, value: T) -> Self {
+        let bytes = unsafe { std::slice::from_raw_parts(&value as *const T as *const u8, std::mem::size_of::<T>()) };
+        self.bytes(addr, bytes)
+    }
+
+    /// Adds a symbol at `addr`, without mapping anything there
+    pub fn symbol(mut self, name: &str, addr: usize) -> Self {
+        self.target.symbols.insert(name.to_owned(), addr);
+        self
+    }
+
+    /// Adds a symbol at `addr` and maps `value` there, as for the `v8dbg_`
+    /// constants
+    pub fn symbol_value<T: Copy>(self, name: &str, addr: usize, value: T) -> Self {
+        self.symbol(name, addr).value(addr, value)
+    }
+
+    pub fn build(self) -> FakeTarget {
+        self.target
+    }
+}
+
+/// A thread whose stack was walked for a `Recording`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedThread {
+    pub tid: Tid,
+    pub name: Option<String>,
+    /// The registers the stack was walked from
+    pub registers: Registers,
+}
+
+/// What a spy read of a process while walking its stacks once, as taken by
+/// `V8Spy::record`: the symbols it found, the memory it read and the
+/// registers of each thread it walked. Serves the same reads back as a
+/// source of memory, so that `V8Spy::replay` unwinds the stacks again
+/// without the process, reading exactly what was read of it. Memory that
+/// wasn't read can't be read from a recording.
+///
+/// Saved as text, for attaching to bug reports: a line per thread, symbol
+/// and region of memory read, with addresses and bytes in hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    pub pid: Pid,
+    pub captured_at: SystemTime,
+    pub threads: Vec<RecordedThread>,
+    symbols: BTreeMap<String, usize>,
+    /// The memory read, with overlapping and touching reads merged
+    regions: BTreeMap<usize, Vec<u8>>,
+}
+
+/// The first line of a saved recording
+const RECORDING_HEADER: &str = "# v8spy recording 1";
+
+impl Recording {
+    pub fn new(pid: Pid) -> Self {
+        Recording { pid, captured_at: SystemTime::now(), threads: Vec::new(), symbols: BTreeMap::new(), regions: BTreeMap::new() }
+    }
+
+    pub fn add_symbol(&mut self, name: &str, addr: usize) {
+        self.symbols.insert(name.to_owned(), addr);
+    }
+
+    /// Keeps `bytes` as what's at `addr`, merging them with the regions
+    /// already kept that they overlap or touch
+    pub fn add_read(&mut self, addr: usize, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let end = addr.saturating_add(bytes.len());
+        let touching: Vec<(usize, Vec<u8>)> = self
+            .regions
+            .range(..=end)
+            .rev()
+            .take_while(|(&start, region)| start + region.len() >= addr)
+            .map(|(&start, _)| start)
+            .collect::<Vec<usize>>()
+            .into_iter()
+            .filter_map(|start| self.regions.remove_entry(&start))
+            .collect();
+        let start = touching.iter().map(|&(start, _)| start).fold(addr, usize::min);
+        let end = touching.iter().map(|(start, region)| start + region.len()).fold(end, usize::max);
+        let mut merged = vec![0; end - start];
+        for (region_start, region) in &touching {
+            merged[region_start - start..][..region.len()].copy_from_slice(region);
+        }
+        merged[addr - start..][..bytes.len()].copy_from_slice(bytes);
+        self.regions.insert(start, merged);
+    }
+
+    /// Writes the recording out as text
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_text()).with_context(|| format!("Failed to write recording to {}", path.display()))
+    }
+
+    /// Reads a recording saved with `save`
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Failed to read recording {}", path.display()))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let captured_at = self.captured_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let _ = writeln!(text, "{}\npid {}\ncaptured_at {}", RECORDING_HEADER, self.pid, captured_at);
+        for thread in &self.threads {
+            let regs = &thread.registers;
+            let _ = write!(text, "thread {} {:x} {:x} {:x}", thread.tid, regs.pc, regs.sp, regs.fp);
+            if let Some(name) = &thread.name {
+                let _ = write!(text, " {}", name);
+            }
+            text.push('\n');
+        }
+        for (name, addr) in &self.symbols {
+            let _ = writeln!(text, "symbol {} {:x}", name, addr);
+        }
+        for (addr, bytes) in &self.regions {
+            let _ = write!(text, "region {:x} ", addr);
+            for byte in bytes {
+                let _ = write!(text, "{:02x}", byte);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Parses a recording written by `to_text`
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(RECORDING_HEADER) {
+            anyhow::bail!("Not a v8spy recording: expected it to start with '{}'", RECORDING_HEADER);
+        }
+        let mut recording = Recording::new(0);
+        for (number, line) in lines {
+            recording.parse_line(line).with_context(|| format!("Line {}", number + 1))?;
+        }
+        Ok(recording)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<()> {
+        fn hex(value: Option<&str>) -> Result<usize> {
+            let value = value.context("missing value")?;
+            usize::from_str_radix(value, 16).with_context(|| format!("'{}' isn't a hex number", value))
+        }
+        let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match kind {
+            "pid" => self.pid = rest.parse()?,
+            "captured_at" => self.captured_at = UNIX_EPOCH + Duration::from_nanos(rest.parse()?),
+            "thread" => {
+                let mut fields = rest.splitn(5, ' ');
+                let tid = fields.next().context("missing thread id")?.parse()?;
+                let (pc, sp, fp) = (hex(fields.next())?, hex(fields.next())?, hex(fields.next())?);
+                let name = fields.next().map(str::to_owned);
+                self.threads.push(RecordedThread { tid, name, registers: Registers { pc, sp, fp } });
+            }
+            "symbol" => {
+                let (name, addr) = rest.split_once(' ').context("missing address")?;
+                self.symbols.insert(name.to_owned(), hex(Some(addr))?);
+            }
+            "region" => {
+                let (addr, digits) = rest.split_once(' ').context("missing bytes")?;
+                if digits.len() % 2 != 0 || !digits.is_ascii() {
+                    anyhow::bail!("bytes aren't pairs of hex digits");
+                }
+                let bytes = (0..digits.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).context("bytes aren't pairs of hex digits"))
+                    .collect::<Result<Vec<u8>>>()?;
+                self.add_read(hex(Some(addr))?, &bytes);
+            }
+            "" => {}
+            _ => anyhow::bail!("unknown line '{}'", kind),
+        }
+        Ok(())
+    }
+}
+
+impl ProcessMemory for Recording {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+        if let Some((&start, bytes)) = self.regions.range(..=addr).next_back() {
+            let offset = addr - start;
+            if let Some(src) = bytes.get(offset..offset + buf.len()) {
+                buf.copy_from_slice(src);
+                return Ok(());
+            }
+        }
+        Err(remoteprocess::Error::Other(format!("Nothing recorded at 0x{:x} for {} bytes", addr, buf.len())))
+    }
+}
+
+impl MemorySource for Recording {
+    fn symbol(&self, name: &str) -> Option<usize> {
+        self.symbols.get(name).copied()
+    }
+}
+
+/// Wraps a source of memory and symbols, keeping a `Recording` of the
+/// symbols found and the memory read through it
+pub struct Recorder<T> {
+    inner: T,
+    recording: RefCell<Recording>,
+}
+
+impl<T> Recorder<T> {
+    pub fn new(pid: Pid, inner: T) -> Self {
+        Recorder { inner, recording: RefCell::new(Recording::new(pid)) }
+    }
+
+    /// Takes what's been recorded so far, leaving the recorder to start a
+    /// new recording
+    pub fn take_recording(&self) -> Recording {
+        let pid = self.recording.borrow().pid;
+        self.recording.replace(Recording::new(pid))
+    }
+}
+
+impl<T: ProcessMemory> ProcessMemory for Recorder<T> {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+        self.inner.read(addr, buf)?;
+        self.recording.borrow_mut().add_read(addr, buf);
+        Ok(())
+    }
+}
+
+impl<T: MemorySource> MemorySource for Recorder<T> {
+    fn symbol(&self, name: &str) -> Option<usize> {
+        let addr = self.inner.symbol(name)?;
+        self.recording.borrow_mut().add_symbol(name, addr);
+        Some(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(recording: &Recording, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let mut buf = vec![0; len];
+        recording.read(addr, &mut buf).ok().map(|_| buf)
+    }
+
+    #[test]
+    fn parses_memory_map() {
+        let maps = "\
+55d4c8a00000-55d4c8a3b000 r--p 00000000 fd:01 1048602                    /usr/bin/node
+55d4c8a3b000-55d4c9e00000 r-xp 0003b000 fd:01 1048602                    /usr/bin/node
+7f1e2c000000-7f1e2c021000 rw-p 00000000 00:00 0
+7ffd1a2b5000-7ffd1a2d6000 rw-p 00000000 00:00 0                          [stack]
+not a mapping
+ffffffffff600000-ffffffffff601000 --xp 00000000 00:00 0                  [vsyscall]
+";
+        let map = MemoryMap::parse(maps);
+        // The two touching mappings of the binary are merged
+        assert_eq!(
+            map.ranges,
+            [(0x55d4c8a00000, 0x55d4c9e00000), (0x7f1e2c000000, 0x7f1e2c021000), (0x7ffd1a2b5000, 0x7ffd1a2d6000), (0xffffffffff600000, 0xffffffffff601000)]
+        );
+        assert_eq!(MemoryMap::parse(""), MemoryMap::default());
+        assert_eq!(MemoryMap::parse("zz-10 r--p\n10-zz r--p\n"), MemoryMap::default());
+    }
+
+    #[test]
+    fn memory_map_contains() {
+        let map = MemoryMap::from_ranges([(0x3000, 0x4000), (0x1000, 0x2000), (0x1800, 0x2800), (0x5000, 0x5000)]);
+        assert_eq!(map.ranges, [(0x1000, 0x2800), (0x3000, 0x4000)]);
+        assert!(map.contains(0x1000, 8));
+        assert!(map.contains(0x27f8, 8));
+        // Across the end of a range, into the gap after it
+        assert!(!map.contains(0x27f9, 8));
+        assert!(!map.contains(0x2800, 1));
+        assert!(!map.contains(0xff8, 16));
+        assert!(!map.contains(0x2ffc, 8));
+        assert!(map.contains(0x3000, 0x1000));
+        assert!(!map.contains(0x4000, 1));
+        // Empty reads are always in range, and ones that overflow never are
+        assert!(map.contains(0x4800, 0));
+        assert!(!map.contains(usize::MAX - 4, 8));
+        assert!(!MemoryMap::default().contains(0x1000, 8));
+    }
+
+    #[test]
+    fn recording_merges_reads() {
+        let mut recording = Recording::new(1);
+        recording.add_read(0x100, &[1, 2, 3, 4]);
+        // Overlapping the end, and touching the start
+        recording.add_read(0x102, &[9, 9, 5, 6]);
+        recording.add_read(0xfe, &[7, 8]);
+        recording.add_read(0x200, &[10]);
+        assert_eq!(recording.regions.len(), 2);
+        assert_eq!(read(&recording, 0xfe, 8), Some(vec![7, 8, 1, 2, 9, 9, 5, 6]));
+        assert_eq!(read(&recording, 0x200, 1), Some(vec![10]));
+        // Nothing was read in between
+        assert_eq!(read(&recording, 0x105, 4), None);
+        assert_eq!(read(&recording, 0x1ff, 1), None);
+    }
+
+    #[test]
+    fn recording_round_trips_as_text() {
+        let mut recording = Recording::new(42);
+        recording.threads.push(RecordedThread { tid: 43, name: Some("worker one".to_owned()), registers: Registers { pc: 0x10, sp: 0x20, fp: 0x30 } });
+        recording.threads.push(RecordedThread { tid: 44, name: None, registers: Registers { pc: 1, sp: 2, fp: 3 } });
+        recording.add_symbol("v8dbg_SmiTag", 0x1000);
+        recording.add_read(0x1000, &[0, 0xff, 0x7f]);
+        let text = recording.to_text();
+        assert!(text.starts_with(RECORDING_HEADER));
+        assert!(text.contains("thread 43 10 20 30 worker one\n"));
+        assert!(text.contains("region 1000 00ff7f\n"));
+        let parsed = Recording::parse(&text).unwrap();
+        assert_eq!(parsed, recording);
+        assert_eq!(parsed.symbol("v8dbg_SmiTag"), Some(0x1000));
+    }
+
+    #[test]
+    fn recording_parse_errors() {
+        assert!(Recording::parse("pid 1\n").is_err());
+        let parse = |line: &str| Recording::parse(&format!("{}\n{}\n", RECORDING_HEADER, line));
+        assert!(parse("").is_ok());
+        assert!(parse("region 10 abc").is_err());
+        assert!(parse("region 10 zz").is_err());
+        assert!(parse("symbol v8dbg_SmiTag").is_err());
+        assert!(parse("thread 1 10 20").is_err());
+        assert!(parse("pid x").is_err());
+        let error = parse("frames 1").unwrap_err();
+        assert_eq!(format!("{:#}", error), "Line 2: unknown line 'frames'");
+    }
+}
@@ -0,0 +1,111 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use anyhow::Result;
+use remoteprocess::Pid;
+
+/// Upper bound on the number of resolved addresses cached
+const MAX_CACHED_ADDRESSES: usize = 1 << 16;
+
+/// Resolves addresses in native code to `module!symbol` names, using the
+/// symbol tables of the binaries mapped into the process. Symbol tables are
+/// loaded on first use and every resolved address is cached, since return
+/// addresses repeat from one sample to the next.
+pub struct NativeSymbols {
+    #[cfg(target_os = "linux")]
+    symbolicator: remoteprocess::Symbolicator,
+    names: RefCell<HashMap<usize, Option<String>>>,
+}
+
+impl NativeSymbols {
+    #[cfg(target_os = "linux")]
+    pub fn new(pid: Pid) -> Result<Self> {
+        Ok(NativeSymbols {
+            symbolicator: remoteprocess::Symbolicator::new(pid)?,
+            names: RefCell::new(HashMap::new()),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(_pid: Pid) -> Result<Self> {
+        anyhow::bail!("Resolving native symbols is not supported on this platform")
+    }
+
+    /// Returns the name of the function containing `pc`, as `module!symbol`
+    /// with the module's file name, or `module!<unknown>` if the module has
+    /// no symbol for it. Returns `None` for addresses outside any module,
+    /// such as JIT compiled code.
+    pub fn resolve(&self, pc: usize) -> Option<String> {
+        if let Some(name) = self.names.borrow().get(&pc) {
+            return name.clone();
+        }
+        let name = self.lookup(pc);
+        let mut names = self.names.borrow_mut();
+        if names.len() >= MAX_CACHED_ADDRESSES {
+            names.clear();
+        }
+        names.insert(pc, name.clone());
+        name
+    }
+
+    #[cfg(target_os = "linux")]
+    fn lookup(&self, pc: usize) -> Option<String> {
+        let mut name = None;
+        // Without line info there's exactly one callback, for the symbol
+        let _ = self.symbolicator.symbolicate(pc as u64, false, &mut |frame| {
+            let module = std::path::Path::new(&frame.module)
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| frame.module.clone());
+            let function = frame.function.as_deref().map(demangle).unwrap_or_else(|| "<unknown>".to_owned());
+            name = Some(format!("{}!{}", module, function));
+        });
+        name
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn lookup(&self, _pc: usize) -> Option<String> {
+        None
+    }
+}
+
+/// Demangles a C++ symbol, leaving out parameters and return types to keep
+/// names short. Anything that isn't a mangled C++ name is returned as is.
+fn demangle(symbol: &str) -> String {
+    let options = cpp_demangle::DemangleOptions::new().no_params().no_return_type();
+    cpp_demangle::Symbol::new(symbol)
+        .ok()
+        .and_then(|parsed| parsed.demangle(&options).ok())
+        .unwrap_or_else(|| symbol.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" {
+        fn getpid() -> i32;
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resolves_libc_symbols() {
+        let symbols = NativeSymbols::new(std::process::id() as Pid).unwrap();
+        // A return address into libc's getpid, as for a thread stopped in it
+        let pc = getpid as *const () as usize + 1;
+        assert_eq!(symbols.resolve(pc).as_deref(), Some("libc.so.6!getpid"));
+        // Resolved again, from the cache
+        assert_eq!(symbols.resolve(pc).as_deref(), Some("libc.so.6!getpid"));
+        assert_eq!(symbols.names.borrow().len(), 1);
+
+        // The heap is in no module
+        let heap = Box::new(0u64);
+        assert_eq!(symbols.resolve(&*heap as *const u64 as usize), None);
+    }
+
+    #[test]
+    fn demangles_cpp_names() {
+        assert_eq!(demangle("_ZN2v88internal7Isolate4InitEPNS0_12SnapshotDataEb"), "v8::internal::Isolate::Init");
+        assert_eq!(demangle("uv_run"), "uv_run");
+    }
+}
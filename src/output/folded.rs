@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::profile::Profile;
+use crate::stack_trace::{FrameFormat, ResolvedFrame};
+
+/// Writes the profile in the collapsed stack format used by flamegraph.pl
+/// and inferno: one line per stack, root frame first, followed by its count,
+/// with frames written as `frames` says
+pub fn write_folded(profile: &Profile, frames: &FrameFormat, w: &mut impl Write) -> Result<()> {
+    // Stacks that differ only in what isn't shown, like the tier a function
+    // ran in, are written as one line
+    let mut lines: BTreeMap<String, u64> = BTreeMap::new();
+    for (stack, count) in &profile.stacks {
+        *lines.entry(fold(stack, frames)).or_insert(0) += count;
+    }
+    for (line, count) in &lines {
+        writeln!(w, "{} {}", line, count)?;
+    }
+    Ok(())
+}
+
+/// Writes folded stacks as they're sampled, appending the counts added since
+/// the last flush at each flush. Readers of the collapsed format add up the
+/// lines of a stack that appears more than once, so the output read at any
+/// point is the profile up to the last flush. Only the stacks sampled since
+/// then are sorted and written.
+#[derive(Debug)]
+pub struct IncrementalFoldedWriter {
+    /// Counts since the last flush, by folded stack
+    pending: BTreeMap<String, u64>,
+    frames: FrameFormat,
+}
+
+impl IncrementalFoldedWriter {
+    pub fn new(frames: FrameFormat) -> Self {
+        IncrementalFoldedWriter { pending: BTreeMap::new(), frames }
+    }
+
+    /// Counts one sample of a stack, innermost frame first
+    pub fn add_sample(&mut self, stack: &[ResolvedFrame]) {
+        *self.pending.entry(fold(stack, &self.frames)).or_insert(0) += 1;
+    }
+
+    /// Writes the counts added since the last flush and flushes `w`
+    pub fn flush(&mut self, w: &mut impl Write) -> Result<()> {
+        for (line, count) in &self.pending {
+            writeln!(w, "{} {}", line, count)?;
+        }
+        w.flush()?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Joins a stack into a folded line, root frame first
+fn fold(stack: &[ResolvedFrame], format: &FrameFormat) -> String {
+    let frames: Vec<String> = stack.iter().rev().map(|frame| format.render(frame)).collect();
+    frames.join(";")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack_trace::FrameKind;
+
+    fn frame(name: &str, file: &str, line: u32) -> ResolvedFrame {
+        ResolvedFrame { file: Some(file.to_owned()), line: Some(line), ..ResolvedFrame::synthetic(name, FrameKind::Interpreted) }
+    }
+
+    #[test]
+    fn writes_folded_stacks() {
+        let mut profile = Profile::new();
+        let main = frame("main", "app.js", 5);
+        profile.add_sample(vec![frame("fib", "app.js", 1), main.clone()]);
+        // The same stack, but for the tier, which isn't written
+        let optimized = ResolvedFrame { kind: FrameKind::Optimized, tier: Some(crate::Tier::Turbofan), ..frame("fib", "app.js", 1) };
+        profile.add_sample(vec![optimized, main.clone()]);
+        profile.add_sample(vec![main]);
+        let mut out = Vec::new();
+        write_folded(&profile, &FrameFormat::default(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "main (app.js:5) 1\nmain (app.js:5);fib (app.js:1) 2\n");
+
+        let mut out = Vec::new();
+        write_folded(&profile, &"{name}".parse().unwrap(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "main 1\nmain;fib 2\n");
+    }
+
+    #[test]
+    fn appends_counts_at_each_flush() {
+        let fib = [frame("fib", "app.js", 1), frame("main", "app.js", 5)];
+        let main = [frame("main", "app.js", 5)];
+        let mut writer = IncrementalFoldedWriter::new(FrameFormat::default());
+        let mut out = Vec::new();
+        writer.add_sample(&fib);
+        writer.add_sample(&main);
+        writer.add_sample(&fib);
+        writer.flush(&mut out).unwrap();
+        let first = String::from_utf8(out.clone()).unwrap();
+        assert_eq!(first, "main (app.js:5) 1\nmain (app.js:5);fib (app.js:1) 2\n");
+
+        // Only the samples since the first flush are appended
+        writer.add_sample(&fib);
+        writer.flush(&mut out).unwrap();
+        let second = String::from_utf8(out.clone()).unwrap();
+        assert_eq!(second, format!("{}main (app.js:5);fib (app.js:1) 1\n", first));
+        writer.flush(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), second);
+
+        // Read back as the collapsed format is, the lines add up to every sample
+        let mut totals = BTreeMap::new();
+        for line in second.lines() {
+            let (stack, count) = line.rsplit_once(' ').unwrap();
+            *totals.entry(stack).or_insert(0) += count.parse::<u64>().unwrap();
+        }
+        assert_eq!(totals, BTreeMap::from([("main (app.js:5)", 1), ("main (app.js:5);fib (app.js:1)", 3)]));
+    }
+}
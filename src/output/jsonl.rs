@@ -0,0 +1,75 @@
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use remoteprocess::{Pid, Tid};
+
+use super::json_string;
+use crate::stack_trace::{FrameFormat, ResolvedFrame};
+
+/// Writes one sample as a line of JSON, for example
+/// `{"ts":1700000000000000000,"tid":42,"frames":["fib (app.js:1)","main (app.js:5)"]}`,
+/// with the time in nanoseconds since the Unix epoch and the innermost frame
+/// first. Frames are written as `frames` says, as in the folded output. The
+/// line is flushed so that whoever is reading sees samples as they're taken.
+pub fn write_sample(w: &mut impl Write, timestamp: SystemTime, tid: Tid, stack: &[ResolvedFrame], frames: &FrameFormat) -> Result<()> {
+    let ts = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let frames: Vec<String> = stack.iter().map(|frame| json_string(&frames.render(frame))).collect();
+    writeln!(w, "{{\"ts\":{},\"tid\":{},\"frames\":[{}]}}", ts, tid, frames.join(","))?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Writes the line that starts a new segment of samples, when the process
+/// has restarted as `pid`: `{"ts":1700000000000000000,"segment":1,"pid":42}`
+pub fn write_segment_marker(w: &mut impl Write, timestamp: SystemTime, segment: u32, pid: Pid) -> Result<()> {
+    let ts = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    writeln!(w, "{{\"ts\":{},\"segment\":{},\"pid\":{}}}", ts, segment, pid)?;
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::stack_trace::FrameKind;
+
+    fn frame(name: &str, file: &str, line: u32) -> ResolvedFrame {
+        ResolvedFrame { file: Some(file.to_owned()), line: Some(line), ..ResolvedFrame::synthetic(name, FrameKind::Interpreted) }
+    }
+
+    #[test]
+    fn writes_sample_lines() {
+        let timestamp = UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789);
+        let stack = [frame("fib", "app.js", 1), frame("main", "app.js", 5)];
+        let mut out = Vec::new();
+        write_sample(&mut out, timestamp, 42, &stack, &FrameFormat::default()).unwrap();
+        write_sample(&mut out, timestamp, 43, &[], &FrameFormat::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"ts\":1700000000123456789,\"tid\":42,\"frames\":[\"fib (app.js:1)\",\"main (app.js:5)\"]}\n\
+             {\"ts\":1700000000123456789,\"tid\":43,\"frames\":[]}\n"
+        );
+    }
+
+    #[test]
+    fn escapes_frames() {
+        let stack = [frame("get \"x\"", "C:\\app.js", 1)];
+        let mut out = Vec::new();
+        write_sample(&mut out, UNIX_EPOCH, 1, &stack, &"{name}\t{file}".parse().unwrap()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"ts\":0,\"tid\":1,\"frames\":[\"get \\\"x\\\"\\tC:\\\\app.js\"]}\n");
+    }
+
+    #[test]
+    fn writes_segment_markers() {
+        let mut out = Vec::new();
+        write_sample(&mut out, UNIX_EPOCH + Duration::from_secs(1), 42, &[frame("main", "app.js", 5)], &FrameFormat::default()).unwrap();
+        write_segment_marker(&mut out, UNIX_EPOCH + Duration::from_secs(2), 1, 4242).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"ts\":1000000000,\"tid\":42,\"frames\":[\"main (app.js:5)\"]}\n{\"ts\":2000000000,\"segment\":1,\"pid\":4242}\n"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+use crate::profile::Profile;
+use crate::stack_trace::FrameFormat;
+
+pub mod folded;
+pub mod jsonl;
+pub mod otlp;
+pub mod pprof;
+mod proto;
+pub mod svg;
+
+/// Output formats for a finished profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Folded,
+    Pprof,
+    Otlp,
+    /// A self-contained flame graph
+    Svg,
+    /// A line of JSON per sample, written while sampling rather than from a
+    /// finished profile
+    Jsonl,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "folded" => Ok(Format::Folded),
+            "pprof" => Ok(Format::Pprof),
+            "otlp" => Ok(Format::Otlp),
+            "svg" => Ok(Format::Svg),
+            "jsonl" => Ok(Format::Jsonl),
+            _ => anyhow::bail!("Unknown output format '{}'", s),
+        }
+    }
+}
+
+/// Writes a finished profile. Formats that write frames as text, folded and
+/// svg, write them as `frames` says, and the others keep the name, file and
+/// line of frames apart.
+pub fn write_profile(profile: &Profile, format: Format, frames: &FrameFormat, w: &mut impl Write) -> Result<()> {
+    match format {
+        Format::Folded => folded::write_folded(profile, frames, w),
+        Format::Pprof => pprof::write_pprof(profile, w),
+        Format::Otlp => otlp::write_otlp(profile, None, w),
+        Format::Svg => Ok(svg::write_flamegraph(profile, frames, w)?),
+        Format::Jsonl => anyhow::bail!("jsonl output is written per sample, with jsonl::write_sample"),
+    }
+}
+
+/// Writes the profile to `path` by way of a temporary file in the same
+/// directory, which is renamed over `path` once complete. Readers never see
+/// a partially written profile, and a failed write leaves any previous
+/// version of the file intact.
+pub fn write_profile_atomic(profile: &Profile, format: Format, frames: &FrameFormat, path: &Path) -> Result<()> {
+    let file_name = path.file_name().with_context(|| format!("Invalid output path {}", path.display()))?;
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name.to_string_lossy(), std::process::id()));
+
+    let result = (|| -> Result<()> {
+        let mut file = BufWriter::new(File::create(&temp_path)?);
+        write_profile(profile, format, frames, &mut file)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result.with_context(|| format!("Failed to write profile to {}", path.display()))
+}
+
+/// Quotes a string for JSON, escaping what JSON doesn't allow in strings
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::stack_trace::{FrameKind, ResolvedFrame};
+
+    fn frame(name: &str, file: &str, line: u32) -> ResolvedFrame {
+        ResolvedFrame { file: Some(file.to_owned()), line: Some(line), ..ResolvedFrame::synthetic(name, FrameKind::Interpreted) }
+    }
+
+    fn profile() -> Profile {
+        let mut profile = Profile::new();
+        let main = frame("main", "app.js", 5);
+        profile.add_sample(vec![frame("fib", "app.js", 1), main.clone()]);
+        profile.add_sample(vec![frame("fib", "app.js", 1), main.clone()]);
+        profile.add_sample(vec![main]);
+        profile
+    }
+
+    /// An empty directory of its own for a test to write files in
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("v8spy-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn writes_profile_atomically() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("profile.folded");
+        std::fs::write(&path, "stale 1\n").unwrap();
+        write_profile_atomic(&profile(), Format::Folded, &FrameFormat::default(), &path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "main (app.js:5) 1\nmain (app.js:5);fib (app.js:1) 2\n");
+        // The temporary file was renamed over the profile
+        assert_eq!(file_names(&dir), ["profile.folded"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_keeps_previous_profile() {
+        let dir = temp_dir("failed-write");
+        let path = dir.join("profile.folded");
+        std::fs::write(&path, "main 1\n").unwrap();
+        // jsonl can't be written from a finished profile, so the write fails
+        // once the temporary file is made
+        let error = write_profile_atomic(&profile(), Format::Jsonl, &FrameFormat::default(), &path).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            format!("Failed to write profile to {}: jsonl output is written per sample, with jsonl::write_sample", path.display())
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "main 1\n");
+        assert_eq!(file_names(&dir), ["profile.folded"]);
+
+        let error = write_profile_atomic(&profile(), Format::Folded, &FrameFormat::default(), &dir.join("missing").join("profile.folded"));
+        assert!(error.is_err());
+        assert!(write_profile_atomic(&profile(), Format::Folded, &FrameFormat::default(), Path::new("/")).is_err());
+        assert_eq!(file_names(&dir), ["profile.folded"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn quotes_json_strings() {
+        assert_eq!(json_string(""), r#""""#);
+        assert_eq!(json_string("fib (app.js:1)"), r#""fib (app.js:1)""#);
+        assert_eq!(json_string(r#"say "hi" \ bye"#), r#""say \"hi\" \\ bye""#);
+        assert_eq!(json_string("a\nb\rc\td"), r#""a\nb\rc\td""#);
+        assert_eq!(json_string("\u{0}\u{1b}\u{1f}"), r#""\u0000\u001b\u001f""#);
+        // Everything from a space up is written as it is, including non-ASCII
+        assert_eq!(json_string("\u{7f} é 💥 /"), "\"\u{7f} é 💥 /\"");
+    }
+}
@@ -0,0 +1,309 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use super::proto::{encode_bytes_field, encode_packed_field, encode_varint_field, Tables};
+use crate::profile::Profile;
+
+// Field numbers from opentelemetry.proto.collector.profiles.v1development
+// and opentelemetry.proto.profiles.v1development (profiles.proto)
+const REQUEST_RESOURCE_PROFILES: u32 = 1;
+const REQUEST_DICTIONARY: u32 = 2;
+const DICTIONARY_LOCATION_TABLE: u32 = 2;
+const DICTIONARY_FUNCTION_TABLE: u32 = 3;
+const DICTIONARY_STRING_TABLE: u32 = 5;
+const RESOURCE_PROFILES_SCOPE_PROFILES: u32 = 2;
+const SCOPE_PROFILES_SCOPE: u32 = 1;
+const SCOPE_PROFILES_PROFILES: u32 = 2;
+const SCOPE_NAME: u32 = 1;
+const SCOPE_VERSION: u32 = 2;
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION_INDICES: u32 = 3;
+const PROFILE_TIME_NANOS: u32 = 4;
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+const SAMPLE_LOCATIONS_START_INDEX: u32 = 1;
+const SAMPLE_LOCATIONS_LENGTH: u32 = 2;
+const SAMPLE_VALUE: u32 = 3;
+const LOCATION_LINE: u32 = 3;
+const LINE_FUNCTION_INDEX: u32 = 1;
+const LINE_LINE: u32 = 2;
+const FUNCTION_NAME: u32 = 1;
+const FUNCTION_SYSTEM_NAME: u32 = 2;
+const FUNCTION_FILENAME: u32 = 3;
+
+/// Path of the profiles signal on an OTLP/HTTP receiver
+const PROFILES_PATH: &str = "/v1development/profiles";
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Builds the profile as an OTLP `ExportProfilesServiceRequest`. With an
+/// `endpoint`, the request is POSTed to that OTLP/HTTP receiver, otherwise
+/// the protobuf is written to `w`. Only plain `http://` endpoints are
+/// supported.
+pub fn write_otlp(profile: &Profile, endpoint: Option<&str>, w: &mut impl Write) -> Result<()> {
+    let request = encode_request(profile);
+    match endpoint {
+        Some(endpoint) => post(endpoint, &request).with_context(|| format!("Failed to send profile to {}", endpoint)),
+        None => {
+            w.write_all(&request)?;
+            Ok(())
+        }
+    }
+}
+
+fn encode_request(profile: &Profile) -> Vec<u8> {
+    let mut tables = Tables::new();
+
+    let mut encoded_profile = Vec::new();
+    let mut sample_type = Vec::new();
+    encode_varint_field(&mut sample_type, VALUE_TYPE_TYPE, tables.strings.intern("samples"));
+    encode_varint_field(&mut sample_type, VALUE_TYPE_UNIT, tables.strings.intern("count"));
+    encode_bytes_field(&mut encoded_profile, PROFILE_SAMPLE_TYPE, &sample_type);
+
+    // Samples refer to a run of the profile's location indices, which in
+    // turn index the dictionary's location table. Each stack gets its own
+    // run, leaf first as with pprof.
+    let mut location_indices = Vec::new();
+    for (stack, count) in &profile.stacks {
+        let start = location_indices.len() as u64;
+        location_indices.extend(stack.iter().map(|frame| tables.location(frame)));
+        let mut sample = Vec::new();
+        encode_varint_field(&mut sample, SAMPLE_LOCATIONS_START_INDEX, start);
+        encode_varint_field(&mut sample, SAMPLE_LOCATIONS_LENGTH, stack.len() as u64);
+        encode_packed_field(&mut sample, SAMPLE_VALUE, &[*count]);
+        encode_bytes_field(&mut encoded_profile, PROFILE_SAMPLE, &sample);
+    }
+    encode_packed_field(&mut encoded_profile, PROFILE_LOCATION_INDICES, &location_indices);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    encode_varint_field(&mut encoded_profile, PROFILE_TIME_NANOS, now.as_nanos() as u64);
+
+    let mut scope = Vec::new();
+    encode_bytes_field(&mut scope, SCOPE_NAME, b"v8spy");
+    encode_bytes_field(&mut scope, SCOPE_VERSION, env!("CARGO_PKG_VERSION").as_bytes());
+    let mut scope_profiles = Vec::new();
+    encode_bytes_field(&mut scope_profiles, SCOPE_PROFILES_SCOPE, &scope);
+    encode_bytes_field(&mut scope_profiles, SCOPE_PROFILES_PROFILES, &encoded_profile);
+    let mut resource_profiles = Vec::new();
+    encode_bytes_field(&mut resource_profiles, RESOURCE_PROFILES_SCOPE_PROFILES, &scope_profiles);
+
+    // Table ids start at 1, so an empty entry at index 0 lines their indices
+    // up with the ids, and keeps index 0 as the "unset" value OTLP expects
+    let mut dictionary = Vec::new();
+    encode_bytes_field(&mut dictionary, DICTIONARY_LOCATION_TABLE, &[]);
+    for location in &tables.locations {
+        let mut line = Vec::new();
+        encode_varint_field(&mut line, LINE_FUNCTION_INDEX, location.function);
+        encode_varint_field(&mut line, LINE_LINE, location.line);
+        let mut encoded = Vec::new();
+        encode_bytes_field(&mut encoded, LOCATION_LINE, &line);
+        encode_bytes_field(&mut dictionary, DICTIONARY_LOCATION_TABLE, &encoded);
+    }
+    encode_bytes_field(&mut dictionary, DICTIONARY_FUNCTION_TABLE, &[]);
+    for function in &tables.functions {
+        let mut encoded = Vec::new();
+        encode_varint_field(&mut encoded, FUNCTION_NAME, function.name);
+        encode_varint_field(&mut encoded, FUNCTION_SYSTEM_NAME, function.name);
+        encode_varint_field(&mut encoded, FUNCTION_FILENAME, function.filename);
+        encode_bytes_field(&mut dictionary, DICTIONARY_FUNCTION_TABLE, &encoded);
+    }
+    for string in &tables.strings.values {
+        encode_bytes_field(&mut dictionary, DICTIONARY_STRING_TABLE, string.as_bytes());
+    }
+
+    let mut request = Vec::new();
+    encode_bytes_field(&mut request, REQUEST_RESOURCE_PROFILES, &resource_profiles);
+    encode_bytes_field(&mut request, REQUEST_DICTIONARY, &dictionary);
+    request
+}
+
+/// POSTs an encoded request to an OTLP/HTTP receiver. An endpoint without a
+/// path gets the default profiles path, as with the OTLP exporters.
+fn post(endpoint: &str, body: &[u8]) -> Result<()> {
+    let rest = endpoint
+        .strip_prefix("http://")
+        .with_context(|| format!("Unsupported OTLP endpoint '{}': only http:// is supported", endpoint))?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) if slash + 1 < rest.len() => (&rest[..slash], &rest[slash..]),
+        Some(slash) => (&rest[..slash], PROFILES_PATH),
+        None => (rest, PROFILES_PATH),
+    };
+    let address = if authority.contains(':') { authority.to_owned() } else { format!("{}:80", authority) };
+
+    let mut stream = TcpStream::connect(&address)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-protobuf\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or("");
+    let code = status.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok());
+    match code {
+        Some(200..=299) => Ok(()),
+        _ => anyhow::bail!("Receiver responded with '{}'", status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::output::proto::{decode_message, field_values, FieldValue};
+    use crate::stack_trace::{FrameKind, ResolvedFrame};
+
+    fn frame(name: &str, file: &str, line: u32) -> ResolvedFrame {
+        ResolvedFrame { file: Some(file.to_owned()), line: Some(line), ..ResolvedFrame::synthetic(name, FrameKind::Interpreted) }
+    }
+
+    fn profile() -> Profile {
+        let mut profile = Profile::new();
+        let main = frame("main", "app.js", 5);
+        profile.add_sample(vec![frame("fib", "app.js", 1), main.clone()]);
+        profile.add_sample(vec![frame("fib", "app.js", 1), main.clone()]);
+        profile.add_sample(vec![main]);
+        profile
+    }
+
+    /// The one value of a field of a message
+    fn field(fields: &[(u32, FieldValue)], number: u32) -> &FieldValue {
+        match field_values(fields, number)[..] {
+            [value] => value,
+            ref values => panic!("expected one field {}, got {}", number, values.len()),
+        }
+    }
+
+    /// The samples of a request, each as its frames written `name file:line`
+    /// and its count
+    fn decode_samples(request: &[u8]) -> Vec<(Vec<String>, u64)> {
+        let request = decode_message(request);
+        let resource_profiles = decode_message(field(&request, REQUEST_RESOURCE_PROFILES).bytes());
+        let scope_profiles = decode_message(field(&resource_profiles, RESOURCE_PROFILES_SCOPE_PROFILES).bytes());
+        let profile = decode_message(field(&scope_profiles, SCOPE_PROFILES_PROFILES).bytes());
+        let dictionary = decode_message(field(&request, REQUEST_DICTIONARY).bytes());
+
+        let strings: Vec<String> = field_values(&dictionary, DICTIONARY_STRING_TABLE).iter().map(|value| String::from_utf8(value.bytes().to_vec()).unwrap()).collect();
+        assert_eq!(strings[0], "");
+        let functions: Vec<Vec<(u32, FieldValue)>> = field_values(&dictionary, DICTIONARY_FUNCTION_TABLE).iter().map(|value| decode_message(value.bytes())).collect();
+        let locations: Vec<Vec<(u32, FieldValue)>> = field_values(&dictionary, DICTIONARY_LOCATION_TABLE).iter().map(|value| decode_message(value.bytes())).collect();
+        // Index 0 of both is left empty, as unset
+        assert!(functions[0].is_empty() && locations[0].is_empty());
+
+        let sample_type = decode_message(field(&profile, PROFILE_SAMPLE_TYPE).bytes());
+        assert_eq!(strings[field(&sample_type, VALUE_TYPE_TYPE).varint() as usize], "samples");
+        assert_eq!(strings[field(&sample_type, VALUE_TYPE_UNIT).varint() as usize], "count");
+        assert!(field(&profile, PROFILE_TIME_NANOS).varint() > 0);
+
+        let location_indices = field(&profile, PROFILE_LOCATION_INDICES).packed();
+        let frame_name = |location: u64| {
+            let line = decode_message(field(&locations[location as usize], LOCATION_LINE).bytes());
+            let function = &functions[field(&line, LINE_FUNCTION_INDEX).varint() as usize];
+            let name = &strings[field(function, FUNCTION_NAME).varint() as usize];
+            assert_eq!(field(function, FUNCTION_SYSTEM_NAME), field(function, FUNCTION_NAME));
+            let file = &strings[field(function, FUNCTION_FILENAME).varint() as usize];
+            format!("{} {}:{}", name, file, field(&line, LINE_LINE).varint())
+        };
+        field_values(&profile, PROFILE_SAMPLE)
+            .iter()
+            .map(|sample| {
+                let sample = decode_message(sample.bytes());
+                let start = field(&sample, SAMPLE_LOCATIONS_START_INDEX).varint() as usize;
+                let length = field(&sample, SAMPLE_LOCATIONS_LENGTH).varint() as usize;
+                let frames = location_indices[start..start + length].iter().map(|&location| frame_name(location)).collect();
+                (frames, field(&sample, SAMPLE_VALUE).packed()[0])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn encodes_request() {
+        let mut request = Vec::new();
+        write_otlp(&profile(), None, &mut request).unwrap();
+        assert_eq!(
+            decode_samples(&request),
+            [(vec!["fib app.js:1".to_owned(), "main app.js:5".to_owned()], 2), (vec!["main app.js:5".to_owned()], 1)]
+        );
+
+        let request = decode_message(&request);
+        let resource_profiles = decode_message(field(&request, REQUEST_RESOURCE_PROFILES).bytes());
+        let scope_profiles = decode_message(field(&resource_profiles, RESOURCE_PROFILES_SCOPE_PROFILES).bytes());
+        let scope = decode_message(field(&scope_profiles, SCOPE_PROFILES_SCOPE).bytes());
+        assert_eq!(field(&scope, SCOPE_NAME).bytes(), b"v8spy");
+        assert_eq!(field(&scope, SCOPE_VERSION).bytes(), env!("CARGO_PKG_VERSION").as_bytes());
+    }
+
+    #[test]
+    fn encodes_empty_profile() {
+        let mut request = Vec::new();
+        write_otlp(&Profile::new(), None, &mut request).unwrap();
+        assert!(decode_samples(&request).is_empty());
+    }
+
+    /// Accepts one request on a local port, answering with `status`, and
+    /// returns its endpoint and the request as received
+    fn receiver(status: &'static str) -> (String, std::thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 4096];
+            // Read until the whole body named by Content-Length is in
+            loop {
+                let read = stream.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..read]);
+                let text = String::from_utf8_lossy(&received);
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length: usize = text.lines().find_map(|line| line.strip_prefix("Content-Length: ")).unwrap().parse().unwrap();
+                    if received.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+            }
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            received
+        });
+        (endpoint, handle)
+    }
+
+    #[test]
+    fn posts_request() {
+        let (endpoint, handle) = receiver("200 OK");
+        write_otlp(&profile(), Some(&endpoint), &mut std::io::sink()).unwrap();
+        let received = handle.join().unwrap();
+        let end = received.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&received[..end]).into_owned();
+        assert!(head.starts_with("POST /v1development/profiles HTTP/1.1\r\n"), "{}", head);
+        assert!(head.contains("Content-Type: application/x-protobuf"));
+        assert_eq!(decode_samples(&received[end + 4..]).len(), 2);
+    }
+
+    #[test]
+    fn reports_receiver_errors() {
+        let (endpoint, handle) = receiver("415 Unsupported Media Type");
+        let error = write_otlp(&profile(), Some(&format!("{}/custom", endpoint)), &mut std::io::sink()).unwrap_err();
+        let head = String::from_utf8_lossy(&handle.join().unwrap()).into_owned();
+        assert!(head.starts_with("POST /custom HTTP/1.1\r\n"), "{}", head);
+        assert_eq!(
+            format!("{:#}", error),
+            format!("Failed to send profile to {}/custom: Receiver responded with 'HTTP/1.1 415 Unsupported Media Type'", endpoint)
+        );
+
+        let error = write_otlp(&profile(), Some("https://localhost:4318"), &mut std::io::sink()).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to send profile to https://localhost:4318: Unsupported OTLP endpoint 'https://localhost:4318': only http:// is supported"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use super::proto::{encode_bytes_field, encode_packed_field, encode_varint_field, Tables};
+use crate::profile::Profile;
+
+// Field numbers from perftools.profiles.Profile (profile.proto)
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION: u32 = 4;
+const PROFILE_FUNCTION: u32 = 5;
+const PROFILE_STRING_TABLE: u32 = 6;
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+const SAMPLE_LOCATION_ID: u32 = 1;
+const SAMPLE_VALUE: u32 = 2;
+const LOCATION_ID: u32 = 1;
+const LOCATION_LINE: u32 = 4;
+const LINE_FUNCTION_ID: u32 = 1;
+const LINE_LINE: u32 = 2;
+const FUNCTION_ID: u32 = 1;
+const FUNCTION_NAME: u32 = 2;
+const FUNCTION_SYSTEM_NAME: u32 = 3;
+const FUNCTION_FILENAME: u32 = 4;
+
+/// Writes the profile as an uncompressed `perftools.profiles.Profile`
+/// protobuf, as read by `go tool pprof` and Pyroscope
+pub fn write_pprof(profile: &Profile, w: &mut impl Write) -> Result<()> {
+    let mut tables = Tables::new();
+
+    let mut out = Vec::new();
+    let mut sample_type = Vec::new();
+    encode_varint_field(&mut sample_type, VALUE_TYPE_TYPE, tables.strings.intern("samples"));
+    encode_varint_field(&mut sample_type, VALUE_TYPE_UNIT, tables.strings.intern("count"));
+    encode_bytes_field(&mut out, PROFILE_SAMPLE_TYPE, &sample_type);
+
+    for (stack, count) in &profile.stacks {
+        // pprof expects the leaf location first, which matches our stack order
+        let location_ids: Vec<u64> = stack.iter().map(|frame| tables.location(frame)).collect();
+        let mut sample = Vec::new();
+        encode_packed_field(&mut sample, SAMPLE_LOCATION_ID, &location_ids);
+        encode_packed_field(&mut sample, SAMPLE_VALUE, &[*count]);
+        encode_bytes_field(&mut out, PROFILE_SAMPLE, &sample);
+    }
+
+    for (index, location) in tables.locations.iter().enumerate() {
+        let mut line = Vec::new();
+        encode_varint_field(&mut line, LINE_FUNCTION_ID, location.function);
+        encode_varint_field(&mut line, LINE_LINE, location.line);
+
+        let mut encoded = Vec::new();
+        encode_varint_field(&mut encoded, LOCATION_ID, index as u64 + 1);
+        encode_bytes_field(&mut encoded, LOCATION_LINE, &line);
+        encode_bytes_field(&mut out, PROFILE_LOCATION, &encoded);
+    }
+    for (index, function) in tables.functions.iter().enumerate() {
+        let mut encoded = Vec::new();
+        encode_varint_field(&mut encoded, FUNCTION_ID, index as u64 + 1);
+        encode_varint_field(&mut encoded, FUNCTION_NAME, function.name);
+        encode_varint_field(&mut encoded, FUNCTION_SYSTEM_NAME, function.name);
+        encode_varint_field(&mut encoded, FUNCTION_FILENAME, function.filename);
+        encode_bytes_field(&mut out, PROFILE_FUNCTION, &encoded);
+    }
+    for string in &tables.strings.values {
+        encode_bytes_field(&mut out, PROFILE_STRING_TABLE, string.as_bytes());
+    }
+
+    w.write_all(&out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::proto::{decode_message, field_values, FieldValue};
+    use crate::stack_trace::{FrameKind, ResolvedFrame};
+
+    fn frame(name: &str, file: &str, line: u32) -> ResolvedFrame {
+        ResolvedFrame { file: Some(file.to_owned()), line: Some(line), ..ResolvedFrame::synthetic(name, FrameKind::Interpreted) }
+    }
+
+    fn field(fields: &[(u32, FieldValue)], number: u32) -> &FieldValue {
+        match field_values(fields, number)[..] {
+            [value] => value,
+            ref values => panic!("expected one field {}, got {}", number, values.len()),
+        }
+    }
+
+    #[test]
+    fn decodes_location_chain() {
+        let mut profile = Profile::new();
+        let main = frame("main", "app.js", 5);
+        profile.add_sample(vec![frame("fib", "app.js", 1), frame("fib", "app.js", 2), main.clone()]);
+        profile.add_sample(vec![ResolvedFrame::synthetic("<native>", FrameKind::Native), main]);
+        let mut out = Vec::new();
+        write_pprof(&profile, &mut out).unwrap();
+
+        let message = decode_message(&out);
+        let strings: Vec<String> = field_values(&message, PROFILE_STRING_TABLE).iter().map(|value| String::from_utf8(value.bytes().to_vec()).unwrap()).collect();
+        assert_eq!(strings[0], "");
+        let sample_type = decode_message(field(&message, PROFILE_SAMPLE_TYPE).bytes());
+        assert_eq!(strings[field(&sample_type, VALUE_TYPE_TYPE).varint() as usize], "samples");
+        assert_eq!(strings[field(&sample_type, VALUE_TYPE_UNIT).varint() as usize], "count");
+
+        let functions: Vec<Vec<(u32, FieldValue)>> = field_values(&message, PROFILE_FUNCTION).iter().map(|value| decode_message(value.bytes())).collect();
+        let locations: Vec<Vec<(u32, FieldValue)>> = field_values(&message, PROFILE_LOCATION).iter().map(|value| decode_message(value.bytes())).collect();
+        let frame_name = |id: u64| {
+            let location = locations.iter().find(|location| field(location, LOCATION_ID).varint() == id).unwrap();
+            let line = decode_message(field(location, LOCATION_LINE).bytes());
+            let function_id = field(&line, LINE_FUNCTION_ID).varint();
+            let function = functions.iter().find(|function| field(function, FUNCTION_ID).varint() == function_id).unwrap();
+            assert_eq!(field(function, FUNCTION_SYSTEM_NAME), field(function, FUNCTION_NAME));
+            let name = &strings[field(function, FUNCTION_NAME).varint() as usize];
+            let file = &strings[field(function, FUNCTION_FILENAME).varint() as usize];
+            format!("{} {}:{}", name, file, field(&line, LINE_LINE).varint())
+        };
+        // Two lines of fib are two locations of one function
+        assert_eq!((locations.len(), functions.len()), (4, 3));
+
+        let samples = field_values(&message, PROFILE_SAMPLE);
+        assert_eq!(samples.len(), 2);
+        let sample = decode_message(samples[1].bytes());
+        let chain: Vec<String> = field(&sample, SAMPLE_LOCATION_ID).packed().into_iter().map(frame_name).collect();
+        assert_eq!(chain, ["fib app.js:1", "fib app.js:2", "main app.js:5"]);
+        assert_eq!(field(&sample, SAMPLE_VALUE).packed(), [1]);
+        let sample = decode_message(samples[0].bytes());
+        let chain: Vec<String> = field(&sample, SAMPLE_LOCATION_ID).packed().into_iter().map(frame_name).collect();
+        assert_eq!(chain, ["<native> :0", "main app.js:5"]);
+    }
+}
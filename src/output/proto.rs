@@ -0,0 +1,269 @@
+//! Protobuf encoding and the string, function and location tables shared by
+//! the pprof and OTLP writers
+
+use std::collections::HashMap;
+
+use crate::stack_trace::ResolvedFrame;
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_LEN: u32 = 2;
+
+#[derive(Default)]
+pub(super) struct StringTable {
+    pub values: Vec<String>,
+    index: HashMap<String, u64>,
+}
+
+impl StringTable {
+    pub fn intern(&mut self, s: &str) -> u64 {
+        if let Some(&id) = self.index.get(s) {
+            return id;
+        }
+        let id = self.values.len() as u64;
+        self.values.push(s.to_owned());
+        self.index.insert(s.to_owned(), id);
+        id
+    }
+}
+
+/// A function, with its name and file as string table indices
+pub(super) struct Function {
+    pub name: u64,
+    pub filename: u64,
+}
+
+/// A line in a function. `function` is the function's id.
+pub(super) struct Location {
+    pub function: u64,
+    pub line: u64,
+}
+
+/// Functions and locations for the frames of a profile, deduplicated. Ids
+/// start at 1, so the function or location with id `n` is at index `n - 1`.
+pub(super) struct Tables {
+    pub strings: StringTable,
+    pub functions: Vec<Function>,
+    pub locations: Vec<Location>,
+    function_ids: HashMap<(String, Option<String>), u64>,
+    location_ids: HashMap<ResolvedFrame, u64>,
+}
+
+impl Tables {
+    pub fn new() -> Self {
+        let mut strings = StringTable::default();
+        // Both formats require the empty string at index 0
+        strings.intern("");
+        Tables {
+            strings,
+            functions: Vec::new(),
+            locations: Vec::new(),
+            function_ids: HashMap::new(),
+            location_ids: HashMap::new(),
+        }
+    }
+
+    fn function(&mut self, frame: &ResolvedFrame) -> u64 {
+        let key = (frame.name.clone(), frame.file.clone());
+        if let Some(&id) = self.function_ids.get(&key) {
+            return id;
+        }
+        let id = self.functions.len() as u64 + 1;
+        let name = self.strings.intern(&frame.name);
+        let filename = self.strings.intern(frame.file.as_deref().unwrap_or(""));
+        self.functions.push(Function { name, filename });
+        self.function_ids.insert(key, id);
+        id
+    }
+
+    /// Returns the id of the location for `frame`, adding it if it's new
+    pub fn location(&mut self, frame: &ResolvedFrame) -> u64 {
+        if let Some(&id) = self.location_ids.get(frame) {
+            return id;
+        }
+        let id = self.locations.len() as u64 + 1;
+        let function = self.function(frame);
+        self.locations.push(Location { function, line: frame.line.unwrap_or(0) as u64 });
+        self.location_ids.insert(frame.clone(), id);
+        id
+    }
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn encode_key(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    encode_varint(buf, ((field << 3) | wire_type) as u64);
+}
+
+pub(super) fn encode_varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    encode_key(buf, field, WIRE_VARINT);
+    encode_varint(buf, value);
+}
+
+pub(super) fn encode_bytes_field(buf: &mut Vec<u8>, field: u32, value: &[u8]) {
+    encode_key(buf, field, WIRE_LEN);
+    encode_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+pub(super) fn encode_packed_field(buf: &mut Vec<u8>, field: u32, values: &[u64]) {
+    let mut packed = Vec::new();
+    for &value in values {
+        encode_varint(&mut packed, value);
+    }
+    encode_bytes_field(buf, field, &packed);
+}
+
+/// A field of a decoded message, for checking what the writers encode
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum FieldValue {
+    Varint(u64),
+    Bytes(Vec<u8>),
+}
+
+#[cfg(test)]
+impl FieldValue {
+    pub fn varint(&self) -> u64 {
+        match self {
+            FieldValue::Varint(value) => *value,
+            FieldValue::Bytes(_) => panic!("expected a varint, got {:?}", self),
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            FieldValue::Bytes(bytes) => bytes,
+            FieldValue::Varint(_) => panic!("expected bytes, got {:?}", self),
+        }
+    }
+
+    /// The varints of a packed field
+    pub fn packed(&self) -> Vec<u64> {
+        let bytes = self.bytes();
+        let mut index = 0;
+        let mut values = Vec::new();
+        while index < bytes.len() {
+            values.push(decode_varint(bytes, &mut index));
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+fn decode_varint(buf: &[u8], index: &mut usize) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[*index];
+        *index += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// Decodes the fields of a message in the order they were written. Panics on
+/// anything the writers don't encode.
+#[cfg(test)]
+pub(super) fn decode_message(buf: &[u8]) -> Vec<(u32, FieldValue)> {
+    let mut index = 0;
+    let mut fields = Vec::new();
+    while index < buf.len() {
+        let key = decode_varint(buf, &mut index);
+        let field = (key >> 3) as u32;
+        let value = match (key & 7) as u32 {
+            WIRE_VARINT => FieldValue::Varint(decode_varint(buf, &mut index)),
+            WIRE_LEN => {
+                let length = decode_varint(buf, &mut index) as usize;
+                index += length;
+                FieldValue::Bytes(buf[index - length..index].to_vec())
+            }
+            wire_type => panic!("unexpected wire type {}", wire_type),
+        };
+        fields.push((field, value));
+    }
+    fields
+}
+
+/// The values of one field of a message, for repeated fields
+#[cfg(test)]
+pub(super) fn field_values(fields: &[(u32, FieldValue)], field: u32) -> Vec<&FieldValue> {
+    fields.iter().filter(|(number, _)| *number == field).map(|(_, value)| value).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack_trace::FrameKind;
+
+    fn frame(name: &str, file: &str, line: u32) -> ResolvedFrame {
+        ResolvedFrame { file: Some(file.to_owned()), line: Some(line), ..ResolvedFrame::synthetic(name, FrameKind::Interpreted) }
+    }
+
+    #[test]
+    fn encodes_varints() {
+        let encoded = |value| {
+            let mut buf = Vec::new();
+            encode_varint(&mut buf, value);
+            buf
+        };
+        assert_eq!(encoded(0), [0]);
+        assert_eq!(encoded(127), [0x7f]);
+        assert_eq!(encoded(128), [0x80, 0x01]);
+        assert_eq!(encoded(300), [0xac, 0x02]);
+        assert_eq!(encoded(u64::MAX), [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        for value in [0, 1, 127, 128, 300, 1 << 35, u64::MAX] {
+            assert_eq!(decode_varint(&encoded(value), &mut 0), value);
+        }
+    }
+
+    #[test]
+    fn encodes_fields() {
+        let mut buf = Vec::new();
+        encode_varint_field(&mut buf, 1, 150);
+        encode_bytes_field(&mut buf, 2, b"testing");
+        encode_packed_field(&mut buf, 4, &[3, 270, 86942]);
+        encode_bytes_field(&mut buf, 16, &[]);
+        // As in the examples of the protobuf encoding's documentation
+        assert_eq!(buf[..3], [0x08, 0x96, 0x01]);
+        assert_eq!(buf[3..12], [0x12, 0x07, b't', b'e', b's', b't', b'i', b'n', b'g']);
+        assert_eq!(buf[12..20], [0x22, 0x06, 0x03, 0x8e, 0x02, 0x9e, 0xa7, 0x05]);
+        assert_eq!(buf[20..], [0x82, 0x01, 0x00]);
+
+        let fields = decode_message(&buf);
+        assert_eq!(fields[0], (1, FieldValue::Varint(150)));
+        assert_eq!(fields[1], (2, FieldValue::Bytes(b"testing".to_vec())));
+        assert_eq!(fields[2].1.packed(), [3, 270, 86942]);
+        assert_eq!(fields[3], (16, FieldValue::Bytes(Vec::new())));
+    }
+
+    #[test]
+    fn dedupes_tables() {
+        let mut tables = Tables::new();
+        assert_eq!(tables.strings.values, [""]);
+        let fib = frame("fib", "app.js", 1);
+        let fib_line_2 = frame("fib", "app.js", 2);
+        let main = frame("main", "app.js", 5);
+        let native = ResolvedFrame::synthetic("<native>", FrameKind::Native);
+        assert_eq!(tables.location(&fib), 1);
+        assert_eq!(tables.location(&main), 2);
+        assert_eq!(tables.location(&fib), 1);
+        // Another line of the same function is another location of it
+        assert_eq!(tables.location(&fib_line_2), 3);
+        assert_eq!(tables.location(&native), 4);
+
+        assert_eq!(tables.strings.values, ["", "fib", "app.js", "main", "<native>"]);
+        let functions: Vec<(u64, u64)> = tables.functions.iter().map(|function| (function.name, function.filename)).collect();
+        assert_eq!(functions, [(1, 2), (3, 2), (4, 0)]);
+        let locations: Vec<(u64, u64)> = tables.locations.iter().map(|location| (location.function, location.line)).collect();
+        assert_eq!(locations, [(1, 1), (2, 5), (1, 2), (3, 0)]);
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::profile::Profile;
+use crate::stack_trace::{FrameFormat, FrameKind};
+
+const IMAGE_WIDTH: f64 = 1200.0;
+const FRAME_HEIGHT: f64 = 16.0;
+const MARGIN: f64 = 10.0;
+const TITLE_HEIGHT: f64 = 30.0;
+const FONT_SIZE: f64 = 12.0;
+/// Approximate width of a character at `FONT_SIZE`, for fitting labels
+const CHAR_WIDTH: f64 = 7.0;
+/// Frames narrower than this many pixels are left out of the image
+const MIN_WIDTH: f64 = 0.1;
+
+/// One frame of the flame graph: `samples` wide, starting `start` samples
+/// from the left edge, `depth` frames above the root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rect {
+    /// The frame as written in the folded output
+    pub label: String,
+    pub kind: FrameKind,
+    pub depth: usize,
+    pub start: u64,
+    pub samples: u64,
+}
+
+#[derive(Default)]
+struct Node {
+    kind: FrameKind,
+    samples: u64,
+    children: BTreeMap<String, Node>,
+}
+
+/// Lays out the flame graph of a profile. Stacks are merged from the root
+/// frame up, as in the folded output, and siblings are sorted by label so
+/// the layout doesn't depend on sampling order. The rectangles at each depth
+/// never overlap, and those at depth 0 add up to the total sample count.
+/// Frames are labeled as `frames` says.
+pub fn layout(profile: &Profile, frames: &FrameFormat) -> Vec<Rect> {
+    let mut root = Node::default();
+    for (stack, &count) in &profile.stacks {
+        root.samples += count;
+        let mut node = &mut root;
+        for frame in stack.iter().rev() {
+            node = node.children.entry(frames.render(frame)).or_insert_with(|| Node { kind: frame.kind, ..Node::default() });
+            node.samples += count;
+        }
+    }
+    let mut rects = Vec::new();
+    layout_children(&root, 0, 0, &mut rects);
+    rects
+}
+
+fn layout_children(node: &Node, depth: usize, start: u64, rects: &mut Vec<Rect>) {
+    let mut start = start;
+    for (label, child) in &node.children {
+        rects.push(Rect { label: label.clone(), kind: child.kind, depth, start, samples: child.samples });
+        layout_children(child, depth + 1, start, rects);
+        start += child.samples;
+    }
+}
+
+/// Writes the profile as a self-contained flame graph SVG, with the root
+/// frames at the bottom. Hovering over a frame shows it along with its
+/// share of the samples.
+pub fn write_flamegraph(profile: &Profile, frames: &FrameFormat, w: &mut impl Write) -> io::Result<()> {
+    let rects = layout(profile, frames);
+    let total: u64 = profile.stacks.values().sum();
+    let max_depth = rects.iter().map(|rect| rect.depth + 1).max().unwrap_or(0);
+    let height = TITLE_HEIGHT + max_depth as f64 * FRAME_HEIGHT + 2.0 * MARGIN;
+    let scale = if total == 0 { 0.0 } else { (IMAGE_WIDTH - 2.0 * MARGIN) / total as f64 };
+
+    writeln!(w, r#"<?xml version="1.0" standalone="no"?>"#)?;
+    writeln!(
+        w,
+        r#"<svg version="1.1" width="{}" height="{}" viewBox="0 0 {} {}" xmlns="http://www.w3.org/2000/svg">"#,
+        IMAGE_WIDTH, height, IMAGE_WIDTH, height
+    )?;
+    writeln!(w, r##"<rect x="0" y="0" width="100%" height="100%" fill="#f8f8f8"/>"##)?;
+    writeln!(
+        w,
+        r#"<text x="{}" y="{}" font-family="Verdana" font-size="17" text-anchor="middle">Flame Graph ({} samples)</text>"#,
+        IMAGE_WIDTH / 2.0,
+        TITLE_HEIGHT - MARGIN,
+        total
+    )?;
+    for rect in &rects {
+        let width = rect.samples as f64 * scale;
+        if width < MIN_WIDTH {
+            continue;
+        }
+        let x = MARGIN + rect.start as f64 * scale;
+        let y = height - MARGIN - (rect.depth + 1) as f64 * FRAME_HEIGHT;
+        let percent = 100.0 * rect.samples as f64 / total as f64;
+        writeln!(w, "<g>")?;
+        writeln!(
+            w,
+            "<title>{} ({} samples, {:.2}%)</title>",
+            escape(&rect.label),
+            rect.samples,
+            percent
+        )?;
+        writeln!(
+            w,
+            r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}" rx="2" ry="2"/>"#,
+            x,
+            y,
+            width,
+            FRAME_HEIGHT - 1.0,
+            color(rect)
+        )?;
+        if let Some(text) = fit_label(&rect.label, width) {
+            writeln!(
+                w,
+                r#"<text x="{:.1}" y="{:.1}" font-family="Verdana" font-size="{}">{}</text>"#,
+                x + 3.0,
+                y + FRAME_HEIGHT - 4.0,
+                FONT_SIZE,
+                escape(&text)
+            )?;
+        }
+        writeln!(w, "</g>")?;
+    }
+    writeln!(w, "</svg>")?;
+    Ok(())
+}
+
+/// Warm colors for JavaScript, yellows for builtins and stubs, and blues for
+/// everything else. The shade comes from the label, so a function keeps its
+/// color from one profile to the next.
+fn color(rect: &Rect) -> String {
+    let shade = rect.label.bytes().fold(0u32, |hash, b| hash.wrapping_mul(31).wrapping_add(b as u32)) % 64;
+    match rect.kind {
+        kind if kind.is_js() => format!("rgb({},{},{})", 205 + shade % 50, 80 + shade, 40),
+        FrameKind::Builtin | FrameKind::Stub => format!("rgb({},{},{})", 190 + shade % 50, 190 + shade % 50, 55),
+        _ => format!("rgb({},{},{})", 80 + shade, 140 + shade, 200 + shade % 50),
+    }
+}
+
+/// Shortens a label to fit in `width` pixels, or `None` if not even a few
+/// characters would fit
+fn fit_label(label: &str, width: f64) -> Option<String> {
+    let fits = ((width - 6.0) / CHAR_WIDTH) as usize;
+    if fits < 3 {
+        return None;
+    }
+    if label.chars().count() <= fits {
+        return Some(label.to_owned());
+    }
+    let mut text: String = label.chars().take(fits - 2).collect();
+    text.push_str("..");
+    Some(text)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack_trace::ResolvedFrame;
+
+    /// The x, y and width of each frame's rectangle in the SVG, root frames
+    /// at the bottom
+    fn rects(svg: &str) -> Vec<(f64, f64, f64)> {
+        let attribute = |line: &str, name: &str| -> f64 {
+            let start = line.find(&format!(" {}=\"", name)).unwrap() + name.len() + 3;
+            line[start..start + line[start..].find('"').unwrap()].parse().unwrap()
+        };
+        svg.lines()
+            .filter(|line| line.starts_with("<rect") && line.contains("rx="))
+            .map(|line| (attribute(line, "x"), attribute(line, "y"), attribute(line, "width")))
+            .collect()
+    }
+
+    #[test]
+    fn children_fill_their_parent() {
+        let stack = |names: &[&str]| names.iter().map(|name| ResolvedFrame::synthetic(name, FrameKind::Interpreted)).collect();
+        let mut profile = Profile::new();
+        // Every stack ends in a leaf, so no samples are a frame's own
+        profile.stacks.insert(stack(&["parse", "handle", "main"]), 3);
+        profile.stacks.insert(stack(&["write", "handle", "main"]), 2);
+        profile.stacks.insert(stack(&["tick", "main"]), 4);
+        profile.stacks.insert(stack(&["gc"]), 1);
+        let mut svg = Vec::new();
+        write_flamegraph(&profile, &FrameFormat::default(), &mut svg).unwrap();
+        let rects = rects(&String::from_utf8(svg).unwrap());
+        assert_eq!(rects.len(), 6);
+
+        let roots: f64 = rects.iter().filter(|rect| rect.1 == rects[0].1).map(|rect| rect.2).sum();
+        assert!((roots - (IMAGE_WIDTH - 2.0 * MARGIN)).abs() < 0.2, "{}", roots);
+        for &(x, y, width) in &rects {
+            let children: Vec<f64> = rects
+                .iter()
+                .filter(|child| child.1 == y - FRAME_HEIGHT && child.0 >= x && child.0 < x + width)
+                .map(|child| child.2)
+                .collect();
+            if !children.is_empty() {
+                // Each width is rounded to a tenth of a pixel
+                let sum: f64 = children.iter().sum();
+                assert!((sum - width).abs() <= 0.05 * (children.len() + 1) as f64, "{:?} under {}", children, width);
+            }
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use crate::output::json_string;
+use crate::stack_trace::{NameFilter, ResolvedFrame};
+use crate::v8_spy::SampleStats;
+
+/// Aggregated samples collected from a process
+#[derive(Debug, Default)]
+pub struct Profile {
+    /// Sample counts keyed by stack, innermost frame first
+    pub stacks: BTreeMap<Vec<ResolvedFrame>, u64>,
+}
+
+/// Sample counts for one function, aggregated over every stack it appears in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionStat {
+    pub name: String,
+    pub file: Option<String>,
+    /// The line the function was at in the most samples, if lines are known
+    pub line: Option<u32>,
+    /// Samples where the function was the innermost frame
+    pub self_samples: u64,
+    /// Samples where the function was anywhere on the stack
+    pub total_samples: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_sample(&mut self, stack: Vec<ResolvedFrame>) {
+        *self.stacks.entry(stack).or_insert(0) += 1;
+    }
+
+    /// The profile with `filter` applied to each of its stacks. Stacks that
+    /// the filter leaves the same add up, and those left with no frames are
+    /// dropped.
+    pub fn filtered(&self, filter: &NameFilter) -> Profile {
+        let mut profile = Profile::new();
+        for (stack, &count) in &self.stacks {
+            let stack = filter.apply(stack);
+            if !stack.is_empty() {
+                *profile.stacks.entry(stack).or_insert(0) += count;
+            }
+        }
+        profile
+    }
+
+    /// Adds the samples of another profile to this one
+    pub fn merge(&mut self, other: Profile) {
+        for (stack, count) in other.stacks {
+            *self.stacks.entry(stack).or_insert(0) += count;
+        }
+    }
+
+    /// Aggregates the samples by (function name, file), sorted by self
+    /// samples and then total samples, descending, and ties by name and file
+    pub fn function_stats(&self) -> Vec<FunctionStat> {
+        let mut stats: HashMap<(&str, Option<&str>), FunctionStat> = HashMap::new();
+        let mut lines: HashMap<(&str, Option<&str>), HashMap<u32, u64>> = HashMap::new();
+        for (stack, &count) in &self.stacks {
+            // Recursive functions only count once towards the total of a sample
+            let mut seen = HashSet::new();
+            for (depth, frame) in stack.iter().enumerate() {
+                let key = (frame.name.as_str(), frame.file.as_deref());
+                let stat = stats.entry(key).or_insert_with(|| FunctionStat {
+                    name: frame.name.clone(),
+                    file: frame.file.clone(),
+                    line: None,
+                    self_samples: 0,
+                    total_samples: 0,
+                });
+                if depth == 0 {
+                    stat.self_samples += count;
+                }
+                if seen.insert(key) {
+                    stat.total_samples += count;
+                    if let Some(line) = frame.line {
+                        *lines.entry(key).or_default().entry(line).or_insert(0) += count;
+                    }
+                }
+            }
+        }
+        for (key, stat) in &mut stats {
+            // Ties go to the first line, so the pick doesn't depend on hashing
+            stat.line = lines
+                .get(key)
+                .and_then(|lines| lines.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))))
+                .map(|(&line, _)| line);
+        }
+        let mut stats: Vec<FunctionStat> = stats.into_values().collect();
+        stats.sort_by(|a, b| {
+            b.self_samples
+                .cmp(&a.self_samples)
+                .then(b.total_samples.cmp(&a.total_samples))
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.file.cmp(&b.file))
+        });
+        stats
+    }
+
+    /// Summarizes the profile as one line of JSON, for dashboards: the
+    /// sample, stack and function counts, how reliable the samples were as
+    /// `stats` tells, and the `top_n` functions as ordered by
+    /// `function_stats`, for example
+    /// `{"samples":100,"stacks":3,"functions":2,"unwind_errors":0,"truncated":0,"idle":0,"torn":1,"read_timeouts":0,"achieved_rate":99.5,"top":[{"name":"fib","file":"app.js","line":1,"self_samples":90,"total_samples":100}]}`.
+    /// Unknown files and lines are `null`.
+    pub fn summary_json(&self, top_n: usize, stats: &SampleStats) -> String {
+        let functions = self.function_stats();
+        let samples: u64 = self.stacks.values().sum();
+        let top: Vec<String> = functions
+            .iter()
+            .take(top_n)
+            .map(|stat| {
+                format!(
+                    "{{\"name\":{},\"file\":{},\"line\":{},\"self_samples\":{},\"total_samples\":{}}}",
+                    json_string(&stat.name),
+                    stat.file.as_deref().map_or("null".to_owned(), json_string),
+                    stat.line.map_or("null".to_owned(), |line| line.to_string()),
+                    stat.self_samples,
+                    stat.total_samples
+                )
+            })
+            .collect();
+        format!(
+            concat!(
+                "{{\"samples\":{},\"stacks\":{},\"functions\":{},",
+                "\"unwind_errors\":{},\"truncated\":{},\"idle\":{},\"torn\":{},\"read_timeouts\":{},\"achieved_rate\":{:.1},",
+                "\"top\":[{}]}}"
+            ),
+            samples,
+            self.stacks.len(),
+            functions.len(),
+            stats.unwind_errors,
+            stats.truncated,
+            stats.idle,
+            stats.torn,
+            stats.read_timeouts,
+            stats.achieved_rate(),
+            top.join(",")
+        )
+    }
+}
+
+/// A profile of the most recent samples, up to a fixed number of them, for
+/// runs that would otherwise grow without bound. Once full, each new sample
+/// evicts the oldest, whose stack is counted down in the profile and
+/// removed once no sample is left of it, so the counts are always those of
+/// the samples held. Only how many were evicted is kept of them.
+#[derive(Debug)]
+pub struct SampleRing {
+    capacity: usize,
+    /// The stacks held, oldest first
+    samples: VecDeque<Vec<ResolvedFrame>>,
+    profile: Profile,
+    evicted: u64,
+}
+
+impl SampleRing {
+    pub fn new(capacity: usize) -> Self {
+        SampleRing { capacity, samples: VecDeque::new(), profile: Profile::new(), evicted: 0 }
+    }
+
+    /// Adds a sample, first evicting the oldest if the ring is full
+    pub fn add_sample(&mut self, stack: Vec<ResolvedFrame>) {
+        if self.capacity == 0 {
+            self.evicted += 1;
+            return;
+        }
+        if self.is_full() {
+            if let Some(oldest) = self.samples.pop_front() {
+                if let Some(count) = self.profile.stacks.get_mut(&oldest) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.profile.stacks.remove(&oldest);
+                    }
+                }
+                self.evicted += 1;
+            }
+        }
+        self.profile.add_sample(stack.clone());
+        self.samples.push_back(stack);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.samples.len() >= self.capacity
+    }
+
+    /// How many samples have been evicted to make room for newer ones
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// The samples held, aggregated
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn into_profile(self) -> Profile {
+        self.profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack_trace::FrameKind;
+
+    fn stack(names: &[&str]) -> Vec<ResolvedFrame> {
+        names.iter().map(|name| ResolvedFrame::synthetic(name, FrameKind::Interpreted)).collect()
+    }
+
+    fn frame(name: &str, file: &str, line: u32) -> ResolvedFrame {
+        ResolvedFrame { file: Some(file.to_owned()), line: Some(line), ..ResolvedFrame::synthetic(name, FrameKind::Interpreted) }
+    }
+
+    fn counts(profile: &Profile) -> Vec<(String, u64)> {
+        profile
+            .stacks
+            .iter()
+            .map(|(stack, &count)| (stack.iter().map(|frame| frame.name.as_str()).collect::<Vec<_>>().join(";"), count))
+            .collect()
+    }
+
+    #[test]
+    fn ring_evicts_oldest_samples() {
+        let mut ring = SampleRing::new(3);
+        assert!(ring.is_empty());
+        ring.add_sample(stack(&["a", "main"]));
+        ring.add_sample(stack(&["b", "main"]));
+        ring.add_sample(stack(&["a", "main"]));
+        assert!(ring.is_full());
+        assert_eq!(counts(ring.profile()), [("a;main".to_owned(), 2), ("b;main".to_owned(), 1)]);
+
+        // The first a goes, so a is counted down rather than dropped
+        ring.add_sample(stack(&["c", "main"]));
+        assert_eq!((ring.len(), ring.evicted()), (3, 1));
+        assert_eq!(counts(ring.profile()), [("a;main".to_owned(), 1), ("b;main".to_owned(), 1), ("c;main".to_owned(), 1)]);
+        // Then b, the last of its stack, is removed from the profile
+        ring.add_sample(stack(&["c", "main"]));
+        assert_eq!((ring.len(), ring.evicted()), (3, 2));
+        assert_eq!(counts(ring.profile()), [("a;main".to_owned(), 1), ("c;main".to_owned(), 2)]);
+        // The counts always add up to the samples held
+        for i in 0..10 {
+            ring.add_sample(stack(&[if i % 2 == 0 { "d" } else { "e" }]));
+            assert_eq!(ring.profile().stacks.values().sum::<u64>(), ring.len() as u64);
+        }
+        assert_eq!(ring.evicted(), 12);
+        assert_eq!(counts(&ring.into_profile()), [("d".to_owned(), 1), ("e".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn ring_without_room() {
+        let mut ring = SampleRing::new(0);
+        ring.add_sample(stack(&["a"]));
+        ring.add_sample(stack(&["b"]));
+        assert!(ring.is_empty() && ring.is_full());
+        assert_eq!(ring.evicted(), 2);
+        assert!(ring.profile().stacks.is_empty());
+    }
+
+    #[test]
+    fn function_stats_count_self_and_total_samples() {
+        let mut profile = Profile::new();
+        for _ in 0..3 {
+            profile.add_sample(vec![frame("fib", "app.js", 2), frame("fib", "app.js", 4), frame("main", "app.js", 10)]);
+        }
+        profile.add_sample(vec![frame("fib", "app.js", 4), frame("main", "app.js", 10)]);
+        profile.add_sample(vec![frame("main", "app.js", 11)]);
+        // The same name in another file is another function
+        profile.add_sample(vec![frame("fib", "lib.js", 1), frame("main", "app.js", 10)]);
+
+        let stats = profile.function_stats();
+        let row = |name: &str, file: &str| stats.iter().find(|stat| stat.name == name && stat.file.as_deref() == Some(file)).unwrap();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].name, "fib");
+        // The recursive fib counts once towards each sample's total, at the
+        // line it's at in the most of them
+        let fib = row("fib", "app.js");
+        assert_eq!((fib.self_samples, fib.total_samples, fib.line), (4, 4, Some(2)));
+        let main = row("main", "app.js");
+        assert_eq!((main.self_samples, main.total_samples, main.line), (1, 6, Some(10)));
+        let lib = row("fib", "lib.js");
+        assert_eq!((lib.self_samples, lib.total_samples), (1, 1));
+        assert!(Profile::new().function_stats().is_empty());
+
+        // Ties are in order of file too, however they were hashed
+        let mut tied = Profile::new();
+        for file in ["b.js", "c.js", "a.js"] {
+            tied.add_sample(vec![frame("f", file, 1)]);
+        }
+        let files: Vec<_> = tied.function_stats().into_iter().map(|stat| stat.file.unwrap()).collect();
+        assert_eq!(files, ["a.js", "b.js", "c.js"]);
+    }
+
+    #[test]
+    fn summarizes_as_json() {
+        let mut profile = Profile::new();
+        profile.add_sample(vec![frame("fib", "app.js", 1), frame("main", "app.js", 5)]);
+        profile.add_sample(vec![frame("fib", "app.js", 1), frame("main", "app.js", 5)]);
+        profile.add_sample(stack(&["(gc)"]));
+        let stats = SampleStats {
+            total: 8,
+            unwind_errors: 1,
+            truncated: 2,
+            idle: 3,
+            torn: 1,
+            read_timeouts: 1,
+            rounds: 199,
+            sampling_time: std::time::Duration::from_secs(2),
+            requested_rate: 100,
+        };
+        assert_eq!(
+            profile.summary_json(2, &stats),
+            concat!(
+                r#"{"samples":3,"stacks":2,"functions":3,"#,
+                r#""unwind_errors":1,"truncated":2,"idle":3,"torn":1,"read_timeouts":1,"achieved_rate":99.5,"top":["#,
+                r#"{"name":"fib","file":"app.js","line":1,"self_samples":2,"total_samples":2},"#,
+                r#"{"name":"(gc)","file":null,"line":null,"self_samples":1,"total_samples":1}]}"#
+            )
+        );
+        assert_eq!(
+            Profile::new().summary_json(10, &SampleStats::default()),
+            r#"{"samples":0,"stacks":0,"functions":0,"unwind_errors":0,"truncated":0,"idle":0,"torn":0,"read_timeouts":0,"achieved_rate":0.0,"top":[]}"#
+        );
+    }
+}
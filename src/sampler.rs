@@ -0,0 +1,301 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use remoteprocess::Pid;
+
+use crate::profile::Profile;
+use crate::v8_spy::{Deadlines, SampleStats, V8Spy, V8SpyOptions};
+
+/// How often `sample_all` collects the samples of each sampler
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Samples a process on a thread of its own, sending back the stacks found
+/// by each sample as a `Profile` that can be merged into a running total.
+/// Sampling stops when the process exits, when `stop` is called, or when
+/// the handle is dropped.
+pub struct SamplerHandle {
+    running: Arc<AtomicBool>,
+    samples: Receiver<Profile>,
+    thread: Option<JoinHandle<Result<SampleStats>>>,
+}
+
+impl SamplerHandle {
+    /// Attaches to `pid` and starts sampling at `rate` per second. `setup`
+    /// runs on the sampling thread before the first sample, to set filters
+    /// or enable native symbols on the spy. Fails if attaching or `setup`
+    /// does.
+    pub fn spawn(pid: Pid, rate: u32, setup: impl FnOnce(&mut V8Spy) -> Result<()> + Send + 'static) -> Result<Self> {
+        Self::spawn_with_options(pid, V8SpyOptions::default(), rate, setup)
+    }
+
+    /// Like `spawn`, with options for how to attach
+    pub fn spawn_with_options(
+        pid: Pid,
+        options: V8SpyOptions,
+        rate: u32,
+        setup: impl FnOnce(&mut V8Spy) -> Result<()> + Send + 'static,
+    ) -> Result<Self> {
+        spawn_sampler(pid, rate, move || {
+            let mut spy = V8Spy::with_options(pid, options)?;
+            setup(&mut spy)?;
+            Ok(spy)
+        })
+    }
+
+    /// The stacks found by each sample, in the order they were taken. The
+    /// channel disconnects once sampling stops.
+    pub fn samples(&self) -> &Receiver<Profile> {
+        &self.samples
+    }
+
+    /// Stops sampling, and returns the stats of the run or the error that
+    /// ended it early
+    pub fn stop(mut self) -> Result<SampleStats> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<SampleStats> {
+        self.running.store(false, Ordering::SeqCst);
+        match self.thread.take() {
+            Some(thread) => thread.join().map_err(|_| anyhow::anyhow!("Sampling thread panicked"))?,
+            None => Ok(SampleStats::default()),
+        }
+    }
+}
+
+/// What the sampling thread samples: a spy, or a stand-in for one
+trait Sampler {
+    fn sample(&self, profile: &mut Profile) -> Result<()>;
+
+    /// Whether the process has exited, as a failed sample may be from
+    fn has_exited(&self) -> bool;
+
+    fn stats(&self) -> SampleStats;
+}
+
+impl Sampler for V8Spy {
+    fn sample(&self, profile: &mut Profile) -> Result<()> {
+        V8Spy::sample(self, profile)
+    }
+
+    fn has_exited(&self) -> bool {
+        V8Spy::has_exited(self)
+    }
+
+    fn stats(&self) -> SampleStats {
+        V8Spy::stats(self)
+    }
+}
+
+/// Starts a thread sampling whatever `attach` creates on it, at `rate` per
+/// second, once `attach` has succeeded
+fn spawn_sampler<S: Sampler>(pid: Pid, rate: u32, attach: impl FnOnce() -> Result<S> + Send + 'static) -> Result<SamplerHandle> {
+    let (sender, samples) = mpsc::channel();
+    let (attached_sender, attached) = mpsc::channel();
+    let running = Arc::new(AtomicBool::new(true));
+    let thread_running = running.clone();
+
+    let thread = std::thread::spawn(move || -> Result<SampleStats> {
+        let sampler = match attach() {
+            Ok(sampler) => {
+                let _ = attached_sender.send(Ok(()));
+                sampler
+            }
+            Err(e) => {
+                let _ = attached_sender.send(Err(e));
+                return Ok(SampleStats::default());
+            }
+        };
+
+        let mut deadlines = Deadlines::new(rate);
+        while thread_running.load(Ordering::SeqCst) {
+            let mut profile = Profile::new();
+            if let Err(e) = sampler.sample(&mut profile) {
+                if sampler.has_exited() {
+                    // The process exited underneath us
+                    break;
+                }
+                return Err(e);
+            }
+            if sender.send(profile).is_err() {
+                // Nobody is listening anymore
+                break;
+            }
+            deadlines.wait();
+        }
+        Ok(sampler.stats())
+    });
+
+    match attached.recv() {
+        Ok(Ok(())) => Ok(SamplerHandle { running, samples, thread: Some(thread) }),
+        Ok(Err(e)) => Err(e),
+        Err(_) => anyhow::bail!("Sampling thread exited before attaching to process {}", pid),
+    }
+}
+
+/// Collects the samples of several samplers at once into a profile for each,
+/// until `duration` has passed, `running` is cleared, or every one of them
+/// has stopped. A sampler that stops by itself, as when its process exits,
+/// stops contributing and the rest carry on. `on_stop` is called as each
+/// sampler stops with its pid, whether it stopped by itself, and the stats
+/// of its run or the error that ended it. Returns the profiles in the order
+/// the samplers were given.
+pub fn sample_all(
+    samplers: Vec<(Pid, SamplerHandle)>,
+    running: &AtomicBool,
+    duration: Option<Duration>,
+    mut on_stop: impl FnMut(Pid, bool, Result<SampleStats>),
+) -> Vec<(Pid, Profile)> {
+    let mut samplers: Vec<_> = samplers.into_iter().map(|(pid, handle)| (pid, Some(handle), Profile::new())).collect();
+    let start = Instant::now();
+    while running.load(Ordering::SeqCst) && duration.is_none_or(|duration| start.elapsed() < duration) {
+        let mut any_running = false;
+        for (pid, sampler, profile) in &mut samplers {
+            let Some(handle) = sampler else {
+                continue;
+            };
+            loop {
+                match handle.samples().try_recv() {
+                    Ok(sample) => profile.merge(sample),
+                    Err(TryRecvError::Empty) => {
+                        any_running = true;
+                        break;
+                    }
+                    Err(TryRecvError::Disconnected) => {
+                        on_stop(*pid, true, sampler.take().expect("the sampler is running").stop());
+                        break;
+                    }
+                }
+            }
+        }
+        if !any_running {
+            break;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    samplers
+        .into_iter()
+        .map(|(pid, sampler, mut profile)| {
+            if let Some(sampler) = sampler {
+                while let Ok(sample) = sampler.samples().try_recv() {
+                    profile.merge(sample);
+                }
+                on_stop(pid, false, sampler.stop());
+            }
+            (pid, profile)
+        })
+        .collect()
+}
+
+impl Drop for SamplerHandle {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::stack_trace::{FrameKind, ResolvedFrame};
+
+    /// Adds one stack per sample, until it's taken `exit_after`, when it
+    /// fails as if the process had exited. Sets `dropped` when dropped,
+    /// which is once the sampling thread is done with it.
+    struct MockSampler {
+        samples: Cell<u64>,
+        exit_after: Option<u64>,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl Sampler for MockSampler {
+        fn sample(&self, profile: &mut Profile) -> Result<()> {
+            if self.has_exited() {
+                anyhow::bail!("No such process");
+            }
+            self.samples.set(self.samples.get() + 1);
+            profile.add_sample(vec![ResolvedFrame::synthetic("main", FrameKind::Interpreted)]);
+            Ok(())
+        }
+
+        fn has_exited(&self) -> bool {
+            self.exit_after.is_some_and(|samples| self.samples.get() >= samples)
+        }
+
+        fn stats(&self) -> SampleStats {
+            SampleStats { total: self.samples.get(), ..SampleStats::default() }
+        }
+    }
+
+    impl Drop for MockSampler {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn spawn_mock(exit_after: Option<u64>) -> (SamplerHandle, Arc<AtomicBool>) {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let thread_dropped = dropped.clone();
+        let attach = move || Ok(MockSampler { samples: Cell::new(0), exit_after, dropped: thread_dropped });
+        (spawn_sampler(1, 1000, attach).unwrap(), dropped)
+    }
+
+    #[test]
+    fn sends_samples() {
+        let (handle, _) = spawn_mock(None);
+        let profile = handle.samples().recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(profile.stacks.values().sum::<u64>(), 1);
+        assert_eq!(profile.stacks.keys().next().unwrap()[0].name, "main");
+        assert!(handle.stop().unwrap().total >= 1);
+    }
+
+    #[test]
+    fn stops_when_process_exits() {
+        let (handle, dropped) = spawn_mock(Some(3));
+        let profiles: Vec<Profile> = handle.samples().iter().collect();
+        assert_eq!(profiles.len(), 3);
+        assert!(dropped.load(Ordering::SeqCst));
+        assert_eq!(handle.stop().unwrap().total, 3);
+    }
+
+    #[test]
+    fn drop_joins_thread() {
+        let (handle, dropped) = spawn_mock(None);
+        handle.samples().recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!dropped.load(Ordering::SeqCst));
+        drop(handle);
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn fails_to_attach() {
+        let attach = || -> Result<MockSampler> { anyhow::bail!("No V8 symbols") };
+        let error = spawn_sampler(1, 1000, attach).err().unwrap();
+        assert_eq!(error.to_string(), "No V8 symbols");
+    }
+
+    #[test]
+    fn samples_all_at_once() {
+        let (running_handle, _) = spawn_mock(None);
+        let (exiting_handle, _) = spawn_mock(Some(3));
+        let running = AtomicBool::new(true);
+        let mut stopped = Vec::new();
+        let profiles = sample_all(vec![(100, running_handle), (200, exiting_handle)], &running, Some(Duration::from_millis(100)), |pid, exited, stats| {
+            stopped.push((pid, exited, stats.unwrap().total))
+        });
+        let totals: Vec<(Pid, u64)> = profiles.iter().map(|(pid, profile)| (*pid, profile.stacks.values().sum())).collect();
+        assert_eq!(totals[0].0, 100);
+        assert!(totals[0].1 > 0);
+        assert_eq!(totals[1], (200, 3));
+        // The sampler whose process exited is stopped as soon as it does, and
+        // the other once the duration is up
+        assert_eq!(stopped.len(), 2);
+        assert_eq!(stopped[0], (200, true, 3));
+        assert_eq!((stopped[1].0, stopped[1].1), (100, false));
+    }
+}
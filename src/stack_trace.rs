@@ -0,0 +1,732 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use remoteprocess::Thread;
+
+/// Register state needed to start walking a thread's stack
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub pc: usize,
+    pub sp: usize,
+    pub fp: usize,
+}
+
+impl Registers {
+    /// Reads the registers of a thread. The thread must be stopped and
+    /// ptrace-attached (see `Process::lock`).
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "x86")))]
+    pub fn from_thread(thread: &Thread) -> Result<Self> {
+        let tid = thread.id()?;
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETREGS,
+                tid,
+                std::ptr::null_mut::<libc::c_void>(),
+                &mut regs as *mut libc::user_regs_struct as *mut libc::c_void,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error())
+                .context(format!("Failed to read registers for thread {}", tid));
+        }
+        #[cfg(target_arch = "x86_64")]
+        let (pc, sp, fp) = (regs.rip, regs.rsp, regs.rbp);
+        #[cfg(target_arch = "x86")]
+        let (pc, sp, fp) = (regs.eip, regs.esp, regs.ebp);
+        Ok(Registers {
+            pc: pc as usize,
+            sp: sp as usize,
+            fp: fp as usize,
+        })
+    }
+
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "x86"))))]
+    pub fn from_thread(_thread: &Thread) -> Result<Self> {
+        anyhow::bail!("Reading thread registers is not supported on this platform")
+    }
+}
+
+/// The kind of a stack frame, as determined by its frame marker or the
+/// code it's executing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FrameKind {
+    Interpreted,
+    Baseline,
+    Optimized,
+    Builtin,
+    Stub,
+    Entry,
+    /// Set up by `new` calls into a constructor
+    Construct,
+    /// Reconciles argument counts between caller and callee, before V8 8.9
+    ArgumentsAdaptor,
+    Exit,
+    Internal,
+    Wasm,
+    /// A V8 native frame, or a C++ frame outside of V8's frames altogether
+    Native,
+    /// An interpreted or baseline frame caught while V8 was still setting it
+    /// up, whose function slot doesn't hold a JSFunction yet
+    Torn,
+    /// A typed frame whose marker isn't one of the known frame types,
+    /// carrying the raw marker
+    Other(u8),
+    #[default]
+    Unknown,
+}
+
+impl FrameKind {
+    /// Whether this frame is executing JavaScript code and has a JSFunction
+    pub fn is_js(&self) -> bool {
+        matches!(self, FrameKind::Interpreted | FrameKind::Baseline | FrameKind::Optimized)
+    }
+
+    /// Lowercase name of the kind, used to label frames that have no function
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameKind::Interpreted => "interpreted",
+            FrameKind::Baseline => "baseline",
+            FrameKind::Optimized => "optimized",
+            FrameKind::Builtin => "builtin",
+            FrameKind::Stub => "stub",
+            FrameKind::Entry => "entry",
+            FrameKind::Construct => "construct",
+            FrameKind::ArgumentsAdaptor => "arguments_adaptor",
+            FrameKind::Exit => "exit",
+            FrameKind::Internal => "internal",
+            FrameKind::Wasm => "wasm",
+            FrameKind::Native => "native",
+            FrameKind::Torn => "torn",
+            FrameKind::Other(_) | FrameKind::Unknown => "unknown",
+        }
+    }
+}
+
+/// Which of V8's optimizing compilers produced an optimized frame's code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Tier {
+    /// The mid-tier compiler, between Sparkplug and TurboFan, since V8 10.0
+    Maglev,
+    Turbofan,
+}
+
+impl Tier {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tier::Maglev => "maglev",
+            Tier::Turbofan => "turbofan",
+        }
+    }
+}
+
+/// A raw frame read off the stack, holding pointers into the target process
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// The instruction pointer this frame is executing at
+    pub pc: usize,
+    /// The frame pointer of this frame, as given to `V8Spy::context_chain`
+    pub fp: usize,
+    /// The stack pointer of this frame: the thread's for the innermost
+    /// frame, and for callers, where it was when they made the call
+    pub sp: usize,
+    /// Tagged pointer to the JSFunction, or 0 if unknown
+    pub function: usize,
+    /// Tagged pointer to the SharedFunctionInfo, or 0 for non-JS frames
+    pub shared_function_info: usize,
+    /// Tagged pointer to the Code being executed, or 0 if unknown
+    pub code: usize,
+    /// Tagged pointer to the BytecodeArray for interpreted frames, or 0
+    pub bytecode_array: usize,
+    /// Offset into the bytecode for interpreted frames
+    pub bytecode_offset: Option<usize>,
+    /// The compiler of an optimized frame's code, if it could be told
+    pub tier: Option<Tier>,
+}
+
+/// Why a frame couldn't be resolved, with what was read of it, for working
+/// out what's wrong when porting to a new V8 version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameDebugRecord {
+    pub kind: FrameKind,
+    pub pc: usize,
+    pub fp: usize,
+    /// The frame type marker of a typed frame, shifted down from its Smi tag
+    pub marker: Option<usize>,
+    pub function: usize,
+    /// Instance type of the JSFunction, if it could be read
+    pub function_type: Option<u16>,
+    pub shared_function_info: usize,
+    /// Instance type of the SharedFunctionInfo, if it could be read
+    pub shared_function_info_type: Option<u16>,
+    /// The resolution step that failed, such as `"frame type"` or
+    /// `"function name"`
+    pub step: &'static str,
+    pub error: String,
+}
+
+impl std::fmt::Display for FrameDebugRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_owned());
+        write!(
+            f,
+            "step={:?} kind={} marker={} pc=0x{:x} fp=0x{:x} function=0x{:x} function_type={} sfi=0x{:x} sfi_type={} error={:?}",
+            self.step,
+            self.kind.name(),
+            or_dash(self.marker.map(|marker| marker.to_string())),
+            self.pc,
+            self.fp,
+            self.function,
+            or_dash(self.function_type.map(|ty| ty.to_string())),
+            self.shared_function_info,
+            or_dash(self.shared_function_info_type.map(|ty| ty.to_string())),
+            self.error
+        )
+    }
+}
+
+/// Filters applied to unwound stacks. Unwinding always produces the complete
+/// stack, so that what's dropped is left to the user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FilterOptions {
+    /// Drop native frames, leaving only the frames V8 knows about
+    pub exclude_native: bool,
+    /// Keep only the outermost of consecutive builtin frames
+    pub collapse_builtins: bool,
+    /// Keep samples of JavaScript threads that are idle, waiting in a system
+    /// call, merged into a single `<idle>` stack. Applied when sampling, as
+    /// whether a thread is idle can't be told from its frames alone.
+    pub include_idle: bool,
+    /// Add the libuv event loop phase a sample was taken in as its outermost
+    /// frame, such as `<loop:timers>`. Applied when sampling, from frames
+    /// that the other filters may drop.
+    pub event_loop_phase: bool,
+    /// What the frames of a sample stand for. Applied when sampling, once
+    /// the stack is resolved.
+    pub group_by: GroupBy,
+}
+
+/// What the frames of a profile stand for, as given with `--group-by`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GroupBy {
+    /// A frame for each function call, as unwound
+    #[default]
+    Function,
+    /// A frame for each file that the stack runs through, for telling which
+    /// modules the time goes to, as made by `group_by_file`
+    File,
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "function" => Ok(GroupBy::Function),
+            "file" => Ok(GroupBy::File),
+            _ => anyhow::bail!("Unknown grouping '{}': expected function or file", s),
+        }
+    }
+}
+
+/// Turns a resolved stack of function calls into one of the files they're
+/// in, innermost frame first. Each frame is named after its script, with
+/// no file or line of its own, and consecutive frames of the same file are
+/// merged into one, so that a file's share of a profile is the time spent
+/// in it and whatever it calls. Frames with no file are named by what they
+/// are: `<builtin>`, `<anonymous>` for JavaScript not loaded from a file,
+/// and `<native>` for native code. Synthetic frames such as `<idle>` keep
+/// their names.
+pub fn group_by_file(stack: &[ResolvedFrame]) -> Vec<ResolvedFrame> {
+    let mut grouped: Vec<ResolvedFrame> = Vec::with_capacity(stack.len());
+    for frame in stack {
+        let name = match &frame.file {
+            Some(file) => file.as_str(),
+            None if frame.kind.is_js() => "<anonymous>",
+            None if frame.kind == FrameKind::Builtin => "<builtin>",
+            None if frame.name.starts_with('<') => frame.name.as_str(),
+            None => "<native>",
+        };
+        if grouped.last().is_some_and(|last| last.name == name) {
+            continue;
+        }
+        let mut file_frame = ResolvedFrame::synthetic(name, frame.kind);
+        file_frame.address = frame.address;
+        grouped.push(file_frame);
+    }
+    grouped
+}
+
+/// Applies the frame filters of `opts` to a stack, innermost frame first
+pub fn filter_frames(frames: Vec<Frame>, opts: &FilterOptions) -> Vec<Frame> {
+    let mut filtered: Vec<Frame> = Vec::with_capacity(frames.len());
+    for frame in frames {
+        if opts.exclude_native && frame.kind == FrameKind::Native {
+            continue;
+        }
+        if opts.collapse_builtins
+            && frame.kind == FrameKind::Builtin
+            && filtered.last().is_some_and(|last| last.kind == FrameKind::Builtin)
+        {
+            filtered.pop();
+        }
+        filtered.push(frame);
+    }
+    filtered
+}
+
+/// Filters on the names and files of resolved frames. Unlike
+/// `FilterOptions`, these apply to stacks once they're resolved, so that
+/// what's captured is left as it is.
+#[derive(Debug, Clone, Default)]
+pub struct NameFilter {
+    /// Keep only the frames whose name or file matches
+    pub include: Option<Regex>,
+    /// Drop the frames whose name or file matches, even if included
+    pub exclude: Option<Regex>,
+}
+
+impl NameFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_none()
+    }
+
+    /// Whether a frame is kept
+    pub fn keeps(&self, frame: &ResolvedFrame) -> bool {
+        let matches = |regex: &Regex| regex.is_match(&frame.name) || frame.file.as_deref().is_some_and(|file| regex.is_match(file));
+        self.include.as_ref().is_none_or(matches) && !self.exclude.as_ref().is_some_and(matches)
+    }
+
+    /// Applies the filter to a stack, innermost frame first. Dropping frames
+    /// leaves the samples of a dropped leaf to the innermost frame kept
+    /// below it, and a stack left with no frames should be dropped as well.
+    pub fn apply(&self, stack: &[ResolvedFrame]) -> Vec<ResolvedFrame> {
+        stack.iter().filter(|frame| self.keeps(frame)).cloned().collect()
+    }
+}
+
+/// Where a frame was on the stack when it was sampled, for matching it up
+/// with samples of the same thread taken by other tools such as perf
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameAddress {
+    pub pc: usize,
+    pub fp: usize,
+    pub sp: usize,
+}
+
+// Addresses differ from one sample of a frame to the next, so they're left
+// out of comparisons: samples of the same frame still add up in a profile,
+// which keeps the addresses of the first
+impl PartialEq for FrameAddress {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for FrameAddress {}
+
+impl PartialOrd for FrameAddress {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FrameAddress {
+    fn cmp(&self, _: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl std::hash::Hash for FrameAddress {
+    fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+}
+
+/// A frame with its names resolved, ready for output
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResolvedFrame {
+    pub name: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub kind: FrameKind,
+    pub tier: Option<Tier>,
+    /// The class of a JavaScript frame's receiver, shown in front of its
+    /// name as in `Server.handle`, if read with `V8Spy::receiver_classes`
+    pub receiver: Option<String>,
+    /// The URL the frame's script names itself with in a `//# sourceURL=`
+    /// comment, if it has one
+    pub source_url: Option<String>,
+    /// The URL of the frame's script's source map, from its
+    /// `//# sourceMappingURL=` comment, for remapping the frame to the
+    /// source it was built from
+    pub source_mapping_url: Option<String>,
+    /// Where the frame was on the stack. Not part of its identity.
+    pub address: FrameAddress,
+}
+
+impl std::fmt::Display for ResolvedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(receiver) = &self.receiver {
+            write!(f, "{}.", receiver)?;
+        }
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{} ({}:{})", self.name, file, line),
+            (Some(file), None) => write!(f, "{} ({})", self.name, file),
+            _ => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl ResolvedFrame {
+    /// A frame with only a name, such as `<native>`, or `<idle>` and `<gc>`
+    /// standing in for a stack with no JavaScript on it
+    pub fn synthetic(name: &str, kind: FrameKind) -> Self {
+        ResolvedFrame {
+            name: name.to_owned(),
+            file: None,
+            line: None,
+            kind,
+            tier: None,
+            receiver: None,
+            source_url: None,
+            source_mapping_url: None,
+            address: FrameAddress::default(),
+        }
+    }
+}
+
+/// The template `FrameFormat` has by default, which writes frames as they're
+/// displayed, as in `Server.handle (/srv/app/server.js:12)`
+pub const DEFAULT_FRAME_FORMAT: &str = "[{receiver}.]{name}[ ({file}[:{line}])]";
+
+/// A template for writing resolved frames out, as given with `--frame-format`.
+/// The placeholders `{name}`, `{file}`, `{line}`, `{kind}`, `{receiver}`,
+/// `{source_url}` and `{source_map}` are replaced with the frame's, the last
+/// being its script's source map URL, and a part in brackets is left out along
+/// with everything in it if a placeholder in it has nothing to show, such as
+/// the file of a native frame. Outside of brackets, such a placeholder is
+/// left empty. A backslash stands for the character after it, so `\[` writes
+/// a bracket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameFormat {
+    parts: Vec<FormatPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FormatPart {
+    Text(String),
+    Field(FormatField),
+    Optional(Vec<FormatPart>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FormatField {
+    Name,
+    File,
+    Line,
+    Kind,
+    Receiver,
+    SourceUrl,
+    SourceMap,
+}
+
+impl FormatField {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "name" => Ok(FormatField::Name),
+            "file" => Ok(FormatField::File),
+            "line" => Ok(FormatField::Line),
+            "kind" => Ok(FormatField::Kind),
+            "receiver" => Ok(FormatField::Receiver),
+            "source_url" => Ok(FormatField::SourceUrl),
+            "source_map" => Ok(FormatField::SourceMap),
+            _ => anyhow::bail!(
+                "Unknown placeholder {{{}}} in frame format: expected name, file, line, kind, receiver, source_url or source_map",
+                name
+            ),
+        }
+    }
+
+    fn value(&self, frame: &ResolvedFrame) -> Option<String> {
+        match self {
+            FormatField::Name => Some(frame.name.clone()),
+            FormatField::File => frame.file.clone(),
+            FormatField::Line => frame.line.map(|line| line.to_string()),
+            FormatField::Kind => Some(frame.kind.name().to_owned()),
+            FormatField::Receiver => frame.receiver.clone(),
+            FormatField::SourceUrl => frame.source_url.clone(),
+            FormatField::SourceMap => frame.source_mapping_url.clone(),
+        }
+    }
+}
+
+impl Default for FrameFormat {
+    fn default() -> Self {
+        DEFAULT_FRAME_FORMAT.parse().expect("the default frame format parses")
+    }
+}
+
+impl std::str::FromStr for FrameFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut chars = s.chars();
+        let parts = parse_format_parts(&mut chars, false)?;
+        Ok(FrameFormat { parts })
+    }
+}
+
+/// Parses the parts of a frame format up to the end of the template, or up
+/// to the closing bracket when `in_brackets`
+fn parse_format_parts(chars: &mut std::str::Chars, in_brackets: bool) -> Result<Vec<FormatPart>> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    fn end_text(text: &mut String, parts: &mut Vec<FormatPart>) {
+        if !text.is_empty() {
+            parts.push(FormatPart::Text(std::mem::take(text)));
+        }
+    }
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.push(chars.next().context("Frame format ends in a lone backslash")?),
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => anyhow::bail!("Unclosed '{{' in frame format"),
+                    }
+                }
+                end_text(&mut text, &mut parts);
+                parts.push(FormatPart::Field(FormatField::parse(&name)?));
+            }
+            '[' => {
+                end_text(&mut text, &mut parts);
+                parts.push(FormatPart::Optional(parse_format_parts(chars, true)?));
+            }
+            ']' if in_brackets => {
+                end_text(&mut text, &mut parts);
+                return Ok(parts);
+            }
+            '}' | ']' => anyhow::bail!("Unmatched '{}' in frame format, which is written as '\\{}'", c, c),
+            c => text.push(c),
+        }
+    }
+    if in_brackets {
+        anyhow::bail!("Unclosed '[' in frame format");
+    }
+    end_text(&mut text, &mut parts);
+    Ok(parts)
+}
+
+impl FrameFormat {
+    /// Writes a frame out as the template says
+    pub fn render(&self, frame: &ResolvedFrame) -> String {
+        let mut out = String::new();
+        render_format_parts(&self.parts, frame, false, &mut out);
+        out
+    }
+}
+
+/// Appends the parts to `out`. In brackets, a placeholder with nothing to
+/// show leaves `out` as it was.
+fn render_format_parts(parts: &[FormatPart], frame: &ResolvedFrame, in_brackets: bool, out: &mut String) {
+    let start = out.len();
+    for part in parts {
+        match part {
+            FormatPart::Text(text) => out.push_str(text),
+            FormatPart::Field(field) => match field.value(frame) {
+                Some(value) => out.push_str(&value),
+                None if in_brackets => {
+                    out.truncate(start);
+                    return;
+                }
+                None => {}
+            },
+            FormatPart::Optional(parts) => render_format_parts(parts, frame, true, out),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn js_frame(name: &str, file: &str, line: u32) -> ResolvedFrame {
+        ResolvedFrame {
+            file: Some(file.to_owned()),
+            line: Some(line),
+            ..ResolvedFrame::synthetic(name, FrameKind::Interpreted)
+        }
+    }
+
+    /// A stack of frames of `kinds`, innermost first, each told apart by its pc
+    fn frames(kinds: &[FrameKind]) -> Vec<Frame> {
+        kinds.iter().enumerate().map(|(i, &kind)| Frame { kind, pc: i + 1, ..Frame::default() }).collect()
+    }
+
+    fn filtered_pcs(kinds: &[FrameKind], opts: FilterOptions) -> Vec<usize> {
+        filter_frames(frames(kinds), &opts).iter().map(|frame| frame.pc).collect()
+    }
+
+    #[test]
+    fn filters_frames() {
+        use FrameKind::*;
+        let stack = [Native, Builtin, Builtin, Interpreted, Builtin, Native, Builtin, Entry];
+        assert_eq!(filtered_pcs(&stack, FilterOptions::default()), [1, 2, 3, 4, 5, 6, 7, 8]);
+        let exclude_native = FilterOptions { exclude_native: true, ..FilterOptions::default() };
+        assert_eq!(filtered_pcs(&stack, exclude_native), [2, 3, 4, 5, 7, 8]);
+        // The outermost of each run of builtins is kept
+        let collapse_builtins = FilterOptions { collapse_builtins: true, ..FilterOptions::default() };
+        assert_eq!(filtered_pcs(&stack, collapse_builtins), [1, 3, 4, 5, 6, 7, 8]);
+        // Builtins left next to each other once native frames are dropped are
+        // collapsed too
+        let both = FilterOptions { exclude_native: true, collapse_builtins: true, ..FilterOptions::default() };
+        assert_eq!(filtered_pcs(&stack, both), [3, 4, 7, 8]);
+        // Idle samples are kept when sampling, and their frames left as they are
+        let include_idle = FilterOptions { include_idle: true, ..FilterOptions::default() };
+        assert_eq!(filtered_pcs(&stack, include_idle), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(filtered_pcs(&[Native, Native], exclude_native).is_empty());
+    }
+
+    fn render(format: &str, frame: &ResolvedFrame) -> String {
+        format.parse::<FrameFormat>().unwrap().render(frame)
+    }
+
+    #[test]
+    fn default_frame_format() {
+        let frame = js_frame("handle", "/srv/app/server.js", 12);
+        let receiver = ResolvedFrame { receiver: Some("Server".to_owned()), ..frame.clone() };
+        let no_line = ResolvedFrame { line: None, ..frame.clone() };
+        let native = ResolvedFrame::synthetic("<native>", FrameKind::Native);
+        for frame in [&frame, &receiver, &no_line, &native] {
+            assert_eq!(FrameFormat::default().render(frame), frame.to_string());
+        }
+        assert_eq!(FrameFormat::default().render(&receiver), "Server.handle (/srv/app/server.js:12)");
+    }
+
+    #[test]
+    fn frame_format_placeholders() {
+        let frame = ResolvedFrame {
+            receiver: Some("Server".to_owned()),
+            source_url: Some("webpack://app/server.ts".to_owned()),
+            source_mapping_url: Some("server.js.map".to_owned()),
+            ..js_frame("handle", "/srv/app/server.js", 12)
+        };
+        assert_eq!(
+            render("{name}|{file}|{line}|{kind}|{receiver}|{source_url}|{source_map}", &frame),
+            "handle|/srv/app/server.js|12|interpreted|Server|webpack://app/server.ts|server.js.map"
+        );
+        // Outside of brackets, a placeholder with nothing to show is left empty
+        let native = ResolvedFrame::synthetic("<native>", FrameKind::Native);
+        assert_eq!(render("{name}:{file}:{line} {kind}", &native), "<native>:: native");
+    }
+
+    #[test]
+    fn frame_format_brackets() {
+        let frame = js_frame("handle", "/srv/app/server.js", 12);
+        let native = ResolvedFrame::synthetic("<native>", FrameKind::Native);
+        assert_eq!(render("{name}[ in {file}[ at line {line}]]", &frame), "handle in /srv/app/server.js at line 12");
+        assert_eq!(render("{name}[ in {file}[ at line {line}]]", &ResolvedFrame { line: None, ..frame.clone() }), "handle in /srv/app/server.js");
+        assert_eq!(render("{name}[ in {file}[ at line {line}]]", &native), "<native>");
+        // Text alone in brackets is always kept
+        assert_eq!(render("[<]{name}[>]", &native), "<<native>>");
+        assert_eq!(render("\\[{kind}\\] {name} \\{x\\}", &frame), "[interpreted] handle {x}");
+    }
+
+    #[test]
+    fn frame_format_errors() {
+        let error = |format: &str| format.parse::<FrameFormat>().unwrap_err().to_string();
+        assert_eq!(
+            error("{name} {column}"),
+            "Unknown placeholder {column} in frame format: expected name, file, line, kind, receiver, source_url or source_map"
+        );
+        assert_eq!(error("{name"), "Unclosed '{' in frame format");
+        assert_eq!(error("{name}[ ({file})"), "Unclosed '[' in frame format");
+        assert_eq!(error("{name}]"), "Unmatched ']' in frame format, which is written as '\\]'");
+        assert_eq!(error("name}"), "Unmatched '}' in frame format, which is written as '\\}'");
+        assert_eq!(error("{name}\\"), "Frame format ends in a lone backslash");
+        assert_eq!("".parse::<FrameFormat>().unwrap().render(&js_frame("f", "a.js", 1)), "");
+    }
+
+    fn name_filter(include: Option<&str>, exclude: Option<&str>) -> NameFilter {
+        NameFilter { include: include.map(|re| Regex::new(re).unwrap()), exclude: exclude.map(|re| Regex::new(re).unwrap()) }
+    }
+
+    fn names(stack: &[ResolvedFrame]) -> Vec<&str> {
+        stack.iter().map(|frame| frame.name.as_str()).collect()
+    }
+
+    #[test]
+    fn name_filter_matches_name_or_file() {
+        let frame = js_frame("handle", "/srv/node_modules/express/router.js", 40);
+        assert!(NameFilter::default().is_empty());
+        assert!(NameFilter::default().keeps(&frame));
+        assert!(!name_filter(Some("handle"), None).is_empty());
+        assert!(name_filter(Some("^hand"), None).keeps(&frame));
+        assert!(name_filter(Some("express"), None).keeps(&frame));
+        assert!(!name_filter(Some("^express"), None).keeps(&frame));
+        assert!(!name_filter(None, Some("node_modules")).keeps(&frame));
+        // Excluding wins over including
+        assert!(!name_filter(Some("handle"), Some("router")).keeps(&frame));
+        // Frames without a file match by name alone
+        let native = ResolvedFrame::synthetic("<native>", FrameKind::Native);
+        assert!(!name_filter(Some("router"), None).keeps(&native));
+        assert!(name_filter(None, Some("router")).keeps(&native));
+    }
+
+    #[test]
+    fn name_filter_applies_to_stacks() {
+        let stack = vec![
+            js_frame("parse", "/srv/node_modules/qs/parse.js", 3),
+            js_frame("handle", "/srv/app/server.js", 12),
+            ResolvedFrame::synthetic("<native>", FrameKind::Native),
+            js_frame("main", "/srv/app/index.js", 1),
+        ];
+        assert_eq!(names(&NameFilter::default().apply(&stack)), ["parse", "handle", "<native>", "main"]);
+        assert_eq!(names(&name_filter(Some("/srv/app/"), None).apply(&stack)), ["handle", "main"]);
+        assert_eq!(names(&name_filter(None, Some("node_modules|<native>")).apply(&stack)), ["handle", "main"]);
+        assert_eq!(names(&name_filter(Some("/srv/"), Some("index")).apply(&stack)), ["parse", "handle"]);
+        assert!(name_filter(Some("nothing"), None).apply(&stack).is_empty());
+    }
+
+    #[test]
+    fn name_filter_merges_profile_stacks() {
+        let mut profile = crate::Profile::new();
+        let handle = js_frame("handle", "/srv/app/server.js", 12);
+        profile.add_sample(vec![js_frame("parse", "/srv/node_modules/qs/parse.js", 3), handle.clone()]);
+        profile.add_sample(vec![js_frame("stringify", "/srv/node_modules/qs/stringify.js", 8), handle.clone()]);
+        profile.add_sample(vec![handle.clone()]);
+        profile.add_sample(vec![js_frame("lstat", "/srv/node_modules/fs-extra/lstat.js", 2)]);
+
+        let filtered = profile.filtered(&name_filter(None, Some("node_modules")));
+        // The samples of the dropped leaves go to the frame below them, and
+        // the stack left with nothing is dropped
+        assert_eq!(filtered.stacks.len(), 1);
+        assert_eq!(filtered.stacks[&vec![handle]], 3);
+    }
+
+    #[test]
+    fn groups_frames_by_file() {
+        let builtin = ResolvedFrame::synthetic("<builtin: ArrayPrototypeMap>", FrameKind::Builtin);
+        let anonymous = ResolvedFrame { file: None, ..js_frame("eval", "", 1) };
+        let stack = vec![
+            js_frame("parse", "/srv/app/parse.js", 3),
+            js_frame("parseAll", "/srv/app/parse.js", 20),
+            builtin.clone(),
+            builtin,
+            js_frame("handle", "/srv/app/server.js", 12),
+            anonymous,
+            js_frame("route", "/srv/app/server.js", 40),
+            ResolvedFrame::synthetic("<native>", FrameKind::Native),
+            ResolvedFrame::synthetic("uv_run", FrameKind::Native),
+            ResolvedFrame::synthetic("<loop:poll>", FrameKind::Native),
+        ];
+        let grouped = group_by_file(&stack);
+        // The frames of a file go to one frame of it, and frames without a
+        // file are named by what they are, apart from the files around them
+        assert_eq!(names(&grouped), ["/srv/app/parse.js", "<builtin>", "/srv/app/server.js", "<anonymous>", "/srv/app/server.js", "<native>", "<loop:poll>"]);
+        assert!(grouped.iter().all(|frame| frame.file.is_none() && frame.line.is_none()));
+        assert!(group_by_file(&[]).is_empty());
+    }
+}
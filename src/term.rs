@@ -0,0 +1,106 @@
+//! Coloring of what's written to the terminal, shared by the top view and
+//! error messages so they agree on when to use color
+
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+/// When to color output, as given with `--color`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => anyhow::bail!("Unknown color choice '{}': expected auto, always or never", s),
+        }
+    }
+}
+
+/// Wraps text in ANSI colors, or leaves it as is when color is off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    enabled: bool,
+}
+
+impl Style {
+    /// Decides whether to color a stream. An explicit choice wins, and with
+    /// `Auto`, a non-empty `NO_COLOR` turns color off, as https://no-color.org
+    /// asks, and otherwise it's on if the stream is a terminal.
+    pub fn new(choice: ColorChoice, no_color: Option<OsString>, is_terminal: bool) -> Self {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => no_color.is_none_or(|value| value.is_empty()) && is_terminal,
+        };
+        Style { enabled }
+    }
+
+    pub fn stdout(choice: ColorChoice) -> Self {
+        Self::new(choice, std::env::var_os("NO_COLOR"), std::io::stdout().is_terminal())
+    }
+
+    pub fn stderr(choice: ColorChoice) -> Self {
+        Self::new(choice, std::env::var_os("NO_COLOR"), std::io::stderr().is_terminal())
+    }
+
+    pub fn bold(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+
+    pub fn dim(&self, text: &str) -> String {
+        self.paint("2", text)
+    }
+
+    pub fn red(&self, text: &str) -> String {
+        self.paint("31", text)
+    }
+
+    pub fn yellow(&self, text: &str) -> String {
+        self.paint("33", text)
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_color_turns_color_off() {
+        let colored = Style::new(ColorChoice::Auto, None, true);
+        assert_eq!(colored.red("error"), "\x1b[31merror\x1b[0m");
+        let plain = Style::new(ColorChoice::Auto, Some("1".into()), true);
+        assert_eq!(plain.red("error"), "error");
+        // Set but empty, it's as if it weren't set at all
+        assert_eq!(Style::new(ColorChoice::Auto, Some("".into()), true), colored);
+        // Nor does a stream that isn't a terminal get color
+        assert_eq!(Style::new(ColorChoice::Auto, None, false), plain);
+    }
+
+    #[test]
+    fn explicit_choices_win() {
+        assert_eq!(Style::new(ColorChoice::Always, Some("1".into()), false).bold("top"), "\x1b[1mtop\x1b[0m");
+        assert_eq!(Style::new(ColorChoice::Never, None, true).bold("top"), "top");
+        assert_eq!("always".parse::<ColorChoice>().unwrap(), ColorChoice::Always);
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::io::{self, Write};
+
+use v8spy::Profile;
+
+use crate::term::Style;
+
+/// Shares of own samples from which a function is highlighted as hot, or
+/// as warm
+const HOT_PERCENT: f64 = 10.0;
+const WARM_PERCENT: f64 = 1.0;
+
+/// Renders one screen of the top view. Each line is cleared as it's written
+/// so the view can be redrawn in place without flicker. With color, hot
+/// functions are highlighted and their files dimmed. Samples that failed
+/// are counted in the header as `errors`, if there were any.
+pub fn render(profile: &Profile, pid: i32, errors: u64, width: usize, height: usize, style: Style, w: &mut impl Write) -> io::Result<()> {
+    let rows = profile.function_stats();
+    let total: u64 = profile.stacks.values().sum();
+
+    write!(w, "\x1b[H")?;
+    let mut header = format!("v8spy --top: pid {}, {} samples", pid, total);
+    if errors > 0 {
+        header += &format!(", {} failed", errors);
+    }
+    write_line(w, width, &style.bold(&header))?;
+    write_line(w, width, "")?;
+    write_line(w, width, &style.bold("  %Own   %Total  OwnSamples  TotalSamples  Function (file)"))?;
+    for row in rows.iter().take(height.saturating_sub(4)) {
+        let location = match &row.file {
+            Some(file) => format!("{} {}", row.name, style.dim(&format!("({})", file))),
+            None => row.name.clone(),
+        };
+        let own = percent(row.self_samples, total);
+        let own_column = format!("{:>6.2}%", own);
+        let own_column = match own {
+            own if own >= HOT_PERCENT => style.red(&own_column),
+            own if own >= WARM_PERCENT => style.yellow(&own_column),
+            _ => own_column,
+        };
+        let line = format!(
+            "{} {:>6.2}%  {:>10}  {:>12}  {}",
+            own_column,
+            percent(row.total_samples, total),
+            row.self_samples,
+            row.total_samples,
+            location
+        );
+        write_line(w, width, &line)?;
+    }
+    // Clear whatever is left from a previous, longer frame
+    write!(w, "\x1b[J")?;
+    w.flush()
+}
+
+/// Writes a line cut to `width` columns. Color escapes take up no columns,
+/// and a line cut short in a color is reset, so the color doesn't bleed
+/// into the next.
+fn write_line(w: &mut impl Write, width: usize, line: &str) -> io::Result<()> {
+    let mut cut = String::with_capacity(line.len());
+    let (mut columns, mut colored) = (0, false);
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            cut.push(c);
+            cut.extend(chars.by_ref().take_while(|&c| c != 'm'));
+            cut.push('m');
+            colored = true;
+        } else if columns < width {
+            cut.push(c);
+            columns += 1;
+        }
+    }
+    if colored {
+        cut.push_str("\x1b[0m");
+    }
+    write!(w, "{}\x1b[K\r\n", cut)
+}
+
+fn percent(samples: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * samples as f64 / total as f64
+    }
+}
+
+/// Returns the terminal's (columns, rows), falling back to 80x24 when stdout
+/// isn't a terminal. Queried on every redraw so resizes are picked up.
+pub fn terminal_size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret != 0 || size.ws_col == 0 || size.ws_row == 0 {
+        return (80, 24);
+    }
+    (size.ws_col as usize, size.ws_row as usize)
+}
+
+/// Switches to the alternate screen with the cursor hidden, and restores the
+/// terminal when dropped, including when unwinding from a panic
+pub struct TerminalGuard;
+
+impl TerminalGuard {
+    pub fn new() -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        write!(stdout, "\x1b[?1049h\x1b[?25l")?;
+        stdout.flush()?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use v8spy::{FrameKind, ResolvedFrame};
+
+    use super::*;
+    use crate::term::ColorChoice;
+
+    fn frame(name: &str, file: &str) -> ResolvedFrame {
+        ResolvedFrame { file: Some(file.to_owned()), ..ResolvedFrame::synthetic(name, FrameKind::Interpreted) }
+    }
+
+    /// The lines `render` draws, without the escapes that place them
+    fn screen(profile: &Profile, errors: u64, width: usize, height: usize) -> Vec<String> {
+        let mut out = Vec::new();
+        render(profile, 42, errors, width, height, Style::new(ColorChoice::Never, None, false), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let out = out.strip_prefix("\x1b[H").unwrap().strip_suffix("\x1b[J").unwrap();
+        out.split_terminator("\r\n").map(|line| line.strip_suffix("\x1b[K").unwrap().to_owned()).collect()
+    }
+
+    #[test]
+    fn renders_functions_by_own_samples() {
+        let (main, handle) = (frame("main", "/srv/app.js"), frame("handle", "/srv/app.js"));
+        let mut profile = Profile::new();
+        profile.stacks.insert(vec![frame("parse", "/srv/app.js"), handle.clone(), main.clone()], 6);
+        profile.stacks.insert(vec![handle.clone(), main.clone()], 3);
+        // A function of the same name in another file is a row of its own
+        profile.stacks.insert(vec![frame("parse", "/srv/lib.js"), handle, main.clone()], 2);
+        profile.stacks.insert(vec![main], 1);
+        assert_eq!(
+            screen(&profile, 0, 80, 24),
+            [
+                "v8spy --top: pid 42, 12 samples",
+                "",
+                "  %Own   %Total  OwnSamples  TotalSamples  Function (file)",
+                " 50.00%  50.00%           6             6  parse (/srv/app.js)",
+                " 25.00%  91.67%           3            11  handle (/srv/app.js)",
+                " 16.67%  16.67%           2             2  parse (/srv/lib.js)",
+                "  8.33% 100.00%           1            12  main (/srv/app.js)",
+            ]
+        );
+    }
+
+    #[test]
+    fn fits_the_terminal() {
+        let mut profile = Profile::new();
+        profile.stacks.insert(vec![frame("parse", "/srv/app.js"), frame("main", "/srv/app.js")], 3);
+        profile.stacks.insert(vec![frame("main", "/srv/app.js")], 1);
+        // As many rows as fit under the header with a line to spare, each cut
+        // to the width, and the samples that failed counted in the header
+        assert_eq!(
+            screen(&profile, 2, 40, 5),
+            ["v8spy --top: pid 42, 4 samples, 2 failed", "", "  %Own   %Total  OwnSamples  TotalSample", " 75.00%  75.00%           3             "]
+        );
+    }
+}
@@ -1,20 +1,222 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{Context, Result};
 use spytools::ProcessInfo;
 
-use remoteprocess::{Pid, Process, ProcessMemory};
+use remoteprocess::{Pid, Process, ProcessMemory, Thread, Tid};
 
-struct Version {
-    major: u32,
-    minor: u32,
-    build: u32,
-    patch: u32,
+use crate::builtins;
+use crate::memory::{load_shared_libraries, LiveTarget, MemoryMap, MemorySource, RecordedThread, Recorder, Recording};
+use crate::native::NativeSymbols;
+use crate::profile::Profile;
+use crate::stack_trace::{
+    filter_frames, group_by_file, FilterOptions, Frame, FrameAddress, FrameDebugRecord, FrameKind, GroupBy, Registers, ResolvedFrame, Tier,
+};
+use crate::versions::{self, v8_ver};
+
+/// Size of a pointer in the target when its frame layout doesn't tell, as
+/// for a process of the same architecture as the spy
+const POINTER_SIZE: usize = std::mem::size_of::<usize>();
+/// Pointer compression cages are 4GB and aligned to 4GB
+const CAGE_BASE_MASK: usize = !((1 << 32) - 1);
+/// Symbol holding the cage base in V8 builds with a shared pointer compression cage
+const CAGE_BASE_SYMBOL: &str = "_ZN2v88internal23V8HeapCompressionScheme5base_E";
+/// Upper bound on the number of frames walked per stack, in case the frame
+/// pointer chain loops or runs into garbage
+const MAX_STACK_DEPTH: usize = 1024;
+/// Upper bound on the length of strings and arrays read from the heap
+const MAX_READ_LENGTH: usize = 1 << 20;
+/// Instance type bit of external strings that don't cache their data
+/// pointer (`kUncachedExternalStringMask`), which V8 doesn't export
+const UNCACHED_EXTERNAL_STRING_MASK: u16 = 1 << 4;
+/// Longest thread name the kernel keeps, leaving out the NUL of
+/// `TASK_COMM_LEN`
+const THREAD_NAME_MAX: usize = 15;
+/// Least time between reloads of a process's memory map for reads that
+/// miss it. The map can't change while the process is paused for a sample.
+const MEMORY_MAP_RELOAD_INTERVAL: Duration = Duration::from_millis(10);
+/// Upper bound on the number of code ranges cached for `function_for_pc`
+const MAX_CODE_RANGES: usize = 4096;
+/// Upper bound on the number of frames kept by the frame cache
+const MAX_CACHED_FRAMES: usize = 4096;
+/// Upper bound on the size of a thread's stack, for telling which thread is
+/// in the exit frame of the isolate given to `V8Spy::set_isolate`. Linux
+/// gives the main thread 8MB by default, and threads no more.
+const MAX_THREAD_STACK: usize = 8 << 20;
+/// Upper bound on the number of bound functions followed to the function
+/// they call
+const MAX_BOUND_FUNCTIONS: usize = 16;
+/// Upper bound on the number of map transitions followed back to the map
+/// holding an object's constructor. Each property added to an object one at
+/// a time is a transition.
+const MAX_MAP_TRANSITIONS: usize = 1024;
+/// How long `V8Spy::selftest` looks for a thread running JavaScript, for
+/// processes that spend most of their time waiting for I/O
+const SELFTEST_TIMEOUT: Duration = Duration::from_secs(2);
+/// Time between `V8Spy::selftest`'s looks at the process's threads
+const SELFTEST_INTERVAL: Duration = Duration::from_millis(10);
+/// How long `V8Spy::set_worker_filter` looks for the worker running
+/// JavaScript, as workers that wait on messages have none on their stacks
+const WORKER_SEARCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Symbols that any V8 binary exports, used to tell whether a process embeds V8
+const V8_SENTINEL_SYMBOLS: [&str; 4] = [
+    "_ZN2v88internal7Version6major_E",
+    "_ZN2v88internal7Version6minor_E",
+    "_ZN2v88internal7Version6build_E",
+    "_ZN2v88internal7Version6patch_E",
+];
+
+/// The libuv functions that run each phase of the event loop. Pending and
+/// closing callbacks are run in `uv_run` itself in most builds, so those
+/// phases are only seen where the compiler kept them apart.
+const EVENT_LOOP_SYMBOLS: [(&str, EventLoopPhase); 7] = [
+    ("uv__run_timers", EventLoopPhase::Timers),
+    ("uv__run_pending", EventLoopPhase::Pending),
+    ("uv__run_idle", EventLoopPhase::Idle),
+    ("uv__run_prepare", EventLoopPhase::Prepare),
+    ("uv__io_poll", EventLoopPhase::Poll),
+    ("uv__run_check", EventLoopPhase::Check),
+    ("uv__run_closing_handles", EventLoopPhase::Close),
+];
+
+/// Stands in for the `v8dbg_frametype_*` values of frame types a V8 version
+/// doesn't have, so they never match a real marker
+const MISSING_FRAME_TYPE: u8 = 0xFF;
+
+/// Fragments of the mangled names of the C++ functions that run V8's garbage
+/// collectors. A native frame in one of these means the thread is in GC.
+const GC_SYMBOL_PATTERNS: [&str; 7] = [
+    "8internal4Heap14CollectGarbage",
+    "8internal4Heap24PerformGarbageCollection",
+    "MarkCompactCollector",
+    "Scavenger",
+    "IncrementalMarking",
+    "ConcurrentMarking",
+    "Sweeper",
+];
+
+/// Errors that callers may want to handle specifically. Everything else is
+/// reported as an `anyhow::Error` with context.
+#[derive(Debug)]
+pub enum Error {
+    /// The process doesn't embed V8, or its symbols couldn't be found
+    NotAV8Process(Pid),
+    /// A string was expected, but the object's instance type isn't one
+    NotAString { address: usize, instance_type: u16 },
+    /// Debug symbols the spy needs are missing, with `OnMissingSymbol::Error`
+    MissingSymbols(Vec<String>),
+    /// The process's V8 is older than `MIN_SUPPORTED_VERSION`
+    UnsupportedVersion(Version),
+    /// A read would run into memory the process doesn't have mapped
+    UnmappedRegion { address: usize, size: usize },
+    /// A read took longer than `V8SpyOptions::read_timeout`, or came after
+    /// one that did in the same sample
+    ReadTimeout { address: usize, timeout: Duration },
+    /// The process can't be traced by this one, usually because it isn't
+    /// our child and we're neither root nor allowed to by `ptrace_scope`
+    PermissionDenied(Pid),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::NotAV8Process(pid) => write!(f, "Process {} doesn't look like a Node/V8 process: no V8 symbols found", pid),
+            Error::NotAString { address, instance_type } => {
+                write!(f, "Object at 0x{:x} is not a string (instance type {})", address, instance_type)
+            }
+            Error::MissingSymbols(symbols) => write!(f, "Missing V8 debug symbols: {}", symbols.join(", ")),
+            Error::UnsupportedVersion(version) => {
+                let min = &MIN_SUPPORTED_VERSION;
+                write!(f, "V8 {} is not supported, only {}.{} and later are", version, min.major, min.minor)
+            }
+            Error::UnmappedRegion { address, size } => {
+                write!(f, "Read of {} bytes at 0x{:x} runs into unmapped memory", size, address)
+            }
+            Error::ReadTimeout { address, timeout } => {
+                write!(f, "Read at 0x{:x} timed out after {:?}", address, timeout)
+            }
+            Error::PermissionDenied(pid) => write!(
+                f,
+                "Permission denied attaching to process {}: run as root or with CAP_SYS_PTRACE, or allow tracing \
+                 processes other than your own children by setting /proc/sys/kernel/yama/ptrace_scope to 0",
+                pid
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// What to do when V8 debug symbols that describe its layout are missing.
+/// Most are only needed on some V8 versions, or have fallbacks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnMissingSymbol {
+    /// Log the missing symbols as warnings and attach anyway
+    #[default]
+    Warn,
+    /// Fail to attach, with `Error::MissingSymbols` listing them
+    Error,
+}
+
+/// Options for attaching to a process
+#[derive(Debug, Clone, Copy)]
+pub struct V8SpyOptions {
+    pub on_missing_symbol: OnMissingSymbol,
+    /// Attach when the build or patch level of the V8 version can't be read,
+    /// taking them to be 0. The major and minor version are always needed.
+    pub allow_partial_version: bool,
+    /// Longest a single read of the process's memory may take while
+    /// sampling, as when it has to be paged back in on a loaded machine. A
+    /// read can't be interrupted once started on any platform, so the read
+    /// that overruns fails once it returns, as does every read after it in
+    /// the same sample, which is dropped and counted in
+    /// `SampleStats::read_timeouts`. No limit if `None`.
+    pub read_timeout: Option<Duration>,
+}
+
+impl Default for V8SpyOptions {
+    fn default() -> Self {
+        V8SpyOptions { on_missing_symbol: OnMissingSymbol::default(), allow_partial_version: true, read_timeout: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub build: u32,
+    pub patch: u32,
+    /// What V8's version string adds to the numbers, such as the embedder's
+    /// `-node.32` or ` (candidate)` for pre-releases, if anything
+    pub label: Option<String>,
+}
+
+/// The oldest V8 whose layout the fallbacks for missing symbols cover.
+/// Attaching to anything older fails with `Error::UnsupportedVersion`.
+pub const MIN_SUPPORTED_VERSION: Version = Version { major: 7, minor: 2, build: 0, patch: 0, label: None };
+
+/// The newest V8 minor version the fallbacks are known to be right for.
+/// Newer versions are attached to with a warning, as their layout may have
+/// moved on without the symbols saying so.
+pub const MAX_TESTED_VERSION: Version = Version { major: 11, minor: 7, build: 0, patch: 0, label: None };
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}.{}.{}{}", self.major, self.minor, self.build, self.patch, self.label.as_deref().unwrap_or(""))
+    }
 }
 
 #[derive(Default, Debug)]
 struct VMData {
     fixed: Fixed,
     frame_pointer: FramePointer,
+    context_index: ContextIndex,
     scope_info_index: ScopeInfoIndex,
     deoptimization_data_index: DeoptimizationDataIndex,
     code_kind: CodeKind,
@@ -24,12 +226,16 @@ struct VMData {
     map: Map,
     fixed_array_base: FixedArrayBase,
     fixed_array: FixedArray,
-    string: String,
+    string: V8String,
     seq_one_byte_string: SeqOneByteString,
     seq_two_byte_string: SeqTwoByteString,
     cons_string: ConsString,
     thin_string: ThinString,
+    external_string: ExternalString,
     jsfunction: JSFunction,
+    js_object: JSObject,
+    js_bound_function: JSBoundFunction,
+    js_wrapped_function: JSWrappedFunction,
     code: Code,
     shared_function_info: SharedFunctionInfo,
     baseline_data: BaselineData,
@@ -37,6 +243,12 @@ struct VMData {
     scope_info: ScopeInfo,
     deoptimization_literal_array: DeoptimizationLiteralArray,
     script: Script,
+    debug_info: DebugInfo,
+    isolate: Isolate,
+    thread_local_top: ThreadLocalTop,
+    debugger: Debugger,
+    native_context: NativeContext,
+    node_environment: NodeEnvironment,
 }
 
 #[derive(Default, Debug)]
@@ -55,8 +267,11 @@ struct Fixed {
     two_byte_string_tag: u16,
     sliced_string_tag: u16,
     thin_string_tag: u16,
+    external_string_tag: u16,
     first_jsfunction_type: u16,
     last_jsfunction_type: u16,
+    first_context_type: u16,
+    last_context_type: u16,
 }
 
 #[derive(Default, Debug)]
@@ -65,6 +280,70 @@ struct FramePointer {
     context: u8,
     bytecode_array: u8,
     bytecode_offset: u8,
+    /// Slot of an exit frame holding the stack pointer at the call out
+    exit_sp: u8,
+    /// Where a JavaScript frame's arguments start, above the return address
+    args: u8,
+}
+
+/// Computes a `FramePointer` offset for `FpOffset::fallback`
+type FpFallback = fn(&FramePointer, u32, usize) -> Option<i8>;
+
+/// How one of the `FramePointer` offsets is found: the names V8 has given
+/// its `off_fp_*` symbol, newest first, and what the offset was on versions
+/// that have none of them. Fallbacks run in table order, so they can build
+/// on the offsets before them.
+struct FpOffset {
+    field: fn(&mut FramePointer) -> &mut u8,
+    symbols: &'static [&'static str],
+    /// Whether V8 generates the symbol, so that lacking it is worth a warning
+    generated: bool,
+    /// The offset on V8 version `ver` (see `versions::v8_ver`), given the pointer size,
+    /// or `None` if it would be out of a byte's range
+    fallback: Option<FpFallback>,
+}
+
+const FP_OFFSETS: [FpOffset; 6] = [
+    FpOffset { field: |fps| &mut fps.function, symbols: &["v8dbg_off_fp_function"], generated: true, fallback: None },
+    FpOffset { field: |fps| &mut fps.context, symbols: &["v8dbg_off_fp_context"], generated: true, fallback: None },
+    FpOffset {
+        field: |fps| &mut fps.bytecode_array,
+        // Not available before V8 9.5.2. Since 8.7.198, the argument count
+        // sits between the function and the BytecodeArray.
+        symbols: &["v8dbg_off_fp_bytecode_array"],
+        generated: true,
+        fallback: Some(|fps, ver, pointer_size| {
+            let slots = if ver >= versions::ARGUMENT_COUNT_SLOT { 2 } else { 1 };
+            (fps.function as i8).checked_sub(slots * pointer_size as i8)
+        }),
+    },
+    FpOffset {
+        field: |fps| &mut fps.bytecode_offset,
+        // Not available before V8 9.5.2
+        symbols: &["v8dbg_off_fp_bytecode_offset"],
+        generated: true,
+        fallback: Some(|fps, _, pointer_size| (fps.bytecode_array as i8).checked_sub(pointer_size as i8)),
+    },
+    FpOffset {
+        field: |fps| &mut fps.exit_sp,
+        // ExitFrameConstants::kSPOffset, unchanged since at least V8 7.2
+        symbols: &["v8dbg_off_fp_exit_sp"],
+        generated: false,
+        fallback: Some(|_, _, pointer_size| Some(-2 * pointer_size as i8)),
+    },
+    FpOffset {
+        field: |fps| &mut fps.args,
+        // StandardFrameConstants::kCallerSPOffset, past the saved frame
+        // pointer and the return address
+        symbols: &["v8dbg_off_fp_args"],
+        generated: true,
+        fallback: Some(|_, _, pointer_size| Some(2 * pointer_size as i8)),
+    },
+];
+
+#[derive(Default, Debug)]
+struct ContextIndex {
+    previous: u8,
 }
 
 #[derive(Default, Debug)]
@@ -86,6 +365,7 @@ struct CodeKind {
     field_mask: u32,
     field_shift: u8,
     baseline: u8,
+    maglev: u8,
 }
 
 #[derive(Default, Debug)]
@@ -122,6 +402,8 @@ struct Type {
     byte_array: u16,
     bytecode_array: u16,
     code: u16,
+    debug_info: u16,
+    embedder_data_array: u16,
     fixed_array: u16,
     weak_fixed_array: u16,
     js_function: u16,
@@ -129,6 +411,15 @@ struct Type {
     script: u16,
     scope_info: u16,
     shared_function_info: u16,
+    asm_wasm_data: u16,
+    function_template_info: u16,
+    interpreter_data: u16,
+    uncompiled_data_with_preparse_data: u16,
+    uncompiled_data_with_preparse_data_and_job: u16,
+    uncompiled_data_without_preparse_data: u16,
+    uncompiled_data_without_preparse_data_with_job: u16,
+    js_bound_function: u16,
+    js_wrapped_function: u16,
 }
 
 #[derive(Default, Debug)]
@@ -139,6 +430,9 @@ struct HeapObject {
 #[derive(Default, Debug)]
 struct Map {
     instance_type: u16,
+    /// The constructor of the objects with this map, or for a map made by a
+    /// transition, the map it came from
+    constructor_or_back_pointer: u16,
 }
 
 #[derive(Default, Debug)]
@@ -152,7 +446,7 @@ struct FixedArray {
 }
 
 #[derive(Default, Debug)]
-struct String {
+struct V8String {
     length: u16,
 }
 
@@ -177,19 +471,58 @@ struct ThinString {
     actual: u16,
 }
 
+/// A string whose characters live outside the V8 heap, in a resource owned
+/// by the embedder
+#[derive(Default, Debug)]
+struct ExternalString {
+    resource: u16,
+    /// The resource's data pointer, cached unless the string is uncached
+    resource_data: u16,
+}
+
 #[derive(Default, Debug)]
 struct JSFunction {
     code: u16,
     shared_function_info: u16,
 }
 
+#[derive(Default, Debug)]
+struct JSObject {
+    /// Where the header of a JSObject ends, and the fields of its subclasses
+    /// start
+    internal_fields: u16,
+}
+
+/// A function returned by `Function.prototype.bind`, which calls its target
+#[derive(Default, Debug)]
+struct JSBoundFunction {
+    bound_target_function: u16,
+}
+
+/// A function passed into or out of a ShadowRealm, which calls its target
+#[derive(Default, Debug)]
+struct JSWrappedFunction {
+    wrapped_target_function: u16,
+}
+
 #[derive(Default, Debug)]
 struct Code {
     deoptimization_data: u16,
+    /// Where baseline code keeps the BytecodeArray or InterpreterData it
+    /// was compiled from
+    bytecode_or_interpreter_data: u16,
     source_position_table: u16,
     instruction_start: u16,
+    /// Whether `instruction_start` is a field holding the address of the
+    /// instructions, rather than the offset of instructions stored inline
+    instruction_start_is_pointer: bool,
     instruction_size: u16,
     flags: u16,
+    /// Offset of the builtin id, on versions that export it. Zero otherwise.
+    builtin_id: u16,
+    /// Whether the builtin id is an int32 `builtin_index`, as older versions
+    /// keep it, rather than an int16
+    builtin_id_is_int32: bool,
 }
 
 #[derive(Default, Debug)]
@@ -201,6 +534,7 @@ struct SharedFunctionInfo {
 
 #[derive(Default, Debug)]
 struct BaselineData {
+    baseline_code: u16,
     data: u16,
 }
 
@@ -225,20 +559,633 @@ struct Script {
     name: u16,
     line_ends: u16,
     source: u16,
+    /// From a `//# sourceURL=` comment in the source
+    source_url: u16,
+    /// From a `//# sourceMappingURL=` comment in the source
+    source_mapping_url: u16,
+    /// Only read to place `source_url` where it isn't exported
+    eval_from_position: u16,
+    compiled_lazy_function_positions: u16,
+}
+
+/// What `SharedFunctionInfo::script_or_debug_info` holds instead of the
+/// Script while the function is being debugged
+#[derive(Default, Debug)]
+struct DebugInfo {
+    shared: u16,
+    script: u16,
+}
+
+#[derive(Default, Debug)]
+struct Isolate {
+    thread_local_top: u16,
+    /// The isolate's `Debug`, which it points to
+    debug: u16,
+}
+
+#[derive(Default, Debug)]
+struct ThreadLocalTop {
+    c_entry_fp: u16,
+}
+
+/// V8's `Debug`, the debugger's state of an isolate
+#[derive(Default, Debug)]
+struct Debugger {
+    /// Whether a debugger, such as the inspector, is attached
+    is_active: u16,
+    /// The innermost `DebugScope` of its thread, set while the debugger has
+    /// it stopped
+    current_debug_scope: u16,
+}
+
+#[derive(Default, Debug)]
+struct NativeContext {
+    /// The EmbedderDataArray, where the embedder keeps its own state
+    embedder_data: u16,
 }
 
-pub struct V8Spy {
+/// Node's `node::Environment`, one for the main thread and each worker, from
+/// Node's own `nodedbg_` symbols
+#[derive(Default, Debug)]
+struct NodeEnvironment {
+    /// Index of the Environment in a native context's embedder data
+    context_index: u16,
+    handle_wrap_queue: u16,
+    /// The `threadId` of `worker_threads`, 0 on the main thread
+    thread_id: u16,
+}
+
+/// Appends the fields of one part of the VMData, named `part.field`.
+/// Destructuring without `..` makes a field missing from the list a compile
+/// error, so the pairs stay complete as fields are added.
+macro_rules! push_pairs {
+    ($pairs:ident, $part:ident: $ty:ident { $($field:ident),* $(,)? }) => {{
+        let $ty { $($field),* } = $part;
+        $($pairs.push((concat!(stringify!($part), ".", stringify!($field)), *$field as u64));)*
+    }};
+}
+
+impl VMData {
+    /// Every field as a `(part.field, value)` pair, sorted by name, so that
+    /// dumps from different versions and runs line up for diffing
+    fn to_sorted_pairs(&self) -> Vec<(&'static str, u64)> {
+        let VMData {
+            fixed,
+            frame_pointer,
+            context_index,
+            scope_info_index,
+            deoptimization_data_index,
+            code_kind,
+            frame_type,
+            typ,
+            heap_object,
+            map,
+            fixed_array_base,
+            fixed_array,
+            string,
+            seq_one_byte_string,
+            seq_two_byte_string,
+            cons_string,
+            thin_string,
+            external_string,
+            jsfunction,
+            js_object,
+            js_bound_function,
+            js_wrapped_function,
+            code,
+            shared_function_info,
+            baseline_data,
+            bytecode_array,
+            scope_info,
+            deoptimization_literal_array,
+            script,
+            debug_info,
+            isolate,
+            thread_local_top,
+            debugger,
+            native_context,
+            node_environment,
+        } = self;
+        let mut pairs = Vec::new();
+        push_pairs!(pairs, fixed: Fixed {
+            heap_object_tag_mask,
+            smi_tag_mask,
+            heap_object_tag,
+            smi_tag,
+            smi_shift_size,
+            first_nonstring_type,
+            string_encoding_mask,
+            string_representation_mask,
+            seq_string_tag,
+            cons_string_tag,
+            one_byte_string_tag,
+            two_byte_string_tag,
+            sliced_string_tag,
+            thin_string_tag,
+            external_string_tag,
+            first_jsfunction_type,
+            last_jsfunction_type,
+            first_context_type,
+            last_context_type,
+        });
+        push_pairs!(pairs, frame_pointer: FramePointer { function, context, bytecode_array, bytecode_offset, exit_sp, args });
+        push_pairs!(pairs, context_index: ContextIndex { previous });
+        push_pairs!(pairs, scope_info_index: ScopeInfoIndex { first_vars, ncontext_locals });
+        push_pairs!(pairs, deoptimization_data_index: DeoptimizationDataIndex {
+            inlined_function_count,
+            literal_array,
+            shared_function_info,
+            inlining_positions,
+        });
+        push_pairs!(pairs, code_kind: CodeKind { field_mask, field_shift, baseline, maglev });
+        push_pairs!(pairs, frame_type: FrameType {
+            arguments_adaptor_frame,
+            baseline_frame,
+            builtin_continuation_frame,
+            builtin_exit_frame,
+            builtin_frame,
+            cwasm_entry_frame,
+            construct_entry_frame,
+            construct_frame,
+            entry_frame,
+            exit_frame,
+            internal_frame,
+            interpreted_frame,
+            java_script_builtin_continuation_frame,
+            java_script_builtin_continuation_with_catch_frame,
+            java_script_frame,
+            js_to_wasm_frame,
+            native_frame,
+            optimized_frame,
+            stub_frame,
+            wasm_compile_lazy_frame,
+            wasm_compiled_frame,
+            wasm_exit_frame,
+            wasm_interpreter_entry_frame,
+            wasm_to_js_frame,
+        });
+        push_pairs!(pairs, typ: Type {
+            baseline_data,
+            byte_array,
+            bytecode_array,
+            code,
+            debug_info,
+            embedder_data_array,
+            fixed_array,
+            weak_fixed_array,
+            js_function,
+            map,
+            script,
+            scope_info,
+            shared_function_info,
+            asm_wasm_data,
+            function_template_info,
+            interpreter_data,
+            uncompiled_data_with_preparse_data,
+            uncompiled_data_with_preparse_data_and_job,
+            uncompiled_data_without_preparse_data,
+            uncompiled_data_without_preparse_data_with_job,
+            js_bound_function,
+            js_wrapped_function,
+        });
+        push_pairs!(pairs, heap_object: HeapObject { map });
+        push_pairs!(pairs, map: Map { instance_type, constructor_or_back_pointer });
+        push_pairs!(pairs, fixed_array_base: FixedArrayBase { length });
+        push_pairs!(pairs, fixed_array: FixedArray { data });
+        push_pairs!(pairs, string: V8String { length });
+        push_pairs!(pairs, seq_one_byte_string: SeqOneByteString { chars });
+        push_pairs!(pairs, seq_two_byte_string: SeqTwoByteString { chars });
+        push_pairs!(pairs, cons_string: ConsString { first, second });
+        push_pairs!(pairs, thin_string: ThinString { actual });
+        push_pairs!(pairs, external_string: ExternalString { resource, resource_data });
+        push_pairs!(pairs, jsfunction: JSFunction { code, shared_function_info });
+        push_pairs!(pairs, js_object: JSObject { internal_fields });
+        push_pairs!(pairs, js_bound_function: JSBoundFunction { bound_target_function });
+        push_pairs!(pairs, js_wrapped_function: JSWrappedFunction { wrapped_target_function });
+        push_pairs!(pairs, code: Code {
+            deoptimization_data,
+            source_position_table,
+            instruction_start,
+            instruction_start_is_pointer,
+            instruction_size,
+            flags,
+            builtin_id,
+            builtin_id_is_int32,
+            bytecode_or_interpreter_data,
+        });
+        push_pairs!(pairs, shared_function_info: SharedFunctionInfo { name_or_scope_info, function_data, script_or_debug_info });
+        push_pairs!(pairs, baseline_data: BaselineData { baseline_code, data });
+        push_pairs!(pairs, bytecode_array: BytecodeArray { source_position_table, data });
+        push_pairs!(pairs, scope_info: ScopeInfo { heap_object });
+        push_pairs!(pairs, deoptimization_literal_array: DeoptimizationLiteralArray { weak_fixed_array });
+        push_pairs!(pairs, script: Script { name, line_ends, source, source_url, source_mapping_url, eval_from_position, compiled_lazy_function_positions });
+        push_pairs!(pairs, debug_info: DebugInfo { shared, script });
+        push_pairs!(pairs, isolate: Isolate { thread_local_top, debug });
+        push_pairs!(pairs, thread_local_top: ThreadLocalTop { c_entry_fp });
+        push_pairs!(pairs, debugger: Debugger { is_active, current_debug_scope });
+        push_pairs!(pairs, native_context: NativeContext { embedder_data });
+        push_pairs!(pairs, node_environment: NodeEnvironment { context_index, handle_wrap_queue, thread_id });
+        pairs.sort_unstable_by_key(|&(name, _)| name);
+        pairs
+    }
+}
+
+type UnresolvedFrameCallback = Box<dyn FnMut(&FrameDebugRecord)>;
+
+/// A frame resolved by `expand_inlined_frames`, with what it was resolved
+/// at. The same function can be at another line in a later sample at the
+/// same fp.
+struct CachedFrame {
+    pc: usize,
+    bytecode_offset: Option<usize>,
+    resolved: Vec<ResolvedFrame>,
+}
+
+/// Samples the stacks of a V8 process. Memory is read through `P`, which is
+/// the live process itself unless created with `from_memory`.
+///
+/// A spy isn't `Send`, as the symbol tables used for native frames aren't.
+/// To sample from another thread, use a `SamplerHandle`, which creates the
+/// spy on the thread that uses it.
+///
+/// Each pause, as in `with_process_paused`, is a guard that resumes the
+/// threads when dropped, including when a panic unwinds through it. A pause
+/// held across calls with `pause` is released when the spy is dropped, so
+/// the process is never left suspended once the spy is gone.
+pub struct V8Spy<P = Process> {
     pub pid: Pid,
-    pub process: Process,
+    pub process: P,
     pub version: Version,
+    /// Filters applied to every stack before it's added to the profile
+    pub filter: FilterOptions,
+    /// Name builtins from the tables in `builtins`, where there's one for the
+    /// version, rather than as `builtin#<id>`
+    pub demangle_builtins: bool,
+    /// Name JavaScript frames after the class of their receiver as well, as
+    /// `Class.method`, which takes a few more reads for each frame
+    pub receiver_classes: bool,
+    vms: VMData,
+    pointer_size: usize,
+    /// Whether tagged fields on the heap are 32-bit offsets from `cage_base`
+    compression_enabled: bool,
+    /// Base address of the pointer compression cage, or 0 until known. Taken
+    /// from V8's symbols where exported, and otherwise from the first full
+    /// pointer to a heap object found on the stack.
+    cage_base: Cell<usize>,
+    /// Instruction ranges of recently seen optimized code, keyed by start
+    /// address, mapping to the range end and the tagged Code pointer
+    code_ranges: RefCell<BTreeMap<usize, (usize, usize)>>,
+    /// Address ranges of the garbage collector's native code, sorted by start
+    gc_ranges: Vec<(usize, usize)>,
+    /// Address ranges of libuv's event loop phases, sorted by start
+    event_loop_ranges: Vec<(usize, usize, EventLoopPhase)>,
+    /// Address ranges of V8's embedded builtins, keyed by start, mapping to
+    /// the range end and the builtin's name
+    builtin_ranges: BTreeMap<usize, (usize, String)>,
+    /// Threads that have been seen running JavaScript, whose samples are kept
+    /// as `<idle>`, `<gc>` or `<native>` when no JavaScript is on the stack
+    js_threads: RefCell<HashSet<Tid>>,
+    stats: Cell<SampleStats>,
+    /// Names for native frames, if enabled with `enable_native_symbols`
+    native_symbols: Option<NativeSymbols>,
+    /// Called for frames that can't be resolved, if set with
+    /// `on_unresolved_frame`
+    on_unresolved_frame: RefCell<Option<UnresolvedFrameCallback>>,
+    /// Only threads with this name are sampled, if set with
+    /// `set_thread_filter`
+    thread_filter: Option<String>,
+    /// Only this thread is sampled, if set with `set_worker_filter`
+    worker_filter: Option<Tid>,
+    /// The isolate stacks are walked from when a thread's own frame pointer
+    /// chain doesn't reach JavaScript, if set with `set_isolate`
+    isolate: Option<IsolateRef>,
+    /// The mappings of a live process, which every read is checked against,
+    /// and when they were last loaded
+    memory_map: Option<RefCell<MemoryMap>>,
+    memory_map_loaded: Cell<Instant>,
+    /// Resolved JavaScript frames keyed by fp and JSFunction, if enabled with
+    /// `enable_frame_cache`
+    frame_cache: Option<RefCell<HashMap<(usize, usize), CachedFrame>>>,
+    /// Where V8's symbols were found in a live process
+    v8_module: Option<PathBuf>,
+    /// From `V8SpyOptions::read_timeout`
+    read_timeout: Option<Duration>,
+    /// Whether a read has timed out since the last stack walk began
+    read_timed_out: Cell<bool>,
+    /// The guard of the pause taken by `pause`, until `resume` or the spy is
+    /// dropped
+    held_pause: RefCell<Option<Box<dyn Any>>>,
+}
+
+/// Counts of the stacks sampled from JavaScript threads, to judge how
+/// reliable a profile is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SampleStats {
+    /// Stacks sampled, including those that failed
+    pub total: u64,
+    /// Stacks that couldn't be unwound at all, from any thread
+    pub unwind_errors: u64,
+    /// Stacks cut off at the maximum depth
+    pub truncated: u64,
+    /// Stacks of threads that were idle, whether or not they were kept
+    pub idle: u64,
+    /// Stacks left out because a frame was caught half set up
+    pub torn: u64,
+    /// Stacks left out because a read took longer than
+    /// `V8SpyOptions::read_timeout`
+    pub read_timeouts: u64,
+    /// Times the process was sampled by `sample_loop` or `sample_stream`,
+    /// each covering all of its threads
+    pub rounds: u64,
+    /// Time spent in `sample_loop` and `sample_stream`
+    pub sampling_time: Duration,
+    /// The rate sampling was last asked to run at, per second
+    pub requested_rate: u32,
+}
+
+impl SampleStats {
+    /// The rate the process was actually sampled at, per second, which falls
+    /// short of `requested_rate` when sampling takes longer than its interval
+    pub fn achieved_rate(&self) -> f64 {
+        if self.sampling_time.is_zero() {
+            0.0
+        } else {
+            self.rounds as f64 / self.sampling_time.as_secs_f64()
+        }
+    }
+
+    /// Adds the counts of another run, as of another spy sampling the same
+    /// process after it restarted
+    pub fn merge(&mut self, other: &SampleStats) {
+        self.total += other.total;
+        self.unwind_errors += other.unwind_errors;
+        self.truncated += other.truncated;
+        self.idle += other.idle;
+        self.torn += other.torn;
+        self.read_timeouts += other.read_timeouts;
+        self.add_run(other.requested_rate, other.rounds, other.sampling_time);
+    }
+
+    fn add_run(&mut self, rate: u32, rounds: u64, elapsed: Duration) {
+        self.rounds += rounds;
+        self.sampling_time += elapsed;
+        self.requested_rate = rate;
+    }
+}
+
+/// What the flags of a Code object say about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeFlags {
+    /// The CodeKind, as numbered by the process's V8 version
+    pub kind: u8,
+    /// Whether TurboFan compiled the code. Maglev code is optimized code as
+    /// well, but without this set.
+    pub is_turbofanned: bool,
+    /// Which compiler produced the code, if it's optimized code
+    pub tier: Option<Tier>,
+    /// Which builtin the code is, on V8 versions that make it known
+    pub builtin_id: Option<u16>,
+}
+
+/// The phase of Node's libuv event loop a thread is in, told by the libuv
+/// function running it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventLoopPhase {
+    /// Running expired `setTimeout` and `setInterval` callbacks
+    Timers,
+    /// Running I/O callbacks deferred from the last iteration
+    Pending,
+    Idle,
+    Prepare,
+    /// Waiting for I/O, or running its callbacks
+    Poll,
+    /// Running `setImmediate` callbacks
+    Check,
+    /// Running `close` callbacks
+    Close,
+}
+
+impl EventLoopPhase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventLoopPhase::Timers => "timers",
+            EventLoopPhase::Pending => "pending",
+            EventLoopPhase::Idle => "idle",
+            EventLoopPhase::Prepare => "prepare",
+            EventLoopPhase::Poll => "poll",
+            EventLoopPhase::Check => "check",
+            EventLoopPhase::Close => "close",
+        }
+    }
+}
+
+/// A `v8::internal::Isolate` in the target, for `V8Spy::thread_local_top`,
+/// as found from the embedder's own bookkeeping or with a debugger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsolateRef {
+    pub address: usize,
+}
+
+/// What a SharedFunctionInfo's function data holds, as told by
+/// `V8Spy::function_data_kind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionDataKind {
+    /// Compiled to bytecode, kept directly or in an InterpreterData
+    Bytecode { bytecode_array: usize },
+    /// Compiled by Sparkplug. The bytecode it was compiled from is still
+    /// there, unless it couldn't be read.
+    Baseline { code: usize, bytecode_array: Option<usize> },
+    /// asm.js compiled to WebAssembly
+    AsmWasm,
+    /// Not compiled yet, or its bytecode was flushed
+    Uncompiled,
+    /// An API function, backed by a FunctionTemplateInfo
+    Api,
+    /// A builtin, by its id
+    Builtin(i64),
+    /// Some other instance type
+    Other(u16),
+}
+
+impl FunctionDataKind {
+    /// The BytecodeArray reachable from the function data, if any
+    pub fn bytecode_array(&self) -> Option<usize> {
+        match *self {
+            FunctionDataKind::Bytecode { bytecode_array } => Some(bytecode_array),
+            FunctionDataKind::Baseline { bytecode_array, .. } => bytecode_array,
+            _ => None,
+        }
+    }
+}
+
+/// A Script found by `V8Spy::list_scripts`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptInfo {
+    /// Tagged pointer to the Script
+    pub address: usize,
+    /// The script's URL or file name, if it has one
+    pub name: Option<String>,
+    /// The URL from the script's `//# sourceURL=` comment, if it has one
+    pub source_url: Option<String>,
+    /// The URL of the script's source map, from its `//# sourceMappingURL=`
+    /// comment
+    pub source_mapping_url: Option<String>,
+}
+
+/// A thread of the process, as captured by `V8Spy::snapshot`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadInfo {
+    pub tid: Tid,
+    /// The thread's name, as in /proc/<pid>/task/<tid>/comm
+    pub name: Option<String>,
+}
+
+/// Every JavaScript thread's stack at one point in time, resolved, as taken
+/// by `V8Spy::snapshot`. Displays as a report of each thread's stack,
+/// innermost frame first.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub threads: Vec<(ThreadInfo, Vec<ResolvedFrame>)>,
+    pub captured_at: SystemTime,
+}
+
+impl std::fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, (thread, stack)) in self.threads.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match &thread.name {
+                Some(name) => writeln!(f, "Thread {} ({}):", thread.tid, name)?,
+                None => writeln!(f, "Thread {}:", thread.tid)?,
+            }
+            for frame in stack {
+                writeln!(f, "    {}", frame)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A step of `V8Spy::selftest`, in the order they're taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestStep {
+    /// Attaching to the process and finding V8's symbols in it
+    Attach,
+    /// Reading the V8 version and checking that it's supported
+    Version,
+    /// Reading the layout of V8's objects and frames
+    Offsets,
+    /// Walking a thread's stack down through JavaScript frames
+    Unwinding,
+    /// Naming the JavaScript frames of that stack
+    Resolution,
+}
+
+impl std::fmt::Display for SelfTestStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            SelfTestStep::Attach => "attach",
+            SelfTestStep::Version => "version read",
+            SelfTestStep::Offsets => "offset resolution",
+            SelfTestStep::Unwinding => "unwinding",
+            SelfTestStep::Resolution => "resolution",
+        })
+    }
+}
+
+/// What `V8Spy::selftest` made of a process. Displays as `PASS (12 frames)`,
+/// or as `FAIL at unwinding: ...` with the step that failed and why.
+#[derive(Debug)]
+pub enum SelfTest {
+    /// A stack was walked and each of its JavaScript frames resolved
+    Pass { frames: usize },
+    Fail { step: SelfTestStep, error: anyhow::Error },
+}
+
+impl SelfTest {
+    pub fn passed(&self) -> bool {
+        matches!(self, SelfTest::Pass { .. })
+    }
+
+    fn from_result(result: std::result::Result<usize, (SelfTestStep, anyhow::Error)>) -> Self {
+        match result {
+            Ok(frames) => SelfTest::Pass { frames },
+            Err((step, error)) => SelfTest::Fail { step, error },
+        }
+    }
+}
+
+impl std::fmt::Display for SelfTest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SelfTest::Pass { frames } => write!(f, "PASS ({} frames)", frames),
+            SelfTest::Fail { step, error } => write!(f, "FAIL at {}: {:#}", step, error),
+        }
+    }
+}
+
+/// Paces sampling to a fixed schedule: the deadline of each sample is set a
+/// whole interval after the one before, so the time taken by a sample comes
+/// out of the wait for the next one rather than adding to it. When sampling
+/// falls behind by more than an interval, the missed deadlines are skipped
+/// instead of sampling in a burst to catch up.
+pub struct Deadlines {
+    interval: Duration,
+    next: Instant,
+}
+
+impl Deadlines {
+    pub fn new(rate: u32) -> Self {
+        Deadlines { interval: Duration::from_secs(1) / rate, next: Instant::now() }
+    }
+
+    /// Sleeps until the next deadline
+    pub fn wait(&mut self) {
+        self.next += self.interval;
+        let now = Instant::now();
+        if self.next > now {
+            std::thread::sleep(self.next - now);
+        } else {
+            self.next = now;
+        }
+    }
+}
+
+impl std::fmt::Display for SampleStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let percent = |count: u64| if self.total == 0 { 0.0 } else { 100.0 * count as f64 / self.total as f64 };
+        write!(
+            f,
+            "{} samples, {} unwind errors ({:.2}%), {} truncated ({:.2}%), {} idle ({:.2}%), {} torn ({:.2}%)",
+            self.total,
+            self.unwind_errors,
+            percent(self.unwind_errors),
+            self.truncated,
+            percent(self.truncated),
+            self.idle,
+            percent(self.idle),
+            self.torn,
+            percent(self.torn)
+        )?;
+        if self.read_timeouts != 0 {
+            write!(f, ", {} read timeouts ({:.2}%)", self.read_timeouts, percent(self.read_timeouts))?;
+        }
+        if self.requested_rate != 0 {
+            write!(f, ", sampled at {:.1}/s of {}/s requested", self.achieved_rate(), self.requested_rate)?;
+        }
+        Ok(())
+    }
 }
 
 impl V8Spy {
     pub fn new(pid: Pid) -> Result<Self> {
-        let process = remoteprocess::Process::new(pid)
-            .context(format!("Failed to open process {} - check if it is running.", pid))?;
+        Self::with_options(pid, V8SpyOptions::default())
+    }
 
-        let process_info = ProcessInfo::new::<spytools::process::NodeProcessType>(&process)?;
+    /// Like `new`, with options for how to attach
+    pub fn with_options(pid: Pid, options: V8SpyOptions) -> Result<Self> {
+        let (process, process_info) = open_process(pid)?;
 
         // lock the process when loading up on freebsd (rather than locking
         // on every memory read). Needs done after getting python process info
@@ -246,307 +1193,4521 @@ impl V8Spy {
         #[cfg(target_os = "freebsd")]
         let _lock = process.lock();
 
-        let version = get_v8_version(&process_info, &process);
-        println!("v8 version: {}.{}.{}.{}", version.major, version.minor, version.build, version.patch);
+        let libraries = load_shared_libraries(pid, &process_info);
+        let target = LiveTarget { process_info: &process_info, libraries: &libraries, process: &process };
+        if !has_v8_symbols(&target) {
+            return Err(Error::NotAV8Process(pid).into());
+        }
+        // With ptrace_scope 1, a process of our own can be listed but not
+        // traced, and every read of its memory would fail the same way
+        if let Err(e) = process.lock() {
+            if is_permission_denied(&anyhow::Error::new(e)) {
+                return Err(Error::PermissionDenied(pid).into());
+            }
+        }
+
+        let v8_module = V8_SENTINEL_SYMBOLS.iter().find_map(|symbol| target.symbol_module(symbol));
+        if let Some(module) = &v8_module {
+            log::info!("Found V8's symbols in {}", module.display());
+        }
+        let (version, vms) = read_layout(&target, &options)?;
+        let cage_base = read_cage_base(&target, target_pointer_size(&vms));
+        let gc_ranges = get_gc_ranges(&target);
+        let event_loop_ranges = get_event_loop_ranges(&target);
+        let builtin_ranges = get_builtin_ranges(&target);
+        let mut spy = Self::with_layout(pid, process, version, vms, cage_base, gc_ranges, event_loop_ranges);
+        spy.v8_module = v8_module;
+        spy.read_timeout = options.read_timeout;
+        spy.builtin_ranges = builtin_ranges;
+        spy.memory_map = Some(RefCell::new(MemoryMap::load(pid)?));
+        spy.validate_smi_constants()?;
+        Ok(spy)
+    }
+
+    /// The executable or shared library that V8's symbols were found in, such
+    /// as the node binary, or libnode where Node is linked against it. `None`
+    /// unless attached to a live process.
+    pub fn v8_module(&self) -> Option<&Path> {
+        self.v8_module.as_deref()
+    }
 
-        let mut vms = get_v8_data(&process_info, &process);
-        println!("{:?}", vms);
+    /// Whether the process embeds V8, judged by the presence of its version
+    /// symbols in the binaries that `ProcessInfo` parsed
+    pub fn is_v8_process(process_info: &ProcessInfo) -> bool {
+        V8_SENTINEL_SYMBOLS.iter().any(|symbol| process_info.get_symbol(symbol).is_some())
+    }
 
-        let ver = v8_ver(version.major, version.minor, version.build);
-        let pointer_size = 8;
+    /// Reads the V8 version of a process without attaching to it, or `None` if
+    /// it doesn't embed V8 or its version can't be read. Never fails, for
+    /// scanning a list of processes for Node ones.
+    pub fn try_version(process_info: &ProcessInfo, process: &remoteprocess::Process) -> Option<Version> {
+        let libraries = load_shared_libraries(process.pid, process_info);
+        version_of(&LiveTarget { process_info, libraries: &libraries, process })
+    }
 
-        // Add some defaults when needed
-        if vms.frame_pointer.bytecode_array == 0 {
-            // Not available before V8 9.5.2
-            if ver >= v8_ver(8, 7, 198) {
-                vms.frame_pointer.bytecode_array = vms.frame_pointer.function - 2 * pointer_size;
-            } else {
-                vms.frame_pointer.bytecode_array = vms.frame_pointer.function - 1 * pointer_size;
-            }
-        }
-        if vms.frame_pointer.bytecode_offset == 0 {
-            // Not available before V8 9.5.2
-            vms.frame_pointer.bytecode_offset = vms.frame_pointer.bytecode_array - pointer_size;
-        }
-        if vms.fixed.first_jsfunction_type == 0 {
-            // Since V8 9.0.14 the JSFunction is no longer a final class, but has several
-            // classes inheriting form it. The only way to check for the inheritance is to
-            // know which InstaceType tags belong to the range.
-            let mut num_jsfunc_types = 1u16;
-            if ver >= v8_ver(9, 6, 138) {
-                // Class constructor special case
-                num_jsfunc_types = 15;
-            } else if ver >= v8_ver(9, 0, 14) {
-                // Several constructor special cases added
-                num_jsfunc_types = 14;
-            }
-            vms.fixed.first_jsfunction_type = vms.typ.js_function;
-            vms.fixed.last_jsfunction_type = vms.fixed.first_jsfunction_type + num_jsfunc_types - 1;
-        }
-        if vms.jsfunction.code == 0 {
-            if ver >= v8_ver(11, 7, 368) {
-                vms.jsfunction.code = vms.jsfunction.shared_function_info - pointer_size as u16;
-            } else {
-                // At least back to V8 8.4
-                vms.jsfunction.code = vms.jsfunction.shared_function_info + 3 * pointer_size as u16;
-            }
+    /// Attaches to every running Node process whose executable or arguments
+    /// contain `name`. Returns an error if nothing matches.
+    pub fn from_process_name(name: &str) -> Result<Vec<Self>> {
+        Self::from_process_name_with_options(name, V8SpyOptions::default())
+    }
+
+    /// Like `from_process_name`, attaching to each process with `options`
+    pub fn from_process_name_with_options(name: &str, options: V8SpyOptions) -> Result<Vec<Self>> {
+        let candidates: Vec<ProcessCandidate> = list_processes()?
+            .into_iter()
+            .filter(|candidate| candidate.pid != std::process::id() as Pid && candidate.matches(name))
+            .collect();
+        if candidates.is_empty() {
+            anyhow::bail!("No Node processes found matching '{}'", name);
         }
-        if vms.code.instruction_size != 0 {
-            if vms.code.source_position_table == 0 {
-                // At least back to V8 8.4
-                vms.code.source_position_table = vms.code.instruction_size - 2 * pointer_size as u16;
-            }
-            if vms.code.flags == 0 {
-                // Back to V8 8.8.172
-                vms.code.flags = vms.code.instruction_size + 2 * 4; // 2 * sizeof(int)
-            }
-        } else if vms.code.source_position_table != 0 {
-            // Likely V8 11.x where the Code postmortem data was accidentally deleted
-            if vms.code.deoptimization_data == 0 {
-                vms.code.deoptimization_data = vms.code.source_position_table - pointer_size as u16;
+        let mut spies = Vec::new();
+        for candidate in &candidates {
+            match Self::with_options(candidate.pid, options) {
+                Ok(spy) => spies.push(spy),
+                Err(e) if matches!(e.downcast_ref::<Error>(), Some(Error::NotAV8Process(_))) => continue,
+                Err(e) => return Err(e),
             }
-            if vms.code.instruction_start == 0 {
-                vms.code.instruction_start = vms.code.source_position_table + 2 * pointer_size as u16;
+        }
+        if spies.is_empty() {
+            anyhow::bail!("No Node processes found matching '{}'", name);
+        }
+        Ok(spies)
+    }
+
+    /// Samples at `rate` per second until `duration` has passed, `running` is
+    /// cleared, or the process exits. `on_sample` is called with the profile
+    /// after every sample. Samples are taken on a fixed schedule, so the
+    /// time spent sampling doesn't lower the rate. Returns the spy's stats
+    /// once sampling stops.
+    pub fn sample_loop(
+        &self,
+        rate: u32,
+        duration: Option<Duration>,
+        running: &AtomicBool,
+        profile: &mut Profile,
+        mut on_sample: impl FnMut(&Profile) -> Result<()>,
+    ) -> Result<SampleStats> {
+        self.sample_on_schedule(rate, duration, running, || {
+            if let Err(e) = self.sample(profile) {
+                if self.has_exited() {
+                    // The process exited underneath us
+                    return Ok(false);
+                }
+                return Err(e);
             }
-            if vms.code.flags == 0 {
-                vms.code.flags = vms.code.instruction_start + pointer_size as u16;
+            on_sample(profile)?;
+            Ok(true)
+        })
+    }
+
+    /// Like `sample_loop`, but hands every stack to `on_sample` as soon as
+    /// it's taken instead of adding it to a profile, for forwarding samples
+    /// elsewhere as they happen, along with the thread it's from and when
+    /// the process was paused to take it. The callback runs after the
+    /// process has been resumed, and sampling waits for it to return. An
+    /// error from the callback stops sampling and is returned.
+    pub fn sample_stream(
+        &self,
+        rate: u32,
+        duration: Option<Duration>,
+        running: &AtomicBool,
+        on_sample: impl FnMut(Instant, Tid, &[ResolvedFrame]) -> Result<()>,
+    ) -> Result<SampleStats> {
+        let take_stacks = || {
+            let mut stacks = Vec::new();
+            let sampled = self.with_process_paused(|| {
+                let taken_at = Instant::now();
+                self.sample_threads(&mut |tid, stack| stacks.push((tid, stack))).map(|()| taken_at)
+            });
+            match sampled.and_then(|result| result) {
+                Ok(taken_at) => Ok(Some((taken_at, stacks))),
+                Err(_) if self.has_exited() => Ok(None),
+                Err(e) => Err(e),
             }
-            if vms.code.instruction_size == 0 {
-                vms.code.instruction_size = vms.code.flags + 4;
-                if ver >= v8_ver(11, 4, 59) {
-                    // V8 starting 11.1.x Code has kBuiltinIdOffset and kKindSpecificFlagsOffset
-                    // which changed again in 11.4.59 when these were removed in commit
-                    // cb8be519f0add9b7 "[code] Merge kind_specific_flags with flags"
-                    vms.code.instruction_size += 2 + 2;
+        };
+        self.stream_on_schedule(rate, duration, running, take_stacks, on_sample)
+    }
+
+    /// Names native frames after the C++ function they're in, instead of
+    /// `<native>`. Resolving symbols slows sampling down, more so at first
+    /// while each binary's symbols are loaded.
+    pub fn enable_native_symbols(&mut self) -> Result<()> {
+        self.native_symbols = Some(NativeSymbols::new(self.pid)?);
+        Ok(())
+    }
+
+    /// Samples only the threads named `name`, as set with
+    /// `pthread_setname_np` and shown in `/proc/<pid>/task/<tid>/comm`, such
+    /// as a worker thread running its own isolate. Fails, listing the names
+    /// there are, if no thread of the process has the name.
+    pub fn set_thread_filter(&mut self, name: &str) -> Result<()> {
+        let mut names = Vec::new();
+        for thread in self.process.threads()? {
+            if let Some(comm) = thread_name(self.pid, thread.id()?) {
+                if thread_name_matches(&comm, name) {
+                    self.thread_filter = Some(name.to_owned());
+                    return Ok(());
                 }
+                names.push(comm);
             }
         }
-        if vms.code.deoptimization_data == 0 && vms.code.source_position_table != 0 {
-            // Used unconditionally, pending patch for V8 to export this
-            // At least back to V8 7.2
-            vms.code.deoptimization_data = vms.code.source_position_table - pointer_size as u16;
-        }
-        if vms.script.source == 0 {
-            // At least back to V8 8.4
-            vms.script.source = vms.script.name - pointer_size as u16;
+        names.sort();
+        names.dedup();
+        anyhow::bail!("No thread of process {} is named '{}', only: {}", self.pid, name, names.join(", "))
+    }
+
+    /// Samples only the thread of the worker whose `threadId` in Node's
+    /// `worker_threads` is `thread_id`, or the main thread for 0. Workers are
+    /// told apart by the JavaScript they run, so the threads are looked at
+    /// for up to two seconds until the worker runs some. Fails, listing the
+    /// workers seen, if it doesn't.
+    pub fn set_worker_filter(&mut self, thread_id: u64) -> Result<()> {
+        let start = Instant::now();
+        let mut seen = BTreeMap::new();
+        while start.elapsed() < WORKER_SEARCH_TIMEOUT {
+            seen.extend(self.with_process_paused(|| self.worker_threads())??);
+            if let Some(&tid) = seen.get(&thread_id) {
+                self.worker_filter = Some(tid);
+                return Ok(());
+            }
+            std::thread::sleep(SELFTEST_INTERVAL);
         }
-        if vms.bytecode_array.source_position_table == 0 {
-            // Lost in V8 9.4
-            vms.bytecode_array.source_position_table = vms.fixed_array_base.length + 3 * pointer_size as u16;
+        let workers: Vec<String> = seen.iter().map(|(id, tid)| format!("{} (thread {})", id, tid)).collect();
+        if workers.is_empty() {
+            anyhow::bail!("No worker with threadId {} ran JavaScript in process {}, nor did any other", thread_id, self.pid);
         }
-        if vms.bytecode_array.data == 0 {
-            // At least back to V8 8.4 (16 = 3*int32 + uint16)
-            vms.bytecode_array.data = vms.bytecode_array.source_position_table + pointer_size as u16 + 14;
+        anyhow::bail!("No worker with threadId {} ran JavaScript in process {}, only: {}", thread_id, self.pid, workers.join(", "))
+    }
+
+    /// The `threadId` of each thread running JavaScript, as given by
+    /// `frame_node_thread_id`, mapped to the thread. Call with the process
+    /// paused. Threads that aren't running JavaScript can't be told and are
+    /// left out.
+    pub fn worker_threads(&self) -> Result<BTreeMap<u64, Tid>> {
+        let mut workers = BTreeMap::new();
+        for thread in self.process.threads()? {
+            let tid = thread.id()?;
+            let frames = match self.get_stack_trace(&thread) {
+                Ok(frames) => frames,
+                // e.g. the thread exited after being listed
+                Err(_) => continue,
+            };
+            let thread_id = frames
+                .iter()
+                .filter(|frame| frame.kind.is_js())
+                .find_map(|frame| self.frame_node_thread_id(frame.fp).ok().flatten());
+            if let Some(thread_id) = thread_id {
+                workers.insert(thread_id, tid);
+            }
         }
-        if vms.deoptimization_data_index.inlined_function_count == 0 {
-            vms.deoptimization_data_index.inlined_function_count = 1;
+        Ok(workers)
+    }
+
+    /// Whether the process has exited since the spy attached to it
+    pub fn has_exited(&self) -> bool {
+        self.process.exe().is_err()
+    }
+
+    /// Counts of the samples taken so far, over the life of the spy
+    pub fn stats(&self) -> SampleStats {
+        self.stats.get()
+    }
+
+    /// Takes one sample of every thread's JavaScript stack and adds it to the
+    /// profile. JavaScript threads that aren't running JavaScript are counted
+    /// under a synthetic frame, so the profile accounts for all of their
+    /// time, except for time spent idle unless `filter.include_idle` is set.
+    pub fn sample(&self, profile: &mut Profile) -> Result<()> {
+        self.with_process_paused(|| self.sample_threads(&mut |_, stack| profile.add_sample(stack)))?
+    }
+
+    /// Runs `f` with every thread of the process suspended, so that it sees
+    /// a consistent snapshot of the stacks and heap. The threads are resumed
+    /// once `f` returns, including when it fails or panics, unless they were
+    /// suspended by `pause` to begin with.
+    pub fn with_process_paused<R>(&self, f: impl FnOnce() -> R) -> Result<R> {
+        if self.held_pause.borrow().is_some() {
+            return Ok(f());
         }
-        if vms.deoptimization_data_index.literal_array == 0 {
-            let val = vms.deoptimization_data_index.inlined_function_count + 1;
-            vms.deoptimization_data_index.literal_array = val;
+        run_paused(&self.process, self.pid, f)
+    }
+
+    /// Suspends every thread of the process until `resume` is called or the
+    /// spy is dropped, for several calls that should all see the process in
+    /// the same state. Calls that pause the process themselves, such as
+    /// `sample`, run within this pause rather than taking their own. Does
+    /// nothing if the process is already paused.
+    pub fn pause(&self) -> Result<()> {
+        hold_pause(&self.process, self.pid, &self.held_pause)
+    }
+
+    /// Resumes the process if it was suspended by `pause`
+    pub fn resume(&self) {
+        self.held_pause.borrow_mut().take();
+    }
+
+    /// Samples every thread, passing each resulting stack to `add_sample`
+    /// with the thread's id
+    fn sample_threads(&self, add_sample: &mut impl FnMut(Tid, Vec<ResolvedFrame>)) -> Result<()> {
+        let mut stats = self.stats.get();
+        for thread in self.process.threads()? {
+            let tid = thread.id()?;
+            if self.worker_filter.is_some_and(|worker| worker != tid) {
+                continue;
+            }
+            if let Some(filter) = &self.thread_filter {
+                if !thread_name(self.pid, tid).is_some_and(|comm| thread_name_matches(&comm, filter)) {
+                    continue;
+                }
+            }
+            if let Some(stack) = self.sample_thread(tid, Registers::from_thread(&thread), &mut stats) {
+                add_sample(tid, stack);
+            }
         }
-        if vms.deoptimization_data_index.shared_function_info == 0 {
-            vms.deoptimization_data_index.shared_function_info = 6;
+        self.stats.set(stats);
+        Ok(())
+    }
+
+    /// Captures the stack of every JavaScript thread at once, resolved, for
+    /// a one-off look at what the process is doing. Unlike a sample, idle
+    /// threads and stacks caught half set up are kept, and nothing is
+    /// counted in the stats. Threads are in the order the OS lists them.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        self.with_process_paused(|| {
+            let captured_at = SystemTime::now();
+            let mut threads = Vec::new();
+            for thread in self.process.threads()? {
+                let tid = thread.id()?;
+                if self.worker_filter.is_some_and(|worker| worker != tid) {
+                    continue;
+                }
+                let name = thread_name(self.pid, tid);
+                if let Some(filter) = &self.thread_filter {
+                    if !name.as_deref().is_some_and(|comm| thread_name_matches(comm, filter)) {
+                        continue;
+                    }
+                }
+                let frames = match self.get_stack_trace(&thread) {
+                    Ok(frames) => frames,
+                    // e.g. the thread exited after being listed
+                    Err(_) => continue,
+                };
+                let has_js = frames.iter().any(|frame| frame.kind.is_js() || frame.kind == FrameKind::Torn);
+                if has_js {
+                    self.js_threads.borrow_mut().insert(tid);
+                } else if tid as Pid != self.pid && !self.js_threads.borrow().contains(&tid) {
+                    continue;
+                }
+                let stack = if has_js {
+                    self.resolve_stack(&filter_frames(frames, &self.filter))
+                } else {
+                    vec![ResolvedFrame::synthetic(self.classify_non_js_stack(tid, &frames), FrameKind::Native)]
+                };
+                threads.push((ThreadInfo { tid, name }, stack));
+            }
+            Ok(Snapshot { threads, captured_at })
+        })?
+    }
+
+    /// Walks the stack of every JavaScript thread once, with the process
+    /// paused, keeping a recording of everything read to do so. The recording
+    /// can be saved and attached to a bug report, and `replay` walks the same
+    /// stacks from it without the process. Returns the stacks as resolved
+    /// along with the recording, for comparing with what `replay` makes of it.
+    pub fn record(pid: Pid, options: V8SpyOptions) -> Result<(Snapshot, Recording)> {
+        let (process, process_info) = open_process(pid)?;
+        let libraries = load_shared_libraries(pid, &process_info);
+        let target = LiveTarget { process_info: &process_info, libraries: &libraries, process: &process };
+        if !has_v8_symbols(&target) {
+            return Err(Error::NotAV8Process(pid).into());
         }
-        if vms.deoptimization_data_index.inlining_positions == 0 {
-            let val = vms.deoptimization_data_index.shared_function_info + 1;
-            vms.deoptimization_data_index.inlining_positions = val;
+        let spy = V8Spy::from_memory_with_options(pid, Recorder::new(pid, target), options)?;
+        let (threads, recorded) = {
+            // Resumes the threads when dropped
+            let _lock = process.lock().context(format!("Failed to suspend process {}", pid))?;
+            let mut threads = Vec::new();
+            let mut recorded = Vec::new();
+            for thread in process.threads()? {
+                let tid = thread.id()?;
+                let registers = match Registers::from_thread(&thread) {
+                    Ok(registers) => registers,
+                    // e.g. the thread exited after being listed
+                    Err(_) => continue,
+                };
+                let frames = spy.walk_stack(registers);
+                if !frames.iter().any(|frame| frame.kind.is_js()) {
+                    continue;
+                }
+                let name = thread_name(pid, tid);
+                threads.push((ThreadInfo { tid, name: name.clone() }, spy.resolve_stack(&frames)));
+                recorded.push(RecordedThread { tid, name, registers });
+            }
+            (threads, recorded)
+        };
+        let mut recording = spy.process.take_recording();
+        recording.threads = recorded;
+        Ok((Snapshot { threads, captured_at: recording.captured_at }, recording))
+    }
+
+    /// Checks that the process can be profiled, end to end: attaches, reads
+    /// the V8 version and the layout of its objects, then walks the stack of
+    /// a thread running JavaScript and resolves its frames. Stops at the
+    /// first step that fails and says which it was, for making sure the spy
+    /// works against a process before relying on it. Threads are looked at
+    /// for up to two seconds until one is running JavaScript.
+    pub fn selftest(pid: Pid, options: V8SpyOptions) -> SelfTest {
+        SelfTest::from_result(Self::try_selftest(pid, options))
+    }
+
+    fn try_selftest(pid: Pid, options: V8SpyOptions) -> std::result::Result<usize, (SelfTestStep, anyhow::Error)> {
+        let (process, process_info) = open_process(pid).map_err(|e| (SelfTestStep::Attach, e))?;
+        let libraries = load_shared_libraries(pid, &process_info);
+        let target = LiveTarget { process_info: &process_info, libraries: &libraries, process: &process };
+        let spy = V8Spy::selftest_attach(pid, target, options)?;
+
+        let start = Instant::now();
+        while start.elapsed() < SELFTEST_TIMEOUT {
+            // Resumes the threads when dropped, before waiting for another look
+            let lock = process.lock().context(format!("Failed to suspend process {}", pid)).map_err(|e| (SelfTestStep::Unwinding, e))?;
+            for thread in process.threads().map_err(|e| (SelfTestStep::Unwinding, e.into()))? {
+                let registers = match Registers::from_thread(&thread) {
+                    Ok(registers) => registers,
+                    // e.g. the thread exited after being listed
+                    Err(_) => continue,
+                };
+                if let Some(frames) = spy.selftest_stack(registers)? {
+                    return Ok(frames);
+                }
+            }
+            drop(lock);
+            std::thread::sleep(SELFTEST_INTERVAL);
         }
-        if vms.code_kind.baseline == 0 {
-            if ver >= v8_ver(9, 0, 240) {
-                // Back to V8 9.0.240, and metadata available after that
-                vms.code_kind.field_mask = 0xf;
-                vms.code_kind.field_shift = 0;
-                vms.code_kind.baseline = 11;
-            } else {
-                // Leave mask and shift to zero, and set baseline to something
-                // so that the Baseline code is never triggered.
-                vms.code_kind.baseline = 0xff;
+        let error = anyhow::anyhow!("No thread ran JavaScript within {:?}; the process may be idle", SELFTEST_TIMEOUT);
+        Err((SelfTestStep::Unwinding, error))
+    }
+
+    /// Lists the scripts of the functions on every thread's stack, sorted by
+    /// name. V8 exports nothing that locates the Heap's list of all scripts,
+    /// so this only finds those with code running or waiting to be returned
+    /// to, and fails if there are none: call it again for a fuller picture.
+    pub fn list_scripts(&self) -> Result<Vec<ScriptInfo>> {
+        let stacks = self.with_process_paused(|| -> Result<Vec<Vec<Frame>>> {
+            // Threads that can't be read, e.g. because they've just exited,
+            // have no scripts to contribute
+            Ok(self.process.threads()?.iter().filter_map(|thread| self.get_stack_trace(thread).ok()).collect())
+        })??;
+        let mut addresses = HashSet::new();
+        let mut scripts = Vec::new();
+        for frame in stacks.iter().flatten() {
+            if frame.shared_function_info == 0 {
+                continue;
+            }
+            let address = match self.read_script(frame.shared_function_info) {
+                Ok(Some(address)) => address,
+                _ => continue,
+            };
+            if addresses.insert(address) {
+                scripts.push(ScriptInfo {
+                    address,
+                    name: self.read_script_name(address).ok().flatten(),
+                    source_url: self.read_script_source_url(address).ok().flatten(),
+                    source_mapping_url: self.read_script_source_mapping_url(address).ok().flatten(),
+                });
             }
         }
-        if vms.baseline_data.data == 0 && vms.code_kind.field_mask != 0 {
-            // Unfortunately no metadata currently. Has been static.
-            vms.baseline_data.data = vms.heap_object.map + 2 * pointer_size as u16;
+        if scripts.is_empty() {
+            anyhow::bail!("No scripts reachable: no thread of process {} is running JavaScript", self.pid);
         }
+        scripts.sort_by(|a, b| a.name.cmp(&b.name).then(a.address.cmp(&b.address)));
+        Ok(scripts)
+    }
 
-        Ok(Self { pid, process, version })
+    /// Walks the frame pointer chain of a stopped thread, innermost frame first
+    pub fn get_stack_trace(&self, thread: &Thread) -> Result<Vec<Frame>> {
+        Ok(self.walk_thread(Registers::from_thread(thread)?))
     }
 }
 
-fn v8_ver(major: u32, minor: u32, build: u32) -> u32 {
-    (major << 24) + (minor << 16) + build
-}
+impl<P: MemorySource> V8Spy<P> {
+    /// Creates a spy reading from any source of memory and symbols, such as a
+    /// `FakeTarget`. Only the live process returned by `new` can be sampled,
+    /// but everything that reads the heap or walks a stack works with either.
+    /// Fails if the source has no major and minor V8 version, or one older
+    /// than `MIN_SUPPORTED_VERSION`.
+    pub fn from_memory(pid: Pid, process: P) -> Result<Self> {
+        Self::from_memory_with_options(pid, process, V8SpyOptions::default())
+    }
 
-fn get_v8_data(process_info: &ProcessInfo, process: &Process) -> VMData {
-    let mut data = VMData::default();
-    read_memory(process_info, process, "v8dbg_HeapObjectTagMask", &mut data.fixed.heap_object_tag_mask);
-    read_memory(process_info, process, "v8dbg_SmiTagMask", &mut data.fixed.smi_tag_mask);
-    read_memory(process_info, process, "v8dbg_HeapObjectTag", &mut data.fixed.heap_object_tag);
-    read_memory(process_info, process, "v8dbg_SmiTag", &mut data.fixed.smi_tag);
-    read_memory(process_info, process, "v8dbg_SmiShiftSize", &mut data.fixed.smi_shift_size);
-    read_memory(process_info, process, "v8dbg_FirstNonstringType", &mut data.fixed.first_nonstring_type);
-    read_memory(process_info, process, "v8dbg_StringEncodingMask", &mut data.fixed.string_encoding_mask);
-    read_memory(process_info, process, "v8dbg_StringRepresentationMask", &mut data.fixed.string_representation_mask);
-    read_memory(process_info, process, "v8dbg_SeqStringTag", &mut data.fixed.seq_string_tag);
-    read_memory(process_info, process, "v8dbg_ConsStringTag", &mut data.fixed.cons_string_tag);
-    read_memory(process_info, process, "v8dbg_OneByteStringTag", &mut data.fixed.one_byte_string_tag);
-    read_memory(process_info, process, "v8dbg_TwoByteStringTag", &mut data.fixed.two_byte_string_tag);
-    read_memory(process_info, process, "v8dbg_SlicedStringTag", &mut data.fixed.sliced_string_tag);
-    read_memory(process_info, process, "v8dbg_ThinStringTag", &mut data.fixed.thin_string_tag);
-    read_memory(process_info, process, "v8dbg_FirstJSFunctionType", &mut data.fixed.first_jsfunction_type);
-    read_memory(process_info, process, "v8dbg_LastJSFunctionType", &mut data.fixed.last_jsfunction_type);
-    read_memory(process_info, process, "v8dbg_off_fp_function", &mut data.frame_pointer.function);
-    read_memory(process_info, process, "v8dbg_off_fp_context", &mut data.frame_pointer.context);
-    read_memory(process_info, process, "v8dbg_off_fp_bytecode_array", &mut data.frame_pointer.bytecode_array);
-    read_memory(process_info, process, "v8dbg_off_fp_bytecode_offset", &mut data.frame_pointer.bytecode_offset);
-    read_memory(process_info, process, "v8dbg_scopeinfo_idx_first_vars", &mut data.scope_info_index.first_vars);
-    read_memory(process_info, process, "v8dbg_scopeinfo_idx_ncontextlocals", &mut data.scope_info_index.ncontext_locals);
-    read_memory(process_info, process, "v8dbg_DeoptimizationDataInlinedFunctionCountIndex", &mut data.deoptimization_data_index.inlined_function_count);
-    read_memory(process_info, process, "v8dbg_DeoptimizationDataLiteralArrayIndex", &mut data.deoptimization_data_index.literal_array);
-    read_memory(process_info, process, "v8dbg_DeoptimizationDataSharedFunctionInfoIndex", &mut data.deoptimization_data_index.shared_function_info);
-    read_memory(process_info, process, "v8dbg_DeoptimizationDataInliningPositionsIndex", &mut data.deoptimization_data_index.inlining_positions);
-    read_memory(process_info, process, "v8dbg_CodeKindFieldMask", &mut data.code_kind.field_mask);
-    read_memory(process_info, process, "v8dbg_CodeKindFieldShift", &mut data.code_kind.field_shift);
-    read_memory(process_info, process, "v8dbg_CodeKindBaseline", &mut data.code_kind.baseline);
-    read_memory(process_info, process, "v8dbg_frametype_ArgumentsAdaptorFrame", &mut data.frame_type.arguments_adaptor_frame);
-    read_memory(process_info, process, "v8dbg_frametype_BaselineFrame", &mut data.frame_type.baseline_frame);
-    read_memory(process_info, process, "v8dbg_frametype_BuiltinContinuationFrame", &mut data.frame_type.builtin_continuation_frame);
-    read_memory(process_info, process, "v8dbg_frametype_BuiltinExitFrame", &mut data.frame_type.builtin_exit_frame);
-    read_memory(process_info, process, "v8dbg_frametype_BuiltinFrame", &mut data.frame_type.builtin_frame);
-    read_memory(process_info, process, "v8dbg_frametype_CwasmEntryFrame", &mut data.frame_type.cwasm_entry_frame);
-    read_memory(process_info, process, "v8dbg_frametype_ConstructEntryFrame", &mut data.frame_type.construct_entry_frame);
-    read_memory(process_info, process, "v8dbg_frametype_ConstructFrame", &mut data.frame_type.construct_frame);
-    read_memory(process_info, process, "v8dbg_frametype_EntryFrame", &mut data.frame_type.entry_frame);
-    read_memory(process_info, process, "v8dbg_frametype_ExitFrame", &mut data.frame_type.exit_frame);
-    read_memory(process_info, process, "v8dbg_frametype_InternalFrame", &mut data.frame_type.internal_frame);
-    read_memory(process_info, process, "v8dbg_frametype_InterpretedFrame", &mut data.frame_type.interpreted_frame);
-    read_memory(process_info, process, "v8dbg_frametype_JavaScriptBuiltinContinuationFrame", &mut data.frame_type.java_script_builtin_continuation_frame);
-    read_memory(process_info, process, "v8dbg_frametype_JavaScriptBuiltinContinuationWithCatchFrame", &mut data.frame_type.java_script_builtin_continuation_with_catch_frame);
-    read_memory(process_info, process, "v8dbg_frametype_JavaScriptFrame", &mut data.frame_type.java_script_frame);
-    read_memory(process_info, process, "v8dbg_frametype_JsToWasmFrame", &mut data.frame_type.js_to_wasm_frame);
-    read_memory(process_info, process, "v8dbg_frametype_NativeFrame", &mut data.frame_type.native_frame);
-    read_memory(process_info, process, "v8dbg_frametype_OptimizedFrame", &mut data.frame_type.optimized_frame);
-    read_memory(process_info, process, "v8dbg_frametype_StubFrame", &mut data.frame_type.stub_frame);
-    read_memory(process_info, process, "v8dbg_frametype_WasmCompileLazyFrame", &mut data.frame_type.wasm_compile_lazy_frame);
-    read_memory(process_info, process, "v8dbg_frametype_WasmCompiledFrame", &mut data.frame_type.wasm_compiled_frame);
-    read_memory(process_info, process, "v8dbg_frametype_WasmExitFrame", &mut data.frame_type.wasm_exit_frame);
-    read_memory(process_info, process, "v8dbg_frametype_WasmInterpreterEntryFrame", &mut data.frame_type.wasm_interpreter_entry_frame);
-    read_memory(process_info, process, "v8dbg_frametype_WasmToJsFrame", &mut data.frame_type.wasm_to_js_frame);
-    read_memory(process_info, process, "v8dbg_type_BaselineData__BASELINE_DATA_TYPE", &mut data.typ.baseline_data);
-    read_memory(process_info, process, "v8dbg_type_ByteArray__BYTE_ARRAY_TYPE", &mut data.typ.byte_array);
-    read_memory(process_info, process, "v8dbg_type_BytecodeArray__BYTECODE_ARRAY_TYPE", &mut data.typ.bytecode_array);
-    read_memory(process_info, process, "v8dbg_type_Code__CODE_TYPE", &mut data.typ.code);
-    read_memory(process_info, process, "v8dbg_type_FixedArray__FIXED_ARRAY_TYPE", &mut data.typ.fixed_array);
-    read_memory(process_info, process, "v8dbg_type_WeakFixedArray__WEAK_FIXED_ARRAY_TYPE", &mut data.typ.weak_fixed_array);
-    read_memory(process_info, process, "v8dbg_type_JSFunction__JS_FUNCTION_TYPE", &mut data.typ.js_function);
-    read_memory(process_info, process, "v8dbg_type_Map__MAP_TYPE", &mut data.typ.map);
-    read_memory(process_info, process, "v8dbg_type_Script__SCRIPT_TYPE", &mut data.typ.script);
-    read_memory(process_info, process, "v8dbg_type_ScopeInfo__SCOPE_INFO_TYPE", &mut data.typ.scope_info);
-    read_memory(process_info, process, "v8dbg_type_SharedFunctionInfo__SHARED_FUNCTION_INFO_TYPE", &mut data.typ.shared_function_info);
-    read_memory(process_info, process, "v8dbg_class_HeapObject__map__Map", &mut data.heap_object.map);
-    read_memory(process_info, process, "v8dbg_class_Map__instance_type__uint16_t", &mut data.map.instance_type);
-    read_memory(process_info, process, "v8dbg_class_FixedArrayBase__length__SMI", &mut data.fixed_array_base.length);
-    read_memory(process_info, process, "v8dbg_class_FixedArray__data__uintptr_t", &mut data.fixed_array.data);
-    read_memory(process_info, process, "v8dbg_class_String__length__int32_t", &mut data.string.length);
-    read_memory(process_info, process, "v8dbg_class_SeqOneByteString__chars__char", &mut data.seq_one_byte_string.chars);
-    read_memory(process_info, process, "v8dbg_class_SeqTwoByteString__chars__char", &mut data.seq_two_byte_string.chars);
-    read_memory(process_info, process, "v8dbg_class_ConsString__first__String", &mut data.cons_string.first);
-    read_memory(process_info, process, "v8dbg_class_ConsString__second__String", &mut data.cons_string.second);
-    read_memory(process_info, process, "v8dbg_class_ThinString__actual__String", &mut data.thin_string.actual);
-    if !read_memory(process_info, process, "v8dbg_class_JSFunction__code__Code", &mut data.jsfunction.code) {
-        read_memory(process_info, process, "v8dbg_class_JSFunction__code__Tagged_Code_", &mut data.jsfunction.code);
-    }
-    read_memory(process_info, process, "v8dbg_class_JSFunction__shared__SharedFunctionInfo", &mut data.jsfunction.shared_function_info);
-    if !read_memory(process_info, process, "v8dbg_class_Code__deoptimization_data__FixedArray", &mut data.code.deoptimization_data) {
-        read_memory(process_info, process, "v8dbg_class_Code__deoptimization_data__Tagged_FixedArray_", &mut data.code.deoptimization_data);
-    }
-    if !read_memory(process_info, process, "v8dbg_class_Code__source_position_table__ByteArray", &mut data.code.source_position_table) {
-        read_memory(process_info, process, "v8dbg_class_Code__source_position_table__Tagged_ByteArray_", &mut data.code.source_position_table);
-    }
-    if !read_memory(process_info, process, "v8dbg_class_Code__instruction_start__uintptr_t", &mut data.code.instruction_start) {
-        read_memory(process_info, process, "v8dbg_class_Code__instruction_start__Address", &mut data.code.instruction_start);
-    }
-    read_memory(process_info, process, "v8dbg_class_Code__instruction_size__int", &mut data.code.instruction_size);
-    read_memory(process_info, process, "v8dbg_class_Code__flags__uint32_t", &mut data.code.flags);
-    if !read_memory(process_info, process, "v8dbg_class_SharedFunctionInfo__name_or_scope_info__Object", &mut data.shared_function_info.name_or_scope_info) {
-        read_memory(process_info, process, "v8dbg_class_SharedFunctionInfo__name_or_scope_info__Tagged_Object_", &mut data.shared_function_info.name_or_scope_info);
-    }
-    if !read_memory(process_info, process, "v8dbg_class_SharedFunctionInfo__function_data__Object", &mut data.shared_function_info.function_data) {
-        read_memory(process_info, process, "v8dbg_class_SharedFunctionInfo__function_data__Tagged_Object_", &mut data.shared_function_info.function_data);
-    }
-    if !read_memory(process_info, process, "v8dbg_class_SharedFunctionInfo__script_or_debug_info__Object", &mut data.shared_function_info.script_or_debug_info) {
-        if !read_memory(process_info, process, "v8dbg_class_SharedFunctionInfo__script_or_debug_info__HeapObject", &mut data.shared_function_info.script_or_debug_info) {
-            read_memory(process_info, process, "v8dbg_class_SharedFunctionInfo__script_or_debug_info__Tagged_HeapObject_", &mut data.shared_function_info.script_or_debug_info);
-        }
-    }
-    read_memory(process_info, process, "v8dbg_class_BaselineData__data__Object", &mut data.baseline_data.data);
-    if !read_memory(process_info, process, "v8dbg_class_BytecodeArray__source_position_table__Object", &mut data.bytecode_array.source_position_table) {
-        read_memory(process_info, process, "v8dbg_class_BytecodeArray__source_position_table__Tagged_HeapObject_", &mut data.bytecode_array.source_position_table);
-    }
-    read_memory(process_info, process, "v8dbg_class_BytecodeArray__data__uintptr_t", &mut data.bytecode_array.data);
-    if process_info.get_symbol("v8dbg_parent_ScopeInfo__HeapObject").is_some() {
-        data.scope_info.heap_object = true;
+    /// Like `from_memory`, with options for how to attach
+    pub fn from_memory_with_options(pid: Pid, process: P, options: V8SpyOptions) -> Result<Self> {
+        let (version, vms) = read_layout(&process, &options)?;
+        let cage_base = read_cage_base(&process, target_pointer_size(&vms));
+        let mut spy = Self::with_layout(pid, process, version, vms, cage_base, Vec::new(), Vec::new());
+        spy.read_timeout = options.read_timeout;
+        Ok(spy)
     }
-    if process_info.get_symbol("v8dbg_parent_DeoptimizationLiteralArray__WeakFixedArray").is_some() {
-        data.deoptimization_literal_array.weak_fixed_array = true;
+
+    /// The steps of `selftest` up to reading the layout of V8's objects
+    fn selftest_attach(pid: Pid, target: P, options: V8SpyOptions) -> std::result::Result<Self, (SelfTestStep, anyhow::Error)> {
+        if !has_v8_symbols(&target) {
+            return Err((SelfTestStep::Attach, Error::NotAV8Process(pid).into()));
+        }
+        get_v8_version(&target, options.allow_partial_version).map_err(|e| (SelfTestStep::Version, e))?;
+        V8Spy::from_memory_with_options(pid, target, options)
+            .and_then(|spy| spy.validate_smi_constants().map(|_| spy))
+            .map_err(|e| match e.downcast_ref::<Error>() {
+                Some(Error::UnsupportedVersion(_)) => (SelfTestStep::Version, e),
+                _ => (SelfTestStep::Offsets, e),
+            })
     }
-    read_memory(process_info, process, "v8dbg_class_Script__name__Object", &mut data.script.name);
-    read_memory(process_info, process, "v8dbg_class_Script__line_ends__Object", &mut data.script.line_ends);
-    read_memory(process_info, process, "v8dbg_class_Script__source__Object", &mut data.script.source);
-    return data;
 }
 
-fn read_memory<T>(process_info: &ProcessInfo, process: &Process, symbol: &str, data: &mut T) -> bool {
-    let addr = process_info.get_symbol(symbol);
-    if addr.is_none() {
-        if symbol.starts_with("v8dbg_frametype_") {
-            unsafe {
-                if let Some(data_ptr) = (data as *mut T).cast::<u8>().as_mut() {
-                    *data_ptr = 0b11111111u8;
-                    return true;
+impl V8Spy<Recording> {
+    /// Walks the stacks of a recording again, as `record` walked them from
+    /// the live process. Everything read comes from the recording, so the
+    /// stacks resolve the same as they did then, down to any frame that went
+    /// wrong the same way.
+    pub fn replay(&self) -> Snapshot {
+        let threads = self
+            .process
+            .threads
+            .iter()
+            .map(|thread| {
+                let stack = self.resolve_stack(&self.walk_stack(thread.registers));
+                (ThreadInfo { tid: thread.tid, name: thread.name.clone() }, stack)
+            })
+            .collect();
+        Snapshot { threads, captured_at: self.process.captured_at }
+    }
+}
+
+impl<P: ProcessMemory> V8Spy<P> {
+    fn with_layout(
+        pid: Pid,
+        process: P,
+        version: Version,
+        vms: VMData,
+        cage_base: usize,
+        gc_ranges: Vec<(usize, usize)>,
+        event_loop_ranges: Vec<(usize, usize, EventLoopPhase)>,
+    ) -> Self {
+        let pointer_size = target_pointer_size(&vms);
+        // With pointer compression, the FixedArray length follows a 4 byte
+        // map, and Smis are 31 bits, unshifted. A 32-bit target has no
+        // compression, and its map is 4 bytes to begin with.
+        let compression_enabled = vms.fixed_array_base.length as usize == pointer_size / 2 && vms.fixed.smi_shift_size == 0;
+        Self {
+            pid,
+            process,
+            version,
+            filter: FilterOptions::default(),
+            demangle_builtins: false,
+            receiver_classes: false,
+            vms,
+            pointer_size,
+            compression_enabled,
+            cage_base: Cell::new(cage_base),
+            code_ranges: RefCell::new(BTreeMap::new()),
+            gc_ranges,
+            event_loop_ranges,
+            builtin_ranges: BTreeMap::new(),
+            js_threads: RefCell::new(HashSet::new()),
+            stats: Cell::new(SampleStats::default()),
+            native_symbols: None,
+            on_unresolved_frame: RefCell::new(None),
+            thread_filter: None,
+            worker_filter: None,
+            isolate: None,
+            memory_map: None,
+            memory_map_loaded: Cell::new(Instant::now()),
+            frame_cache: None,
+            v8_module: None,
+            read_timeout: None,
+            read_timed_out: Cell::new(false),
+            held_pause: RefCell::new(None),
+        }
+    }
+
+    /// Calls `sample` at `rate` per second on the schedule kept by
+    /// `Deadlines`, until `duration` has passed, `running` is cleared, or
+    /// `sample` returns `Ok(false)`. The run is added to the stats however
+    /// it ends, and the stats returned unless `sample` failed.
+    fn sample_on_schedule(
+        &self,
+        rate: u32,
+        duration: Option<Duration>,
+        running: &AtomicBool,
+        mut sample: impl FnMut() -> Result<bool>,
+    ) -> Result<SampleStats> {
+        let start = Instant::now();
+        let mut deadlines = Deadlines::new(rate);
+        let mut rounds = 0;
+        let result = (|| {
+            while running.load(Ordering::SeqCst) && duration.is_none_or(|duration| start.elapsed() < duration) {
+                if !sample()? {
+                    break;
                 }
+                rounds += 1;
+                deadlines.wait();
             }
-        }
-        println!("Failed to get symbol {}", symbol);
-        return false;
+            Ok(())
+        })();
+        self.record_run(rate, rounds, start.elapsed());
+        result.map(|()| self.stats.get())
     }
-    let addr = addr.unwrap();
 
-    let size = match std::any::type_name::<T>() {
-        "u32" => 4,
-        "u16" => 2,
-        "u8" => 1,
-        _ => panic!("Unsupported type"),
-    };
+    /// The schedule of `sample_stream`: each round hands the stacks
+    /// `take_stacks` returns to `on_sample` one at a time, stopping once it
+    /// returns `None` as the process has exited.
+    fn stream_on_schedule(
+        &self,
+        rate: u32,
+        duration: Option<Duration>,
+        running: &AtomicBool,
+        mut take_stacks: impl FnMut() -> Result<Option<(Instant, Vec<(Tid, Vec<ResolvedFrame>)>)>>,
+        mut on_sample: impl FnMut(Instant, Tid, &[ResolvedFrame]) -> Result<()>,
+    ) -> Result<SampleStats> {
+        self.sample_on_schedule(rate, duration, running, || {
+            let (taken_at, stacks) = match take_stacks()? {
+                Some(taken) => taken,
+                None => return Ok(false),
+            };
+            for (tid, stack) in &stacks {
+                on_sample(taken_at, *tid, stack)?;
+            }
+            Ok(true)
+        })
+    }
 
-    let mut buf = vec![0u8; size];
+    fn record_run(&self, rate: u32, rounds: u64, elapsed: Duration) {
+        let mut stats = self.stats.get();
+        stats.add_run(rate, rounds, elapsed);
+        self.stats.set(stats);
+    }
 
-    if let Ok(()) = process.read(*addr as usize, &mut buf) {
-        unsafe {
-            let data_ptr: *mut T = data as *mut T;
-            std::ptr::copy_nonoverlapping(buf.as_ptr(), data_ptr as *mut u8, size);
+    /// The steps of `selftest` after attaching, for the thread stopped at
+    /// `registers`: walks its stack and resolves each JavaScript frame.
+    /// Returns the number of frames resolved, or `None` if the thread isn't
+    /// running JavaScript.
+    fn selftest_stack(&self, registers: Registers) -> std::result::Result<Option<usize>, (SelfTestStep, anyhow::Error)> {
+        let frames = self.walk_stack(registers);
+        if !frames.iter().any(|frame| frame.kind.is_js()) {
+            return Ok(None);
+        }
+        for frame in frames.iter().filter(|frame| frame.kind.is_js()) {
+            self.resolve_frame(frame)
+                .with_context(|| format!("Failed to resolve the {:?} frame at {:#x}", frame.kind, frame.pc))
+                .map_err(|e| (SelfTestStep::Resolution, e))?;
         }
-        return true;
+        Ok(Some(self.resolve_stack(&frames).len()))
     }
-    return false;
-}
 
-fn get_v8_version(process_info: &ProcessInfo, process: &Process) -> Version {
-    let mut version = [0u32; 4];
-    for (i, ver) in ["major", "minor", "build", "patch"].iter().enumerate() {
-        let symbol = format!("_ZN2v88internal7Version6{}_E", ver);
-        let symbol = process_info.get_symbol(symbol.as_str()).unwrap();
-        let mut buf = [0u8; 4];
-        if let Ok(()) = process.read(*symbol as usize, &mut buf) {
-            version[i] = buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24;
+    /// Walks one thread's stack from `registers` and resolves it as a
+    /// sample, counting it in `stats`. Returns `None` for stacks that aren't
+    /// kept: those that couldn't be read in full, of threads that have never
+    /// run JavaScript, or idle unless `filter.include_idle` is set.
+    fn sample_thread(&self, tid: Tid, registers: Result<Registers>, stats: &mut SampleStats) -> Option<Vec<ResolvedFrame>> {
+        let frames = match registers {
+            Ok(registers) => self.walk_thread(registers),
+            Err(_) => {
+                // e.g. the thread exited after being listed
+                stats.total += 1;
+                stats.unwind_errors += 1;
+                return None;
+            }
+        };
+        let has_js = frames.iter().any(|frame| frame.kind.is_js() || frame.kind == FrameKind::Torn);
+        if has_js {
+            self.js_threads.borrow_mut().insert(tid);
+        } else if tid as Pid != self.pid && !self.js_threads.borrow().contains(&tid) {
+            return None;
+        }
+        stats.total += 1;
+        if frames.iter().any(|frame| frame.kind == FrameKind::Torn) {
+            stats.torn += 1;
+            return None;
+        }
+        if frames.len() >= MAX_STACK_DEPTH {
+            stats.truncated += 1;
+        }
+
+        let phase = self.event_loop_phase_of(&frames).filter(|_| self.filter.event_loop_phase);
+        let mut stack = if has_js {
+            self.resolve_stack(&filter_frames(frames, &self.filter))
+        } else {
+            let name = self.classify_non_js_stack(tid, &frames);
+            if name == "<idle>" {
+                stats.idle += 1;
+                if !self.filter.include_idle {
+                    return None;
+                }
+            }
+            vec![ResolvedFrame::synthetic(name, FrameKind::Native)]
+        };
+        if self.filter.group_by == GroupBy::File {
+            stack = group_by_file(&stack);
+        }
+        if let Some(phase) = phase {
+            stack.push(ResolvedFrame::synthetic(&format!("<loop:{}>", phase.name()), FrameKind::Native));
+        }
+        if self.read_timed_out.replace(false) {
+            // Whatever was read after the timeout is missing from the stack
+            stats.read_timeouts += 1;
+            return None;
+        }
+        Some(stack)
+    }
+
+    /// Names what a JavaScript thread is doing when there's no JavaScript on
+    /// its stack: collecting garbage, waiting in a system call (usually the
+    /// event loop polling for I/O), or running other native code
+    fn classify_non_js_stack(&self, tid: Tid, frames: &[Frame]) -> &'static str {
+        if frames.iter().any(|frame| self.is_gc_pc(frame.pc)) {
+            "<gc>"
+        } else if is_in_syscall(self.pid, tid) {
+            "<idle>"
+        } else {
+            "<native>"
+        }
+    }
+
+    /// Whether `pc` is in one of the garbage collector's native functions
+    fn is_gc_pc(&self, pc: usize) -> bool {
+        let index = self.gc_ranges.partition_point(|&(start, _)| start <= pc);
+        index > 0 && pc < self.gc_ranges[index - 1].1
+    }
+
+    /// Reuses the resolution of a JavaScript frame when a later sample has
+    /// the same function at the same fp and pc, as the frames below whatever
+    /// a steady workload is busy with do. This assumes a JSFunction and what
+    /// it points to stay where they are, which holds until the garbage
+    /// collector compacts the heap: call `clear_symbol_cache` after that to
+    /// drop frames whose addresses may have been reused.
+    pub fn enable_frame_cache(&mut self) {
+        self.frame_cache = Some(RefCell::new(HashMap::new()));
+    }
+
+    /// Drops the frame cache and the ranges of optimized code seen so far,
+    /// so that frames are resolved from the heap afresh
+    pub fn clear_symbol_cache(&self) {
+        if let Some(frame_cache) = &self.frame_cache {
+            frame_cache.borrow_mut().clear();
+        }
+        self.code_ranges.borrow_mut().clear();
+    }
+
+    /// Walks the frame pointer chain starting from `regs`, innermost frame
+    /// first. The walk ends at the first frame that can't be read.
+    pub fn walk_stack(&self, regs: Registers) -> Vec<Frame> {
+        // A new sample, so the reads of the last one timing out don't matter
+        self.read_timed_out.set(false);
+        let mut frames = Vec::new();
+        let mut fp = regs.fp;
+        let mut pc = regs.pc;
+        let mut sp = regs.sp;
+        while fp != 0 && frames.len() < MAX_STACK_DEPTH {
+            // The chain runs through native code too, where the frame pointer
+            // may have been omitted. Stop at the first frame we can't read.
+            match self.read_frame(fp, pc) {
+                Ok(Some(frame)) => frames.push(Frame { sp, ..frame }),
+                Ok(None) => frames.push(Frame { kind: FrameKind::Native, pc, fp, sp, ..Frame::default() }),
+                Err(_) => break,
+            }
+            let (caller_fp, return_address) = match (
+                self.read_ptr(fp),
+                self.read_ptr(fp + self.pointer_size),
+            ) {
+                (Ok(caller_fp), Ok(return_address)) => (caller_fp, return_address),
+                _ => break,
+            };
+            // The stack grows down, so callers always have a higher frame pointer
+            if caller_fp <= fp {
+                break;
+            }
+            // The call pushed the return address just below the saved fp
+            sp = fp + 2 * self.pointer_size;
+            fp = caller_fp;
+            pc = return_address;
+        }
+        frames
+    }
+
+    /// Walks the stack of a thread from its registers, as `walk_stack` does.
+    /// Should the frame pointer chain break in native code before reaching
+    /// JavaScript, and the thread be the one in the exit frame of the isolate
+    /// given to `set_isolate`, the walk goes on from that exit frame.
+    fn walk_thread(&self, regs: Registers) -> Vec<Frame> {
+        let mut frames = self.walk_stack(regs);
+        let Some(isolate) = self.isolate else {
+            return frames;
+        };
+        if frames.iter().any(|frame| frame.kind.is_js()) {
+            return frames;
+        }
+        let Ok(top) = self.thread_local_top(&isolate) else {
+            return frames;
+        };
+        // The exit frame is on this thread's stack, above where it is now
+        if top.fp < regs.sp || top.fp - regs.sp > MAX_THREAD_STACK {
+            return frames;
+        }
+        frames.retain(|frame| frame.fp < top.fp);
+        frames.extend(self.walk_stack(top));
+        frames
+    }
+
+    /// Walks stacks on from the exit frame of this isolate, as found with a
+    /// debugger, for threads whose frame pointer chain is broken by native
+    /// code built without frame pointers. Warns if the address doesn't look
+    /// like an isolate's, as checked by `check_isolate`, but uses it anyway.
+    pub fn set_isolate(&mut self, addr: usize) {
+        if self.vms.isolate.thread_local_top == 0 {
+            log::warn!("Can't check isolate 0x{:x}: the ThreadLocalTop layout is unknown for V8 {}", addr, self.version);
+        } else if let Err(err) = self.check_isolate(addr) {
+            log::warn!("0x{:x} doesn't look like an isolate: {:#}", addr, err);
+        }
+        self.isolate = Some(IsolateRef { address: addr });
+    }
+
+    /// Checks that `addr` could be an isolate: its ThreadLocalTop has to be
+    /// readable, and the exit frame kept there either unset, while no call
+    /// out of JavaScript is under way, or an aligned frame pointer into
+    /// mapped memory. Call with the ThreadLocalTop layout known.
+    fn check_isolate(&self, addr: usize) -> Result<()> {
+        let top = addr + self.vms.isolate.thread_local_top as usize;
+        let fp = self.read_ptr(top + self.vms.thread_local_top.c_entry_fp as usize).context("its ThreadLocalTop can't be read")?;
+        if fp != 0 && (fp % self.pointer_size != 0 || self.read_ptr(fp).is_err()) {
+            anyhow::bail!("the exit frame in its ThreadLocalTop, 0x{:x}, isn't a frame pointer", fp);
+        }
+        Ok(())
+    }
+
+    /// Resolves the function the innermost frame at `regs` is running,
+    /// without walking the rest of the stack, for polling what a thread is
+    /// doing right now. `None` if that frame isn't a JavaScript one, as when
+    /// the thread is in native code.
+    pub fn current_function(&self, regs: &Registers) -> Result<Option<ResolvedFrame>> {
+        if regs.fp == 0 {
+            return Ok(None);
+        }
+        // As in `walk_stack`, a frame that can't be read is native code
+        // that doesn't keep a frame pointer
+        match self.read_frame(regs.fp, regs.pc) {
+            Ok(Some(frame)) if frame.kind.is_js() => self.resolve_frame(&Frame { sp: regs.sp, ..frame }).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// The event loop phase of the thread with these registers, found by
+    /// the innermost frame in one of libuv's phase functions. `None` if the
+    /// thread isn't running the event loop, or it can't be unwound that far.
+    pub fn event_loop_phase(&self, regs: &Registers) -> Option<EventLoopPhase> {
+        self.event_loop_phase_of(&self.walk_stack(*regs))
+    }
+
+    /// Finds where to unwind a thread from without its registers, in the
+    /// ThreadLocalTop of the isolate it runs: the exit frame of its innermost
+    /// call out of JavaScript into C++, such as an API callback or a runtime
+    /// function, with the pc it will return to. The C++ frames of the call
+    /// are left out. Fails while the thread is running JavaScript, when there
+    /// is no such frame, and on V8 versions whose ThreadLocalTop isn't known.
+    pub fn thread_local_top(&self, isolate: &IsolateRef) -> Result<Registers> {
+        let offset = self.vms.isolate.thread_local_top as usize;
+        if offset == 0 {
+            anyhow::bail!("ThreadLocalTop layout unknown for V8 {}", self.version);
+        }
+        let top = isolate.address + offset;
+        let fp = self.read_ptr(top + self.vms.thread_local_top.c_entry_fp as usize)?;
+        if fp == 0 {
+            anyhow::bail!("Isolate 0x{:x} isn't in a call out of JavaScript", isolate.address);
+        }
+        let sp = self.read_ptr(fp_slot(fp, self.vms.frame_pointer.exit_sp))?;
+        let pc = self.read_ptr(sp.wrapping_sub(self.pointer_size))?;
+        Ok(Registers { pc, sp, fp })
+    }
+
+    /// Whether the isolate set with `set_isolate` is stopped in the
+    /// debugger, as at a breakpoint or a `debugger` statement with the
+    /// inspector attached. Its thread then waits for the debugger instead of
+    /// running JavaScript, so every sample shows it where it stopped. Fails
+    /// without an isolate, and on V8 versions whose Debug layout isn't known.
+    pub fn is_paused_in_debugger(&self) -> Result<bool> {
+        let Some(isolate) = self.isolate else {
+            anyhow::bail!("No isolate to check for a debugger: set one with set_isolate");
+        };
+        if self.vms.isolate.debug == 0 {
+            anyhow::bail!("Debug layout unknown for V8 {}", self.version);
+        }
+        let debug = self.read_ptr(isolate.address + self.vms.isolate.debug as usize)?;
+        if debug == 0 {
+            return Ok(false);
+        }
+        let mut is_active = [0u8];
+        self.read_into(debug + self.vms.debugger.is_active as usize, &mut is_active)?;
+        if is_active[0] == 0 {
+            return Ok(false);
+        }
+        Ok(self.read_ptr(debug + self.vms.debugger.current_debug_scope as usize)? != 0)
+    }
+
+    fn event_loop_phase_of(&self, frames: &[Frame]) -> Option<EventLoopPhase> {
+        frames.iter().find_map(|frame| {
+            let index = self.event_loop_ranges.partition_point(|&(start, _, _)| start <= frame.pc);
+            let (_, end, phase) = *self.event_loop_ranges.get(index.checked_sub(1)?)?;
+            (frame.pc < end).then_some(phase)
+        })
+    }
+
+    fn read_frame(&self, fp: usize, pc: usize) -> Result<Option<Frame>> {
+        let fps = &self.vms.frame_pointer;
+        let marker = self.read_ptr(fp_slot(fp, fps.context))?;
+        if self.is_smi(marker) {
+            // Typed frames store a marker instead of the context: the frame type
+            // shifted by the Smi tag size
+            let marker = marker >> self.smi_tag_size();
+            let kind = match u8::try_from(marker) {
+                Ok(marker) => self.map_frame_type(marker),
+                // Truncated, it could pass for a real frame type
+                Err(_) => FrameKind::Unknown,
+            };
+            if matches!(kind, FrameKind::Other(_) | FrameKind::Unknown) {
+                log::debug!("Unrecognized frame marker {} at fp 0x{:x}", marker, fp);
+            }
+            return Ok(Some(Frame { kind, pc, fp, ..Frame::default() }));
+        }
+
+        let function = self.read_ptr(fp_slot(fp, fps.function))?;
+        if !self.is_heap_object(function) {
+            return Ok(None);
+        }
+        // Pointers on the stack are never compressed, so give away the cage
+        if self.compression_enabled && self.cage_base.get() == 0 {
+            self.cage_base.set(function & CAGE_BASE_MASK);
+        }
+        let bytecode_array = self.read_ptr(fp_slot(fp, fps.bytecode_array))?;
+        let is_interpreted = self.has_instance_type(bytecode_array, self.vms.typ.bytecode_array)?;
+        if !self.is_jsfunction(function)? {
+            // Caught while V8 is still pushing the frame, the slots may hold
+            // what the stack held before, which reads as garbage. Without a
+            // BytecodeArray either, it's a native frame whose slot happens to
+            // hold a heap object.
+            if is_interpreted {
+                return Ok(Some(Frame { kind: FrameKind::Torn, pc, fp, ..Frame::default() }));
+            }
+            return Ok(None);
+        }
+        let shared_function_info = self.read_tagged_pointer(function, self.vms.jsfunction.shared_function_info)?;
+        let code = self.read_tagged_pointer(function, self.vms.jsfunction.code)?;
+
+        if is_interpreted {
+            if self.code_kind_of(code)? == Some(self.vms.code_kind.baseline) {
+                return Ok(Some(Frame { kind: FrameKind::Baseline, pc, fp, function, shared_function_info, code, bytecode_array, bytecode_offset: None, ..Frame::default() }));
+            }
+            let raw_offset = self.read_ptr(fp_slot(fp, fps.bytecode_offset))?;
+            // The saved offset is relative to the tagged BytecodeArray pointer
+            let header_size = self.vms.bytecode_array.data as i64 - self.vms.fixed.heap_object_tag as i64;
+            let bytecode_offset = usize::try_from(self.smi_to_int(raw_offset) - header_size).ok();
+            return Ok(Some(Frame { kind: FrameKind::Interpreted, pc, fp, function, shared_function_info, code, bytecode_array, bytecode_offset, ..Frame::default() }));
+        }
+
+        // The function's current code may not be what this frame is running,
+        // e.g. after a deopt, so prefer a Code we've seen that contains the pc
+        if let Some(frame) = self.function_for_pc(pc)? {
+            return Ok(Some(Frame { pc, fp, function, ..frame }));
+        }
+        self.remember_code(code);
+        let tier = self.tier_of(code)?;
+        Ok(Some(Frame { kind: FrameKind::Optimized, pc, fp, function, shared_function_info, code, tier, ..Frame::default() }))
+    }
+
+    /// Looks up the optimized Code containing `pc` among recently seen code
+    /// objects, and returns a frame for the function it was compiled from.
+    /// Maglev and TurboFan code share the DeoptimizationData layout, so the
+    /// function is found the same way for both.
+    pub fn function_for_pc(&self, pc: usize) -> Result<Option<Frame>> {
+        let code = match self.code_ranges.borrow().range(..=pc).next_back() {
+            Some((_, &(end, code))) if pc < end => code,
+            _ => return Ok(None),
+        };
+        let deoptimization_data = self.read_tagged_pointer(code, self.vms.code.deoptimization_data)?;
+        if !self.has_instance_type(deoptimization_data, self.vms.typ.fixed_array)? {
+            return Ok(None);
+        }
+        let index = self.vms.deoptimization_data_index.shared_function_info as usize;
+        let slot = self.field_address(deoptimization_data, self.vms.fixed_array.data) + index * self.tagged_size();
+        let shared_function_info = self.read_tagged(slot)?;
+        if !self.has_instance_type(shared_function_info, self.vms.typ.shared_function_info)? {
+            return Ok(None);
+        }
+        let tier = self.tier_of(code)?;
+        Ok(Some(Frame { kind: FrameKind::Optimized, shared_function_info, code, tier, ..Frame::default() }))
+    }
+
+    /// Tells what JavaScript a pc sampled by a native profiler such as perf is
+    /// running, for merging its stacks with ours: the function whose optimized
+    /// code contains it, among the code seen on the stacks sampled so far, or
+    /// the builtin it's in. `None` for any other pc, which includes a pc in
+    /// baseline code, and is native code as far as what's known of it goes.
+    /// Interpreted functions have no code of their own, so their pcs are in the
+    /// interpreter's builtins.
+    pub fn js_location_for_pc(&self, pc: usize) -> Result<Option<ResolvedFrame>> {
+        if let Some(frame) = self.function_for_pc(pc)? {
+            return self.resolve_frame(&Frame { pc, ..frame }).map(Some);
+        }
+        match self.builtin_at(pc) {
+            Some(name) => {
+                let address = FrameAddress { pc, ..FrameAddress::default() };
+                Ok(Some(ResolvedFrame { address, ..ResolvedFrame::synthetic(name, FrameKind::Builtin) }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The name of the embedded builtin whose code contains `pc`, if any
+    fn builtin_at(&self, pc: usize) -> Option<&str> {
+        match self.builtin_ranges.range(..=pc).next_back() {
+            Some((_, (end, name))) if pc < *end => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Adds a Code object to the range cache used by `function_for_pc`
+    fn remember_code(&self, code: usize) {
+        let (start, end) = match self.code_instruction_range(code) {
+            Ok(range) => range,
+            Err(_) => return,
+        };
+        let mut code_ranges = self.code_ranges.borrow_mut();
+        // Code objects move or die as the heap is collected, so only keep
+        // recent ones rather than trying to track them precisely
+        if code_ranges.len() >= MAX_CODE_RANGES {
+            code_ranges.clear();
+        }
+        // Drop stale ranges overlapping the new one
+        let overlapping: Vec<usize> = code_ranges
+            .range(..end)
+            .rev()
+            .take_while(|(_, &(other_end, _))| other_end > start)
+            .map(|(&other_start, _)| other_start)
+            .collect();
+        for other_start in overlapping {
+            code_ranges.remove(&other_start);
+        }
+        code_ranges.insert(start, (end, code));
+    }
+
+    /// Maps a typed frame's marker to its kind. Typed frames such as
+    /// construct and adaptor frames are walked through like any other, via
+    /// their saved frame pointer, but aren't JavaScript frames themselves.
+    fn map_frame_type(&self, marker: u8) -> FrameKind {
+        match self.frame_type_name(marker) {
+            "InterpretedFrame" => FrameKind::Interpreted,
+            "BaselineFrame" => FrameKind::Baseline,
+            "OptimizedFrame" | "JavaScriptFrame" => FrameKind::Optimized,
+            "BuiltinFrame"
+            | "BuiltinExitFrame"
+            | "BuiltinContinuationFrame"
+            | "JavaScriptBuiltinContinuationFrame"
+            | "JavaScriptBuiltinContinuationWithCatchFrame" => FrameKind::Builtin,
+            "StubFrame" => FrameKind::Stub,
+            "EntryFrame" | "ConstructEntryFrame" | "CwasmEntryFrame" => FrameKind::Entry,
+            "ConstructFrame" => FrameKind::Construct,
+            // Removed in V8 8.9. Later versions have no such symbol, and the
+            // frame type is MISSING_FRAME_TYPE, which no marker is named for.
+            "ArgumentsAdaptorFrame" => FrameKind::ArgumentsAdaptor,
+            "ExitFrame" => FrameKind::Exit,
+            "InternalFrame" => FrameKind::Internal,
+            "NativeFrame" => FrameKind::Native,
+            "WasmCompiledFrame"
+            | "WasmCompileLazyFrame"
+            | "WasmExitFrame"
+            | "WasmInterpreterEntryFrame"
+            | "WasmToJsFrame"
+            | "JsToWasmFrame" => FrameKind::Wasm,
+            _ => FrameKind::Other(marker),
+        }
+    }
+
+    /// Names the frame type a marker stands for in this V8 version, after its
+    /// `v8dbg_frametype_` symbol, e.g. `"OptimizedFrame"`. Returns `"Unknown"`
+    /// for markers that don't match any frame type the process defines.
+    pub fn frame_type_name(&self, marker: u8) -> &'static str {
+        // Every frame type the process doesn't have is MISSING_FRAME_TYPE, so
+        // that marker can't be told apart from any of them
+        if marker == MISSING_FRAME_TYPE {
+            return "Unknown";
+        }
+        let ft = &self.vms.frame_type;
+        let frame_types = [
+            (ft.arguments_adaptor_frame, "ArgumentsAdaptorFrame"),
+            (ft.baseline_frame, "BaselineFrame"),
+            (ft.builtin_continuation_frame, "BuiltinContinuationFrame"),
+            (ft.builtin_exit_frame, "BuiltinExitFrame"),
+            (ft.builtin_frame, "BuiltinFrame"),
+            (ft.cwasm_entry_frame, "CwasmEntryFrame"),
+            (ft.construct_entry_frame, "ConstructEntryFrame"),
+            (ft.construct_frame, "ConstructFrame"),
+            (ft.entry_frame, "EntryFrame"),
+            (ft.exit_frame, "ExitFrame"),
+            (ft.internal_frame, "InternalFrame"),
+            (ft.interpreted_frame, "InterpretedFrame"),
+            (ft.java_script_builtin_continuation_frame, "JavaScriptBuiltinContinuationFrame"),
+            (ft.java_script_builtin_continuation_with_catch_frame, "JavaScriptBuiltinContinuationWithCatchFrame"),
+            (ft.java_script_frame, "JavaScriptFrame"),
+            (ft.js_to_wasm_frame, "JsToWasmFrame"),
+            (ft.native_frame, "NativeFrame"),
+            (ft.optimized_frame, "OptimizedFrame"),
+            (ft.stub_frame, "StubFrame"),
+            (ft.wasm_compile_lazy_frame, "WasmCompileLazyFrame"),
+            (ft.wasm_compiled_frame, "WasmCompiledFrame"),
+            (ft.wasm_exit_frame, "WasmExitFrame"),
+            (ft.wasm_interpreter_entry_frame, "WasmInterpreterEntryFrame"),
+            (ft.wasm_to_js_frame, "WasmToJsFrame"),
+        ];
+        frame_types.iter().find(|(value, _)| *value == marker).map_or("Unknown", |(_, name)| name)
+    }
+
+    /// Reads the `words` stack slots on either side of frame pointer `fp`,
+    /// lowest address first, for working out the frame layout of a new V8
+    /// version. Each slot comes with its address, its value and a guess at
+    /// what the value is: a Smi, a tagged pointer to an object of some
+    /// instance type, or a raw value, such as a return address or saved
+    /// frame pointer. Instance types are named where the spy knows them.
+    pub fn dump_frame_bytes(&self, fp: usize, words: usize) -> Result<Vec<(usize, usize, String)>> {
+        let span = words.checked_mul(self.pointer_size).context("Too many words to dump")?;
+        let start = fp.checked_sub(span).context("Frame pointer too low to dump below")?;
+        fp.checked_add(span).context("Frame pointer too high to dump above")?;
+        (0..=2 * words)
+            .map(|i| {
+                let address = start + i * self.pointer_size;
+                let value = self.read_ptr(address).with_context(|| format!("Failed to read the stack slot at 0x{:x}", address))?;
+                Ok((address, value, self.describe_slot(value)))
+            })
+            .collect()
+    }
+
+    /// Guesses what a stack slot's value is, for `dump_frame_bytes`
+    fn describe_slot(&self, value: usize) -> String {
+        // A word on the stack is only a Smi if the bits below its value,
+        // which is shifted up to the top half on 64 bit targets, are clear
+        let smi_shift = self.vms.fixed.smi_shift_size as u32 + self.smi_tag_size();
+        if self.is_smi(value) && value & ((1usize << smi_shift) - 1) == 0 {
+            return format!("Smi {}", self.smi_to_int(value));
+        }
+        if self.is_heap_object(value) {
+            if let Ok(instance_type) = self.instance_type(value) {
+                let name = if self.is_string_type(instance_type) {
+                    Some("string")
+                } else if self.is_js_function_type(instance_type) {
+                    Some("js_function")
+                } else {
+                    self.vms
+                        .to_sorted_pairs()
+                        .into_iter()
+                        .find(|&(field, t)| t == instance_type as u64 && t != 0 && field.starts_with("typ."))
+                        .map(|(field, _)| &field["typ.".len()..])
+                };
+                return match name {
+                    Some(name) => format!("tagged {} (instance type {})", name, instance_type),
+                    None => format!("tagged, instance type {}", instance_type),
+                };
+            }
+        }
+        match self.builtin_at(value) {
+            Some(name) => format!("raw, in builtin {}", name),
+            None => "raw".to_owned(),
+        }
+    }
+
+    /// Calls `callback` with what's known of every frame that `resolve_stack`
+    /// can't resolve, whether it has a frame type marker that isn't known or
+    /// its function can't be named. The profile is unaffected, and still
+    /// shows such frames as `<unknown>` or leaves them out.
+    pub fn on_unresolved_frame(&mut self, callback: impl FnMut(&FrameDebugRecord) + 'static) {
+        self.on_unresolved_frame = RefCell::new(Some(Box::new(callback)));
+    }
+
+    /// Resolves a stack for output. JavaScript, builtin and native frames are
+    /// kept, and V8's other bookkeeping frames are dropped. Native frames
+    /// without a symbol are indistinguishable, so a run of them is shown as a
+    /// single `<native>`.
+    pub fn resolve_stack(&self, frames: &[Frame]) -> Vec<ResolvedFrame> {
+        let mut stack: Vec<ResolvedFrame> = Vec::new();
+        for frame in frames {
+            if matches!(frame.kind, FrameKind::Other(_) | FrameKind::Unknown) {
+                self.report_unresolved(frame, "frame type", "unrecognized frame marker".to_owned());
+            }
+            if !frame.kind.is_js() && frame.kind != FrameKind::Builtin && frame.kind != FrameKind::Native {
+                continue;
+            }
+            let resolved = self.expand_inlined_frames(frame).unwrap_or_else(|e| {
+                self.report_unresolved(frame, "function name", e.to_string());
+                vec![ResolvedFrame::synthetic("<unknown>", frame.kind)]
+            });
+            for resolved in resolved {
+                if resolved.name == "<native>" && stack.last() == Some(&resolved) {
+                    continue;
+                }
+                stack.push(resolved);
+            }
+        }
+        stack
+    }
+
+    fn report_unresolved(&self, frame: &Frame, step: &'static str, error: String) {
+        let mut on_unresolved_frame = self.on_unresolved_frame.borrow_mut();
+        let callback = match on_unresolved_frame.as_mut() {
+            Some(callback) => callback,
+            None => return,
+        };
+        let marker = self
+            .read_ptr(fp_slot(frame.fp, self.vms.frame_pointer.context))
+            .ok()
+            .filter(|&marker| self.is_smi(marker))
+            .map(|marker| marker >> self.smi_tag_size());
+        let instance_type = |ptr: usize| if self.is_heap_object(ptr) { self.instance_type(ptr).ok() } else { None };
+        callback(&FrameDebugRecord {
+            kind: frame.kind,
+            pc: frame.pc,
+            fp: frame.fp,
+            marker,
+            function: frame.function,
+            function_type: instance_type(frame.function),
+            shared_function_info: frame.shared_function_info,
+            shared_function_info_type: instance_type(frame.shared_function_info),
+            step,
+            error,
+        });
+    }
+
+    /// Resolves a frame to the name of its function, and the file and line it's
+    /// executing where known. Frames without a function are named after their
+    /// kind, such as `<native>`. A JavaScript function whose name can't be read
+    /// is named after the address of its code instead, as `<code@0x...>`, so
+    /// that its samples still add up. Fails only if there's no code to name it
+    /// after either.
+    pub fn resolve_frame(&self, frame: &Frame) -> Result<ResolvedFrame> {
+        let mut resolved = self.expand_inlined_frames(frame)?;
+        Ok(resolved.pop().expect("a frame resolves to at least itself"))
+    }
+
+    /// Resolves a frame as `resolve_frame` does, along with the functions
+    /// optimized code inlined into it, innermost first and ending with the
+    /// frame's own function. Every frame but an optimized one resolves to
+    /// just itself.
+    ///
+    /// Each of the code's source positions is in the innermost function
+    /// inlined at that point, and has an inlining id. The code's inlining
+    /// positions give, for each id, the inlined function and the position it
+    /// was called from, which has the inlining id of the function it was
+    /// inlined into, and so on out to the function the code was compiled for.
+    /// So each function gets the line it's at in its own script.
+    pub fn expand_inlined_frames(&self, frame: &Frame) -> Result<Vec<ResolvedFrame>> {
+        let frame_cache = match &self.frame_cache {
+            Some(frame_cache) if frame.function != 0 => frame_cache,
+            _ => return self.resolve_frame_uncached(frame),
+        };
+        let key = (frame.fp, frame.function);
+        let address = FrameAddress { pc: frame.pc, fp: frame.fp, sp: frame.sp };
+        if let Some(cached) = frame_cache.borrow().get(&key) {
+            if cached.pc == frame.pc && cached.bytecode_offset == frame.bytecode_offset {
+                return Ok(cached.resolved.iter().map(|resolved| ResolvedFrame { address, ..resolved.clone() }).collect());
+            }
+        }
+        let resolved = self.resolve_frame_uncached(frame)?;
+        let mut frame_cache = frame_cache.borrow_mut();
+        if frame_cache.len() >= MAX_CACHED_FRAMES {
+            frame_cache.clear();
+        }
+        frame_cache.insert(key, CachedFrame { pc: frame.pc, bytecode_offset: frame.bytecode_offset, resolved: resolved.clone() });
+        Ok(resolved)
+    }
+
+    fn resolve_frame_uncached(&self, frame: &Frame) -> Result<Vec<ResolvedFrame>> {
+        if matches!(frame.kind, FrameKind::Builtin | FrameKind::Native) {
+            if let Some(name) = self.builtin_at(frame.pc) {
+                return Ok(vec![ResolvedFrame::synthetic(&builtin_label(name), FrameKind::Builtin)]);
+            }
+        }
+        if frame.kind == FrameKind::Native {
+            if let Some(name) = self.native_symbols.as_ref().and_then(|symbols| symbols.resolve(frame.pc)) {
+                return Ok(vec![ResolvedFrame::synthetic(&name, frame.kind)]);
+            }
+        }
+        if !frame.kind.is_js() {
+            return Ok(vec![ResolvedFrame::synthetic(&format!("<{}>", frame.kind.name()), frame.kind)]);
+        }
+        let positions = match (frame.kind, frame.bytecode_offset) {
+            (FrameKind::Optimized, _) if frame.code != 0 => {
+                self.inlined_positions(frame).unwrap_or_else(|_| vec![(frame.shared_function_info, None)])
+            }
+            (_, Some(offset)) => {
+                let position = self.source_position_for_offset(frame.bytecode_array, offset).ok().flatten();
+                vec![(frame.shared_function_info, position)]
+            }
+            _ => vec![(frame.shared_function_info, None)],
+        };
+        let mut resolved = positions
+            .into_iter()
+            .map(|(sfi, position)| self.resolve_function(frame, sfi, position))
+            .collect::<Result<Vec<_>>>()?;
+        if self.receiver_classes {
+            // Inlined functions leave no trace of their own receivers
+            let outer = resolved.last_mut().expect("a frame has a function of its own");
+            outer.receiver = self.frame_receiver_class(frame.fp).ok().flatten();
+        }
+        Ok(resolved)
+    }
+
+    /// Resolves function `sfi` of a frame, at script offset `position` if
+    /// known
+    fn resolve_function(&self, frame: &Frame, sfi: usize, position: Option<u32>) -> Result<ResolvedFrame> {
+        let name = match self.read_function_name(sfi) {
+            Ok(name) if name.is_empty() => "<anonymous>".to_owned(),
+            Ok(name) => name,
+            Err(e) => {
+                // Interpreted and baseline frames share the trampoline's or
+                // baseline compiler's code with other functions, but not
+                // their bytecode
+                let code = if frame.bytecode_array != 0 { frame.bytecode_array } else { frame.code };
+                if code == 0 {
+                    return Err(e);
+                }
+                self.report_unresolved(frame, "function name", e.to_string());
+                format!("<code@0x{:x}>", self.field_address(code, 0))
+            }
+        };
+        let script = self.read_script(sfi).ok().flatten();
+        let file = script.and_then(|script| self.read_script_name(script).ok().flatten());
+        let source_url = script.and_then(|script| self.read_script_source_url(script).ok().flatten());
+        let source_mapping_url = script.and_then(|script| self.read_script_source_mapping_url(script).ok().flatten());
+        let line = match (script, position) {
+            (Some(script), Some(position)) => self.line_for_position(script, position).ok().flatten(),
+            _ => None,
+        };
+        let address = FrameAddress { pc: frame.pc, fp: frame.fp, sp: frame.sp };
+        Ok(ResolvedFrame { name, file, line, kind: frame.kind, tier: frame.tier, receiver: None, source_url, source_mapping_url, address })
+    }
+
+    /// The functions an optimized frame is in at its pc, paired with the
+    /// script offset each is at, as `expand_inlined_frames` describes. A
+    /// frame whose code doesn't contain its pc, or has no source positions,
+    /// is only in its own function, at an unknown offset.
+    fn inlined_positions(&self, frame: &Frame) -> Result<Vec<(usize, Option<u32>)>> {
+        let outer = vec![(frame.shared_function_info, None)];
+        let (start, end) = self.code_instruction_range(frame.code)?;
+        if frame.pc <= start || frame.pc > end {
+            return Ok(outer);
+        }
+        let table = self.read_tagged_pointer(frame.code, self.vms.code.source_position_table)?;
+        let table = match self.read_byte_array(table)? {
+            Some(table) => table,
+            None => return Ok(outer),
+        };
+        // A caller's pc is its return address, just past the call, so look
+        // up the last byte of the call instead
+        let mut position = match decode_source_position(&table, frame.pc - start - 1) {
+            Some(position) => position,
+            None => return Ok(outer),
+        };
+        let mut positions = Vec::new();
+        if let Some(id) = inlining_id(position) {
+            let deoptimization_data = self.read_tagged_pointer(frame.code, self.vms.code.deoptimization_data)?;
+            if !self.has_instance_type(deoptimization_data, self.vms.typ.fixed_array)? {
+                return Ok(outer);
+            }
+            let index = &self.vms.deoptimization_data_index;
+            let element = |index: u8| self.read_tagged(self.field_address(deoptimization_data, self.vms.fixed_array.data) + index as usize * self.tagged_size());
+            let literals = element(index.literal_array)?;
+            let inlining_positions = self.read_byte_array(element(index.inlining_positions)?)?.unwrap_or_default();
+            // InliningPosition is a SourcePosition and the index of the
+            // function in the literals, padded to 16 bytes
+            let inlining_positions: Vec<(i64, i32)> = inlining_positions
+                .chunks_exact(16)
+                .map(|entry| (i64::from_ne_bytes(entry[..8].try_into().unwrap()), i32::from_ne_bytes(entry[8..12].try_into().unwrap())))
+                .collect();
+            let mut id = Some(id);
+            while let Some(current) = id {
+                // Each step is out to a function that was inlined earlier, so
+                // it takes at most one step per inlining position
+                if positions.len() >= inlining_positions.len() {
+                    anyhow::bail!("Inlining id {} out of range of {} inlining positions", current, inlining_positions.len());
+                }
+                let (caller_position, function_id) = inlining_positions[current];
+                let literal = usize::try_from(function_id).context("Negative inlined function id")?;
+                let sfi = self.read_tagged(self.field_address(literals, self.vms.fixed_array.data) + literal * self.tagged_size())?;
+                // The literals may be weak references, which differ from
+                // strong ones in the bit above the heap object tag
+                let sfi = sfi & !0b10;
+                if !self.has_instance_type(sfi, self.vms.typ.shared_function_info)? {
+                    anyhow::bail!("Inlined function {} at 0x{:x} isn't a SharedFunctionInfo", literal, sfi);
+                }
+                positions.push((sfi, script_offset(position)));
+                position = caller_position;
+                id = inlining_id(position);
+            }
+        }
+        positions.push((frame.shared_function_info, script_offset(position)));
+        Ok(positions)
+    }
+
+    /// Reads the name of a SharedFunctionInfo, which is either stored directly
+    /// or in its ScopeInfo. Unnamed builtins are named after their builtin id,
+    /// from the version's table with `demangle_builtins`, or else as
+    /// `builtin#<id>`.
+    ///
+    /// Also takes a function, which a bound or wrapped function has in place
+    /// of a SharedFunctionInfo. Those are named after the function they call,
+    /// with `bound ` in front for a bound function, as `Function.prototype.bind`
+    /// names it.
+    pub fn read_function_name(&self, sfi: usize) -> Result<String> {
+        let typ = &self.vms.typ;
+        let is = |t: u16, instance_type: u16| t != 0 && t == instance_type;
+        let is_function = |target: usize| -> Result<bool> {
+            if !self.is_heap_object(target) {
+                return Ok(false);
+            }
+            let instance_type = self.instance_type(target)?;
+            Ok(self.is_js_function_type(instance_type) || is(typ.js_bound_function, instance_type) || is(typ.js_wrapped_function, instance_type))
+        };
+        if !is_function(sfi)? {
+            return self.read_sfi_name(sfi);
+        }
+        let mut function = sfi;
+        let mut prefix = String::new();
+        // Bound functions can be bound again, but not endlessly
+        for _ in 0..MAX_BOUND_FUNCTIONS {
+            let instance_type = self.instance_type(function)?;
+            if self.is_js_function_type(instance_type) {
+                let sfi = self.read_tagged_pointer(function, self.vms.jsfunction.shared_function_info)?;
+                return Ok(prefix + &self.read_sfi_name(sfi)?);
+            }
+            let target = if is(typ.js_bound_function, instance_type) {
+                prefix.push_str("bound ");
+                self.read_tagged_pointer(function, self.vms.js_bound_function.bound_target_function)?
+            } else {
+                self.read_tagged_pointer(function, self.vms.js_wrapped_function.wrapped_target_function)?
+            };
+            if !is_function(target)? {
+                anyhow::bail!("Target 0x{:x} of bound function 0x{:x} isn't a function", target, function);
+            }
+            function = target;
+        }
+        anyhow::bail!("More than {} bound functions from 0x{:x}", MAX_BOUND_FUNCTIONS, sfi)
+    }
+
+    fn read_sfi_name(&self, sfi: usize) -> Result<String> {
+        let name_or_scope_info = self.read_tagged_pointer(sfi, self.vms.shared_function_info.name_or_scope_info)?;
+        if !self.is_heap_object(name_or_scope_info) {
+            // Builtins keep their id as a Smi in place of the function data.
+            // The ids are renumbered between V8 versions, so only the
+            // versions with a table can be named further.
+            let function_data = self.read_tagged_pointer(sfi, self.vms.shared_function_info.function_data)?;
+            if self.is_smi(function_data) {
+                let id = self.smi_to_int(function_data);
+                let name = u32::try_from(id)
+                    .ok()
+                    .filter(|_| self.demangle_builtins)
+                    .and_then(|id| builtins::builtin_name(&self.version, id));
+                return Ok(match name {
+                    Some(name) => name.to_owned(),
+                    None => format!("builtin#{}", id),
+                });
+            }
+            anyhow::bail!("SharedFunctionInfo has no name");
+        }
+        if self.instance_type(name_or_scope_info)? != self.vms.typ.scope_info {
+            return self.read_string(name_or_scope_info);
+        }
+
+        // The function name follows the context local names and infos
+        let index = &self.vms.scope_info_index;
+        let ncontext_locals = self.read_tagged(self.scope_info_slot(name_or_scope_info, index.ncontext_locals as usize))?;
+        let ncontext_locals = usize::try_from(self.smi_to_int(ncontext_locals))?;
+        let slot = index.first_vars as usize + 2 * ncontext_locals;
+        let name = self.read_tagged(self.scope_info_slot(name_or_scope_info, slot))?;
+        self.read_string(name)
+    }
+
+    /// The address of slot `index` of a ScopeInfo, as the `scopeinfo_idx_*`
+    /// constants number them. They count from the first FixedArray element
+    /// in older V8, and from the first field after the map since ScopeInfo
+    /// became a plain HeapObject.
+    fn scope_info_slot(&self, scope_info: usize, index: usize) -> usize {
+        let base = if self.vms.scope_info.heap_object {
+            self.field_address(scope_info, self.vms.heap_object.map) + self.tagged_size()
+        } else {
+            self.field_address(scope_info, self.vms.fixed_array.data)
+        };
+        base + index * self.tagged_size()
+    }
+
+    /// Tells what the function data of a SharedFunctionInfo holds, and so
+    /// whether a BytecodeArray can be reached from it. Instance types the
+    /// version doesn't have never match.
+    pub fn function_data_kind(&self, sfi: usize) -> Result<FunctionDataKind> {
+        let data = self.read_tagged_pointer(sfi, self.vms.shared_function_info.function_data)?;
+        if self.is_smi(data) {
+            return Ok(FunctionDataKind::Builtin(self.smi_to_int(data)));
+        }
+        let instance_type = self.instance_type(data)?;
+        let typ = &self.vms.typ;
+        let is = |t: u16| t != 0 && t == instance_type;
+        if is(typ.bytecode_array) || is(typ.interpreter_data) {
+            return Ok(FunctionDataKind::Bytecode { bytecode_array: self.unwrap_interpreter_data(data)? });
+        }
+        if is(typ.code) || is(typ.baseline_data) {
+            // Newer V8 keeps the baseline Code itself, with the bytecode in
+            // place of its DeoptimizationData. Older V8 wraps both in a
+            // BaselineData.
+            let (code, bytecode) = if is(typ.code) {
+                (data, self.read_tagged_pointer(data, self.vms.code.bytecode_or_interpreter_data))
+            } else {
+                (
+                    self.read_tagged_pointer(data, self.vms.baseline_data.baseline_code)?,
+                    self.read_tagged_pointer(data, self.vms.baseline_data.data),
+                )
+            };
+            let bytecode_array = bytecode.and_then(|bytecode| self.unwrap_interpreter_data(bytecode)).ok();
+            return Ok(FunctionDataKind::Baseline { code, bytecode_array });
+        }
+        if is(typ.asm_wasm_data) {
+            return Ok(FunctionDataKind::AsmWasm);
+        }
+        if is(typ.function_template_info) {
+            return Ok(FunctionDataKind::Api);
+        }
+        let uncompiled = [
+            typ.uncompiled_data_with_preparse_data,
+            typ.uncompiled_data_with_preparse_data_and_job,
+            typ.uncompiled_data_without_preparse_data,
+            typ.uncompiled_data_without_preparse_data_with_job,
+        ];
+        if uncompiled.into_iter().any(is) {
+            return Ok(FunctionDataKind::Uncompiled);
+        }
+        Ok(FunctionDataKind::Other(instance_type))
+    }
+
+    /// The BytecodeArray of `data`, which is either one or an InterpreterData
+    /// holding one as its first field
+    fn unwrap_interpreter_data(&self, data: usize) -> Result<usize> {
+        let typ = &self.vms.typ;
+        let mut data = data;
+        if typ.interpreter_data != 0 && self.has_instance_type(data, typ.interpreter_data)? {
+            data = self.read_tagged_pointer(data, self.vms.heap_object.map + self.tagged_size() as u16)?;
+        }
+        if !self.has_instance_type(data, typ.bytecode_array)? {
+            anyhow::bail!("Not a BytecodeArray: 0x{:x}", data);
+        }
+        Ok(data)
+    }
+
+    /// Returns the contexts a JavaScript frame can see, innermost first: the
+    /// frame's own context, then each enclosing one by its `previous` link,
+    /// up to `max` of them. The chain ends at the native context, whose
+    /// previous slot doesn't hold a context. Typed frames have no context.
+    pub fn context_chain(&self, fp: usize, max: usize) -> Result<Vec<usize>> {
+        let mut context = self.read_ptr(fp_slot(fp, self.vms.frame_pointer.context))?;
+        let mut chain = Vec::new();
+        while chain.len() < max && self.is_context(context)? {
+            chain.push(context);
+            let slot = self.vms.context_index.previous as usize * self.tagged_size();
+            context = self.read_tagged(self.field_address(context, self.vms.fixed_array.data) + slot)?;
+        }
+        Ok(chain)
+    }
+
+    /// Returns the class of the receiver (`this`) of the JavaScript frame at
+    /// `fp`: the name of the constructor its object was made by, such as
+    /// `Object` for an object literal. `None` if the receiver is a primitive,
+    /// such as `undefined` for a strict mode function called on its own, or
+    /// its constructor has no name.
+    ///
+    /// Only found since V8 8.9, which pushes the receiver below the
+    /// arguments rather than above them, where it's at a fixed offset.
+    pub fn frame_receiver_class(&self, fp: usize) -> Result<Option<String>> {
+        let version = &self.version;
+        if self.vms.map.constructor_or_back_pointer == 0 || v8_ver(version.major, version.minor, version.build) < versions::RECEIVER_BELOW_ARGUMENTS {
+            return Ok(None);
+        }
+        let receiver = self.read_ptr(fp_slot(fp, self.vms.frame_pointer.args))?;
+        if !self.is_heap_object(receiver) {
+            return Ok(None);
+        }
+        // Maps made by adding a property point back at the map they were made
+        // from. The first map of the chain holds the constructor, and the maps
+        // of primitives hold none.
+        let mut map = self.read_tagged_pointer(receiver, self.vms.heap_object.map)?;
+        for _ in 0..MAX_MAP_TRANSITIONS {
+            let constructor = self.read_tagged_pointer(map, self.vms.map.constructor_or_back_pointer)?;
+            if self.has_instance_type(constructor, self.vms.typ.map)? {
+                map = constructor;
+                continue;
+            }
+            if !self.is_jsfunction(constructor)? {
+                return Ok(None);
+            }
+            let name = self.read_function_name(constructor)?;
+            return Ok(Some(name).filter(|name| !name.is_empty()));
+        }
+        anyhow::bail!("More than {} map transitions from receiver 0x{:x}", MAX_MAP_TRANSITIONS, receiver)
+    }
+
+    fn is_jsfunction(&self, value: usize) -> Result<bool> {
+        if !self.is_heap_object(value) {
+            return Ok(false);
+        }
+        Ok(self.is_js_function_type(self.instance_type(value)?))
+    }
+
+    fn is_context(&self, value: usize) -> Result<bool> {
+        if !self.is_heap_object(value) {
+            return Ok(false);
+        }
+        let instance_type = self.instance_type(value)?;
+        Ok((self.vms.fixed.first_context_type..=self.vms.fixed.last_context_type).contains(&instance_type))
+    }
+
+    /// The `threadId` that Node's `worker_threads` gives the thread running a
+    /// JavaScript frame, 0 for the main thread. Read from the Environment
+    /// that Node keeps with the native context of the frame's context.
+    /// `None` if the process isn't Node, or the Environment's layout isn't
+    /// known for the version.
+    pub fn frame_node_thread_id(&self, fp: usize) -> Result<Option<u64>> {
+        let environment = &self.vms.node_environment;
+        if environment.thread_id == 0 || self.vms.native_context.embedder_data == 0 || self.vms.map.constructor_or_back_pointer == 0 {
+            return Ok(None);
+        }
+        let context = self.read_ptr(fp_slot(fp, self.vms.frame_pointer.context))?;
+        if !self.is_context(context)? {
+            return Ok(None);
+        }
+        // The maps of contexts point at their native context where other maps
+        // keep their constructor
+        let map = self.read_tagged_pointer(context, self.vms.heap_object.map)?;
+        let native_context = self.read_tagged_pointer(map, self.vms.map.constructor_or_back_pointer)?;
+        let embedder_data = self.read_tagged_pointer(native_context, self.vms.native_context.embedder_data)?;
+        if !self.has_instance_type(embedder_data, self.vms.typ.embedder_data_array)? {
+            return Ok(None);
+        }
+        // Embedder data slots are a full pointer each, compressed or not
+        let slot = self.vms.fixed_array.data as usize + environment.context_index as usize * self.pointer_size;
+        let slot = u16::try_from(slot).context("Environment slot out of range of the embedder data")?;
+        let environment_address = self.read_ptr(self.field_address(embedder_data, slot))?;
+        if environment_address == 0 {
+            // A context Node doesn't know about, as made by the vm module
+            return Ok(None);
+        }
+        Ok(Some(self.read_ptr(environment_address + environment.thread_id as usize)? as u64))
+    }
+
+    /// Returns the Script a SharedFunctionInfo was compiled from, if any
+    pub fn read_script(&self, sfi: usize) -> Result<Option<usize>> {
+        let script = self.read_tagged_pointer(sfi, self.vms.shared_function_info.script_or_debug_info)?;
+        self.resolve_script(script)
+    }
+
+    /// Returns `object` if it's a Script, or the Script of the function a
+    /// DebugInfo belongs to. A function being debugged keeps its DebugInfo
+    /// where the Script would be.
+    fn resolve_script(&self, object: usize) -> Result<Option<usize>> {
+        if self.has_instance_type(object, self.vms.typ.script)? {
+            return Ok(Some(object));
+        }
+        // Builds without the DebugInfo type would otherwise match type 0
+        if self.vms.typ.debug_info == 0 || !self.has_instance_type(object, self.vms.typ.debug_info)? {
+            return Ok(None);
+        }
+        let script = self.read_tagged_pointer(object, self.vms.debug_info.script)?;
+        if self.has_instance_type(script, self.vms.typ.script)? {
+            return Ok(Some(script));
+        }
+        // Without its own link to the Script, go through the function. This
+        // only goes one step, as the function's slot holds the DebugInfo.
+        let sfi = self.read_tagged_pointer(object, self.vms.debug_info.shared)?;
+        if !self.has_instance_type(sfi, self.vms.typ.shared_function_info)? {
+            return Ok(None);
+        }
+        let script = self.read_tagged_pointer(sfi, self.vms.shared_function_info.script_or_debug_info)?;
+        if !self.has_instance_type(script, self.vms.typ.script)? {
+            return Ok(None);
+        }
+        Ok(Some(script))
+    }
+
+    /// Reads the name of a Script, which may also be given as a DebugInfo
+    pub fn read_script_name(&self, script: usize) -> Result<Option<String>> {
+        let script = match self.resolve_script(script)? {
+            Some(script) => script,
+            None => return Ok(None),
+        };
+        let name = self.read_tagged_pointer(script, self.vms.script.name)?;
+        if !self.is_heap_object(name) || !self.is_string_object(name)? {
+            return Ok(None);
+        }
+        Ok(Some(self.read_string(name)?))
+    }
+
+    /// Reads the URL a Script names itself with in a `//# sourceURL=`
+    /// comment, as for code that's evaluated or bundled. Returns `None` if it
+    /// has none, or the field can't be placed in this version of V8.
+    pub fn read_script_source_url(&self, script: usize) -> Result<Option<String>> {
+        self.read_script_url(script, self.vms.script.source_url)
+    }
+
+    /// Reads the URL of a Script's source map, from its `//# sourceMappingURL=`
+    /// comment, for remapping its frames to the sources it was built from.
+    /// Returns `None` as `read_script_source_url` does.
+    pub fn read_script_source_mapping_url(&self, script: usize) -> Result<Option<String>> {
+        self.read_script_url(script, self.vms.script.source_mapping_url)
+    }
+
+    fn read_script_url(&self, script: usize, offset: u16) -> Result<Option<String>> {
+        if offset == 0 {
+            return Ok(None);
+        }
+        let script = match self.resolve_script(script)? {
+            Some(script) => script,
+            None => return Ok(None),
+        };
+        let url = self.read_tagged_pointer(script, offset)?;
+        if !self.is_heap_object(url) || !self.is_string_object(url)? {
+            return Ok(None);
+        }
+        Ok(Some(self.read_string(url)?))
+    }
+
+    /// Reads the source text of a Script. Returns `None` if the script has no
+    /// source, or it's a sliced string.
+    pub fn read_script_source(&self, script: usize) -> Result<Option<String>> {
+        let source = self.read_tagged_pointer(script, self.vms.script.source)?;
+        if !self.is_heap_object(source) || !self.is_string_object(source)? {
+            return Ok(None);
+        }
+        let fixed = &self.vms.fixed;
+        let representation = self.instance_type(source)? & fixed.string_representation_mask;
+        if representation == fixed.sliced_string_tag {
+            return Ok(None);
+        }
+        Ok(Some(self.read_string(source)?))
+    }
+
+    /// Maps a bytecode offset to a script offset using the BytecodeArray's
+    /// source position table. Fails if the offset is past the end of the
+    /// bytecode, which happens when the frame is read mid-update, e.g. while
+    /// the function is tiering up.
+    fn source_position_for_offset(&self, bytecode_array: usize, offset: usize) -> Result<Option<u32>> {
+        // BytecodeArray keeps its length where FixedArrayBase does
+        let bytecode_length = self.read_tagged_pointer(bytecode_array, self.vms.fixed_array_base.length)?;
+        let bytecode_length = usize::try_from(self.smi_to_int(bytecode_length))?;
+        if offset >= bytecode_length {
+            anyhow::bail!("Bytecode offset {} out of range for {} bytes of bytecode", offset, bytecode_length);
+        }
+        let table = self.read_tagged_pointer(bytecode_array, self.vms.bytecode_array.source_position_table)?;
+        let table = match self.read_byte_array(table)? {
+            Some(table) => table,
+            None => return Ok(None),
+        };
+        Ok(decode_source_position(&table, offset).and_then(script_offset))
+    }
+
+    /// Reads the contents of a ByteArray, or `None` if `array` isn't one, as
+    /// when V8 hasn't collected the source positions it would hold
+    fn read_byte_array(&self, array: usize) -> Result<Option<Vec<u8>>> {
+        if !self.has_instance_type(array, self.vms.typ.byte_array)? {
+            return Ok(None);
+        }
+        let length = self.read_tagged_pointer(array, self.vms.fixed_array_base.length)?;
+        let length = usize::try_from(self.smi_to_int(length))?;
+        if length > MAX_READ_LENGTH {
+            anyhow::bail!("Byte array too large ({} bytes)", length);
+        }
+        Ok(Some(self.copy(self.field_address(array, self.vms.fixed_array.data), length)?))
+    }
+
+    /// Maps a script offset to a 1-based line number using the Script's line
+    /// ends, which V8 only computes on demand
+    fn line_for_position(&self, script: usize, position: u32) -> Result<Option<u32>> {
+        let line_ends = self.read_tagged_pointer(script, self.vms.script.line_ends)?;
+        if !self.has_instance_type(line_ends, self.vms.typ.fixed_array)? {
+            return Ok(None);
+        }
+        let length = self.read_tagged_pointer(line_ends, self.vms.fixed_array_base.length)?;
+        let length = usize::try_from(self.smi_to_int(length))?;
+        if length > MAX_READ_LENGTH {
+            anyhow::bail!("Line ends array too large ({} entries)", length);
+        }
+        let ends = self.read_tagged_array(self.field_address(line_ends, self.vms.fixed_array.data), length)?;
+        let line = ends.partition_point(|&end| self.smi_to_int(end) < position as i64);
+        Ok(Some(line as u32 + 1))
+    }
+
+    pub fn read_string(&self, ptr: usize) -> Result<String> {
+        let mut units = Vec::new();
+        self.read_string_units(ptr, 0, &mut units)?;
+        // Decoded only once the whole string is read, as a cons string may
+        // split a surrogate pair between its halves
+        Ok(String::from_utf16_lossy(&units))
+    }
+
+    /// Appends the UTF-16 code units of a string to `units`
+    fn read_string_units(&self, ptr: usize, depth: usize, units: &mut Vec<u16>) -> Result<()> {
+        if depth > 32 {
+            anyhow::bail!("String nested too deeply");
+        }
+        if !self.is_heap_object(ptr) {
+            anyhow::bail!("Not a heap object: 0x{:x}", ptr);
+        }
+        let fixed = &self.vms.fixed;
+        let instance_type = self.instance_type(ptr)?;
+        if !self.is_string_type(instance_type) {
+            return Err(Error::NotAString { address: ptr, instance_type }.into());
+        }
+        let length = self.read_u32(self.field_address(ptr, self.vms.string.length))? as usize;
+        if units.len() + length > MAX_READ_LENGTH {
+            anyhow::bail!("String too long ({} chars)", units.len() + length);
+        }
+
+        let representation = instance_type & fixed.string_representation_mask;
+        if representation == fixed.seq_string_tag {
+            if instance_type & fixed.string_encoding_mask == fixed.one_byte_string_tag {
+                let chars = self.copy(self.field_address(ptr, self.vms.seq_one_byte_string.chars), length)?;
+                // One-byte strings are Latin-1, the first 256 code points
+                units.extend(chars.iter().map(|&c| c as u16));
+            } else {
+                let chars: Vec<u16> = self.copy_vec(self.field_address(ptr, self.vms.seq_two_byte_string.chars), length)?;
+                units.extend(chars);
+            }
+            Ok(())
+        } else if representation == fixed.cons_string_tag {
+            let first = self.read_tagged_pointer(ptr, self.vms.cons_string.first)?;
+            let second = self.read_tagged_pointer(ptr, self.vms.cons_string.second)?;
+            self.read_string_units(first, depth + 1, units)?;
+            self.read_string_units(second, depth + 1, units)
+        } else if representation == fixed.thin_string_tag {
+            let actual = self.read_tagged_pointer(ptr, self.vms.thin_string.actual)?;
+            self.read_string_units(actual, depth + 1, units)
+        } else if representation == fixed.external_string_tag {
+            // Uncached strings only have the resource, whose data is behind a
+            // virtual call we can't make from outside the process
+            if instance_type & UNCACHED_EXTERNAL_STRING_MASK != 0 {
+                anyhow::bail!("Uncached external string at 0x{:x}", ptr);
+            }
+            let data = self.read_ptr(self.field_address(ptr, self.vms.external_string.resource_data))?;
+            if data == 0 {
+                anyhow::bail!("External string at 0x{:x} has no data", ptr);
+            }
+            if instance_type & fixed.string_encoding_mask == fixed.one_byte_string_tag {
+                let chars = self.copy(data, length)?;
+                units.extend(chars.iter().map(|&c| c as u16));
+            } else {
+                let chars: Vec<u16> = self.copy_vec(data, length)?;
+                units.extend(chars);
+            }
+            Ok(())
+        } else {
+            anyhow::bail!("Unsupported string representation {}", representation)
+        }
+    }
+
+    fn is_string_object(&self, ptr: usize) -> Result<bool> {
+        Ok(self.is_string_type(self.instance_type(ptr)?))
+    }
+
+    /// Whether an instance type denotes a String. V8 numbers all of its
+    /// string types below `FirstNonstringType`.
+    pub fn is_string_type(&self, instance_type: u16) -> bool {
+        instance_type < self.vms.fixed.first_nonstring_type
+    }
+
+    /// Whether an instance type denotes a JSFunction. Since V8 9.0, class
+    /// constructors and a few other kinds of function have instance types of
+    /// their own, numbered from `FirstJSFunctionType` to `LastJSFunctionType`.
+    pub fn is_js_function_type(&self, instance_type: u16) -> bool {
+        let fixed = &self.vms.fixed;
+        fixed.first_jsfunction_type != 0 && (fixed.first_jsfunction_type..=fixed.last_jsfunction_type).contains(&instance_type)
+    }
+
+    /// Reads the instance type of a heap object from its Map, which says what
+    /// kind of object it is
+    pub fn instance_type(&self, ptr: usize) -> Result<u16> {
+        if !self.is_heap_object(ptr) {
+            anyhow::bail!("Not a heap object: 0x{:x}", ptr);
+        }
+        let map = self.read_tagged_pointer(ptr, self.vms.heap_object.map)?;
+        self.read_u16(self.field_address(map, self.vms.map.instance_type))
+    }
+
+    /// Whether `value` is a pointer to a heap object of the given instance type
+    fn has_instance_type(&self, value: usize, instance_type: u16) -> Result<bool> {
+        Ok(self.is_heap_object(value) && self.instance_type(value)? == instance_type)
+    }
+
+    /// Returns the `[start, end)` address range of a Code object's instructions
+    pub fn code_instruction_range(&self, code: usize) -> Result<(usize, usize)> {
+        if !self.is_heap_object(code) {
+            anyhow::bail!("Not a Code object: 0x{:x}", code);
+        }
+        let code_fields = &self.vms.code;
+        // Before the Code/InstructionStream split, instructions directly
+        // follow the Code header. Since then, Code points at them.
+        let start = if code_fields.instruction_start_is_pointer {
+            // A raw address rather than a tagged value, so never compressed
+            self.read_ptr(self.field_address(code, code_fields.instruction_start))?
+        } else {
+            self.field_address(code, code_fields.instruction_start)
+        };
+        let size = self.read_u32(self.field_address(code, code_fields.instruction_size))? as usize;
+        Ok((start, start + size))
+    }
+
+    /// Extracts the CodeKind from a Code object's flags, if this V8 exposes it
+    fn code_kind_of(&self, code: usize) -> Result<Option<u8>> {
+        let code_kind = &self.vms.code_kind;
+        if code_kind.field_mask == 0 || !self.is_heap_object(code) {
+            return Ok(None);
+        }
+        let flags = self.read_u32(self.field_address(code, self.vms.code.flags))?;
+        Ok(Some(((flags & code_kind.field_mask) >> code_kind.field_shift) as u8))
+    }
+
+    /// Tells which compiler produced a Code object, or `None` if it isn't
+    /// optimized code or this V8 doesn't expose its kind
+    fn tier_of(&self, code: usize) -> Result<Option<Tier>> {
+        if self.vms.code_kind.field_mask == 0 || !self.is_heap_object(code) {
+            return Ok(None);
+        }
+        let flags = self.read_u32(self.field_address(code, self.vms.code.flags))?;
+        Ok(self.tier_from_flags(flags))
+    }
+
+    fn tier_from_flags(&self, flags: u32) -> Option<Tier> {
+        let code_kind = &self.vms.code_kind;
+        let kind = ((flags & code_kind.field_mask) >> code_kind.field_shift) as u8;
+        if kind == code_kind.maglev {
+            return Some(Tier::Maglev);
+        }
+        // The is_turbofanned bit has always come right after the kind
+        let turbofanned_bit = code_kind.field_shift as u32 + code_kind.field_mask.count_ones();
+        (flags & (1 << turbofanned_bit) != 0).then_some(Tier::Turbofan)
+    }
+
+    /// Decodes the flags of a Code object. Fails on V8 versions older than
+    /// 9.0 whose flags layout isn't known.
+    pub fn code_flags(&self, code: usize) -> Result<CodeFlags> {
+        let code_kind = &self.vms.code_kind;
+        if code_kind.field_mask == 0 {
+            anyhow::bail!("Code flags layout unknown for V8 {}", self.version);
+        }
+        if !self.has_instance_type(code, self.vms.typ.code)? {
+            anyhow::bail!("Not a Code object: 0x{:x}", code);
+        }
+        let flags = self.read_u32(self.field_address(code, self.vms.code.flags))?;
+        let kind = ((flags & code_kind.field_mask) >> code_kind.field_shift) as u8;
+        let tier = self.tier_from_flags(flags);
+        let is_turbofanned = tier == Some(Tier::Turbofan);
+
+        let builtin_id = match self.vms.code.builtin_id {
+            0 => None,
+            offset if self.vms.code.builtin_id_is_int32 => {
+                let id = self.read_u32(self.field_address(code, offset))? as i32;
+                u16::try_from(id).ok()
+            }
+            offset => {
+                let id = self.read_u16(self.field_address(code, offset))? as i16;
+                u16::try_from(id).ok()
+            }
+        };
+        Ok(CodeFlags { kind, is_turbofanned, tier, builtin_id })
+    }
+
+    /// Reads a tagged pointer field of a heap object
+    fn read_tagged_pointer(&self, ptr: usize, offset: u16) -> Result<usize> {
+        self.read_tagged(self.field_address(ptr, offset))
+    }
+
+    /// Reads `buf.len()` bytes at `addr` straight into `buf`. Unlike
+    /// `ProcessMemory::copy_struct`, nothing is allocated, so the small reads
+    /// made while unwinding can use buffers on the stack. A zero-length read
+    /// succeeds without reading anything, wherever it is.
+    pub fn read_into(&self, addr: usize, buf: &mut [u8]) -> Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        self.check_mapped(addr, buf.len())?;
+        self.timed_read(addr, || self.process.read(addr, buf))
+    }
+
+    /// Reads `length` bytes, checked as `read_into` does
+    fn copy(&self, addr: usize, length: usize) -> Result<Vec<u8>> {
+        self.check_mapped(addr, length)?;
+        self.timed_read(addr, || self.process.copy(addr, length))
+    }
+
+    /// Reads `length` values of `T`, checked as `read_into` does
+    fn copy_vec<T: Copy>(&self, addr: usize, length: usize) -> Result<Vec<T>> {
+        self.check_mapped(addr, length.saturating_mul(std::mem::size_of::<T>()))?;
+        self.timed_read(addr, || self.process.copy_vec(addr, length))
+    }
+
+    /// Makes a read, failing it with `Error::ReadTimeout` if it takes longer
+    /// than the read timeout, or if an earlier read of the sample did
+    fn timed_read<R>(&self, addr: usize, read: impl FnOnce() -> Result<R, remoteprocess::Error>) -> Result<R> {
+        let timeout = match self.read_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(read()?),
+        };
+        if self.read_timed_out.get() {
+            return Err(Error::ReadTimeout { address: addr, timeout }.into());
+        }
+        let start = Instant::now();
+        let result = read();
+        if start.elapsed() > timeout {
+            self.read_timed_out.set(true);
+            return Err(Error::ReadTimeout { address: addr, timeout }.into());
+        }
+        Ok(result?)
+    }
+
+    /// Fails with `Error::UnmappedRegion` if a live process doesn't have all
+    /// of `[addr, addr + size)` mapped. The map is reloaded on a miss, in
+    /// case the process has mapped more since, at most once per
+    /// `MEMORY_MAP_RELOAD_INTERVAL`.
+    fn check_mapped(&self, addr: usize, size: usize) -> Result<()> {
+        let memory_map = match &self.memory_map {
+            Some(memory_map) => memory_map,
+            None => return Ok(()),
+        };
+        if memory_map.borrow().contains(addr, size) {
+            return Ok(());
+        }
+        if self.memory_map_loaded.get().elapsed() >= MEMORY_MAP_RELOAD_INTERVAL {
+            if let Ok(reloaded) = MemoryMap::load(self.pid) {
+                *memory_map.borrow_mut() = reloaded;
+            }
+            self.memory_map_loaded.set(Instant::now());
+            if memory_map.borrow().contains(addr, size) {
+                return Ok(());
+            }
+        }
+        Err(Error::UnmappedRegion { address: addr, size }.into())
+    }
+
+    /// Reads a u64 in the target's byte order, which is also ours
+    pub fn read_u64(&self, addr: usize) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        self.read_into(addr, &mut buf)?;
+        Ok(u64::from_ne_bytes(buf))
+    }
+
+    fn read_u32(&self, addr: usize) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.read_into(addr, &mut buf)?;
+        Ok(u32::from_ne_bytes(buf))
+    }
+
+    fn read_u16(&self, addr: usize) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        self.read_into(addr, &mut buf)?;
+        Ok(u16::from_ne_bytes(buf))
+    }
+
+    /// Reads a value the width of a pointer in the target, such as a stack
+    /// slot, an address, or a tagged value without pointer compression
+    pub fn read_ptr(&self, addr: usize) -> Result<usize> {
+        if self.pointer_size == 4 {
+            return Ok(self.read_u32(addr)? as usize);
+        }
+        Ok(self.read_u64(addr)? as usize)
+    }
+
+    /// Reads a tagged value from the heap, decompressing it if needed
+    fn read_tagged(&self, addr: usize) -> Result<usize> {
+        if !self.compression_enabled {
+            return self.read_ptr(addr);
+        }
+        Ok(self.decompress(self.read_u32(addr)?))
+    }
+
+    /// Reads `length` consecutive tagged values from the heap
+    fn read_tagged_array(&self, addr: usize, length: usize) -> Result<Vec<usize>> {
+        if !self.compression_enabled && self.pointer_size == 8 {
+            let values: Vec<u64> = self.copy_vec(addr, length)?;
+            return Ok(values.into_iter().map(|value| value as usize).collect());
+        }
+        if !self.compression_enabled {
+            // 32-bit pointers, read as they are
+            let values: Vec<u32> = self.copy_vec(addr, length)?;
+            return Ok(values.into_iter().map(|value| value as usize).collect());
+        }
+        let values: Vec<u32> = self.copy_vec(addr, length)?;
+        Ok(values.into_iter().map(|value| self.decompress(value)).collect())
+    }
+
+    /// Expands a compressed tagged value to a full one: Smis are sign
+    /// extended, and heap objects are offsets from the cage base
+    fn decompress(&self, value: u32) -> usize {
+        if self.is_smi(value as usize) {
+            value as i32 as isize as usize
+        } else {
+            self.cage_base.get() + value as usize
+        }
+    }
+
+    /// Size of a tagged field on the heap
+    fn tagged_size(&self) -> usize {
+        if self.compression_enabled {
+            self.pointer_size / 2
+        } else {
+            self.pointer_size
+        }
+    }
+
+    /// Converts a tagged pointer and a field offset to the field's address
+    fn field_address(&self, ptr: usize, offset: u16) -> usize {
+        // Wrapping, so a bogus pointer makes the read fail rather than panic
+        ptr.wrapping_sub(self.vms.fixed.heap_object_tag as usize).wrapping_add(offset as usize)
+    }
+
+    fn is_heap_object(&self, value: usize) -> bool {
+        value as u32 & self.vms.fixed.heap_object_tag_mask == self.vms.fixed.heap_object_tag as u32
+    }
+
+    fn is_smi(&self, value: usize) -> bool {
+        value as u32 & self.vms.fixed.smi_tag_mask == self.vms.fixed.smi_tag as u32
+    }
+
+    fn smi_tag_size(&self) -> u32 {
+        self.vms.fixed.smi_tag_mask.count_ones()
+    }
+
+    fn smi_to_int(&self, value: usize) -> i64 {
+        // The tagged values of a 32-bit target are read zero extended, so the
+        // sign of a Smi is in bit 31. Compressed ones are sign extended as
+        // they're decompressed.
+        let value = if self.pointer_size == 4 { value as u32 as i32 as i64 } else { value as i64 };
+        value >> (self.vms.fixed.smi_shift_size as u32 + self.smi_tag_size())
+    }
+
+    /// The object offsets, frame slots and constants this spy reads V8 with,
+    /// fallbacks included, as `(part.field, value)` pairs sorted by name. Two
+    /// dumps line up field for field, to diff one version against another.
+    pub fn layout(&self) -> Vec<(&'static str, u64)> {
+        self.vms.to_sorted_pairs()
+    }
+
+    /// Checks that the Smi constants read from the process describe one of
+    /// the layouts V8 has: 32 bit Smis in the upper half of a 64 bit word, or
+    /// 31 bit Smis with 32 bit or compressed pointers. Anything else would
+    /// misread every length and offset.
+    pub fn validate_smi_constants(&self) -> Result<()> {
+        let fixed = &self.vms.fixed;
+        if fixed.smi_tag_mask == 0 || fixed.smi_tag as u32 & !fixed.smi_tag_mask != 0 {
+            anyhow::bail!("Invalid Smi tag {} for tag mask {}", fixed.smi_tag, fixed.smi_tag_mask);
+        }
+        if fixed.heap_object_tag as u32 & fixed.smi_tag_mask == fixed.smi_tag as u32 {
+            anyhow::bail!("Heap object tag {} can't be told apart from the Smi tag {}", fixed.heap_object_tag, fixed.smi_tag);
+        }
+        let smi_bits = 8 * self.pointer_size as u32 - fixed.smi_shift_size as u32 - self.smi_tag_size();
+        let valid = match fixed.smi_shift_size {
+            0 => smi_bits == 31 || (smi_bits == 63 && !self.compression_enabled),
+            31 => self.pointer_size == 8 && !self.compression_enabled,
+            _ => false,
+        };
+        if !valid {
+            anyhow::bail!(
+                "Inconsistent Smi layout: shift size {} with {} byte pointers{}",
+                fixed.smi_shift_size,
+                self.pointer_size,
+                if self.compression_enabled { " and pointer compression" } else { "" }
+            );
+        }
+        Ok(())
+    }
+}
+
+/// What `with_process_paused` suspends: something whose threads can be
+/// stopped until the guard it returns is dropped
+trait Pause {
+    type Guard;
+
+    fn pause(&self) -> Result<Self::Guard>;
+}
+
+impl Pause for Process {
+    type Guard = remoteprocess::Lock;
+
+    fn pause(&self) -> Result<remoteprocess::Lock> {
+        Ok(self.lock()?)
+    }
+}
+
+/// Runs `f` with `target` paused, resuming it however `f` ends, as the
+/// guard is dropped on return and while a panic unwinds alike
+fn run_paused<T: Pause, R>(target: &T, pid: Pid, f: impl FnOnce() -> R) -> Result<R> {
+    let _guard = pause_process(target, pid)?;
+    Ok(f())
+}
+
+fn pause_process<T: Pause>(target: &T, pid: Pid) -> Result<T::Guard> {
+    target.pause().context(format!("Failed to suspend process {}", pid))
+}
+
+/// Pauses `target` until the guard left in `held` is dropped, unless it
+/// holds one already
+fn hold_pause<T: Pause>(target: &T, pid: Pid, held: &RefCell<Option<Box<dyn Any>>>) -> Result<()>
+where
+    T::Guard: 'static,
+{
+    if held.borrow().is_none() {
+        let guard = pause_process(target, pid)?;
+        *held.borrow_mut() = Some(Box::new(guard));
+    }
+    Ok(())
+}
+
+impl<P> Drop for V8Spy<P> {
+    fn drop(&mut self) {
+        // Dropping the guard resumes the threads, as at the end of `run_paused`
+        if self.held_pause.get_mut().take().is_some() {
+            log::debug!("Resumed process {}, which was still paused when the spy was dropped", self.pid);
+        }
+    }
+}
+
+/// Labels a frame running the embedded builtin `name`, as
+/// `<builtin: name>`, or as `<bytecode handler>` for the builtins that enter
+/// the interpreter or run its bytecodes. The handlers are all named after
+/// their bytecode with `Handler` at the end, and are labeled alike, as it's
+/// being in the interpreter that matters rather than the bytecode. The
+/// CodeKind would tell them apart too, but the Code objects of embedded
+/// builtins can't be found from their frames.
+fn builtin_label(name: &str) -> String {
+    if name.ends_with("Handler") || name.starts_with("InterpreterEntryTrampoline") || name.starts_with("InterpreterEnterAt") {
+        "<bytecode handler>".to_owned()
+    } else {
+        format!("<builtin: {}>", name)
+    }
+}
+
+/// Returns the address of a frame slot. The `off_fp_*` constants are signed
+/// offsets from the frame pointer, truncated to a byte when read.
+fn fp_slot(fp: usize, offset: u8) -> usize {
+    fp.wrapping_add_signed(offset as i8 as isize)
+}
+
+/// Decodes a V8 source position table and returns the packed SourcePosition
+/// of the last entry at or before the code offset, which is a bytecode
+/// offset for bytecode and a pc offset for optimized code. Entries are pairs
+/// of zigzag-encoded VLQ deltas: the code offset (negated for expression
+/// positions) and the SourcePosition.
+fn decode_source_position(table: &[u8], code_offset: usize) -> Option<i64> {
+    fn decode_int(table: &[u8], index: &mut usize) -> Option<i64> {
+        let mut bits = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = *table.get(*index)?;
+            *index += 1;
+            bits |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 || shift >= 64 {
+                break;
+            }
+        }
+        Some((bits >> 1) as i64 ^ -((bits & 1) as i64))
+    }
+
+    let mut index = 0;
+    let mut entry_offset = 0i64;
+    let mut source_position = 0i64;
+    let mut result = None;
+    while index < table.len() {
+        let delta = decode_int(table, &mut index)?;
+        entry_offset += if delta >= 0 { delta } else { -(delta + 1) };
+        source_position += decode_int(table, &mut index)?;
+        if entry_offset > code_offset as i64 {
+            break;
+        }
+        result = Some(source_position);
+    }
+    result
+}
+
+/// The script offset of a packed SourcePosition, which keeps it in 30 bits
+/// above an "is external" bit, plus one so that 0 is no position. External
+/// positions are lines of files outside any script, and have none.
+fn script_offset(position: i64) -> Option<u32> {
+    if position & 1 != 0 {
+        return None;
+    }
+    (((position >> 1) & ((1 << 30) - 1)) as u32).checked_sub(1)
+}
+
+/// The inlining id of a packed SourcePosition, which keeps it in 16 bits
+/// above the script offset, plus one so that 0 is the code's own function
+fn inlining_id(position: i64) -> Option<usize> {
+    (((position >> 31) & 0xffff) as usize).checked_sub(1)
+}
+
+/// A running process considered when attaching by name
+#[derive(Debug, Clone)]
+pub struct ProcessCandidate {
+    pub pid: Pid,
+    pub exe: std::string::String,
+    pub cmdline: Vec<std::string::String>,
+}
+
+impl ProcessCandidate {
+    /// Whether this looks like a Node process and its executable or any of
+    /// its arguments contain `name`. A Node process is one whose executable
+    /// is named `node`, `nodejs` or `node` and a version, as distributions
+    /// install it, so tools such as `nodemon` that merely have node in their
+    /// name don't match.
+    pub fn matches(&self, name: &str) -> bool {
+        let exe_name = std::path::Path::new(&self.exe)
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let is_node = exe_name
+            .strip_prefix("node")
+            .is_some_and(|version| version == "js" || version.chars().all(|c| c.is_ascii_digit() || c == '.'));
+        is_node && (self.exe.contains(name) || self.cmdline.iter().any(|arg| arg.contains(name)))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn list_processes() -> Result<Vec<ProcessCandidate>> {
+    let mut candidates = Vec::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let pid = match entry?.file_name().to_str().and_then(|pid| pid.parse::<Pid>().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // Processes can exit, or be inaccessible to us, while we're listing
+        let process = match remoteprocess::Process::new(pid) {
+            Ok(process) => process,
+            Err(_) => continue,
+        };
+        if let (Ok(exe), Ok(cmdline)) = (process.exe(), process.cmdline()) {
+            candidates.push(ProcessCandidate { pid, exe, cmdline });
+        }
+    }
+    Ok(candidates)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn list_processes() -> Result<Vec<ProcessCandidate>> {
+    anyhow::bail!("Finding processes by name is not supported on this platform")
+}
+
+/// Opens a process and reads its memory maps and binaries
+fn open_process(pid: Pid) -> Result<(Process, ProcessInfo)> {
+    let process = remoteprocess::Process::new(pid).context(format!("Failed to open process {} - check if it is running.", pid))?;
+    match ProcessInfo::new::<spytools::process::NodeProcessType>(&process) {
+        Ok(process_info) => Ok((process, process_info)),
+        // The memory maps of another user's process can't be read either
+        Err(e) if is_permission_denied(&e) => Err(Error::PermissionDenied(pid).into()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether an error comes down to the OS refusing access, as it does when
+/// tracing or reading the memory of a process we aren't allowed to
+fn is_permission_denied(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return e.kind() == std::io::ErrorKind::PermissionDenied || matches!(e.raw_os_error(), Some(libc::EPERM | libc::EACCES));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(remoteprocess::Error::NixError(errno)) = cause.downcast_ref::<remoteprocess::Error>() {
+            return matches!(*errno as i32, libc::EPERM | libc::EACCES);
+        }
+        false
+    })
+}
+
+/// The name of a thread, as the kernel keeps it
+#[cfg(target_os = "linux")]
+fn thread_name(pid: Pid, tid: Tid) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{}/task/{}/comm", pid, tid)).ok()?;
+    Some(comm.trim_end_matches('\n').to_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn thread_name(_pid: Pid, _tid: Tid) -> Option<String> {
+    None
+}
+
+/// Whether a thread's kernel name is `name`. The kernel cuts names off at
+/// `THREAD_NAME_MAX` bytes, so a longer name matches its first bytes.
+fn thread_name_matches(comm: &str, name: &str) -> bool {
+    comm == name || (comm.len() == THREAD_NAME_MAX && name.len() > THREAD_NAME_MAX && name.as_bytes().starts_with(comm.as_bytes()))
+}
+
+/// Whether a stopped thread was blocked in a system call when it was stopped
+#[cfg(target_os = "linux")]
+fn is_in_syscall(pid: Pid, tid: Tid) -> bool {
+    // Holds the number of the syscall being executed, or -1 when stopped in
+    // user space
+    match std::fs::read_to_string(format!("/proc/{}/task/{}/syscall", pid, tid)) {
+        Ok(syscall) => syscall.split_whitespace().next().is_some_and(|nr| nr.parse::<i64>().is_ok_and(|nr| nr >= 0)),
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_in_syscall(_pid: Pid, _tid: Tid) -> bool {
+    false
+}
+
+/// Whether any of V8's version symbols can be found in the target
+fn has_v8_symbols(target: &impl MemorySource) -> bool {
+    V8_SENTINEL_SYMBOLS.iter().any(|symbol| target.symbol(symbol).is_some())
+}
+
+/// The V8 version of a target, for `V8Spy::try_version`, or `None` if it
+/// doesn't embed V8 or its version can't be read
+fn version_of(target: &impl MemorySource) -> Option<Version> {
+    if !has_v8_symbols(target) {
+        return None;
+    }
+    get_v8_version(target, true).ok()
+}
+
+/// Builds the address ranges of the garbage collector's native functions
+fn get_gc_ranges(target: &LiveTarget) -> Vec<(usize, usize)> {
+    let is_gc = |name: &str| GC_SYMBOL_PATTERNS.iter().any(|pattern| name.contains(pattern)).then_some(());
+    symbol_ranges(target, is_gc).into_iter().map(|(start, end, ())| (start, end)).collect()
+}
+
+/// Builds the address ranges of the libuv functions that run each phase of
+/// the event loop, sorted by start
+fn get_event_loop_ranges(target: &LiveTarget) -> Vec<(usize, usize, EventLoopPhase)> {
+    let phase = |name: &str| EVENT_LOOP_SYMBOLS.iter().find(|&&(symbol, _)| symbol == name).map(|&(_, phase)| phase);
+    symbol_ranges(target, phase)
+}
+
+/// Builds the address ranges of V8's embedded builtins from the `Builtins_`
+/// symbols the binary has for them, keyed by start
+fn get_builtin_ranges(target: &LiveTarget) -> BTreeMap<usize, (usize, String)> {
+    let names: Vec<&str> = target
+        .modules()
+        .flat_map(|module| module.symbols.keys())
+        .filter_map(|name| name.strip_prefix("Builtins_"))
+        .collect();
+    let index: HashMap<&str, usize> = names.iter().enumerate().map(|(i, &name)| (name, i)).collect();
+    let builtin = |name: &str| name.strip_prefix("Builtins_").and_then(|name| index.get(name).copied());
+    symbol_ranges(target, builtin).into_iter().map(|(start, end, i)| (start, (end, names[i].to_owned()))).collect()
+}
+
+/// Builds the address ranges of the native functions that `classify` gives
+/// a value, merging neighbouring functions with the same value. Symbol sizes
+/// aren't available, so each function is taken to extend to the next symbol.
+fn symbol_ranges<T: Copy + PartialEq>(target: &LiveTarget, classify: impl Fn(&str) -> Option<T>) -> Vec<(usize, usize, T)> {
+    let mut symbols: Vec<(usize, Option<T>)> = target
+        .modules()
+        .flat_map(|binary| binary.symbols.iter())
+        .map(|(name, &addr)| (addr as usize, classify(name)))
+        .collect();
+    symbols.sort_unstable_by_key(|&(addr, value)| (addr, value.is_none()));
+    symbols.dedup_by_key(|&mut (addr, _)| addr);
+
+    let mut ranges: Vec<(usize, usize, T)> = Vec::new();
+    for window in symbols.windows(2) {
+        let ((start, value), (end, _)) = (window[0], window[1]);
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        match ranges.last_mut() {
+            Some(last) if last.1 == start && last.2 == value => last.1 = end,
+            _ => ranges.push((start, end, value)),
+        }
+    }
+    ranges
+}
+
+/// Fills in offsets that the binary's postmortem symbols don't provide, from
+/// what's known about the layout of V8 version `ver` (see `versions::v8_ver`)
+fn apply_vmdata_fallbacks(vms: &mut VMData, ver: u32, pointer_size: usize) -> Result<()> {
+    apply_fp_offset_fallbacks(&mut vms.frame_pointer, ver, pointer_size)?;
+    let ps = pointer_size as i32;
+    if vms.isolate.thread_local_top == 0 && versions::same_minor(ver, versions::KNOWN_ISOLATE_LAYOUT) {
+        // The ThreadLocalTop moves with most versions, as IsolateData grows.
+        // Only known for V8 11.3, as in Node 20.
+        vms.isolate.thread_local_top = 0x108;
+        vms.thread_local_top.c_entry_fp = 0x78;
+    }
+    if vms.isolate.debug == 0 && versions::same_minor(ver, versions::KNOWN_ISOLATE_LAYOUT) {
+        // Far into the Isolate, after its IsolateData and heap. Only known for
+        // V8 11.3, like the ThreadLocalTop.
+        vms.isolate.debug = 0xe5e8;
+        vms.debugger.is_active = 0x8;
+        vms.debugger.current_debug_scope = 0x40;
+    }
+    if vms.node_environment.thread_id == 0 && vms.node_environment.handle_wrap_queue != 0 && versions::same_minor(ver, versions::KNOWN_ISOLATE_LAYOUT) {
+        // Not exported by Node, and moves as the Environment grows. Only
+        // known for Node 20, where it's this far before the handle wraps.
+        vms.node_environment.thread_id = relative_offset("node_environment.thread_id", vms.node_environment.handle_wrap_queue, -0x78)?;
+    }
+    if vms.fixed.first_jsfunction_type == 0 {
+        // Since V8 9.0.14 the JSFunction is no longer a final class, but has several
+        // classes inheriting form it. The only way to check for the inheritance is to
+        // know which InstaceType tags belong to the range.
+        let mut num_jsfunc_types = 1u16;
+        if ver >= versions::JSFUNCTION_16_TYPES {
+            // One more by 11.3, as in Node 20, where JS_CLASS_CONSTRUCTOR_TYPE
+            // is the last of the range
+            num_jsfunc_types = 16;
+        } else if ver >= versions::JSFUNCTION_CLASS_CONSTRUCTOR {
+            // Class constructor special case
+            num_jsfunc_types = 15;
+        } else if ver >= versions::JSFUNCTION_SUBCLASSES {
+            // Several constructor special cases added
+            num_jsfunc_types = 14;
+        }
+        vms.fixed.first_jsfunction_type = vms.typ.js_function;
+        vms.fixed.last_jsfunction_type = relative_offset("fixed.last_jsfunction_type", vms.fixed.first_jsfunction_type, num_jsfunc_types as i32 - 1)?;
+    }
+    if vms.js_bound_function.bound_target_function == 0 {
+        // The target is the first field after the JSObject header, as far
+        // back as the class goes
+        vms.js_bound_function.bound_target_function = vms.js_object.internal_fields;
+    }
+    if vms.js_wrapped_function.wrapped_target_function == 0 {
+        vms.js_wrapped_function.wrapped_target_function = vms.js_object.internal_fields;
+    }
+    if vms.jsfunction.code == 0 {
+        if ver >= versions::JSFUNCTION_CODE_BEFORE_SHARED {
+            vms.jsfunction.code = relative_offset("jsfunction.code", vms.jsfunction.shared_function_info, -ps)?;
+        } else {
+            // At least back to V8 8.4
+            vms.jsfunction.code = relative_offset("jsfunction.code", vms.jsfunction.shared_function_info, 3 * ps)?;
+        }
+    }
+    if vms.code.instruction_size != 0 {
+        if vms.code.source_position_table == 0 {
+            // At least back to V8 8.4
+            vms.code.source_position_table = relative_offset("code.source_position_table", vms.code.instruction_size, -2 * ps)?;
+        }
+        if vms.code.flags == 0 {
+            // Back to V8 8.8.172
+            vms.code.flags = relative_offset("code.flags", vms.code.instruction_size, 2 * 4)?; // 2 * sizeof(int)
+        }
+    } else if vms.code.source_position_table != 0 {
+        // Likely V8 11.x where the Code postmortem data was accidentally deleted
+        if vms.code.deoptimization_data == 0 {
+            vms.code.deoptimization_data = relative_offset("code.deoptimization_data", vms.code.source_position_table, -ps)?;
+        }
+        if vms.code.instruction_start == 0 {
+            vms.code.instruction_start = relative_offset("code.instruction_start", vms.code.source_position_table, 2 * ps)?;
+            vms.code.instruction_start_is_pointer = true;
+        }
+        if vms.code.flags == 0 {
+            vms.code.flags = relative_offset("code.flags", vms.code.instruction_start, ps)?;
+        }
+        if vms.code.instruction_size == 0 {
+            let mut after_flags = 4;
+            if ver >= versions::CODE_FLAGS_MERGED {
+                // V8 starting 11.1.x Code has kBuiltinIdOffset and kKindSpecificFlagsOffset
+                // which changed again in 11.4.59 when these were removed in commit
+                // cb8be519f0add9b7 "[code] Merge kind_specific_flags with flags"
+                after_flags += 2 + 2;
+            }
+            vms.code.instruction_size = relative_offset("code.instruction_size", vms.code.flags, after_flags)?;
+        }
+    }
+    if vms.code.deoptimization_data == 0 && vms.code.source_position_table != 0 {
+        // Used unconditionally, pending patch for V8 to export this
+        // At least back to V8 7.2
+        vms.code.deoptimization_data = relative_offset("code.deoptimization_data", vms.code.source_position_table, -ps)?;
+    }
+    if vms.script.source == 0 {
+        // At least back to V8 8.4
+        vms.script.source = relative_offset("script.source", vms.script.name, -ps)?;
+    }
+    if vms.script.source_url == 0 {
+        // Following shared_function_infos, compiled_lazy_function_positions
+        // where there is one, and flags
+        if vms.script.compiled_lazy_function_positions != 0 {
+            vms.script.source_url = relative_offset("script.source_url", vms.script.compiled_lazy_function_positions, 2 * ps)?;
+        } else if vms.script.eval_from_position != 0 {
+            vms.script.source_url = relative_offset("script.source_url", vms.script.eval_from_position, 3 * ps)?;
+        }
+    }
+    if vms.script.source_mapping_url == 0 && vms.script.source_url != 0 {
+        vms.script.source_mapping_url = relative_offset("script.source_mapping_url", vms.script.source_url, ps)?;
+    }
+    if vms.fixed.external_string_tag == 0 {
+        // Unchanged since at least V8 7.2
+        vms.fixed.external_string_tag = 2;
+    }
+    if vms.external_string.resource == 0 {
+        // The first field after String's hash and length, at least back to V8 8.4
+        vms.external_string.resource = relative_offset("external_string.resource", vms.string.length, 4)?;
+    }
+    if vms.external_string.resource_data == 0 {
+        // Not exported, and directly after the resource since V8 7.2
+        vms.external_string.resource_data = relative_offset("external_string.resource_data", vms.external_string.resource, ps)?;
+    }
+    if vms.debug_info.shared == 0 {
+        // The first field after the map, at least back to V8 8.4
+        vms.debug_info.shared = relative_offset("debug_info.shared", vms.heap_object.map, ps)?;
+    }
+    if vms.debug_info.script == 0 {
+        // After shared and debugger_hints, at least back to V8 8.4
+        vms.debug_info.script = relative_offset("debug_info.script", vms.debug_info.shared, 2 * ps)?;
+    }
+    if vms.bytecode_array.source_position_table == 0 {
+        // Lost in V8 9.4
+        vms.bytecode_array.source_position_table = relative_offset("bytecode_array.source_position_table", vms.fixed_array_base.length, 3 * ps)?;
+    }
+    if vms.bytecode_array.data == 0 {
+        // At least back to V8 8.4 (16 = 3*int32 + uint16)
+        vms.bytecode_array.data = relative_offset("bytecode_array.data", vms.bytecode_array.source_position_table, ps + 14)?;
+    }
+    if vms.deoptimization_data_index.inlined_function_count == 0 {
+        vms.deoptimization_data_index.inlined_function_count = 1;
+    }
+    if vms.deoptimization_data_index.literal_array == 0 {
+        let val = relative_offset("deoptimization_data_index.literal_array", vms.deoptimization_data_index.inlined_function_count, 1)?;
+        vms.deoptimization_data_index.literal_array = val;
+    }
+    if vms.deoptimization_data_index.shared_function_info == 0 {
+        vms.deoptimization_data_index.shared_function_info = 6;
+    }
+    if vms.deoptimization_data_index.inlining_positions == 0 {
+        let val = relative_offset("deoptimization_data_index.inlining_positions", vms.deoptimization_data_index.shared_function_info, 1)?;
+        vms.deoptimization_data_index.inlining_positions = val;
+    }
+    if vms.code_kind.baseline == 0 {
+        if ver >= versions::BASELINE_METADATA {
+            // Back to V8 9.0.240, and metadata available after that
+            vms.code_kind.field_mask = 0xf;
+            vms.code_kind.field_shift = 0;
+            vms.code_kind.baseline = 11;
+        } else {
+            // Leave mask and shift to zero, and set baseline to something
+            // so that the Baseline code is never triggered.
+            vms.code_kind.baseline = 0xff;
+        }
+    }
+    if vms.code_kind.maglev == 0 {
+        // MAGLEV came in right after BASELINE when TURBOPROP was dropped, in
+        // V8 10.0. Before that, no kind is Maglev's.
+        vms.code_kind.maglev = match vms.code_kind.baseline {
+            baseline if baseline != 0xff && ver >= versions::MAGLEV_CODE_KIND => relative_offset("code_kind.maglev", baseline, 1)?,
+            _ => 0xff,
+        };
+    }
+    if vms.baseline_data.data == 0 && vms.code_kind.field_mask != 0 {
+        // Unfortunately no metadata currently. Has been static.
+        vms.baseline_data.baseline_code = relative_offset("baseline_data.baseline_code", vms.heap_object.map, ps)?;
+        vms.baseline_data.data = relative_offset("baseline_data.data", vms.heap_object.map, 2 * ps)?;
+    }
+    if vms.code.bytecode_or_interpreter_data == 0 {
+        // Baseline code keeps its bytecode where optimized code keeps its
+        // DeoptimizationData
+        vms.code.bytecode_or_interpreter_data = vms.code.deoptimization_data;
+    }
+    Ok(())
+}
+
+/// Fills in the frame pointer offsets that weren't read, by `FP_OFFSETS`.
+/// Fails if one would be out of range, from an offset it builds on that's
+/// out of place.
+fn apply_fp_offset_fallbacks(fps: &mut FramePointer, ver: u32, pointer_size: usize) -> Result<()> {
+    for offset in &FP_OFFSETS {
+        if let Some(fallback) = offset.fallback {
+            if *(offset.field)(fps) == 0 {
+                let value = fallback(fps, ver, pointer_size)
+                    .with_context(|| format!("The fallback for {} is out of range of a frame", offset.symbols[0]))?;
+                *(offset.field)(fps) = value as u8;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `base` moved by `delta` bytes, for the fallbacks that place a field
+/// relative to another. Fails rather than wrapping around if the base is
+/// too small or large for that, as when it's missing or was read wrong,
+/// which would leave the field to be read from wherever the wrapped offset
+/// points.
+fn relative_offset<T>(field: &str, base: T, delta: i32) -> Result<T>
+where
+    T: Copy + Into<i32> + TryFrom<i32> + std::fmt::Display,
+{
+    let direction = if delta < 0 { "before" } else { "after" };
+    T::try_from(base.into() + delta).map_err(|_| {
+        anyhow::anyhow!("The fallback for {}, {} bytes {} offset {}, is out of range", field, delta.unsigned_abs(), direction, base)
+    })
+}
+
+/// Reads the base of the pointer compression cage, for V8 builds that have
+/// one cage shared by all isolates. Returns 0 if it isn't exported.
+fn read_cage_base(target: &impl MemorySource, pointer_size: usize) -> usize {
+    target
+        .symbol(CAGE_BASE_SYMBOL)
+        .and_then(|addr| read_target_ptr(target, addr, pointer_size).ok())
+        .unwrap_or(0)
+}
+
+/// Reads a pointer of the target's size, before there's a spy to read it with
+fn read_target_ptr(target: &impl MemorySource, addr: usize, pointer_size: usize) -> Result<usize> {
+    let mut buf = [0u8; 8];
+    let size = pointer_size.min(buf.len());
+    target.read(addr, &mut buf[..size])?;
+    Ok(u64::from_le_bytes(buf) as usize)
+}
+
+/// Reads V8's version and object layout from its postmortem symbols
+fn read_layout(target: &impl MemorySource, options: &V8SpyOptions) -> Result<(Version, VMData)> {
+    let version = get_v8_version(target, options.allow_partial_version)?;
+    log::debug!("V8 version {}", version);
+    let numbers = |version: &Version| (version.major, version.minor, version.build);
+    if numbers(&version) < numbers(&MIN_SUPPORTED_VERSION) {
+        return Err(Error::UnsupportedVersion(version).into());
+    }
+    if (version.major, version.minor) > (MAX_TESTED_VERSION.major, MAX_TESTED_VERSION.minor) {
+        log::warn!(
+            "V8 {} is newer than the newest tested version, {}.{}, and may not be profiled correctly",
+            version, MAX_TESTED_VERSION.major, MAX_TESTED_VERSION.minor
+        );
+    }
+
+    let mut missing = Vec::new();
+    let mut vms = get_v8_data(target, &mut missing);
+    match options.on_missing_symbol {
+        OnMissingSymbol::Warn => {
+            for symbol in &missing {
+                log::warn!("Failed to get symbol {}", symbol);
+            }
+        }
+        OnMissingSymbol::Error if !missing.is_empty() => {
+            return Err(Error::MissingSymbols(missing).into());
+        }
+        OnMissingSymbol::Error => {}
+    }
+    if log::log_enabled!(log::Level::Debug) {
+        let pairs: Vec<String> = vms.to_sorted_pairs().iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        log::debug!("VMData {}", pairs.join(" "));
+    }
+
+    let pointer_size = target_pointer_size(&vms);
+    apply_vmdata_fallbacks(&mut vms, v8_ver(version.major, version.minor, version.build), pointer_size)?;
+    Ok((version, vms))
+}
+
+/// The size of a pointer in the target: 4 for ia32 and other 32-bit builds
+/// of V8, and 8 for 64-bit ones, pointer compression or not. Told by the
+/// context slot of a frame, which is the slot just below the saved frame
+/// pointer.
+fn target_pointer_size(vms: &VMData) -> usize {
+    pointer_size_from_context_slot(vms.frame_pointer.context)
+}
+
+/// Like `target_pointer_size`, but read straight from the symbol of the
+/// context slot, for the reads made before the layout is read
+fn symbol_pointer_size(target: &impl MemorySource) -> usize {
+    let mut context = 0u8;
+    match try_read_symbol(target, "v8dbg_off_fp_context", &mut context) {
+        Ok(true) => pointer_size_from_context_slot(context),
+        _ => POINTER_SIZE,
+    }
+}
+
+/// The pointer size told by the frame offset of the context slot, kept as
+/// the low byte of a negative offset
+fn pointer_size_from_context_slot(offset: u8) -> usize {
+    match (offset as i8).unsigned_abs() {
+        size @ (4 | 8) => size as usize,
+        _ => POINTER_SIZE,
+    }
+}
+
+/// Reads the layout of V8's objects and frames from its debug symbols. The
+/// symbols that couldn't be found are added to `missing`.
+fn get_v8_data(target: &impl MemorySource, missing: &mut Vec<String>) -> VMData {
+    let mut data = VMData::default();
+    read_memory(target, missing, "v8dbg_HeapObjectTagMask", &mut data.fixed.heap_object_tag_mask);
+    read_memory(target, missing, "v8dbg_SmiTagMask", &mut data.fixed.smi_tag_mask);
+    read_memory(target, missing, "v8dbg_HeapObjectTag", &mut data.fixed.heap_object_tag);
+    read_memory(target, missing, "v8dbg_SmiTag", &mut data.fixed.smi_tag);
+    read_memory(target, missing, "v8dbg_SmiShiftSize", &mut data.fixed.smi_shift_size);
+    read_memory(target, missing, "v8dbg_FirstNonstringType", &mut data.fixed.first_nonstring_type);
+    read_memory(target, missing, "v8dbg_StringEncodingMask", &mut data.fixed.string_encoding_mask);
+    read_memory(target, missing, "v8dbg_StringRepresentationMask", &mut data.fixed.string_representation_mask);
+    read_memory(target, missing, "v8dbg_SeqStringTag", &mut data.fixed.seq_string_tag);
+    read_memory(target, missing, "v8dbg_ConsStringTag", &mut data.fixed.cons_string_tag);
+    read_memory(target, missing, "v8dbg_OneByteStringTag", &mut data.fixed.one_byte_string_tag);
+    read_memory(target, missing, "v8dbg_TwoByteStringTag", &mut data.fixed.two_byte_string_tag);
+    read_memory(target, missing, "v8dbg_SlicedStringTag", &mut data.fixed.sliced_string_tag);
+    read_memory(target, missing, "v8dbg_ThinStringTag", &mut data.fixed.thin_string_tag);
+    read_memory(target, missing, "v8dbg_ExternalStringTag", &mut data.fixed.external_string_tag);
+    read_memory(target, missing, "v8dbg_FirstJSFunctionType", &mut data.fixed.first_jsfunction_type);
+    read_memory(target, missing, "v8dbg_LastJSFunctionType", &mut data.fixed.last_jsfunction_type);
+    read_memory(target, missing, "v8dbg_FirstContextType", &mut data.fixed.first_context_type);
+    read_memory(target, missing, "v8dbg_LastContextType", &mut data.fixed.last_context_type);
+    read_memory(target, missing, "v8dbg_context_idx_prev", &mut data.context_index.previous);
+    read_fp_offsets(target, missing, &mut data.frame_pointer);
+    read_memory(target, missing, "v8dbg_scopeinfo_idx_first_vars", &mut data.scope_info_index.first_vars);
+    read_memory(target, missing, "v8dbg_scopeinfo_idx_ncontextlocals", &mut data.scope_info_index.ncontext_locals);
+    read_memory(target, missing, "v8dbg_DeoptimizationDataInlinedFunctionCountIndex", &mut data.deoptimization_data_index.inlined_function_count);
+    read_memory(target, missing, "v8dbg_DeoptimizationDataLiteralArrayIndex", &mut data.deoptimization_data_index.literal_array);
+    read_memory(target, missing, "v8dbg_DeoptimizationDataSharedFunctionInfoIndex", &mut data.deoptimization_data_index.shared_function_info);
+    read_memory(target, missing, "v8dbg_DeoptimizationDataInliningPositionsIndex", &mut data.deoptimization_data_index.inlining_positions);
+    read_memory(target, missing, "v8dbg_CodeKindFieldMask", &mut data.code_kind.field_mask);
+    read_memory(target, missing, "v8dbg_CodeKindFieldShift", &mut data.code_kind.field_shift);
+    read_memory(target, missing, "v8dbg_CodeKindBaseline", &mut data.code_kind.baseline);
+    // Not generated by any version yet, so not missed
+    read_symbol(target, "v8dbg_CodeKindMaglev", &mut data.code_kind.maglev);
+    read_memory(target, missing, "v8dbg_frametype_ArgumentsAdaptorFrame", &mut data.frame_type.arguments_adaptor_frame);
+    read_memory(target, missing, "v8dbg_frametype_BaselineFrame", &mut data.frame_type.baseline_frame);
+    read_memory(target, missing, "v8dbg_frametype_BuiltinContinuationFrame", &mut data.frame_type.builtin_continuation_frame);
+    read_memory(target, missing, "v8dbg_frametype_BuiltinExitFrame", &mut data.frame_type.builtin_exit_frame);
+    read_memory(target, missing, "v8dbg_frametype_BuiltinFrame", &mut data.frame_type.builtin_frame);
+    read_memory(target, missing, "v8dbg_frametype_CwasmEntryFrame", &mut data.frame_type.cwasm_entry_frame);
+    read_memory(target, missing, "v8dbg_frametype_ConstructEntryFrame", &mut data.frame_type.construct_entry_frame);
+    read_memory(target, missing, "v8dbg_frametype_ConstructFrame", &mut data.frame_type.construct_frame);
+    read_memory(target, missing, "v8dbg_frametype_EntryFrame", &mut data.frame_type.entry_frame);
+    read_memory(target, missing, "v8dbg_frametype_ExitFrame", &mut data.frame_type.exit_frame);
+    read_memory(target, missing, "v8dbg_frametype_InternalFrame", &mut data.frame_type.internal_frame);
+    read_memory(target, missing, "v8dbg_frametype_InterpretedFrame", &mut data.frame_type.interpreted_frame);
+    read_memory(target, missing, "v8dbg_frametype_JavaScriptBuiltinContinuationFrame", &mut data.frame_type.java_script_builtin_continuation_frame);
+    read_memory(target, missing, "v8dbg_frametype_JavaScriptBuiltinContinuationWithCatchFrame", &mut data.frame_type.java_script_builtin_continuation_with_catch_frame);
+    read_memory(target, missing, "v8dbg_frametype_JavaScriptFrame", &mut data.frame_type.java_script_frame);
+    read_memory(target, missing, "v8dbg_frametype_JsToWasmFrame", &mut data.frame_type.js_to_wasm_frame);
+    read_memory(target, missing, "v8dbg_frametype_NativeFrame", &mut data.frame_type.native_frame);
+    read_memory(target, missing, "v8dbg_frametype_OptimizedFrame", &mut data.frame_type.optimized_frame);
+    read_memory(target, missing, "v8dbg_frametype_StubFrame", &mut data.frame_type.stub_frame);
+    read_memory(target, missing, "v8dbg_frametype_WasmCompileLazyFrame", &mut data.frame_type.wasm_compile_lazy_frame);
+    read_memory(target, missing, "v8dbg_frametype_WasmCompiledFrame", &mut data.frame_type.wasm_compiled_frame);
+    read_memory(target, missing, "v8dbg_frametype_WasmExitFrame", &mut data.frame_type.wasm_exit_frame);
+    read_memory(target, missing, "v8dbg_frametype_WasmInterpreterEntryFrame", &mut data.frame_type.wasm_interpreter_entry_frame);
+    read_memory(target, missing, "v8dbg_frametype_WasmToJsFrame", &mut data.frame_type.wasm_to_js_frame);
+    read_memory(target, missing, "v8dbg_type_BaselineData__BASELINE_DATA_TYPE", &mut data.typ.baseline_data);
+    read_memory(target, missing, "v8dbg_type_ByteArray__BYTE_ARRAY_TYPE", &mut data.typ.byte_array);
+    read_memory(target, missing, "v8dbg_type_BytecodeArray__BYTECODE_ARRAY_TYPE", &mut data.typ.bytecode_array);
+    read_memory(target, missing, "v8dbg_type_Code__CODE_TYPE", &mut data.typ.code);
+    read_memory(target, missing, "v8dbg_type_DebugInfo__DEBUG_INFO_TYPE", &mut data.typ.debug_info);
+    read_memory(target, missing, "v8dbg_type_FixedArray__FIXED_ARRAY_TYPE", &mut data.typ.fixed_array);
+    read_memory(target, missing, "v8dbg_type_WeakFixedArray__WEAK_FIXED_ARRAY_TYPE", &mut data.typ.weak_fixed_array);
+    read_memory(target, missing, "v8dbg_type_JSFunction__JS_FUNCTION_TYPE", &mut data.typ.js_function);
+    read_memory(target, missing, "v8dbg_type_Map__MAP_TYPE", &mut data.typ.map);
+    read_memory(target, missing, "v8dbg_type_Script__SCRIPT_TYPE", &mut data.typ.script);
+    read_memory(target, missing, "v8dbg_type_ScopeInfo__SCOPE_INFO_TYPE", &mut data.typ.scope_info);
+    read_memory(target, missing, "v8dbg_type_SharedFunctionInfo__SHARED_FUNCTION_INFO_TYPE", &mut data.typ.shared_function_info);
+    read_tagged_field(target, missing, "v8dbg_class_HeapObject__map", &["Map"], &mut data.heap_object.map);
+    read_memory(target, missing, "v8dbg_class_Map__instance_type__uint16_t", &mut data.map.instance_type);
+    read_memory(target, missing, "v8dbg_class_FixedArrayBase__length__SMI", &mut data.fixed_array_base.length);
+    read_memory(target, missing, "v8dbg_class_FixedArray__data__uintptr_t", &mut data.fixed_array.data);
+    read_memory(target, missing, "v8dbg_class_String__length__int32_t", &mut data.string.length);
+    read_memory(target, missing, "v8dbg_class_SeqOneByteString__chars__char", &mut data.seq_one_byte_string.chars);
+    read_memory(target, missing, "v8dbg_class_SeqTwoByteString__chars__char", &mut data.seq_two_byte_string.chars);
+    read_tagged_field(target, missing, "v8dbg_class_ConsString__first", &["String"], &mut data.cons_string.first);
+    read_tagged_field(target, missing, "v8dbg_class_ConsString__second", &["String"], &mut data.cons_string.second);
+    read_tagged_field(target, missing, "v8dbg_class_ThinString__actual", &["String"], &mut data.thin_string.actual);
+    read_tagged_field(target, missing, "v8dbg_class_ExternalString__resource", &["Object"], &mut data.external_string.resource);
+    read_tagged_field(target, missing, "v8dbg_class_JSFunction__code", &["Code"], &mut data.jsfunction.code);
+    read_tagged_field(target, missing, "v8dbg_class_JSFunction__shared", &["SharedFunctionInfo"], &mut data.jsfunction.shared_function_info);
+    read_tagged_field(target, missing, "v8dbg_class_Code__deoptimization_data", &["FixedArray"], &mut data.code.deoptimization_data);
+    read_tagged_field(target, missing, "v8dbg_class_Code__source_position_table", &["ByteArray"], &mut data.code.source_position_table);
+    let instruction_start = read_memory_any(
+        target,
+        missing,
+        &["v8dbg_class_Code__instruction_start__uintptr_t", "v8dbg_class_Code__instruction_start__Address"],
+        &mut data.code.instruction_start,
+    );
+    data.code.instruction_start_is_pointer = instruction_start == Some(1);
+    read_memory(target, missing, "v8dbg_class_Code__instruction_size__int", &mut data.code.instruction_size);
+    read_memory(target, missing, "v8dbg_class_Code__flags__uint32_t", &mut data.code.flags);
+    // Only some versions export where the builtin id is, and nothing falls
+    // back on it, so it isn't reported as missing. Non-builtins have -1.
+    if !read_symbol(target, "v8dbg_class_Code__builtin_id__int16_t", &mut data.code.builtin_id) {
+        data.code.builtin_id_is_int32 = read_symbol(target, "v8dbg_class_Code__builtin_index__int", &mut data.code.builtin_id);
+    }
+    let sfi = &mut data.shared_function_info;
+    read_tagged_field(target, missing, "v8dbg_class_SharedFunctionInfo__name_or_scope_info", &["Object"], &mut sfi.name_or_scope_info);
+    read_tagged_field(target, missing, "v8dbg_class_SharedFunctionInfo__function_data", &["Object"], &mut sfi.function_data);
+    read_tagged_field(target, missing, "v8dbg_class_SharedFunctionInfo__script_or_debug_info", &["Object"], &mut sfi.script_or_debug_info);
+    read_tagged_field(target, missing, "v8dbg_class_BaselineData__data", &["Object"], &mut data.baseline_data.data);
+    read_tagged_field(
+        target,
+        missing,
+        "v8dbg_class_BytecodeArray__source_position_table",
+        &["Object"],
+        &mut data.bytecode_array.source_position_table,
+    );
+    read_memory(target, missing, "v8dbg_class_BytecodeArray__data__uintptr_t", &mut data.bytecode_array.data);
+    if target.symbol("v8dbg_parent_ScopeInfo__HeapObject").is_some() {
+        data.scope_info.heap_object = true;
+    }
+    if target.symbol("v8dbg_parent_DeoptimizationLiteralArray__WeakFixedArray").is_some() {
+        data.deoptimization_literal_array.weak_fixed_array = true;
+    }
+    read_tagged_field(target, missing, "v8dbg_class_Script__name", &["Object"], &mut data.script.name);
+    read_tagged_field(target, missing, "v8dbg_class_Script__line_ends", &["Object"], &mut data.script.line_ends);
+    read_tagged_field(target, missing, "v8dbg_class_Script__source", &["Object"], &mut data.script.source);
+    read_tagged_field(target, missing, "v8dbg_class_DebugInfo__shared", &["SharedFunctionInfo"], &mut data.debug_info.shared);
+    read_tagged_field(target, missing, "v8dbg_class_DebugInfo__script", &["Object"], &mut data.debug_info.script);
+    // Only needed to tell what a function's data is, so not missed
+    read_symbol(target, "v8dbg_type_AsmWasmData__ASM_WASM_DATA_TYPE", &mut data.typ.asm_wasm_data);
+    read_symbol(target, "v8dbg_type_FunctionTemplateInfo__FUNCTION_TEMPLATE_INFO_TYPE", &mut data.typ.function_template_info);
+    read_symbol(target, "v8dbg_type_InterpreterData__INTERPRETER_DATA_TYPE", &mut data.typ.interpreter_data);
+    read_symbol(
+        target,
+        "v8dbg_type_UncompiledDataWithPreparseData__UNCOMPILED_DATA_WITH_PREPARSE_DATA_TYPE",
+        &mut data.typ.uncompiled_data_with_preparse_data,
+    );
+    read_symbol(
+        target,
+        "v8dbg_type_UncompiledDataWithPreparseDataAndJob__UNCOMPILED_DATA_WITH_PREPARSE_DATA_AND_JOB_TYPE",
+        &mut data.typ.uncompiled_data_with_preparse_data_and_job,
+    );
+    read_symbol(
+        target,
+        "v8dbg_type_UncompiledDataWithoutPreparseData__UNCOMPILED_DATA_WITHOUT_PREPARSE_DATA_TYPE",
+        &mut data.typ.uncompiled_data_without_preparse_data,
+    );
+    read_symbol(
+        target,
+        "v8dbg_type_UncompiledDataWithoutPreparseDataWithJob__UNCOMPILED_DATA_WITHOUT_PREPARSE_DATA_WITH_JOB_TYPE",
+        &mut data.typ.uncompiled_data_without_preparse_data_with_job,
+    );
+    read_tagged_field_symbol(target, "v8dbg_class_Code__bytecode_or_interpreter_data", &[], &mut data.code.bytecode_or_interpreter_data);
+    // Only needed to name bound and wrapped functions, so not missed
+    read_symbol(target, "v8dbg_type_JSBoundFunction__JS_BOUND_FUNCTION_TYPE", &mut data.typ.js_bound_function);
+    read_symbol(target, "v8dbg_type_JSWrappedFunction__JS_WRAPPED_FUNCTION_TYPE", &mut data.typ.js_wrapped_function);
+    read_symbol(target, "v8dbg_class_JSObject__internal_fields__uintptr_t", &mut data.js_object.internal_fields);
+    read_tagged_field_symbol(
+        target,
+        "v8dbg_class_JSBoundFunction__bound_target_function",
+        &["JSReceiver", "Object"],
+        &mut data.js_bound_function.bound_target_function,
+    );
+    read_tagged_field_symbol(
+        target,
+        "v8dbg_class_JSWrappedFunction__wrapped_target_function",
+        &["JSReceiver"],
+        &mut data.js_wrapped_function.wrapped_target_function,
+    );
+    // Only needed to name the classes of receivers, so not missed
+    read_tagged_field_symbol(target, "v8dbg_class_Map__constructor_or_back_pointer", &["Object"], &mut data.map.constructor_or_back_pointer);
+    // Only needed for the URLs of scripts' source maps, so not missed
+    read_tagged_field_symbol(target, "v8dbg_class_Script__source_url", &["Object", "String"], &mut data.script.source_url);
+    read_tagged_field_symbol(target, "v8dbg_class_Script__source_mapping_url", &["Object", "String"], &mut data.script.source_mapping_url);
+    read_tagged_field_symbol(target, "v8dbg_class_Script__eval_from_position", &["SMI", "Object"], &mut data.script.eval_from_position);
+    read_tagged_field_symbol(
+        target,
+        "v8dbg_class_Script__compiled_lazy_function_positions",
+        &["Object"],
+        &mut data.script.compiled_lazy_function_positions,
+    );
+    // Only needed to tell workers apart, so not missed. Node's symbols are
+    // set when it starts, not linked in.
+    read_symbol(target, "v8dbg_native_context_embedder_data_offset", &mut data.native_context.embedder_data);
+    read_symbol(target, "v8dbg_type_EmbedderDataArray__EMBEDDER_DATA_ARRAY_TYPE", &mut data.typ.embedder_data_array);
+    read_symbol(target, "nodedbg_const_ContextEmbedderIndex__kEnvironment__int", &mut data.node_environment.context_index);
+    read_symbol(
+        target,
+        "nodedbg_offset_Environment__handle_wrap_queue___Environment_HandleWrapQueue",
+        &mut data.node_environment.handle_wrap_queue,
+    );
+    // Not generated by V8, so not missed
+    read_symbol(target, "v8dbg_class_Isolate__thread_local_top__ThreadLocalTop", &mut data.isolate.thread_local_top);
+    read_symbol(target, "v8dbg_class_ThreadLocalTop__c_entry_fp__Address", &mut data.thread_local_top.c_entry_fp);
+    read_symbol(target, "v8dbg_class_Isolate__debug__Debug", &mut data.isolate.debug);
+    read_symbol(target, "v8dbg_class_Debug__is_active__bool", &mut data.debugger.is_active);
+    read_symbol(target, "v8dbg_class_Debug__current_debug_scope__DebugScope", &mut data.debugger.current_debug_scope);
+    data
+}
+
+/// Reads the frame pointer offsets by any of their names in `FP_OFFSETS`
+fn read_fp_offsets(target: &impl MemorySource, missing: &mut Vec<String>, fps: &mut FramePointer) {
+    for offset in &FP_OFFSETS {
+        let field = (offset.field)(fps);
+        if offset.generated {
+            read_memory_any(target, missing, offset.symbols, field);
         } else {
-            println!("Failed to read memory for symbol {}", ver);
+            offset.symbols.iter().any(|symbol| read_symbol(target, symbol, &mut *field));
+        }
+    }
+}
+
+/// The names the symbol of a tagged field may have: `base`, such as
+/// `v8dbg_class_JSFunction__code`, followed by each of the types V8 has
+/// declared the field as, newest first, and then by HeapObject, which is
+/// declared in place of a narrower type now and then. Each type is also
+/// tried as `Tagged_T_`, as V8 spells fields moved to `Tagged<T>`.
+fn tagged_field_symbols(base: &str, types: &[&str]) -> Vec<String> {
+    let mut symbols = Vec::new();
+    for typ in types.iter().copied().chain(types.iter().all(|&typ| typ != "HeapObject").then_some("HeapObject")) {
+        symbols.push(format!("{}__{}", base, typ));
+        symbols.push(format!("{}__Tagged_{}_", base, typ));
+    }
+    symbols
+}
+
+/// Reads the offset of a tagged field by any of the names from
+/// `tagged_field_symbols`, adding them to `missing` if none can be read
+fn read_tagged_field<T>(target: &impl MemorySource, missing: &mut Vec<String>, base: &str, types: &[&str], data: &mut T) -> bool {
+    let symbols = tagged_field_symbols(base, types);
+    let symbols: Vec<&str> = symbols.iter().map(String::as_str).collect();
+    read_memory_any(target, missing, &symbols, data).is_some()
+}
+
+/// Reads the offset of a tagged field as `read_tagged_field` does, for the
+/// fields that aren't missed
+fn read_tagged_field_symbol<T>(target: &impl MemorySource, base: &str, types: &[&str], data: &mut T) -> bool {
+    tagged_field_symbols(base, types).iter().any(|symbol| read_symbol(target, symbol, &mut *data))
+}
+
+/// Reads the value of a symbol into `data`, adding the symbol to `missing` if
+/// it can't be read. Frame types that the version doesn't have aren't
+/// counted as missing.
+fn read_memory<T>(target: &impl MemorySource, missing: &mut Vec<String>, symbol: &str, data: &mut T) -> bool {
+    match try_read_symbol(target, symbol, data) {
+        Ok(found) => {
+            if !found {
+                missing.push(symbol.to_owned());
+            }
+            found
+        }
+        Err(e) => {
+            missing.push(unreadable_symbol(symbol, &e));
+            false
+        }
+    }
+}
+
+/// How a symbol that's there but can't be read is listed among the missing
+/// ones, with why it can't be read
+fn unreadable_symbol(symbol: &str, e: &anyhow::Error) -> String {
+    format!("{} (unreadable: {})", symbol, e.root_cause())
+}
+
+/// Reads the first of several alternative symbols for the same value, which
+/// V8 has renamed between versions. Returns the index of the symbol that was
+/// read, or adds them all to `missing` as one entry if none could be.
+fn read_memory_any<T>(target: &impl MemorySource, missing: &mut Vec<String>, symbols: &[&str], data: &mut T) -> Option<usize> {
+    let mut tried = Vec::new();
+    let found = symbols.iter().position(|symbol| match try_read_symbol(target, symbol, &mut *data) {
+        Ok(found) => {
+            tried.push(symbol.to_string());
+            found
+        }
+        Err(e) => {
+            tried.push(unreadable_symbol(symbol, &e));
+            false
+        }
+    });
+    if found.is_none() {
+        missing.push(tried.join(" or "));
+    }
+    found
+}
+
+/// Reads a symbol as `try_read_symbol` does, taking one that can't be read
+/// as missing after logging why, so that the rest of the layout is still
+/// read
+fn read_symbol<T>(target: &impl MemorySource, symbol: &str, data: &mut T) -> bool {
+    try_read_symbol(target, symbol, data).unwrap_or_else(|e| {
+        log::warn!("{:#}", e);
+        false
+    })
+}
+
+/// Reads the value of a symbol into `data`, whose type gives its size.
+/// Returns whether the symbol was found. One that's found but can't be read
+/// fails with the error of the read, naming the symbol and its address.
+fn try_read_symbol<T>(target: &impl MemorySource, symbol: &str, data: &mut T) -> Result<bool> {
+    let addr = target.symbol(symbol);
+    if addr.is_none() {
+        if symbol.starts_with("v8dbg_frametype_") {
+            unsafe {
+                if let Some(data_ptr) = (data as *mut T).cast::<u8>().as_mut() {
+                    *data_ptr = MISSING_FRAME_TYPE;
+                    return Ok(true);
+                }
+            }
+        }
+        return Ok(false);
+    }
+    let addr = addr.unwrap();
+
+    let size = match std::any::type_name::<T>() {
+        "u32" => 4,
+        "u16" => 2,
+        "u8" => 1,
+        _ => panic!("Unsupported type"),
+    };
+
+    let mut buf = [0u8; 8];
+
+    target.read(addr, &mut buf[..size]).with_context(|| format!("Failed to read {} at 0x{:x}", symbol, addr))?;
+    unsafe {
+        let data_ptr: *mut T = data as *mut T;
+        std::ptr::copy_nonoverlapping(buf.as_ptr(), data_ptr as *mut u8, size);
+    }
+    Ok(true)
+}
+
+/// Reads V8's version numbers and label. Fails if the major or minor number
+/// can't be read, and if the build or patch number can't be unless
+/// `allow_partial`, in which case they're taken to be 0.
+fn get_v8_version(target: &impl MemorySource, allow_partial: bool) -> Result<Version> {
+    let mut version = [0u32; 4];
+    let mut unread = Vec::new();
+    for (i, ver) in ["major", "minor", "build", "patch"].into_iter().enumerate() {
+        let symbol = format!("_ZN2v88internal7Version6{}_E", ver);
+        if !try_read_symbol(target, &symbol, &mut version[i]).context("Failed to read the V8 version of the process")? {
+            unread.push(ver);
+        }
+    }
+    if unread.contains(&"major") || unread.contains(&"minor") || version[0] == 0 {
+        // e.g. the process is still being exec'd, and its memory isn't the
+        // binary's yet
+        anyhow::bail!("Failed to read the V8 version of the process");
+    }
+    if !unread.is_empty() {
+        if !allow_partial {
+            anyhow::bail!("Failed to read the V8 version of the process: no {} number", unread.join(" or "));
         }
+        // The fallbacks for missing symbols go by the build number, and
+        // assume the earliest build of the minor version without it
+        log::warn!("Failed to read the V8 {} number, taking it to be 0", unread.join(" and "));
     }
-    Version {
+    let numbers = format!("{}.{}.{}.{}", version[0], version[1], version[2], version[3]);
+    Ok(Version {
         major: version[0],
         minor: version[1],
         build: version[2],
         patch: version[3],
+        label: read_version_label(target, &numbers, symbol_pointer_size(target)),
+    })
+}
+
+/// Reads the suffix of V8's version string, after the version numbers. The
+/// string is `version_string_`, falling back to the embedder string on its
+/// own. A patch level of 0 is left out of the string.
+fn read_version_label(target: &impl MemorySource, numbers: &str, pointer_size: usize) -> Option<String> {
+    let read_string_symbol = |symbol: &str| {
+        let addr = target.symbol(symbol)?;
+        read_c_string(target, read_target_ptr(target, addr, pointer_size).ok()?)
+    };
+    let label = match read_string_symbol("_ZN2v88internal7Version15version_string_E") {
+        Some(version_string) => {
+            let short = numbers.strip_suffix(".0").unwrap_or(numbers);
+            let label = version_string.strip_prefix(numbers).or_else(|| version_string.strip_prefix(short));
+            label.unwrap_or(&version_string).to_owned()
+        }
+        None => read_string_symbol("_ZN2v88internal7Version9embedder_E")?,
+    };
+    Some(label).filter(|label| !label.is_empty())
+}
+
+/// Reads a NUL-terminated string of up to 256 bytes
+fn read_c_string(target: &impl MemorySource, addr: usize) -> Option<String> {
+    let mut bytes = Vec::new();
+    // In small chunks, so the read doesn't run off the end of the mapping
+    let mut chunk = [0u8; 16];
+    while bytes.len() < 256 {
+        target.read(addr + bytes.len(), &mut chunk).ok()?;
+        match chunk.iter().position(|&b| b == 0) {
+            Some(nul) => {
+                bytes.extend_from_slice(&chunk[..nul]);
+                return Some(String::from_utf8_lossy(&bytes).into_owned());
+            }
+            None => bytes.extend_from_slice(&chunk),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{FakeTarget, FakeTargetBuilder};
+
+    /// The offset of a frame slot as `FramePointer` keeps it
+    const fn fp(offset: i8) -> u64 {
+        offset as u8 as u64
+    }
+
+    /// What a 64-bit V8 exports that the fallbacks build on, with everything
+    /// they'd fill in left out, and the Code layout of the V8 11 builds
+    /// whose Code postmortem data was deleted
+    fn exported_layout() -> VMData {
+        let mut vms = VMData::default();
+        vms.frame_pointer.function = fp(-16) as u8;
+        vms.frame_pointer.context = fp(-8) as u8;
+        vms.typ.js_function = 2066;
+        vms.heap_object.map = 0;
+        vms.fixed_array_base.length = 8;
+        vms.string.length = 12;
+        vms.js_object.internal_fields = 24;
+        vms.jsfunction.shared_function_info = 24;
+        vms.code.source_position_table = 32;
+        vms.script.name = 16;
+        vms.script.eval_from_position = 64;
+        vms.node_environment.handle_wrap_queue = 0x100;
+        vms
+    }
+
+    /// Fields by name, as `VMData::to_sorted_pairs` names them, with values
+    type Offsets<'a> = &'a [(&'a str, u64)];
+
+    /// The fields that `apply_vmdata_fallbacks` changed, with their new values
+    fn derived(ver: u32) -> BTreeMap<&'static str, u64> {
+        let before: BTreeMap<_, _> = exported_layout().to_sorted_pairs().into_iter().collect();
+        let mut vms = exported_layout();
+        apply_vmdata_fallbacks(&mut vms, ver, 8).unwrap();
+        vms.to_sorted_pairs().into_iter().filter(|(name, value)| before[name] != *value).collect()
+    }
+
+    // Instance types, as in Node 20
+    const ONE_BYTE_STRING_TYPE: u16 = 8;
+    const CONTEXT_TYPE: u16 = 205;
+    const JS_FUNCTION_TYPE: u16 = 2066;
+    const SHARED_FUNCTION_INFO_TYPE: u16 = 262;
+    const SCRIPT_TYPE: u16 = 167;
+    const FIXED_ARRAY_TYPE: u16 = 175;
+    const BYTE_ARRAY_TYPE: u16 = 190;
+    const BYTECODE_ARRAY_TYPE: u16 = 191;
+    const CODE_TYPE: u16 = 245;
+    const ENTRY_FRAME: i32 = 1;
+
+    /// Most of the `v8dbg_` constants of Node 20, with the kinds of names
+    /// they come in
+    const NODE_20_SYMBOLS: &[(&str, i32)] = &[
+        ("v8dbg_HeapObjectTagMask", 3),
+        ("v8dbg_SmiTagMask", 1),
+        ("v8dbg_HeapObjectTag", 1),
+        ("v8dbg_SmiTag", 0),
+        ("v8dbg_SmiShiftSize", 31),
+        ("v8dbg_FirstNonstringType", 128),
+        ("v8dbg_StringEncodingMask", 8),
+        ("v8dbg_StringRepresentationMask", 7),
+        ("v8dbg_SeqStringTag", 0),
+        ("v8dbg_OneByteStringTag", 8),
+        ("v8dbg_FirstJSFunctionType", 2066),
+        ("v8dbg_LastJSFunctionType", 2081),
+        ("v8dbg_FirstContextType", 205),
+        ("v8dbg_LastContextType", 214),
+        ("v8dbg_off_fp_function", -16),
+        ("v8dbg_off_fp_context", -8),
+        ("v8dbg_off_fp_bytecode_array", -32),
+        ("v8dbg_off_fp_bytecode_offset", -40),
+        ("v8dbg_frametype_EntryFrame", ENTRY_FRAME),
+        ("v8dbg_CodeKindFieldMask", 15),
+        ("v8dbg_CodeKindFieldShift", 0),
+        ("v8dbg_CodeKindBaseline", 11),
+        ("v8dbg_class_HeapObject__map__Map", 0),
+        ("v8dbg_class_Map__instance_type__uint16_t", 12),
+        ("v8dbg_class_String__length__int32_t", 12),
+        ("v8dbg_class_SeqOneByteString__chars__char", 16),
+        ("v8dbg_class_FixedArrayBase__length__SMI", 8),
+        ("v8dbg_class_FixedArray__data__uintptr_t", 16),
+        ("v8dbg_class_JSFunction__shared__SharedFunctionInfo", 24),
+        ("v8dbg_class_JSFunction__code__Code", 48),
+        ("v8dbg_class_SharedFunctionInfo__function_data__Object", 8),
+        ("v8dbg_class_SharedFunctionInfo__name_or_scope_info__Object", 16),
+        ("v8dbg_class_SharedFunctionInfo__script_or_debug_info__HeapObject", 32),
+        ("v8dbg_class_Script__source__Object", 8),
+        ("v8dbg_class_Script__name__Object", 16),
+        ("v8dbg_class_Script__line_ends__Object", 56),
+        ("v8dbg_class_BytecodeArray__source_position_table__Object", 32),
+        ("v8dbg_class_BytecodeArray__data__uintptr_t", 54),
+        ("v8dbg_class_Code__flags__uint32_t", 48),
+        ("v8dbg_type_JSFunction__JS_FUNCTION_TYPE", JS_FUNCTION_TYPE as i32),
+        ("v8dbg_type_SharedFunctionInfo__SHARED_FUNCTION_INFO_TYPE", SHARED_FUNCTION_INFO_TYPE as i32),
+        ("v8dbg_type_Script__SCRIPT_TYPE", SCRIPT_TYPE as i32),
+        ("v8dbg_type_FixedArray__FIXED_ARRAY_TYPE", FIXED_ARRAY_TYPE as i32),
+        ("v8dbg_type_ByteArray__BYTE_ARRAY_TYPE", BYTE_ARRAY_TYPE as i32),
+        ("v8dbg_type_BytecodeArray__BYTECODE_ARRAY_TYPE", BYTECODE_ARRAY_TYPE as i32),
+        ("v8dbg_type_Code__CODE_TYPE", CODE_TYPE as i32),
+        ("v8dbg_parent_ScopeInfo__HeapObject", 0),
+    ];
+
+    /// The major, minor, build and patch number of Node 20's V8
+    const NODE_20_VERSION: [Option<u32>; 4] = [Some(11), Some(3), Some(244), Some(8)];
+
+    /// A target with the version numbers that are given, and `symbols`,
+    /// each a 4 byte constant
+    fn target_with(version: [Option<u32>; 4], symbols: &[(&str, i32)]) -> FakeTargetBuilder {
+        let mut builder = FakeTarget::builder();
+        for (i, (symbol, number)) in V8_SENTINEL_SYMBOLS.iter().zip(version).enumerate() {
+            if let Some(number) = number {
+                builder = builder.symbol_value(symbol, 0x1000 + 4 * i, number);
+            }
+        }
+        for (i, &(name, value)) in symbols.iter().enumerate() {
+            builder = builder.symbol_value(name, 0x2000 + 4 * i, value);
+        }
+        builder
+    }
+
+    const HEAP_BASE: usize = 0x1000_0000;
+    const STACK_BASE: usize = 0x7ff0_0000;
+    const FRAME_SIZE: usize = 0x80;
+    /// Offset of the bytecode from a tagged BytecodeArray pointer
+    const BYTECODE_HEADER_SIZE: i64 = 54 - 1;
+    /// Each line of the fake script is this long, newline included
+    const LINE_LENGTH: i64 = 40;
+
+    fn smi(value: i64) -> u64 {
+        (value as u64) << 32
+    }
+
+    /// Lays out heap objects one after the other, each returned as a tagged
+    /// pointer, with a map for each instance type the fakes use
+    struct Heap {
+        bytes: Vec<u8>,
+        string_map: usize,
+        context_map: usize,
+        function_map: usize,
+        sfi_map: usize,
+        script_map: usize,
+        fixed_array_map: usize,
+        byte_array_map: usize,
+        bytecode_array_map: usize,
+        code_map: usize,
+    }
+
+    impl Heap {
+        fn new() -> Self {
+            let mut heap = Heap { bytes: Vec::new(), string_map: 0, context_map: 0, function_map: 0, sfi_map: 0, script_map: 0, fixed_array_map: 0, byte_array_map: 0, bytecode_array_map: 0, code_map: 0 };
+            heap.string_map = heap.map(ONE_BYTE_STRING_TYPE);
+            heap.context_map = heap.map(CONTEXT_TYPE);
+            heap.function_map = heap.map(JS_FUNCTION_TYPE);
+            heap.sfi_map = heap.map(SHARED_FUNCTION_INFO_TYPE);
+            heap.script_map = heap.map(SCRIPT_TYPE);
+            heap.fixed_array_map = heap.map(FIXED_ARRAY_TYPE);
+            heap.byte_array_map = heap.map(BYTE_ARRAY_TYPE);
+            heap.bytecode_array_map = heap.map(BYTECODE_ARRAY_TYPE);
+            heap.code_map = heap.map(CODE_TYPE);
+            heap
+        }
+
+        fn alloc(&mut self, size: usize) -> usize {
+            let addr = HEAP_BASE + self.bytes.len();
+            self.bytes.resize(self.bytes.len() + size.next_multiple_of(8), 0);
+            addr + 1
+        }
+
+        fn write(&mut self, object: usize, offset: usize, bytes: &[u8]) {
+            let start = object - 1 - HEAP_BASE + offset;
+            self.bytes[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+
+        fn write_u64(&mut self, object: usize, offset: usize, value: u64) {
+            self.write(object, offset, &value.to_ne_bytes());
+        }
+
+        fn object(&mut self, map: usize, size: usize) -> usize {
+            let object = self.alloc(size);
+            self.write_u64(object, 0, map as u64);
+            object
+        }
+
+        fn map(&mut self, instance_type: u16) -> usize {
+            let map = self.alloc(16);
+            self.write(map, 12, &instance_type.to_ne_bytes());
+            map
+        }
+
+        fn string(&mut self, s: &str) -> usize {
+            let string = self.object(self.string_map, 16 + s.len());
+            self.write(string, 12, &(s.len() as i32).to_ne_bytes());
+            self.write(string, 16, s.as_bytes());
+            string
+        }
+
+        fn fixed_array(&mut self, elements: &[u64]) -> usize {
+            let array = self.object(self.fixed_array_map, 16 + 8 * elements.len());
+            self.write_u64(array, 8, smi(elements.len() as i64));
+            for (i, &element) in elements.iter().enumerate() {
+                self.write_u64(array, 16 + 8 * i, element);
+            }
+            array
+        }
+
+        fn byte_array(&mut self, bytes: &[u8]) -> usize {
+            let array = self.object(self.byte_array_map, 16 + bytes.len());
+            self.write_u64(array, 8, smi(bytes.len() as i64));
+            self.write(array, 16, bytes);
+            array
+        }
+
+        /// A script of 100 lines
+        fn script(&mut self, name: &str) -> usize {
+            let script = self.object(self.script_map, 64);
+            let name = self.string(name);
+            let line_ends: Vec<u64> = (1..=100).map(|line| smi(line * LINE_LENGTH - 1)).collect();
+            let line_ends = self.fixed_array(&line_ends);
+            self.write_u64(script, 8, smi(0));
+            self.write_u64(script, 16, name as u64);
+            self.write_u64(script, 56, line_ends as u64);
+            script
+        }
+
+        /// A SharedFunctionInfo named `name`, with `function_data`
+        fn shared_function_info(&mut self, name: &str, function_data: usize, script: usize) -> usize {
+            let sfi = self.object(self.sfi_map, 64);
+            let name = self.string(name);
+            self.write_u64(sfi, 8, function_data as u64);
+            self.write_u64(sfi, 16, name as u64);
+            self.write_u64(sfi, 32, script as u64);
+            sfi
+        }
+
+        /// An interpreted function whose bytecode is all at the start of
+        /// `line`, returned with its BytecodeArray
+        fn interpreted_function(&mut self, name: &str, line: i64, script: usize) -> (usize, usize) {
+            let mut table = Vec::new();
+            push_vlq(&mut table, 0);
+            push_vlq(&mut table, source_position((line - 1) * LINE_LENGTH, None));
+            let table = self.byte_array(&table);
+            let bytecode_array = self.object(self.bytecode_array_map, 54 + 16);
+            self.write_u64(bytecode_array, 8, smi(16));
+            self.write_u64(bytecode_array, 32, table as u64);
+            let sfi = self.shared_function_info(name, bytecode_array, script);
+            // Interpreted functions run the InterpreterEntryTrampoline builtin
+            let code = self.object(self.code_map, 64);
+            self.write(code, 48, &4u32.to_ne_bytes());
+            let function = self.object(self.function_map, 64);
+            self.write_u64(function, 24, sfi as u64);
+            self.write_u64(function, 48, code as u64);
+            (function, bytecode_array)
+        }
+    }
+
+    /// Appends a zigzag VLQ, as V8 encodes source position tables
+    fn push_vlq(table: &mut Vec<u8>, value: i64) {
+        let mut bits = ((value << 1) ^ (value >> 63)) as u64;
+        loop {
+            let byte = (bits & 0x7f) as u8;
+            bits >>= 7;
+            if bits == 0 {
+                table.push(byte);
+                break;
+            }
+            table.push(byte | 0x80);
+        }
+    }
+
+    /// Packs a SourcePosition as V8 11.3 does: the script offset plus one
+    /// above the external bit, and the inlining id plus one above that
+    fn source_position(script_offset: i64, inlining_id: Option<i64>) -> i64 {
+        ((inlining_id.map_or(0, |id| id + 1)) << 31) | ((script_offset + 1) << 1)
+    }
+
+    /// The JavaScript functions on the stack of `fake_process`, innermost
+    /// first, with the lines they're at
+    const STACK_FUNCTIONS: [(&str, i64); 3] = [("leaf", 4), ("mid", 8), ("outer", 12)];
+
+    /// A Node 20 process with one thread, stopped in native code called from
+    /// `STACK_FUNCTIONS`, called from an entry frame. Returns the target and
+    /// the registers of its thread.
+    fn fake_process() -> (FakeTarget, Registers) {
+        let (builder, registers) = fake_process_builder();
+        (builder.build(), registers)
+    }
+
+    /// Like `fake_process`, for mapping more memory in the target
+    fn fake_process_builder() -> (FakeTargetBuilder, Registers) {
+        let mut heap = Heap::new();
+        let script = heap.script("/srv/app.js");
+        let context = heap.object(heap.context_map, 16);
+        let functions: Vec<(usize, usize)> = STACK_FUNCTIONS.iter().map(|&(name, line)| heap.interpreted_function(name, line, script)).collect();
+
+        // Frames from the innermost up, each with its caller's fp and return
+        // address at fp, above the slots V8 keeps below it
+        let depth = functions.len() + 2;
+        let mut stack = vec![0u8; (depth + 1) * FRAME_SIZE];
+        let fp_of = |depth: usize| STACK_BASE + (depth + 1) * FRAME_SIZE;
+        let mut slot = |fp: usize, offset: isize, value: u64| {
+            let start = (fp as isize + offset) as usize - STACK_BASE;
+            stack[start..start + 8].copy_from_slice(&value.to_ne_bytes());
+        };
+        for i in 0..depth {
+            let fp = fp_of(i);
+            slot(fp, 0, if i + 1 < depth { fp_of(i + 1) as u64 } else { 0 });
+            slot(fp, 8, 0x5555_0000 + i as u64);
+            if i == 0 {
+                // Native code keeps no context or function where V8 frames do
+                slot(fp, -8, 0x7777_0001);
+                slot(fp, -16, 0x7777_0000);
+            } else if i == depth - 1 {
+                slot(fp, -8, (ENTRY_FRAME as u64) << 1);
+            } else {
+                let (function, bytecode_array) = functions[i - 1];
+                slot(fp, -8, context as u64);
+                slot(fp, -16, function as u64);
+                slot(fp, -32, bytecode_array as u64);
+                slot(fp, -40, smi(BYTECODE_HEADER_SIZE + 1));
+            }
+        }
+
+        let builder = target_with(NODE_20_VERSION, NODE_20_SYMBOLS).bytes(HEAP_BASE, &heap.bytes).bytes(STACK_BASE, &stack);
+        let registers = Registers { pc: 0x5555_0000, sp: fp_of(0) - FRAME_SIZE / 2, fp: fp_of(0) };
+        (builder, registers)
+    }
+
+    #[test]
+    fn walks_fake_stack() {
+        let (target, registers) = fake_process();
+        let spy = V8Spy::from_memory(1, target).unwrap();
+        let stack = spy.resolve_stack(&spy.walk_stack(registers));
+        let frames: Vec<String> = stack.iter().map(ToString::to_string).collect();
+        assert_eq!(frames, ["<native>", "leaf (/srv/app.js:4)", "mid (/srv/app.js:8)", "outer (/srv/app.js:12)"]);
+    }
+
+    #[test]
+    fn reads_layout_from_symbols() {
+        let target = target_with(NODE_20_VERSION, NODE_20_SYMBOLS).build();
+        let mut missing = Vec::new();
+        let vms = get_v8_data(&target, &mut missing);
+        assert_eq!(vms.fixed.heap_object_tag_mask, 3);
+        assert_eq!(vms.fixed.smi_shift_size, 31);
+        assert_eq!(vms.fixed.first_nonstring_type, 128);
+        assert_eq!((vms.fixed.first_jsfunction_type, vms.fixed.last_jsfunction_type), (2066, 2081));
+        assert_eq!(vms.frame_pointer.function as u64, fp(-16));
+        assert_eq!(vms.frame_pointer.context as u64, fp(-8));
+        assert_eq!(vms.frame_pointer.bytecode_array as u64, fp(-32));
+        assert_eq!(vms.frame_pointer.bytecode_offset as u64, fp(-40));
+        assert_eq!(vms.frame_type.entry_frame, 1);
+        // Frame types the version doesn't have are marked, not missed
+        assert_eq!(vms.frame_type.arguments_adaptor_frame, MISSING_FRAME_TYPE);
+        assert_eq!((vms.code_kind.field_mask, vms.code_kind.baseline), (15, 11));
+        assert_eq!(vms.map.instance_type, 12);
+        assert_eq!(vms.fixed_array_base.length, 8);
+        assert_eq!(vms.fixed_array.data, 16);
+        assert_eq!(vms.jsfunction.shared_function_info, 24);
+        assert_eq!(vms.jsfunction.code, 48);
+        assert_eq!(vms.shared_function_info.name_or_scope_info, 16);
+        assert_eq!(vms.script.line_ends, 56);
+        assert_eq!(vms.bytecode_array.data, 54);
+        assert_eq!((vms.typ.js_function, vms.typ.script), (JS_FUNCTION_TYPE, SCRIPT_TYPE));
+        assert!(vms.scope_info.heap_object);
+        assert!(!vms.deoptimization_literal_array.weak_fixed_array);
+
+        let missed = |symbol: &str| missing.iter().any(|missing| missing.split(" or ").any(|name| name == symbol));
+        assert!(missed("v8dbg_ConsStringTag"));
+        assert!(missed("v8dbg_ThinStringTag"));
+        assert!(!missed("v8dbg_SmiTag"));
+        assert!(!missed("v8dbg_frametype_ArgumentsAdaptorFrame"));
+    }
+
+    #[test]
+    fn reads_tagged_fields_by_any_name() {
+        let symbols = [("v8dbg_class_JSFunction__shared__Tagged_SharedFunctionInfo_", 32), ("v8dbg_class_Script__name__HeapObject", 8)];
+        let vms = get_v8_data(&target_with([Some(12), Some(4), Some(254), Some(21)], &symbols).build(), &mut Vec::new());
+        assert_eq!(vms.jsfunction.shared_function_info, 32);
+        assert_eq!(vms.script.name, 8);
+    }
+
+    #[test]
+    fn fallbacks_by_version() {
+        // Derived the same on every version
+        let common: Offsets = &[
+            ("code.bytecode_or_interpreter_data", 24),
+            ("code.deoptimization_data", 24),
+            ("code.flags", 56),
+            ("code.instruction_start", 48),
+            ("code.instruction_start_is_pointer", 1),
+            ("bytecode_array.data", 54),
+            ("bytecode_array.source_position_table", 32),
+            ("debug_info.script", 24),
+            ("debug_info.shared", 8),
+            ("deoptimization_data_index.inlined_function_count", 1),
+            ("deoptimization_data_index.inlining_positions", 7),
+            ("deoptimization_data_index.literal_array", 2),
+            ("deoptimization_data_index.shared_function_info", 6),
+            ("external_string.resource", 16),
+            ("external_string.resource_data", 24),
+            ("fixed.external_string_tag", 2),
+            ("fixed.first_jsfunction_type", 2066),
+            ("frame_pointer.args", 16),
+            ("frame_pointer.exit_sp", fp(-16)),
+            ("js_bound_function.bound_target_function", 24),
+            ("js_wrapped_function.wrapped_target_function", 24),
+            ("script.source", 8),
+            ("script.source_mapping_url", 96),
+            ("script.source_url", 88),
+        ];
+        // On both sides of each of the versions the layout changes at
+        let table: &[((u32, u32, u32), Offsets)] = &[
+            ((8, 4, 371), &[
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 0xff),
+                ("code_kind.maglev", 0xff),
+                ("fixed.last_jsfunction_type", 2066),
+                ("frame_pointer.bytecode_array", fp(-24)),
+                ("frame_pointer.bytecode_offset", fp(-32)),
+                ("jsfunction.code", 48),
+            ]),
+            ((8, 7, 197), &[
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 0xff),
+                ("code_kind.maglev", 0xff),
+                ("fixed.last_jsfunction_type", 2066),
+                ("frame_pointer.bytecode_array", fp(-24)),
+                ("frame_pointer.bytecode_offset", fp(-32)),
+                ("jsfunction.code", 48),
+            ]),
+            ((8, 7, 198), &[
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 0xff),
+                ("code_kind.maglev", 0xff),
+                ("fixed.last_jsfunction_type", 2066),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 48),
+            ]),
+            ((8, 9, 0), &[
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 0xff),
+                ("code_kind.maglev", 0xff),
+                ("fixed.last_jsfunction_type", 2066),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 48),
+            ]),
+            ((9, 0, 14), &[
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 0xff),
+                ("code_kind.maglev", 0xff),
+                ("fixed.last_jsfunction_type", 2079),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 48),
+            ]),
+            ((9, 0, 239), &[
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 0xff),
+                ("code_kind.maglev", 0xff),
+                ("fixed.last_jsfunction_type", 2079),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 48),
+            ]),
+            ((9, 0, 240), &[
+                ("baseline_data.baseline_code", 8),
+                ("baseline_data.data", 16),
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 11),
+                ("code_kind.field_mask", 0xf),
+                ("code_kind.maglev", 0xff),
+                ("fixed.last_jsfunction_type", 2079),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 48),
+            ]),
+            ((9, 6, 138), &[
+                ("baseline_data.baseline_code", 8),
+                ("baseline_data.data", 16),
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 11),
+                ("code_kind.field_mask", 0xf),
+                ("code_kind.maglev", 0xff),
+                ("fixed.last_jsfunction_type", 2080),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 48),
+            ]),
+            ((10, 0, 0), &[
+                ("baseline_data.baseline_code", 8),
+                ("baseline_data.data", 16),
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 11),
+                ("code_kind.field_mask", 0xf),
+                ("code_kind.maglev", 12),
+                ("fixed.last_jsfunction_type", 2080),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 48),
+            ]),
+            ((11, 3, 0), &[
+                ("baseline_data.baseline_code", 8),
+                ("baseline_data.data", 16),
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 11),
+                ("code_kind.field_mask", 0xf),
+                ("code_kind.maglev", 12),
+                ("debugger.current_debug_scope", 0x40),
+                ("debugger.is_active", 0x8),
+                ("fixed.last_jsfunction_type", 2081),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("isolate.debug", 0xe5e8),
+                ("isolate.thread_local_top", 0x108),
+                ("jsfunction.code", 48),
+                ("node_environment.thread_id", 0x88),
+                ("thread_local_top.c_entry_fp", 0x78),
+            ]),
+            ((11, 4, 58), &[
+                ("baseline_data.baseline_code", 8),
+                ("baseline_data.data", 16),
+                ("code.instruction_size", 60),
+                ("code_kind.baseline", 11),
+                ("code_kind.field_mask", 0xf),
+                ("code_kind.maglev", 12),
+                ("fixed.last_jsfunction_type", 2081),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 48),
+            ]),
+            ((11, 4, 59), &[
+                ("baseline_data.baseline_code", 8),
+                ("baseline_data.data", 16),
+                ("code.instruction_size", 64),
+                ("code_kind.baseline", 11),
+                ("code_kind.field_mask", 0xf),
+                ("code_kind.maglev", 12),
+                ("fixed.last_jsfunction_type", 2081),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 48),
+            ]),
+            ((11, 7, 367), &[
+                ("baseline_data.baseline_code", 8),
+                ("baseline_data.data", 16),
+                ("code.instruction_size", 64),
+                ("code_kind.baseline", 11),
+                ("code_kind.field_mask", 0xf),
+                ("code_kind.maglev", 12),
+                ("fixed.last_jsfunction_type", 2081),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 48),
+            ]),
+            ((11, 7, 368), &[
+                ("baseline_data.baseline_code", 8),
+                ("baseline_data.data", 16),
+                ("code.instruction_size", 64),
+                ("code_kind.baseline", 11),
+                ("code_kind.field_mask", 0xf),
+                ("code_kind.maglev", 12),
+                ("fixed.last_jsfunction_type", 2081),
+                ("frame_pointer.bytecode_array", fp(-32)),
+                ("frame_pointer.bytecode_offset", fp(-40)),
+                ("jsfunction.code", 16),
+            ]),
+        ];
+        for &((major, minor, build), by_version) in table {
+            let expected: BTreeMap<&str, u64> = common.iter().chain(by_version).copied().collect();
+            assert_eq!(derived(v8_ver(major, minor, build)), expected, "V8 {}.{}.{}", major, minor, build);
+        }
+    }
+
+    #[test]
+    fn fallbacks_with_code_instruction_size() {
+        // Where the Code postmortem data is there, the rest of it is placed
+        // around the instruction size instead
+        let mut vms = exported_layout();
+        vms.code.source_position_table = 0;
+        vms.code.instruction_size = 40;
+        apply_vmdata_fallbacks(&mut vms, v8_ver(9, 4, 146), 8).unwrap();
+        assert_eq!(vms.code.source_position_table, 24);
+        assert_eq!(vms.code.flags, 48);
+        assert_eq!(vms.code.deoptimization_data, 16);
+        assert_eq!(vms.code.instruction_start, 0);
+        assert!(!vms.code.instruction_start_is_pointer);
+    }
+
+    #[test]
+    fn fallbacks_keep_exported_offsets() {
+        let mut vms = exported_layout();
+        vms.frame_pointer.bytecode_array = fp(-48) as u8;
+        vms.jsfunction.code = 40;
+        vms.code_kind.baseline = 13;
+        apply_vmdata_fallbacks(&mut vms, v8_ver(11, 3, 0), 8).unwrap();
+        assert_eq!(vms.frame_pointer.bytecode_array as u64, fp(-48));
+        assert_eq!(vms.frame_pointer.bytecode_offset as u64, fp(-56));
+        assert_eq!(vms.jsfunction.code, 40);
+        assert_eq!(vms.code_kind.baseline, 13);
+        assert_eq!(vms.code_kind.maglev, 14);
+    }
+
+    #[test]
+    fn fallbacks_fail_out_of_range() {
+        // A frame slot too far below the frame pointer for a byte
+        let mut vms = exported_layout();
+        vms.frame_pointer.function = fp(-120) as u8;
+        assert!(apply_vmdata_fallbacks(&mut vms, v8_ver(11, 3, 0), 8).is_err());
+    }
+
+    #[test]
+    fn counts_unwind_errors() {
+        let target = target_with(NODE_20_VERSION, NODE_20_SYMBOLS).build();
+        let version = get_v8_version(&target, true).unwrap();
+        let spy = V8Spy::with_layout(1, target, version, exported_layout(), 0, Vec::new(), Vec::new());
+        let mut stats = SampleStats::default();
+        // A thread whose registers can't be read, as when it exits after
+        // being listed, has no stack to unwind
+        assert!(spy.sample_thread(1, Err(anyhow::anyhow!("No such process")), &mut stats).is_none());
+        assert!(spy.sample_thread(1, Err(anyhow::anyhow!("No such process")), &mut stats).is_none());
+        assert_eq!(stats, SampleStats { total: 2, unwind_errors: 2, ..SampleStats::default() });
+        // The stack of a thread that has never run JavaScript isn't counted
+        assert!(spy.sample_thread(2, Ok(Registers { pc: 0, sp: 0, fp: 0 }), &mut stats).is_none());
+        assert_eq!(stats, SampleStats { total: 2, unwind_errors: 2, ..SampleStats::default() });
+    }
+
+    /// A target whose reads can be slowed down, as when the pages read have
+    /// to be brought back in from swap
+    struct SlowTarget {
+        target: FakeTarget,
+        delay: Cell<Duration>,
+    }
+
+    impl ProcessMemory for SlowTarget {
+        fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+            std::thread::sleep(self.delay.get());
+            self.target.read(addr, buf)
+        }
+    }
+
+    impl MemorySource for SlowTarget {
+        fn symbol(&self, name: &str) -> Option<usize> {
+            self.target.symbol(name)
+        }
+    }
+
+    #[test]
+    fn drops_samples_with_slow_reads() {
+        let (target, registers) = fake_process();
+        let target = SlowTarget { target, delay: Cell::new(Duration::ZERO) };
+        let options = V8SpyOptions { read_timeout: Some(Duration::from_millis(5)), ..V8SpyOptions::default() };
+        let spy = V8Spy::from_memory_with_options(1, target, options).unwrap();
+        // Not the spy's pid, so that once it's been seen running JavaScript
+        // nothing is looked up about it in /proc
+        let tid = 0x3fff_ffff;
+        let mut stats = SampleStats::default();
+        assert!(spy.sample_thread(tid, Ok(registers), &mut stats).is_some());
+
+        spy.process.delay.set(Duration::from_millis(10));
+        assert!(spy.sample_thread(tid, Ok(registers), &mut stats).is_none());
+        assert_eq!(stats, SampleStats { total: 2, read_timeouts: 1, ..SampleStats::default() });
+
+        // Reads are fast again by the next sample, which is kept
+        spy.process.delay.set(Duration::ZERO);
+        let stack = spy.sample_thread(tid, Ok(registers), &mut stats).unwrap();
+        assert_eq!(stack.len(), STACK_FUNCTIONS.len() + 1);
+        assert_eq!(stats, SampleStats { total: 3, read_timeouts: 1, ..SampleStats::default() });
+    }
+
+    #[test]
+    fn streams_each_stack() {
+        let running = AtomicBool::new(true);
+        let target = target_with(NODE_20_VERSION, NODE_20_SYMBOLS).build();
+        let version = get_v8_version(&target, true).unwrap();
+        let spy = V8Spy::with_layout(1, target, version, exported_layout(), 0, Vec::new(), Vec::new());
+
+        // Two threads stopped at the same stack, for three rounds before the
+        // process exits
+        let mut rounds = Vec::new();
+        let take_stacks = || {
+            if rounds.len() == 3 {
+                return Ok(None);
+            }
+            let taken_at = Instant::now();
+            rounds.push(taken_at);
+            let stack = vec![ResolvedFrame::synthetic("main", FrameKind::Interpreted)];
+            Ok(Some((taken_at, vec![(11, stack.clone()), (12, stack)])))
+        };
+        let mut samples = Vec::new();
+        spy.stream_on_schedule(1000, None, &running, take_stacks, |taken_at, tid, stack| {
+            samples.push((taken_at, tid, stack.len()));
+            Ok(())
+        })
+        .unwrap();
+        let expected: Vec<(Instant, Tid, usize)> = rounds.iter().flat_map(|&taken_at| [11, 12].map(|tid| (taken_at, tid, 1))).collect();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn skips_missed_deadlines() {
+        let running = AtomicBool::new(true);
+        let interval = Duration::from_millis(10);
+        let slow = interval * 5 / 2;
+
+        // An unwinder that takes two and a half intervals for each of the
+        // first few samples falls behind. The samples after it keep to the
+        // interval from when it caught up, rather than coming back to back
+        // to make up for the samples missed.
+        let spy = node_20_spy(target_with(NODE_20_VERSION, NODE_20_SYMBOLS));
+        let mut starts = Vec::new();
+        let mut caught_up = None;
+        spy.sample_on_schedule(100, None, &running, || {
+            starts.push(Instant::now());
+            if starts.len() <= 4 {
+                std::thread::sleep(slow);
+                caught_up = Some(Instant::now());
+            }
+            Ok(starts.len() < 12)
+        })
+        .unwrap();
+        let caught_up = caught_up.unwrap();
+        for (i, &start) in starts[4..].iter().enumerate() {
+            assert!(start >= caught_up + interval * i as u32, "sample {} came {:?} after catching up", i, start - caught_up);
+        }
+
+        // Every round taking that long lowers the rate achieved to at most
+        // one a slow sample
+        let spy = node_20_spy(target_with(NODE_20_VERSION, NODE_20_SYMBOLS));
+        let stats = spy
+            .sample_on_schedule(100, Some(Duration::from_millis(200)), &running, || {
+                std::thread::sleep(slow);
+                Ok(true)
+            })
+            .unwrap();
+        assert_eq!(stats.requested_rate, 100);
+        assert!(stats.rounds > 0);
+        assert!(stats.achieved_rate() <= 1.0 / slow.as_secs_f64(), "{}", stats.achieved_rate());
+    }
+
+    /// Where the stacks of the tests below are mapped
+    const STACK: usize = 0x7000_0000;
+
+    /// A 1KB stack to map at `STACK`, with the values given at their addresses
+    fn stack_with(values: &[(usize, u64)]) -> Vec<u8> {
+        let mut stack = vec![0u8; 0x400];
+        for &(addr, value) in values {
+            stack[addr - STACK..addr - STACK + 8].copy_from_slice(&value.to_ne_bytes());
+        }
+        stack
+    }
+
+    /// An isolate whose ThreadLocalTop, at the offsets of the fallbacks for
+    /// V8 11.3, keeps `c_entry_fp` as the exit frame
+    fn isolate_with(c_entry_fp: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; 0x200];
+        bytes[0x108 + 0x78..0x108 + 0x80].copy_from_slice(&(c_entry_fp as u64).to_ne_bytes());
+        bytes
+    }
+
+    /// A spy on `target`, with the layout its symbols and the fallbacks give
+    fn node_20_spy(target: FakeTargetBuilder) -> V8Spy<FakeTarget> {
+        V8Spy::from_memory(1, target.build()).unwrap()
+    }
+
+    #[test]
+    fn keeps_frame_addresses() {
+        // Three native frames, each calling from 0x100 bytes up the stack
+        let fp_of = |depth: usize| STACK + 0x100 + depth * 0x100;
+        let mut slots = Vec::new();
+        for depth in 0..3 {
+            let caller_fp = if depth < 2 { fp_of(depth + 1) } else { 0 };
+            slots.extend([(fp_of(depth) - 8, 1), (fp_of(depth), caller_fp as u64), (fp_of(depth) + 8, 0x5555_0000 + depth as u64)]);
+        }
+        let spy = node_20_spy(target_with(NODE_20_VERSION, NODE_20_SYMBOLS).bytes(STACK, &stack_with(&slots)));
+        let registers = Registers { pc: 0x6666_0000, sp: fp_of(0) - 0x40, fp: fp_of(0) };
+        // Each caller's pc is the return address its callee saved, and its sp
+        // is just above that
+        let expected = [(registers.pc, fp_of(0), registers.sp), (0x5555_0000, fp_of(1), fp_of(0) + 16), (0x5555_0001, fp_of(2), fp_of(1) + 16)];
+        let walked: Vec<_> = spy.walk_stack(registers).iter().map(|frame| (frame.pc, frame.fp, frame.sp)).collect();
+        assert_eq!(walked, expected);
+    }
+
+    #[test]
+    fn resolves_current_function() {
+        let (target, registers) = fake_process();
+        let spy = V8Spy::from_memory(1, target).unwrap();
+        // Stopped in native code there's no JavaScript function running
+        assert!(spy.current_function(&registers).unwrap().is_none());
+        // Back in the leaf frame, that's the one resolved, not its callers
+        let fp = STACK_BASE + 2 * FRAME_SIZE;
+        let leaf = Registers { pc: 0x5555_0000, sp: registers.fp + 16, fp };
+        let frame = spy.current_function(&leaf).unwrap().unwrap();
+        assert_eq!(frame.to_string(), "leaf (/srv/app.js:4)");
+        assert_eq!(frame.address, FrameAddress { pc: leaf.pc, fp, sp: leaf.sp });
+    }
+
+    #[test]
+    fn reads_thread_local_top() {
+        // An exit frame whose sp is in its slot 16 bytes down, with the
+        // return address below that
+        let (exit_fp, exit_sp) = (STACK + 0x100, STACK + 0xc0);
+        let stack = stack_with(&[(exit_sp - 8, 0x5555_0000), (exit_fp - 16, exit_sp as u64)]);
+        let builder = target_with(NODE_20_VERSION, NODE_20_SYMBOLS)
+            .bytes(STACK, &stack)
+            .bytes(0x3000_0000, &isolate_with(exit_fp))
+            .bytes(0x3001_0000, &isolate_with(0));
+        let spy = node_20_spy(builder);
+        let top = spy.thread_local_top(&IsolateRef { address: 0x3000_0000 }).unwrap();
+        assert_eq!(top, Registers { pc: 0x5555_0000, sp: exit_sp, fp: exit_fp });
+        let error = spy.thread_local_top(&IsolateRef { address: 0x3001_0000 }).unwrap_err();
+        assert_eq!(error.to_string(), "Isolate 0x30010000 isn't in a call out of JavaScript");
+    }
+
+    #[test]
+    fn walks_from_isolate() {
+        // Two native frames above an exit frame, whose sp is in its slot 16
+        // bytes down and the return address below that
+        let (exit_fp, exit_sp, caller_fp) = (STACK + 0x100, STACK + 0xc0, STACK + 0x200);
+        let native = 1;
+        let isolate = 0x3000_0000;
+        let stack = stack_with(&[
+            (exit_sp - 8, 0x5555_0000),
+            (exit_fp - 16, exit_sp as u64),
+            (exit_fp - 8, native),
+            (exit_fp, caller_fp as u64),
+            (exit_fp + 8, 0x5555_1000),
+            (caller_fp - 8, native),
+        ]);
+        let builder = target_with(NODE_20_VERSION, NODE_20_SYMBOLS).bytes(STACK, &stack).bytes(isolate, &isolate_with(exit_fp));
+        let mut spy = node_20_spy(builder);
+        let fps = |frames: Vec<Frame>| frames.iter().map(|frame| (frame.pc, frame.fp)).collect::<Vec<_>>();
+
+        // Stopped in native code without frame pointers, below the exit frame
+        let broken = Registers { pc: 0x6666_0000, sp: exit_fp - 0x80, fp: 0x10 };
+        assert!(spy.walk_thread(broken).is_empty());
+        spy.set_isolate(isolate);
+        assert_eq!(spy.isolate, Some(IsolateRef { address: isolate }));
+        assert_eq!(fps(spy.walk_thread(broken)), [(0x5555_0000, exit_fp), (0x5555_1000, caller_fp)]);
+        // Other threads, whose stacks the exit frame isn't on
+        let above = Registers { sp: exit_fp + 8, ..broken };
+        assert!(spy.walk_thread(above).is_empty());
+        let far_below = Registers { sp: exit_fp - MAX_THREAD_STACK - 8, ..broken };
+        assert!(spy.walk_thread(far_below).is_empty());
+    }
+
+    #[test]
+    fn checks_isolate_address() {
+        let fp = STACK + 0x100;
+        let builder = target_with(NODE_20_VERSION, NODE_20_SYMBOLS)
+            .bytes(STACK, &stack_with(&[]))
+            .bytes(0x3000_0000, &isolate_with(fp))
+            .bytes(0x3001_0000, &isolate_with(0))
+            .bytes(0x3002_0000, &isolate_with(fp + 4))
+            .bytes(0x3003_0000, &isolate_with(0x5000_0000));
+        let spy = node_20_spy(builder);
+        assert!(spy.check_isolate(0x3000_0000).is_ok());
+        // Not in a call out of JavaScript
+        assert!(spy.check_isolate(0x3001_0000).is_ok());
+        let error = |addr| format!("{:#}", spy.check_isolate(addr).unwrap_err());
+        assert_eq!(error(0x3002_0000), "the exit frame in its ThreadLocalTop, 0x70000104, isn't a frame pointer");
+        assert_eq!(error(0x3003_0000), "the exit frame in its ThreadLocalTop, 0x50000000, isn't a frame pointer");
+        assert!(error(0x3004_0000).starts_with("its ThreadLocalTop can't be read"));
+    }
+
+    #[test]
+    fn tells_debugger_pause() {
+        // Isolates as V8 11.3 lays them out, each pointing to its Debug
+        let isolate_with = |debug: u64| {
+            let mut bytes = vec![0u8; 0xe600];
+            bytes[0xe5e8..0xe5f0].copy_from_slice(&debug.to_ne_bytes());
+            bytes
+        };
+        let debug_with = |is_active: u8, current_debug_scope: u64| {
+            let mut bytes = vec![0u8; 0x48];
+            bytes[0x8] = is_active;
+            bytes[0x40..0x48].copy_from_slice(&current_debug_scope.to_ne_bytes());
+            bytes
+        };
+        let target = target_with(NODE_20_VERSION, NODE_20_SYMBOLS)
+            .bytes(0x3000_0000, &isolate_with(0x3100_0000))
+            .bytes(0x3100_0000, &debug_with(1, 0x7ffe_1000))
+            .bytes(0x3200_0000, &isolate_with(0x3300_0000))
+            .bytes(0x3300_0000, &debug_with(1, 0))
+            .bytes(0x3400_0000, &isolate_with(0x3500_0000))
+            .bytes(0x3500_0000, &debug_with(0, 0))
+            .bytes(0x3600_0000, &isolate_with(0))
+            .build();
+        let mut spy = V8Spy::from_memory(1, target).unwrap();
+        assert!(spy.is_paused_in_debugger().is_err());
+        let mut paused = |isolate| {
+            spy.set_isolate(isolate);
+            spy.is_paused_in_debugger().unwrap()
+        };
+        // Stopped at a breakpoint, with the debugger's scope on its stack
+        assert!(paused(0x3000_0000));
+        // Running with the debugger attached, without one, and before the
+        // isolate has a Debug at all
+        assert!(!paused(0x3200_0000));
+        assert!(!paused(0x3400_0000));
+        assert!(!paused(0x3600_0000));
+
+        let target = target_with([Some(11), Some(4), Some(0), Some(0)], NODE_20_SYMBOLS).build();
+        let mut spy = V8Spy::from_memory(1, target).unwrap();
+        spy.set_isolate(0x3000_0000);
+        assert_eq!(spy.is_paused_in_debugger().unwrap_err().to_string(), "Debug layout unknown for V8 11.4.0.0");
+    }
+
+    #[test]
+    fn labels_builtins() {
+        assert_eq!(builtin_label("LdaSmiHandler"), "<bytecode handler>");
+        assert_eq!(builtin_label("LdaSmiWideHandler"), "<bytecode handler>");
+        assert_eq!(builtin_label("InterpreterEntryTrampoline"), "<bytecode handler>");
+        assert_eq!(builtin_label("InterpreterEntryTrampolineForProfiling"), "<bytecode handler>");
+        assert_eq!(builtin_label("InterpreterEnterAtBytecode"), "<bytecode handler>");
+        assert_eq!(builtin_label("ArrayPrototypePush"), "<builtin: ArrayPrototypePush>");
+        assert_eq!(builtin_label("CEntry_Return1_ArgvOnStack_BuiltinExit"), "<builtin: CEntry_Return1_ArgvOnStack_BuiltinExit>");
+        // Only a handler by the end of its name
+        assert_eq!(builtin_label("HandlerTable"), "<builtin: HandlerTable>");
+    }
+
+    #[test]
+    fn resolves_frames_in_builtins() {
+        let (target, _) = fake_process();
+        let mut spy = V8Spy::from_memory(1, target).unwrap();
+        spy.builtin_ranges.insert(0x4000, (0x4100, "LdaSmiHandler".to_owned()));
+        spy.builtin_ranges.insert(0x4100, (0x4200, "ArrayPrototypePush".to_owned()));
+        let resolve = |kind, pc| {
+            let frame = spy.resolve_frame(&Frame { kind, pc, ..Frame::default() }).unwrap();
+            (frame.name, frame.kind)
+        };
+        assert_eq!(resolve(FrameKind::Builtin, 0x4000), ("<bytecode handler>".to_owned(), FrameKind::Builtin));
+        assert_eq!(resolve(FrameKind::Native, 0x40ff), ("<bytecode handler>".to_owned(), FrameKind::Builtin));
+        assert_eq!(resolve(FrameKind::Native, 0x4100), ("<builtin: ArrayPrototypePush>".to_owned(), FrameKind::Builtin));
+        // Past the end of the last builtin, and frames of other kinds
+        assert_eq!(resolve(FrameKind::Native, 0x4200), ("<native>".to_owned(), FrameKind::Native));
+        assert_eq!(resolve(FrameKind::Builtin, 0x3fff), ("<builtin>".to_owned(), FrameKind::Builtin));
+        assert_eq!(resolve(FrameKind::Exit, 0x4000), ("<exit>".to_owned(), FrameKind::Exit));
+    }
+
+    #[test]
+    fn matches_node_processes_by_name() {
+        let candidate = |pid, exe: &str, cmdline: &[&str]| ProcessCandidate { pid, exe: exe.to_owned(), cmdline: cmdline.iter().map(|&arg| arg.to_owned()).collect() };
+        let processes = [
+            candidate(10, "/usr/bin/node", &["node", "/srv/api/server.js", "--port=8080"]),
+            candidate(11, "/opt/node-v20/bin/node", &["node", "worker.js"]),
+            candidate(12, "/usr/bin/python3", &["python3", "server.py"]),
+            candidate(13, "/usr/local/bin/nodemon", &["nodemon", "server.js"]),
+            candidate(14, "/usr/bin/vim", &["vim", "/srv/api/server.js"]),
+            candidate(15, "/usr/bin/nodejs", &["nodejs", "server.js"]),
+            candidate(16, "/usr/bin/node18", &["node18", "server.js"]),
+        ];
+        let matching = |name| processes.iter().filter(|process| process.matches(name)).map(|process| process.pid).collect::<Vec<_>>();
+        // Not nodemon, which runs the server under a node process of its own
+        assert_eq!(matching("server"), [10, 15, 16]);
+        assert_eq!(matching("8080"), [10]);
+        // By the path of the executable, too
+        assert_eq!(matching("node-v20"), [11]);
+        // Everything running node
+        assert_eq!(matching(""), [10, 11, 15, 16]);
+        assert!(matching("nothing").is_empty());
+    }
+
+    /// Counts its pauses and resumes, failing to pause if `fails`
+    #[derive(Default)]
+    struct MockProcess {
+        fails: bool,
+        paused: Cell<u32>,
+        resumed: std::rc::Rc<Cell<u32>>,
+    }
+
+    struct MockGuard(std::rc::Rc<Cell<u32>>);
+
+    impl Drop for MockGuard {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    impl Pause for MockProcess {
+        type Guard = MockGuard;
+
+        fn pause(&self) -> Result<MockGuard> {
+            if self.fails {
+                anyhow::bail!("no such process");
+            }
+            self.paused.set(self.paused.get() + 1);
+            Ok(MockGuard(self.resumed.clone()))
+        }
+    }
+
+    #[test]
+    fn resumes_after_running_paused() {
+        let process = MockProcess::default();
+        let result = run_paused(&process, 1, || {
+            assert_eq!((process.paused.get(), process.resumed.get()), (1, 0));
+            7
+        });
+        assert_eq!(result.unwrap(), 7);
+        assert_eq!((process.paused.get(), process.resumed.get()), (1, 1));
+
+        let result = run_paused(&process, 1, || -> Result<()> { anyhow::bail!("unreadable") });
+        assert!(result.unwrap().is_err());
+        assert_eq!((process.paused.get(), process.resumed.get()), (2, 2));
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| run_paused(&process, 1, || panic!("walking the stack"))));
+        assert!(panicked.is_err());
+        assert_eq!((process.paused.get(), process.resumed.get()), (3, 3));
+    }
+
+    #[test]
+    fn fails_to_run_unpaused() {
+        let process = MockProcess { fails: true, ..MockProcess::default() };
+        let ran = Cell::new(false);
+        let error = run_paused(&process, 42, || ran.set(true)).unwrap_err();
+        assert!(!ran.get());
+        assert_eq!(format!("{:#}", error), "Failed to suspend process 42: no such process");
+        assert_eq!(process.resumed.get(), 0);
+    }
+
+    impl ProcessMemory for MockProcess {
+        fn read(&self, addr: usize, _buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+            Err(remoteprocess::Error::Other(format!("No memory at 0x{:x}", addr)))
+        }
+    }
+
+    #[test]
+    fn resumes_when_dropped_paused() {
+        let resumed = std::rc::Rc::new(Cell::new(0));
+        let spy_of = |process| V8Spy::with_layout(1, process, MIN_SUPPORTED_VERSION, exported_layout(), 0, Vec::new(), Vec::new());
+        let spy = spy_of(MockProcess { resumed: resumed.clone(), ..MockProcess::default() });
+        hold_pause(&spy.process, spy.pid, &spy.held_pause).unwrap();
+        // Pausing again keeps the pause there is
+        hold_pause(&spy.process, spy.pid, &spy.held_pause).unwrap();
+        assert_eq!((spy.process.paused.get(), resumed.get()), (1, 0));
+        drop(spy);
+        assert_eq!(resumed.get(), 1);
+
+        // A spy that isn't paused has nothing to resume
+        let spy = spy_of(MockProcess { resumed: resumed.clone(), ..MockProcess::default() });
+        drop(spy);
+        assert_eq!(resumed.get(), 1);
+    }
+
+    #[test]
+    fn attaches_without_patch_number() {
+        let target = target_with([Some(11), Some(3), Some(244), None], NODE_20_SYMBOLS).build();
+        let version = get_v8_version(&target, true).unwrap();
+        assert_eq!((version.major, version.minor, version.build, version.patch), (11, 3, 244, 0));
+        let spy = V8Spy::from_memory(1, target).unwrap();
+        assert_eq!(spy.version.to_string(), "11.3.244.0");
+
+        let target = target_with([Some(11), Some(3), Some(244), None], NODE_20_SYMBOLS).build();
+        let options = V8SpyOptions { allow_partial_version: false, ..V8SpyOptions::default() };
+        let error = V8Spy::from_memory_with_options(1, target, options).err().unwrap();
+        assert_eq!(error.to_string(), "Failed to read the V8 version of the process: no patch number");
+    }
+
+    #[test]
+    fn needs_major_and_minor_number() {
+        for version in [[None, Some(3), Some(244), Some(8)], [Some(11), None, Some(244), Some(8)], [Some(0), Some(3), Some(244), Some(8)]] {
+            let target = target_with(version, NODE_20_SYMBOLS).build();
+            assert!(get_v8_version(&target, true).is_err(), "{:?}", version);
+            assert!(V8Spy::from_memory(1, target).is_err(), "{:?}", version);
+        }
+    }
+
+    #[test]
+    fn refuses_unsupported_version() {
+        let target = target_with([Some(7), Some(1), Some(302), Some(0)], NODE_20_SYMBOLS).build();
+        let error = V8Spy::from_memory(1, target).err().unwrap();
+        assert!(matches!(error.downcast_ref::<Error>(), Some(Error::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn records_unreadable_shared_function_info() {
+        // A JSFunction whose SharedFunctionInfo is in memory that isn't mapped
+        let (map, function, sfi) = (0x1000_0001, 0x1000_0101, 0x2000_0001);
+        let target = target_with(NODE_20_VERSION, NODE_20_SYMBOLS)
+            .bytes(0x1000_0000, &[0; 0x200])
+            .value(map - 1 + 12, 2066u16)
+            .value(function - 1, map as u64)
+            .value(function - 1 + 24, sfi as u64);
+        let mut spy = node_20_spy(target);
+        let records = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let recorded = records.clone();
+        spy.on_unresolved_frame(move |record| recorded.borrow_mut().push(record.clone()));
+
+        let frame = Frame { kind: FrameKind::Interpreted, pc: 0x5555_0000, fp: STACK, function, shared_function_info: sfi, ..Frame::default() };
+        assert_eq!(spy.resolve_stack(&[frame]), [ResolvedFrame::synthetic("<unknown>", FrameKind::Interpreted)]);
+        let records = records.borrow();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0],
+            FrameDebugRecord {
+                kind: FrameKind::Interpreted,
+                pc: 0x5555_0000,
+                fp: STACK,
+                marker: None,
+                function,
+                function_type: Some(2066),
+                shared_function_info: sfi,
+                shared_function_info_type: None,
+                step: "function name",
+                error: "No memory mapped at 0x20000000 for 8 bytes".to_owned(),
+            }
+        );
+    }
+
+    /// Node 20's symbols, with the layout of Code, which Node 20 leaves out
+    fn symbols_with_code() -> Vec<(&'static str, i32)> {
+        NODE_20_SYMBOLS
+            .iter()
+            .copied()
+            .chain([
+                ("v8dbg_class_Code__deoptimization_data__FixedArray", 8),
+                ("v8dbg_class_Code__source_position_table__ByteArray", 16),
+                ("v8dbg_class_Code__instruction_size__int", 24),
+                ("v8dbg_class_Code__instruction_start__uintptr_t", 64),
+            ])
+            .collect()
+    }
+
+    #[test]
+    fn baseline_code_keeps_bytecode() {
+        let mut heap = Heap::new();
+        let script = heap.script("/srv/app.js");
+        let (_, bytecode_array) = heap.interpreted_function("tick", 3, script);
+        // Once Sparkplug has compiled the function, its function data is the
+        // baseline Code, holding the bytecode it was compiled from where
+        // optimized code keeps its DeoptimizationData
+        let code = heap.object(heap.code_map, 64);
+        heap.write_u64(code, 8, bytecode_array as u64);
+        let sfi = heap.shared_function_info("tick", code, script);
+        let target = target_with(NODE_20_VERSION, &symbols_with_code()).bytes(HEAP_BASE, &heap.bytes).build();
+        let spy = V8Spy::from_memory(1, target).unwrap();
+
+        let kind = spy.function_data_kind(sfi).unwrap();
+        assert_eq!(kind, FunctionDataKind::Baseline { code, bytecode_array: Some(bytecode_array) });
+        assert_eq!(kind.bytecode_array(), Some(bytecode_array));
+    }
+
+    #[test]
+    fn expands_inlined_frames() {
+        let mut heap = Heap::new();
+        let script = heap.script("/srv/app.js");
+        let [leaf, mid, outer] = ["leaf", "mid", "outer"].map(|name| heap.shared_function_info(name, 0, script));
+        let at = |line: i64| (line - 1) * LINE_LENGTH;
+
+        // mid is inlined into outer at line 30, and leaf into mid at line 20
+        let mut inlining_positions = Vec::new();
+        for (position, literal) in [(source_position(at(30), None), 0i32), (source_position(at(20), Some(0)), 1)] {
+            inlining_positions.extend_from_slice(&position.to_ne_bytes());
+            inlining_positions.extend_from_slice(&literal.to_ne_bytes());
+            inlining_positions.extend_from_slice(&[0; 4]);
+        }
+        let inlining_positions = heap.byte_array(&inlining_positions);
+        // With leaf held weakly
+        let literals = heap.fixed_array(&[mid as u64, leaf as u64 | 0b10]);
+        let mut deoptimization_data = [smi(0); 8];
+        deoptimization_data[2] = literals as u64;
+        deoptimization_data[7] = inlining_positions as u64;
+        let deoptimization_data = heap.fixed_array(&deoptimization_data);
+
+        // leaf at line 4 from code offset 0, and at line 5 from 0x40, then
+        // outer's own code at line 12 from 0x80
+        let mut table = Vec::new();
+        let (mut last_offset, mut last_position) = (0, 0);
+        for (offset, position) in [(0, source_position(at(4), Some(1))), (0x40, source_position(at(5), Some(1))), (0x80, source_position(at(12), None))] {
+            push_vlq(&mut table, offset - last_offset);
+            push_vlq(&mut table, position - last_position);
+            (last_offset, last_position) = (offset, position);
+        }
+        let table = heap.byte_array(&table);
+        let code = heap.object(heap.code_map, 64 + 0x100);
+        heap.write_u64(code, 8, deoptimization_data as u64);
+        heap.write_u64(code, 16, table as u64);
+        heap.write(code, 24, &0x100u32.to_ne_bytes());
+        let start = code - 1 + 64;
+
+        let target = target_with(NODE_20_VERSION, &symbols_with_code()).bytes(HEAP_BASE, &heap.bytes).build();
+        let spy = V8Spy::from_memory(1, target).unwrap();
+        let frames = |pc| {
+            let frame = Frame { kind: FrameKind::Optimized, pc, code, shared_function_info: outer, ..Frame::default() };
+            spy.expand_inlined_frames(&frame).unwrap().iter().map(ToString::to_string).collect::<Vec<_>>()
+        };
+        // Return addresses, just past the call made from the line
+        assert_eq!(frames(start + 0x10), ["leaf (/srv/app.js:4)", "mid (/srv/app.js:20)", "outer (/srv/app.js:30)"]);
+        assert_eq!(frames(start + 0x40), ["leaf (/srv/app.js:4)", "mid (/srv/app.js:20)", "outer (/srv/app.js:30)"]);
+        assert_eq!(frames(start + 0x41), ["leaf (/srv/app.js:5)", "mid (/srv/app.js:20)", "outer (/srv/app.js:30)"]);
+        assert_eq!(frames(start + 0x81), ["outer (/srv/app.js:12)"]);
+        // Outside the code, only the frame's own function is known
+        assert_eq!(frames(start + 0x101), ["outer (/srv/app.js)"]);
+        assert_eq!(spy.resolve_frame(&Frame { kind: FrameKind::Optimized, pc: start + 0x10, code, shared_function_info: outer, ..Frame::default() }).unwrap().to_string(), "outer (/srv/app.js:30)");
+    }
+
+    #[test]
+    fn replays_recording() {
+        let (target, registers) = fake_process();
+        let spy = V8Spy::from_memory(1, Recorder::new(1, target)).unwrap();
+        let recorded = spy.resolve_stack(&spy.walk_stack(registers));
+        let mut recording = spy.process.take_recording();
+        recording.threads.push(RecordedThread { tid: 1, name: Some("node".to_owned()), registers });
+
+        let parsed = Recording::parse(&recording.to_text()).unwrap();
+        assert_eq!(parsed, recording);
+        let replayed = V8Spy::from_memory(1, parsed).unwrap().replay();
+        assert_eq!(replayed.captured_at, recording.captured_at);
+        assert_eq!(replayed.threads, [(ThreadInfo { tid: 1, name: Some("node".to_owned()) }, recorded)]);
+    }
+
+    /// What `V8Spy::selftest` makes of `target`, with its thread stopped at
+    /// `registers`
+    fn selftest_of<P: MemorySource>(target: P, registers: Registers) -> SelfTest {
+        SelfTest::from_result(V8Spy::selftest_attach(1, target, V8SpyOptions::default()).and_then(|spy| {
+            let frames = spy.selftest_stack(registers)?;
+            Ok(frames.expect("a thread running JavaScript"))
+        }))
+    }
+
+    #[test]
+    fn selftest_passes_replayed() {
+        let (target, registers) = fake_process();
+        let spy = V8Spy::selftest_attach(1, Recorder::new(1, target), V8SpyOptions::default()).unwrap();
+        assert_eq!(spy.selftest_stack(registers).unwrap(), Some(STACK_FUNCTIONS.len() + 1));
+        let selftest = selftest_of(spy.process.take_recording(), registers);
+        assert!(selftest.passed());
+        assert_eq!(selftest.to_string(), format!("PASS ({} frames)", STACK_FUNCTIONS.len() + 1));
+    }
+
+    #[test]
+    fn selftest_fails_at_offsets() {
+        let (_, registers) = fake_process();
+        // The Smi tag mask's symbol is there, but its value can't be read
+        let symbols: Vec<_> = NODE_20_SYMBOLS.iter().copied().filter(|&(name, _)| name != "v8dbg_SmiTagMask").collect();
+        let target = target_with(NODE_20_VERSION, &symbols).symbol("v8dbg_SmiTagMask", 0x9000).build();
+        let selftest = selftest_of(target, registers);
+        assert!(!selftest.passed());
+        assert_eq!(selftest.to_string(), "FAIL at offset resolution: Invalid Smi tag 0 for tag mask 0");
+    }
+
+    #[test]
+    fn reads_into_buffers() {
+        let base = 0x1000_0000;
+        let region: Vec<u8> = (1..=16).collect();
+        let mut spy = node_20_spy(target_with(NODE_20_VERSION, NODE_20_SYMBOLS).bytes(base, &region));
+        let mut short = [0u8; 3];
+        spy.read_into(base + 5, &mut short).unwrap();
+        assert_eq!(short, [6, 7, 8]);
+        let mut whole = [0u8; 16];
+        spy.read_into(base, &mut whole).unwrap();
+        assert_eq!(whole[..], region[..]);
+        spy.read_into(base + 16, &mut []).unwrap();
+        spy.read_into(0x10, &mut []).unwrap();
+
+        // Past the end of the region. A fake only fails the read itself, but
+        // with the process's memory map the read isn't made at all.
+        let mut long = [0u8; 8];
+        assert!(spy.read_into(base + 12, &mut long).is_err());
+        spy.memory_map = Some(RefCell::new(MemoryMap::from_ranges([(base, base + 16)])));
+        let error = spy.read_into(base + 12, &mut long).unwrap_err();
+        assert!(matches!(error.downcast_ref::<Error>(), Some(&Error::UnmappedRegion { address, size: 8 }) if address == base + 12));
+        assert_eq!(long, [0; 8]);
+        spy.read_into(base + 8, &mut long).unwrap();
+        assert_eq!(long, [9, 10, 11, 12, 13, 14, 15, 16]);
+    }
+
+    #[test]
+    fn untags_heap_pointers() {
+        let target = target_with(NODE_20_VERSION, NODE_20_SYMBOLS).value(HEAP_BASE + 8, (HEAP_BASE + 0x41) as u64).build();
+        let spy = V8Spy::from_memory(1, target).unwrap();
+        assert_eq!(spy.pointer_size, 8);
+        assert!(!spy.compression_enabled);
+        let object = HEAP_BASE + 1;
+        assert!(spy.is_heap_object(object));
+        assert!(!spy.is_smi(object));
+        assert_eq!(spy.field_address(object, 8), HEAP_BASE + 8);
+        assert_eq!(spy.read_tagged_pointer(object, 8).unwrap(), HEAP_BASE + 0x41);
+        assert!(spy.is_smi(smi(-5) as usize));
+        assert_eq!(spy.smi_to_int(smi(-5) as usize), -5);
+        // A bogus pointer makes for a failed read rather than an overflow
+        assert_eq!(spy.field_address(0, 8), 7);
+    }
+
+    #[test]
+    fn decompresses_with_cage_base() {
+        let cage_base = 0x4_0000_0000usize;
+        let symbols = symbols_with(&[("v8dbg_SmiShiftSize", 0), ("v8dbg_class_FixedArrayBase__length__SMI", 4)]);
+        let target = target_with(NODE_20_VERSION, &symbols)
+            .symbol_value(CAGE_BASE_SYMBOL, 0x3000, cage_base as u64)
+            .bytes(0x3100, &[0x41, 0, 0, 0, 0xfe, 0xff, 0xff, 0xff])
+            .build();
+        let spy = V8Spy::from_memory(1, target).unwrap();
+        assert!(spy.compression_enabled);
+        assert_eq!(spy.cage_base.get(), cage_base);
+        assert_eq!(spy.read_tagged(0x3100).unwrap(), cage_base + 0x41);
+        assert_eq!(spy.smi_to_int(spy.read_tagged(0x3104).unwrap()), -1);
+        assert_eq!(spy.read_tagged_array(0x3100, 2).unwrap(), vec![cage_base + 0x41, usize::MAX - 1]);
+    }
+
+    #[test]
+    fn reads_32_bit_pointers_before_the_layout() {
+        let mut label = b"11.3.244.8-node.16".to_vec();
+        label.resize(32, 0);
+        let target = target_with(NODE_20_VERSION, &symbols_32_bit())
+            .symbol_value("_ZN2v88internal7Version15version_string_E", 0x3000, 0x3100u32)
+            .symbol_value(CAGE_BASE_SYMBOL, 0x3010, 0x2000_0000u32)
+            .bytes(0x3100, &label)
+            .build();
+        assert_eq!(symbol_pointer_size(&target), 4);
+        assert_eq!(symbol_pointer_size(&FakeTarget::default()), POINTER_SIZE);
+        let version = get_v8_version(&target, false).unwrap();
+        assert_eq!(version.label.as_deref(), Some("-node.16"));
+        let spy = V8Spy::from_memory(1, target).unwrap();
+        assert_eq!(spy.cage_base.get(), 0x2000_0000);
+    }
+
+    #[test]
+    fn matches_thread_names() {
+        assert!(thread_name_matches("node", "node"));
+        assert!(thread_name_matches("V8 DefaultWorke", "V8 DefaultWorke"));
+        // The kernel keeps the first 15 bytes of a longer name
+        assert!(thread_name_matches("V8 DefaultWorke", "V8 DefaultWorker"));
+        assert!(thread_name_matches("libuv-worker-ex", "libuv-worker-extra-long"));
+        assert!(!thread_name_matches("node", "nod"));
+        assert!(!thread_name_matches("node", "node-main"));
+        assert!(!thread_name_matches("V8 DefaultWorke", "V8 PlatformWorker"));
+        // Only a name cut off at the limit is taken to be a prefix
+        assert!(!thread_name_matches("V8 Default", "V8 DefaultWorker"));
+    }
+
+    #[test]
+    fn no_version_without_v8() {
+        // A process with neither V8's symbols nor its memory
+        let target = FakeTarget::builder().symbol("main", 0x1000).symbol("uv_run", 0x2000).bytes(0x1000, &[0; 16]).build();
+        assert_eq!(version_of(&target), None);
+        // One with V8's symbols where its version isn't readable yet
+        let target = FakeTarget::builder().symbol(V8_SENTINEL_SYMBOLS[0], 0x1000).build();
+        assert_eq!(version_of(&target), None);
+        let version = version_of(&target_with(NODE_20_VERSION, &[]).build()).unwrap();
+        assert_eq!(version.to_string(), "11.3.244.8");
+    }
+
+    /// Node 20's symbols, with some of their values replaced
+    fn symbols_with(overrides: &[(&'static str, i32)]) -> Vec<(&'static str, i32)> {
+        NODE_20_SYMBOLS
+            .iter()
+            .map(|&(name, value)| overrides.iter().find(|(other, _)| *other == name).copied().unwrap_or((name, value)))
+            .collect()
+    }
+
+    /// The symbols of a 32-bit build: 4 byte slots, and Smis in the top 31
+    /// bits of a word
+    fn symbols_32_bit() -> Vec<(&'static str, i32)> {
+        symbols_with(&[
+            ("v8dbg_SmiShiftSize", 0),
+            ("v8dbg_off_fp_function", -8),
+            ("v8dbg_off_fp_context", -4),
+            ("v8dbg_class_FixedArrayBase__length__SMI", 4),
+        ])
+    }
+
+    #[test]
+    fn decodes_32_bit_smis() {
+        let target = target_with(NODE_20_VERSION, &symbols_32_bit())
+            .value(0x3000, 0x8000_0001u32)
+            .value(0x3010, 0xffff_fffeu32)
+            .build();
+        let spy = V8Spy::from_memory(1, target).unwrap();
+        assert_eq!(spy.pointer_size, 4);
+        assert!(!spy.compression_enabled);
+        assert_eq!(spy.smi_to_int(42 << 1), 42);
+        assert_eq!(spy.smi_to_int(0xffff_fffe), -1);
+        assert_eq!(spy.smi_to_int(0x8000_0000), -(1 << 30));
+        // Each read is of a 4 byte region, so a wider read would fail
+        assert_eq!(spy.read_ptr(0x3000).unwrap(), 0x8000_0001);
+        assert_eq!(spy.read_tagged(0x3000).unwrap(), 0x8000_0001);
+        assert_eq!(spy.smi_to_int(spy.read_tagged(0x3010).unwrap()), -1);
+        assert_eq!(spy.field_address(0x8000_0001, 4), 0x8000_0004);
+        assert_eq!(spy.read_tagged_pointer(0x3001, 0x10).unwrap(), 0xffff_fffe);
     }
-}
\ No newline at end of file
+}
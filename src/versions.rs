@@ -0,0 +1,95 @@
+//! The V8 versions where a layout the fallback offsets depend on changed, as
+//! packed by `v8_ver`. A binary without the postmortem symbols for a layout
+//! gets the fallback for the newest of these that its version has reached,
+//! so an offset database keyed by version can line up with the spy's.
+
+/// Packs a version into one number that compares as the version does
+pub const fn v8_ver(major: u32, minor: u32, build: u32) -> u32 {
+    (major << 24) + (minor << 16) + build
+}
+
+/// Whether two packed versions share their major and minor version, which is
+/// as closely as the offsets known for a single version are matched
+pub const fn same_minor(ver: u32, other: u32) -> bool {
+    ver >> 16 == other >> 16
+}
+
+/// The argument count is pushed between the function and the BytecodeArray
+/// of a frame
+pub const ARGUMENT_COUNT_SLOT: u32 = v8_ver(8, 7, 198);
+
+/// The receiver is pushed below the arguments, at a fixed offset from the
+/// frame pointer, rather than above them
+pub const RECEIVER_BELOW_ARGUMENTS: u32 = v8_ver(8, 9, 0);
+
+/// JSFunction has subclasses, taking 14 instance types
+pub const JSFUNCTION_SUBCLASSES: u32 = v8_ver(9, 0, 14);
+
+/// Class constructors get an instance type of their own, making 15
+pub const JSFUNCTION_CLASS_CONSTRUCTOR: u32 = v8_ver(9, 6, 138);
+
+/// 16 JSFunction instance types, ending with JS_CLASS_CONSTRUCTOR_TYPE
+pub const JSFUNCTION_16_TYPES: u32 = v8_ver(11, 3, 0);
+
+/// The CodeKind has a 4 bit field of its own in the Code flags, and includes
+/// BASELINE. The metadata for it is exported after this.
+pub const BASELINE_METADATA: u32 = v8_ver(9, 0, 240);
+
+/// MAGLEV is the CodeKind after BASELINE, once TURBOPROP was dropped
+pub const MAGLEV_CODE_KIND: u32 = v8_ver(10, 0, 0);
+
+/// The kind specific flags and builtin id of Code were merged into its flags,
+/// in commit cb8be519f0add9b7 "[code] Merge kind_specific_flags with flags"
+pub const CODE_FLAGS_MERGED: u32 = v8_ver(11, 4, 59);
+
+/// JSFunction keeps its Code right before its SharedFunctionInfo, rather
+/// than three fields after it
+pub const JSFUNCTION_CODE_BEFORE_SHARED: u32 = v8_ver(11, 7, 368);
+
+/// The one minor version whose Isolate and Node Environment offsets are
+/// known, as in Node 20. Matched with `same_minor`.
+pub const KNOWN_ISOLATE_LAYOUT: u32 = v8_ver(11, 3, 0);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_versions() {
+        // The inline versions each constant replaced
+        let versions = [
+            (ARGUMENT_COUNT_SLOT, v8_ver(8, 7, 198)),
+            (RECEIVER_BELOW_ARGUMENTS, v8_ver(8, 9, 0)),
+            (JSFUNCTION_SUBCLASSES, v8_ver(9, 0, 14)),
+            (JSFUNCTION_CLASS_CONSTRUCTOR, v8_ver(9, 6, 138)),
+            (JSFUNCTION_16_TYPES, v8_ver(11, 3, 0)),
+            (BASELINE_METADATA, v8_ver(9, 0, 240)),
+            (MAGLEV_CODE_KIND, v8_ver(10, 0, 0)),
+            (CODE_FLAGS_MERGED, v8_ver(11, 4, 59)),
+            (JSFUNCTION_CODE_BEFORE_SHARED, v8_ver(11, 7, 368)),
+            (KNOWN_ISOLATE_LAYOUT, v8_ver(11, 3, 0)),
+        ];
+        for (constant, inline) in versions {
+            assert_eq!(constant, inline);
+        }
+    }
+
+    #[test]
+    fn packed_versions_compare_as_versions() {
+        assert!(v8_ver(8, 7, 197) < ARGUMENT_COUNT_SLOT);
+        assert!(v8_ver(8, 8, 0) > ARGUMENT_COUNT_SLOT);
+        assert!(v8_ver(9, 0, 0) > v8_ver(8, 255, 0xffff));
+        assert!(v8_ver(11, 4, 58) < CODE_FLAGS_MERGED);
+    }
+
+    #[test]
+    fn same_minor_ignores_build() {
+        // The isolate offsets were taken from this build of Node 20
+        assert!(same_minor(v8_ver(11, 3, 244), KNOWN_ISOLATE_LAYOUT));
+        assert!(same_minor(v8_ver(11, 3, 0), KNOWN_ISOLATE_LAYOUT));
+        assert!(same_minor(v8_ver(11, 3, 0xffff), KNOWN_ISOLATE_LAYOUT));
+        assert!(!same_minor(v8_ver(11, 4, 0), KNOWN_ISOLATE_LAYOUT));
+        assert!(!same_minor(v8_ver(11, 2, 244), KNOWN_ISOLATE_LAYOUT));
+        assert!(!same_minor(v8_ver(12, 3, 0), KNOWN_ISOLATE_LAYOUT));
+    }
+}